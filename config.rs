@@ -38,10 +38,16 @@ impl AppConfig {
     ) {
         let version = version.parse::<semver::Version>().unwrap();
 
+        // overridable with `DATA_DIR` to run multiple instances of the app on the same machine
         #[cfg(host)]
         let data_dir = {
-            let project_dirs = prest::ProjectDirs::from("", "", name).unwrap();
-            let path = project_dirs.data_dir().to_path_buf();
+            let path = match env_var("DATA_DIR") {
+                Ok(dir) => std::path::PathBuf::from(dir),
+                Err(_) => {
+                    let project_dirs = prest::ProjectDirs::from("", "", name).unwrap();
+                    project_dirs.data_dir().to_path_buf()
+                }
+            };
             std::fs::create_dir_all(&path).unwrap();
             path
         };
@@ -0,0 +1,128 @@
+use crate::*;
+
+const BACKUP_MAGIC: &[u8; 8] = b"PRESTDB\0";
+/// Version of the archive layout, bumped on incompatible changes of [`Backup`]
+pub const BACKUP_FORMAT_VERSION: u32 = 1;
+/// Max number of rows in a single frame of the archive
+pub const BACKUP_FRAME_ROWS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupRow {
+    /// Encoded primary key
+    pub key: Vec<u8>,
+    pub values: Vec<sql::Value>,
+}
+
+/// Part of the archive, frames follow each other so that tables are streamed without holding them in memory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum BackupFrame {
+    Created(NaiveDateTime),
+    /// Starts rows of the table
+    Table {
+        name: String,
+        columns: Vec<StoredColumn>,
+    },
+    Rows(Vec<BackupRow>),
+}
+
+/// Reads the archive frame by frame: magic bytes and format version followed by length-prefixed bitcode of the frames
+pub struct BackupReader<R: std::io::Read> {
+    reader: R,
+}
+
+impl<R: std::io::Read> BackupReader<R> {
+    pub fn new(mut reader: R) -> Result<Self> {
+        let mut header = [0; BACKUP_MAGIC.len() + 4];
+        if reader.read_exact(&mut header).is_err() || &header[..BACKUP_MAGIC.len()] != BACKUP_MAGIC
+        {
+            return Err(e!("not a prest DB backup"));
+        }
+        let version = u32::from_le_bytes(header[BACKUP_MAGIC.len()..].try_into().unwrap());
+        if version != BACKUP_FORMAT_VERSION {
+            return Err(e!(
+                "unsupported backup format v{version}, expected v{BACKUP_FORMAT_VERSION}"
+            ));
+        }
+        Ok(Self { reader })
+    }
+
+    /// Next frame of the archive or `None` once it's over
+    pub fn frame(&mut self) -> Result<Option<BackupFrame>> {
+        let mut len = [0; 4];
+        // archive can end only between the frames
+        match self.reader.read_exact(&mut len[..1]) {
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+            read => read.somehow()?,
+        }
+        if self.reader.read_exact(&mut len[1..]).is_err() {
+            return Err(e!("truncated backup"));
+        }
+        let len = u32::from_le_bytes(len) as u64;
+        // length isn't trusted to allocate the frame upfront
+        let mut frame = vec![];
+        (&mut self.reader)
+            .take(len)
+            .read_to_end(&mut frame)
+            .somehow()?;
+        if frame.len() as u64 != len {
+            return Err(e!("truncated backup"));
+        }
+        Ok(Some(from_bitcode(&frame).somehow()?))
+    }
+}
+
+/// Writes the archive frame by frame
+pub struct BackupWriter<W: std::io::Write> {
+    writer: W,
+    rows: usize,
+}
+
+impl<W: std::io::Write> BackupWriter<W> {
+    pub fn new(mut writer: W, created_at: NaiveDateTime) -> Result<Self> {
+        writer.write_all(BACKUP_MAGIC).somehow()?;
+        writer
+            .write_all(&BACKUP_FORMAT_VERSION.to_le_bytes())
+            .somehow()?;
+        let mut backup = Self { writer, rows: 0 };
+        backup.frame(&BackupFrame::Created(created_at))?;
+        Ok(backup)
+    }
+
+    pub fn table(&mut self, name: &str, columns: Vec<StoredColumn>) -> Result {
+        self.frame(&BackupFrame::Table {
+            name: name.to_owned(),
+            columns,
+        })
+    }
+
+    pub fn rows(&mut self, rows: Vec<BackupRow>) -> Result {
+        if rows.is_empty() {
+            return OK;
+        }
+        self.rows += rows.len();
+        self.frame(&BackupFrame::Rows(rows))
+    }
+
+    /// Flushes the archive and returns the number of written rows
+    pub fn finish(mut self) -> Result<usize> {
+        self.writer.flush().somehow()?;
+        Ok(self.rows)
+    }
+
+    /// Written bytes which weren't taken yet, used to stream the archive in chunks
+    pub fn take_bytes(&mut self) -> Vec<u8>
+    where
+        W: Default,
+    {
+        std::mem::take(&mut self.writer)
+    }
+
+    fn frame(&mut self, frame: &BackupFrame) -> Result {
+        let bytes = into_bitcode(frame).somehow()?;
+        self.writer
+            .write_all(&(bytes.len() as u32).to_le_bytes())
+            .somehow()?;
+        self.writer.write_all(&bytes).somehow()?;
+        OK
+    }
+}
@@ -0,0 +1,226 @@
+use crate::*;
+use std::{
+    marker::PhantomData,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Row modified by a committed write, broadcasted by the writer thread
+#[derive(Debug, Clone)]
+pub struct RowChange {
+    pub table: &'static str,
+    pub old: Option<Vec<sql::Value>>,
+    pub new: Option<Vec<sql::Value>>,
+}
+
+/// Committed change of a [`Storage`] row, streamed by [`Storage::subscribe`]
+#[derive(Debug, Clone)]
+pub enum Change<T> {
+    Inserted(T),
+    Updated { old: T, new: T },
+    Deleted(T),
+}
+
+impl<T: Storage> Change<T> {
+    fn from_row_change(change: &RowChange) -> Result<Self> {
+        match (change.old.clone(), change.new.clone()) {
+            (None, Some(new)) => Ok(Change::Inserted(T::from_row(new)?)),
+            (Some(old), Some(new)) => Ok(Change::Updated {
+                old: T::from_row(old)?,
+                new: T::from_row(new)?,
+            }),
+            (Some(old), None) => Ok(Change::Deleted(T::from_row(old)?)),
+            (None, None) => Err(e!("change of {} without rows", change.table)),
+        }
+    }
+}
+
+/// SSE event name of the change: `inserted` for new rows and the primary key for updated
+/// and deleted ones, so that lists can append new items while existing ones swap themselves
+///
+/// Rows with composite keys are named by the hex of the whole [`composite_key`] so that rows
+/// sharing some of the key columns don't get each other's events, falling back to the joined
+/// key columns if they can't be encoded
+fn event_name<T: Storage>(change: &RowChange) -> String {
+    let Some(row) = &change.old else {
+        return "inserted".to_owned();
+    };
+    let pkey = T::FIELD_SCHEMAS
+        .iter()
+        .zip(row)
+        .filter(|(field, _)| field.pkey)
+        .map(|(_, value)| value)
+        .collect::<Vec<_>>();
+    match pkey[..] {
+        [value] => String::from(value),
+        _ => {
+            let parts = pkey
+                .iter()
+                .filter_map(|v| sql::Key::try_from((*v).clone()).ok());
+            match composite_key(parts) {
+                Ok(sql::Key::Bytea(bytes)) => bytes.iter().map(|b| format!("{b:02x}")).collect(),
+                _ => pkey
+                    .into_iter()
+                    .map(String::from)
+                    .collect::<Vec<_>>()
+                    .join("-"),
+            }
+        }
+    }
+}
+
+/// Stream of committed changes of the table, created by [`Storage::subscribe`]
+///
+/// Subscribers that fall too far behind skip the oldest changes
+pub struct Changes<T> {
+    receiver: async_broadcast::Receiver<Arc<RowChange>>,
+    table: PhantomData<fn() -> T>,
+}
+
+impl<T: Storage> Changes<T> {
+    pub(crate) fn new(receiver: async_broadcast::Receiver<Arc<RowChange>>) -> Self {
+        Self {
+            receiver,
+            table: PhantomData,
+        }
+    }
+
+    fn poll_row_change(&mut self, cx: &mut Context<'_>) -> Poll<Option<Arc<RowChange>>> {
+        loop {
+            match self.receiver.poll_next_unpin(cx) {
+                Poll::Ready(Some(change)) if change.table != T::STRUCT_NAME => continue,
+                other => return other,
+            }
+        }
+    }
+}
+
+impl<T: Storage> Stream for Changes<T> {
+    type Item = Change<T>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            let Some(change) = std::task::ready!(this.poll_row_change(cx)) else {
+                return Poll::Ready(None);
+            };
+            match Change::from_row_change(&change) {
+                Ok(change) => return Poll::Ready(Some(change)),
+                Err(e) => warn!(target: "db", "skipped change of {}: {e}", T::STRUCT_NAME),
+            }
+        }
+    }
+}
+
+impl<T: Storage + 'static> SseBroadcastExt<Change<T>> for Changes<T> {
+    fn stream_and_render<F>(&self, mut f: F) -> Response
+    where
+        F: FnMut(&String, Change<T>) -> Markup + std::marker::Send + 'static,
+    {
+        let mut changes = Changes::<T>::new(self.receiver.new_receiver());
+        let stream = futures::stream::poll_fn(move |cx| changes.poll_row_change(cx)).filter_map(
+            move |change| {
+                let event = match Change::<T>::from_row_change(&change) {
+                    Ok(typed) => {
+                        let event_name = event_name::<T>(&change);
+                        let rendered = f(&event_name, typed);
+                        Some(SseEvent::default().event(event_name).data(rendered.0))
+                    }
+                    Err(e) => {
+                        warn!(target: "db", "skipped change of {}: {e}", T::STRUCT_NAME);
+                        None
+                    }
+                };
+                async move { event }
+            },
+        );
+
+        Sse::new(stream.map(Ok::<SseEvent, std::convert::Infallible>))
+            .keep_alive(SseKeepAlive::default())
+            .into_response()
+    }
+}
+
+impl Db {
+    /// Stream of committed changes of the table
+    pub fn subscribe<T: Storage>(&self) -> Changes<T> {
+        Changes::new(self.changes.activate_cloned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        id: u8,
+        balance: i64,
+    }
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Post {
+        #[pkey]
+        author: u8,
+        #[pkey]
+        seq: u8,
+    }
+
+    fn updated<T: Storage>(row: &T) -> RowChange {
+        RowChange {
+            table: T::STRUCT_NAME,
+            old: Some(row.into_row().unwrap()),
+            new: Some(row.into_row().unwrap()),
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_only_committed_changes_of_the_table() {
+        let _db = TestDb::new([Account::schema(), Post::schema()]).await;
+        let mut changes = Account::subscribe();
+
+        Post { author: 1, seq: 1 }.save().await.unwrap();
+        let failed = DB
+            .transaction(|tx| async move {
+                Account { id: 1, balance: 0 }.save_in(&tx)?;
+                tx.push(Transaction::SqlString(
+                    "DELETE FROM missing_table".to_owned(),
+                ));
+                OK
+            })
+            .await;
+        assert!(failed.is_err());
+
+        let mut account = Account {
+            id: 2,
+            balance: 100,
+        };
+        account.save().await.unwrap();
+        account.update_balance(50).await.unwrap();
+        assert!(matches!(changes.next().await, Some(Change::Inserted(a)) if a.id == 2));
+        let Some(Change::Updated { old, new }) = changes.next().await else {
+            panic!("update expected");
+        };
+        assert_eq!((old.balance, new.balance), (100, 50));
+    }
+
+    #[test]
+    fn names_events_by_primary_keys() {
+        let account = Account { id: 7, balance: 0 };
+        let inserted = RowChange {
+            old: None,
+            ..updated(&account)
+        };
+        assert_eq!(event_name::<Account>(&inserted), "inserted");
+        assert_eq!(event_name::<Account>(&updated(&account)), "7");
+
+        // rows sharing the first key column don't get each other's events
+        let first = event_name::<Post>(&updated(&Post { author: 1, seq: 2 }));
+        let second = event_name::<Post>(&updated(&Post { author: 1, seq: 3 }));
+        assert_ne!(first, second);
+        assert_eq!(
+            first,
+            event_name::<Post>(&updated(&Post { author: 1, seq: 2 }))
+        );
+    }
+}
@@ -0,0 +1,649 @@
+use {
+    crate::*,
+    gluesql_core::{
+        data::Schema,
+        prelude::Glue,
+        store::{DataRow, GStore, GStoreMut, Store, StoreMut, Transaction as _},
+    },
+    std::collections::HashSet,
+};
+
+/// Tables of the registered [`Storage`] structs kept in a plain GlueSQL storage
+///
+/// Used by the service worker on top of the IndexedDB and by the host's memory and SQLite backends.
+/// Unlike the sled backend it doesn't maintain indexes and full-text search so the queries scan the tables,
+/// `#[unique]` values and `#[references]` are checked with scans as well. Tables with histories
+/// or tombstones are rejected by the host's backends, the service worker leaves them to the host
+pub(crate) struct GlueDb<S: GStore + GStoreMut> {
+    pub glue: Glue<S>,
+    pub schemas: Schemas,
+    /// Tables that were checked against their current schemas
+    synced: HashSet<&'static str>,
+    /// Name of the storage used in errors about unsupported queries
+    backend: &'static str,
+    /// Whether rows can be dropped when columns change instead of failing the queries
+    disposable: bool,
+}
+
+/// Row modified by the write with its values before and after it
+#[derive(Debug, Clone)]
+pub(crate) struct WrittenRow {
+    pub table: &'static str,
+    pub key: sql::Key,
+    pub old: Option<Vec<sql::Value>>,
+    pub new: Option<Vec<sql::Value>>,
+}
+
+impl<S: GStore + GStoreMut> GlueDb<S> {
+    pub fn new(storage: S, schemas: Schemas, backend: &'static str, disposable: bool) -> Self {
+        Self {
+            glue: Glue::new(storage),
+            schemas,
+            synced: HashSet::new(),
+            backend,
+            disposable,
+        }
+    }
+
+    /// Creates tables registered since the last query and handles ones with changed columns
+    pub async fn sync_tables(&mut self) -> Result {
+        for schema in self.schemas.all() {
+            let name = schema.name();
+            if self.synced.contains(name) {
+                continue;
+            }
+            #[cfg(host)]
+            if schema.history() || schema.soft_delete() {
+                return Err(e!(
+                    "{name} keeps histories or tombstones which are supported only by the sled backend, not the {}",
+                    self.backend
+                ));
+            }
+            let Some(current) = self.schemas.fetch_glue_schema(name) else {
+                continue;
+            };
+            // indexes aren't supported by these storages so queries scan the tables,
+            // and the comment describes the columns for migrations after they change
+            let columns = StoredColumn::from_schema(schema);
+            let current = Schema {
+                indexes: vec![],
+                comment: Some(serde_json::to_string(&columns).somehow()?),
+                ..current
+            };
+            let storage = &mut self.glue.storage;
+            match storage.fetch_schema(name).await? {
+                Some(stored) if stored.column_defs == current.column_defs => {
+                    // tables created before the columns were described get the description
+                    if stored.comment.is_none() && !self.disposable {
+                        storage.insert_schema(&current).await?;
+                    }
+                }
+                Some(_) if self.disposable => {
+                    warn!(target: "db", "recreating {name} in the {} with the new columns", self.backend);
+                    storage.delete_schema(name).await?;
+                    storage.insert_schema(&current).await?;
+                }
+                Some(stored) => self.migrate(schema, stored, current).await?,
+                None => storage.insert_schema(&current).await?,
+            }
+            self.synced.insert(name);
+        }
+        OK
+    }
+
+    /// Rewrites rows persisted with the previous columns of the table, see [`MigrationPlan`]
+    async fn migrate(&mut self, schema: StructSchema, stored: Schema, current: Schema) -> Result {
+        let name = schema.name();
+        let Some(columns) = stored.comment.as_deref() else {
+            return Err(e!(
+                "columns of {name} changed but the {} doesn't describe the stored ones",
+                self.backend
+            ));
+        };
+        let stored = StoredSchema {
+            version: 0,
+            columns: serde_json::from_str(columns).somehow()?,
+        };
+        let rows = match MigrationPlan::new(&stored, schema) {
+            Some(plan) if !plan.report.blocking.is_empty() => {
+                return Err(e!(
+                    "migration of {name} requires explicit steps: {}",
+                    plan.report.blocking.join("; ")
+                ))
+            }
+            Some(plan) => {
+                let columns = stored
+                    .columns
+                    .into_iter()
+                    .map(|c| c.name)
+                    .collect::<Vec<_>>();
+                let mut rows = vec![];
+                for (key, values) in self.scan(name).await? {
+                    let columns = columns.clone();
+                    let values = plan.apply(&MigrationRow { columns, values })?;
+                    rows.push((key, DataRow::Vec(values)));
+                }
+                rows
+            }
+            None => vec![],
+        };
+
+        let migrated = rows.len();
+        let storage = &mut self.glue.storage;
+        let atomic = storage.begin(false).await.is_ok();
+        let result = async {
+            storage.insert_schema(&current).await?;
+            storage.insert_data(name, rows).await?;
+            OK
+        }
+        .await;
+        match result {
+            Ok(()) if atomic => storage.commit().await?,
+            Err(e) => {
+                if atomic {
+                    storage.rollback().await?;
+                }
+                return Err(e);
+            }
+            Ok(()) => {}
+        }
+        info!(target: "db", "migrated {migrated} rows of {name} in the {}", self.backend);
+        OK
+    }
+
+    pub async fn read(&mut self, query: Query) -> Result<Payload> {
+        match query {
+            Query::SqlString(sql) => Ok(self.glue.execute(sql).await?.pop().unwrap().into()),
+            Query::SqlStatement(stmt) => {
+                let planned = gluesql_core::plan::plan(&self.glue.storage, stmt).await?;
+                Ok(self.glue.execute_stmt(&planned).await?.into())
+            }
+            Query::GetByPKey { name, pkey } => {
+                let rows = match self.fetch(name, &pkey).await? {
+                    Some(row) => vec![row],
+                    None => vec![],
+                };
+                Ok(Payload::Rows(rows))
+            }
+            Query::PKRange {
+                name,
+                pkey_min,
+                pkey_max,
+            } => {
+                let min = pkey_min.to_cmp_be_bytes()?;
+                let max = pkey_max.to_cmp_be_bytes()?;
+                let mut rows = vec![];
+                for (key, row) in self.scan(name).await? {
+                    let key = key.to_cmp_be_bytes()?;
+                    if min <= key && key < max {
+                        rows.push(row);
+                    }
+                }
+                Ok(Payload::Rows(rows))
+            }
+            Query::IndexRange {
+                name,
+                index,
+                min,
+                max,
+            } => {
+                let schema = self
+                    .schemas
+                    .fetch_struct_schema(name)
+                    .ok_or(e!("{name} table isn't registered"))?;
+                let column = schema
+                    .fields()
+                    .iter()
+                    .position(|f| f.name == index)
+                    .ok_or(e!("{name} has no {index} column"))?;
+                let in_range = |value: &sql::Value| {
+                    !matches!(value, sql::Value::Null)
+                        && min.as_ref().map_or(true, |min| value >= min)
+                        && max.as_ref().map_or(true, |max| value <= max)
+                };
+                let rows = self
+                    .scan(name)
+                    .await?
+                    .into_iter()
+                    .map(|(_, row)| row)
+                    .filter(|row| row.get(column).is_some_and(in_range))
+                    .collect();
+                Ok(Payload::Rows(rows))
+            }
+            Query::MigrationReports => Ok(Payload::Migrations(vec![])),
+            Query::Count { name } => Ok(Payload::Count(self.scan(name).await?.len() as u64)),
+            Query::Export { .. }
+            | Query::History { .. }
+            | Query::Deleted { .. }
+            | Query::Search { .. }
+            | Query::SyncChanges { .. }
+            | Query::ReplicationLog { .. }
+            | Query::ReplicaPosition => {
+                Err(e!("{query:?} isn't supported in the {}", self.backend))
+            }
+        }
+    }
+
+    /// Applies all parts of the write or none of them and returns the modified rows
+    ///
+    /// Storages with their own transactions like SQLite keep writes atomic even if the process
+    /// crashes in the middle of them, others restore the previous values of the rows after failures
+    pub async fn write(&mut self, tx: Transaction) -> Result<(Payload, Vec<WrittenRow>)> {
+        let ops = match tx {
+            Transaction::Batch(ops) => ops,
+            op => vec![op],
+        };
+        let atomic = self.glue.storage.begin(false).await.is_ok();
+        let mut written = vec![];
+        let mut result = Ok(Payload::Success);
+        for op in ops {
+            result = self.apply(op, &mut written).await;
+            if result.is_err() {
+                break;
+            }
+        }
+        match result {
+            Ok(payload) if atomic => {
+                self.glue.storage.commit().await?;
+                Ok((payload, written))
+            }
+            Ok(payload) => Ok((payload, written)),
+            Err(e) if atomic => {
+                self.glue.storage.rollback().await?;
+                Err(e)
+            }
+            Err(e) => {
+                self.undo(written).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Restores previous values of the written rows
+    pub async fn undo(&mut self, written: Vec<WrittenRow>) {
+        for row in written.into_iter().rev() {
+            let name = row.table;
+            if let Err(e) = self.write_row(name, row.key, row.old).await {
+                error!(target: "db", "failed to rollback write to {name} in the {}: {e}", self.backend);
+            }
+        }
+    }
+
+    async fn apply(&mut self, op: Transaction, written: &mut Vec<WrittenRow>) -> Result<Payload> {
+        match op {
+            Transaction::SqlString(sql) => Ok(self.glue.execute(sql).await?.pop().unwrap().into()),
+            Transaction::SqlStatement(stmt) => {
+                let planned = gluesql_core::plan::plan(&self.glue.storage, stmt).await?;
+                Ok(self.glue.execute_stmt(&planned).await?.into())
+            }
+            Transaction::Insert { name, key, row } => {
+                if self.fetch(name, &key).await?.is_some() {
+                    return Err(e!("duplicate data insertion for {key:?}"));
+                }
+                self.check_row(name, &key, &row).await?;
+                self.put(name, key, Some(row.clone()), written).await?;
+                Ok(Payload::Rows(vec![row]))
+            }
+            Transaction::Save { name, key, mut row } => {
+                if let Some(old) = self.fetch(name, &key).await? {
+                    self.keep_created_at(name, &old, &mut row);
+                }
+                self.check_row(name, &key, &row).await?;
+                self.put(name, key, Some(row.clone()), written).await?;
+                Ok(Payload::Rows(vec![row]))
+            }
+            Transaction::UpdateField {
+                name,
+                key,
+                column,
+                value,
+            } => {
+                let Some(mut row) = self.fetch(name, &key).await? else {
+                    return Err(e!("updating non-existent value {key:?}"));
+                };
+                *row.get_mut(column)
+                    .ok_or(e!("{name} has no column {column}"))? = value;
+                self.touch_updated_at(name, &mut row, column);
+                self.check_row(name, &key, &row).await?;
+                self.put(name, key, Some(row), written).await?;
+                Ok(Payload::Success)
+            }
+            Transaction::Delete { name, key } => {
+                if self.fetch(name, &key).await?.is_none() {
+                    return Err(e!("deleting non-existent value {key:?}"));
+                }
+                self.delete_row(name, key, written).await?;
+                Ok(Payload::Success)
+            }
+            Transaction::Expire { rows } => {
+                let mut expired = 0;
+                for (name, key) in rows {
+                    if self.fetch(name, &key).await?.is_none() {
+                        continue;
+                    }
+                    self.delete_row(name, key, written).await?;
+                    expired += 1;
+                }
+                Ok(Payload::Affected(expired))
+            }
+            // tables are synced with their schemas before every query
+            Transaction::SyncIndexes { .. } | Transaction::Migrate { .. } => Ok(Payload::Success),
+            Transaction::Batch(_) => Err(e!("nested batches are not supported")),
+            #[cfg(feature = "experimental")]
+            Transaction::Nuke => {
+                for schema in self.schemas.all() {
+                    let name = schema.name();
+                    let keys = self.scan(name).await?.into_iter().map(|(key, _)| key);
+                    self.glue.storage.delete_data(name, keys.collect()).await?;
+                }
+                Ok(Payload::Success)
+            }
+            op => Err(e!("{op:?} isn't supported in the {}", self.backend)),
+        }
+    }
+
+    pub async fn fetch(&self, name: &str, key: &sql::Key) -> Result<Option<Vec<sql::Value>>> {
+        let row = self.glue.storage.fetch_data(name, key).await?;
+        #[cfg(host)]
+        crate::host::db::accessed(QueryPlan::PkLookup, row.is_some() as u64);
+        match row {
+            Some(DataRow::Vec(values)) => Ok(Some(values)),
+            Some(DataRow::Map(_)) => Err(e!("unexpected DataRow variant in {name}")),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn scan(&self, name: &str) -> Result<Vec<(sql::Key, Vec<sql::Value>)>> {
+        let rows: Vec<(sql::Key, DataRow)> = self
+            .glue
+            .storage
+            .scan_data(name)
+            .await?
+            .try_collect()
+            .await?;
+        #[cfg(host)]
+        crate::host::db::accessed(QueryPlan::FullScan, rows.len() as u64);
+        rows.into_iter()
+            .map(|(key, row)| match row {
+                DataRow::Vec(values) => Ok((key, values)),
+                DataRow::Map(_) => Err(e!("unexpected DataRow variant in {name}")),
+            })
+            .collect()
+    }
+
+    /// Checks that `#[unique]` values of the row aren't taken by other rows
+    /// and that rows referenced by its enforced relations exist
+    async fn check_row(&self, name: &str, key: &sql::Key, row: &[sql::Value]) -> Result {
+        let Some(schema) = self.schemas.fetch_struct_schema(name) else {
+            return OK;
+        };
+        let fields = schema.fields();
+
+        let unique = fields
+            .iter()
+            .enumerate()
+            .filter(|(_, f)| f.unique && !f.pkey)
+            .map(|(position, _)| position)
+            .collect::<Vec<_>>();
+        if !unique.is_empty() {
+            for (other, values) in self.scan(name).await? {
+                if &other == key {
+                    continue;
+                }
+                for &position in unique.iter() {
+                    let (Some(value), Some(taken)) = (row.get(position), values.get(position))
+                    else {
+                        continue;
+                    };
+                    if !matches!(value, sql::Value::Null) && value == taken {
+                        return Err(e!(
+                            "{name}.{} value {value:?} is already taken",
+                            fields[position].name
+                        ));
+                    }
+                }
+            }
+        }
+
+        for (field, value) in fields.iter().zip(row) {
+            let Some(referenced) = field.references else {
+                continue;
+            };
+            if field.on_delete == OnDelete::Keep || matches!(value, sql::Value::Null) {
+                continue;
+            }
+            let key = sql::Key::try_from(value.clone())?;
+            if self.fetch(referenced, &key).await?.is_none() {
+                return Err(e!(
+                    "{name}.{} references missing {referenced} row {key:?}",
+                    field.name
+                ));
+            }
+        }
+        OK
+    }
+
+    /// Deletes the row along with the rows referencing it with `OnDelete::Cascade`,
+    /// fails if it's referenced with `OnDelete::Restrict`
+    async fn delete_row(
+        &mut self,
+        name: &'static str,
+        key: sql::Key,
+        written: &mut Vec<WrittenRow>,
+    ) -> Result {
+        let mut pending = vec![(name, key)];
+        while let Some((name, key)) = pending.pop() {
+            // might be already deleted through another relation
+            let Some(old) = self.fetch(name, &key).await? else {
+                continue;
+            };
+            self.put(name, key, None, written).await?;
+            pending.extend(self.referencing_rows(name, &old).await?);
+        }
+        OK
+    }
+
+    /// Keys of the rows which should be deleted along with the referenced one
+    async fn referencing_rows(
+        &self,
+        name: &str,
+        row: &[sql::Value],
+    ) -> Result<Vec<(&'static str, sql::Key)>> {
+        let Some(schema) = self.schemas.fetch_struct_schema(name) else {
+            return Ok(vec![]);
+        };
+        let Some(pkey) = schema
+            .fields()
+            .iter()
+            .position(|f| f.pkey)
+            .and_then(|i| row.get(i))
+        else {
+            return Ok(vec![]);
+        };
+
+        let mut referencing = vec![];
+        for table in self.schemas.all() {
+            for (position, field) in table.fields().iter().enumerate() {
+                if field.references != Some(name) || field.on_delete == OnDelete::Keep {
+                    continue;
+                }
+                for (key, values) in self.scan(table.name()).await? {
+                    if values.get(position) != Some(pkey) {
+                        continue;
+                    }
+                    if field.on_delete == OnDelete::Restrict {
+                        return Err(e!(
+                            "{name} row is referenced by {}.{}",
+                            table.name(),
+                            field.name
+                        ));
+                    }
+                    referencing.push((table.name(), key));
+                }
+            }
+        }
+        Ok(referencing)
+    }
+
+    /// Writes or removes the row keeping its previous value for the rollback
+    async fn put(
+        &mut self,
+        name: &'static str,
+        key: sql::Key,
+        row: Option<Vec<sql::Value>>,
+        written: &mut Vec<WrittenRow>,
+    ) -> Result {
+        let old = self.fetch(name, &key).await?;
+        self.write_row(name, key.clone(), row.clone()).await?;
+        written.push(WrittenRow {
+            table: name,
+            key,
+            old,
+            new: row,
+        });
+        OK
+    }
+
+    /// Writes or removes the row as is
+    pub async fn write_row(
+        &mut self,
+        name: &str,
+        key: sql::Key,
+        row: Option<Vec<sql::Value>>,
+    ) -> Result {
+        let storage = &mut self.glue.storage;
+        match row {
+            Some(row) => {
+                storage
+                    .insert_data(name, vec![(key, DataRow::Vec(row))])
+                    .await?
+            }
+            None => storage.delete_data(name, vec![key]).await?,
+        }
+        OK
+    }
+
+    /// Keeps `#[created_at]` values of the row which is being overwritten
+    fn keep_created_at(&self, name: &str, old: &[sql::Value], row: &mut [sql::Value]) {
+        let Some(schema) = self.schemas.fetch_struct_schema(name) else {
+            return;
+        };
+        for (index, field) in schema.fields().iter().enumerate() {
+            if let (true, Some(old), Some(value)) =
+                (field.created_at, old.get(index), row.get_mut(index))
+            {
+                if !matches!(old, sql::Value::Null) {
+                    *value = old.clone();
+                }
+            }
+        }
+    }
+
+    /// Sets `#[updated_at]` values of the row to the current time unless the column itself was updated
+    fn touch_updated_at(&self, name: &str, row: &mut [sql::Value], updated: usize) {
+        let Some(schema) = self.schemas.fetch_struct_schema(name) else {
+            return;
+        };
+        let now = sql::Value::Timestamp(Utc::now().naive_utc());
+        for (index, field) in schema.fields().iter().enumerate() {
+            if field.updated_at && index != updated {
+                if let Some(value) = row.get_mut(index) {
+                    *value = now.clone();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gluesql_shared_memory_storage::SharedMemoryStorage;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        id: u8,
+        text: String,
+    }
+
+    fn save(note: &Note) -> Transaction {
+        Transaction::Save {
+            name: Note::STRUCT_NAME,
+            key: note.get_pkey().into_sql_key().unwrap(),
+            row: note.into_row().unwrap(),
+        }
+    }
+
+    fn note(id: u8, text: &str) -> Note {
+        Note {
+            id,
+            text: text.to_owned(),
+        }
+    }
+
+    async fn notes<S: GStore + GStoreMut>(db: &GlueDb<S>) -> Vec<Note> {
+        let mut notes = db
+            .scan(Note::STRUCT_NAME)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, row)| Note::from_row(row).unwrap())
+            .collect::<Vec<_>>();
+        notes.sort_by_key(|n| n.id);
+        notes
+    }
+
+    fn schemas() -> Schemas {
+        let schemas = Schemas::default();
+        schemas.register(Note::schema());
+        schemas
+    }
+
+    /// DB like the one of the service worker, on a storage which keeps rows only in memory
+    async fn open() -> GlueDb<SharedMemoryStorage> {
+        let mut db = GlueDb::new(SharedMemoryStorage::new(), schemas(), "test", true);
+        db.sync_tables().await.unwrap();
+        db
+    }
+
+    #[tokio::test]
+    async fn writes_all_parts_or_none_of_them() {
+        let mut db = open().await;
+        db.write(save(&note(1, "first"))).await.unwrap();
+
+        let (_, written) = db.write(save(&note(1, "updated"))).await.unwrap();
+        assert_eq!(written.len(), 1);
+        assert!(written[0].old.is_some() && written[0].new.is_some());
+
+        let failed = db
+            .write(Transaction::Batch(vec![
+                save(&note(1, "lost")),
+                save(&note(2, "lost")),
+                Transaction::SqlString("DELETE FROM missing_table".to_owned()),
+            ]))
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(notes(&db).await, [note(1, "updated")]);
+
+        db.undo(written).await;
+        assert_eq!(notes(&db).await, [note(1, "first")]);
+    }
+
+    #[tokio::test]
+    async fn recreates_disposable_tables_with_changed_columns() {
+        let mut db = open().await;
+        db.write(save(&note(1, "cached"))).await.unwrap();
+
+        // columns of the table stored by a previous version of the app
+        let mut stored = db.schemas.fetch_glue_schema(Note::STRUCT_NAME).unwrap();
+        stored.column_defs.as_mut().unwrap().pop();
+        let storage = db.glue.storage.clone();
+        let mut reopened = GlueDb::new(storage, schemas(), "test", true);
+        reopened.glue.storage.insert_schema(&stored).await.unwrap();
+
+        reopened.sync_tables().await.unwrap();
+        assert!(notes(&reopened).await.is_empty());
+        reopened.write(save(&note(2, "fresh"))).await.unwrap();
+        assert_eq!(notes(&reopened).await, [note(2, "fresh")]);
+    }
+}
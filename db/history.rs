@@ -0,0 +1,92 @@
+use crate::*;
+
+/// Kind of the write which produced a version of the row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum HistoryAction {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// Copy of the row recorded by tables with `#[storage(history)]`, also used as tombstones
+/// of the rows removed from `#[storage(soft_delete)]` tables
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub tx_id: u64,
+    pub timestamp: NaiveDateTime,
+    /// Id of the authenticated user who made the write
+    pub user: Option<Uuid>,
+    pub action: HistoryAction,
+    /// Names of the columns at the moment of the write so that entries outlive migrations
+    pub columns: Vec<String>,
+    /// Values after the write, or the last ones for deletions
+    pub row: Vec<sql::Value>,
+}
+
+impl HistoryEntry {
+    /// Values in the order of the current columns, `NULL` for the ones added after the write
+    pub fn current_row(&self, fields: FieldSchemas) -> Vec<sql::Value> {
+        fields
+            .iter()
+            .map(|field| {
+                self.columns
+                    .iter()
+                    .position(|column| column == field.name)
+                    .and_then(|index| self.row.get(index).cloned())
+                    .unwrap_or(sql::Value::Null)
+            })
+            .collect()
+    }
+
+    pub fn into_version<T: Storage>(self) -> Result<Version<T>> {
+        Ok(Version {
+            value: T::from_row(self.current_row(T::FIELD_SCHEMAS))?,
+            tx_id: self.tx_id,
+            timestamp: self.timestamp,
+            user: self.user,
+            action: self.action,
+        })
+    }
+}
+
+/// Version of a [`Storage`] row returned by [`Storage::history`] and [`Storage::deleted`]
+#[derive(Debug, Clone, Serialize)]
+pub struct Version<T> {
+    pub tx_id: u64,
+    pub timestamp: NaiveDateTime,
+    pub user: Option<Uuid>,
+    pub action: HistoryAction,
+    pub value: T,
+}
+
+/// Version of a row with values formatted for the admin panel
+#[doc(hidden)]
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryRow {
+    pub tx_id: u64,
+    pub timestamp: NaiveDateTime,
+    pub user: Option<Uuid>,
+    pub action: HistoryAction,
+    pub row: Vec<String>,
+}
+
+#[cfg(host)]
+tokio::task_local! {
+    static ACTING_USER: Uuid;
+}
+
+/// Runs the future with its writes attributed to the user in the histories of the tables
+///
+/// Applied to the requests of authenticated users when auth is enabled
+#[cfg(host)]
+pub async fn as_user<F: Future>(user: Uuid, f: F) -> F::Output {
+    ACTING_USER.scope(user, f).await
+}
+
+/// User whose request makes the write, sent to the writer along with it
+pub(crate) fn acting_user() -> Option<Uuid> {
+    #[cfg(host)]
+    return ACTING_USER.try_with(|user| *user).ok();
+    #[cfg(not(host))]
+    None
+}
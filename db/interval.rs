@@ -0,0 +1,146 @@
+//! Conversions of [`chrono::Duration`] columns stored as GlueSQL `INTERVAL`s
+//!
+//! `chrono::Duration` doesn't implement serde traits, so `Storage` structs can use this module
+//! to (de)serialize it in the same ISO 8601 form which is shown in the admin panel:
+//!
+//! ```rust,ignore
+//! #[derive(Storage, Serialize, Deserialize)]
+//! struct Session {
+//!     id: Uuid,
+//!     #[serde(with = "prest::interval")]
+//!     lifetime: chrono::Duration,
+//!     #[serde(with = "prest::interval::option")]
+//!     extension: Option<chrono::Duration>,
+//! }
+//! ```
+
+use crate::*;
+use chrono::Duration;
+use serde::{de::Error as _, Deserialize as _, Deserializer, Serializer};
+
+/// Interval value of the duration, stored with microsecond precision
+pub fn into_sql(duration: &Duration) -> sql::Interval {
+    let micros = duration
+        .num_microseconds()
+        .unwrap_or(match duration < &Duration::zero() {
+            true => i64::MIN,
+            false => i64::MAX,
+        });
+    sql::Interval::Microsecond(micros)
+}
+
+/// Duration of the interval, fails for months which don't have a fixed length
+pub fn from_sql(interval: sql::Interval) -> Result<Duration> {
+    match interval {
+        sql::Interval::Microsecond(micros) => Ok(Duration::microseconds(micros)),
+        sql::Interval::Month(months) => {
+            Err(e!("interval of {months} months has no fixed duration"))
+        }
+    }
+}
+
+pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(duration)
+}
+
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    let value = String::deserialize(deserializer)?;
+    parse(&value)
+        .ok_or_else(|| D::Error::custom(format!("invalid duration {value}, expected like PT1.5S")))
+}
+
+/// `(de)serialize_with` functions for `Option<chrono::Duration>`
+pub mod option {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.collect_str(duration),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        let Some(value) = Option::<String>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+        match parse(&value) {
+            Some(duration) => Ok(Some(duration)),
+            None => Err(D::Error::custom(format!(
+                "invalid duration {value}, expected like PT1.5S"
+            ))),
+        }
+    }
+}
+
+/// Parses durations formatted by chrono like `PT1.5S`, `-P1DT30S` or `P0D`
+fn parse(value: &str) -> Option<Duration> {
+    let (negative, value) = match value.strip_prefix('-') {
+        Some(value) => (true, value),
+        None => (false, value),
+    };
+    let value = value.strip_prefix('P')?;
+    let (days, time) = match value.split_once('T') {
+        Some((days, time)) => (days, Some(time)),
+        None => (value, None),
+    };
+
+    let mut duration = Duration::zero();
+    if !days.is_empty() {
+        let days: i64 = days.strip_suffix('D')?.parse().ok()?;
+        duration += Duration::try_days(days)?;
+    }
+    if let Some(time) = time {
+        let seconds = time.strip_suffix('S')?;
+        let (secs, fraction) = seconds.split_once('.').unwrap_or((seconds, ""));
+        if fraction.len() > 9 || !fraction.chars().all(|c| c.is_ascii_digit()) {
+            return None;
+        }
+        let nanos: i64 = format!("{fraction:0<9}").parse().ok()?;
+        duration += Duration::try_seconds(secs.parse().ok()?)? + Duration::nanoseconds(nanos);
+    }
+
+    Some(if negative { -duration } else { duration })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_formatted_durations() {
+        let durations = [
+            Duration::zero(),
+            Duration::milliseconds(1500),
+            Duration::microseconds(-1),
+            Duration::days(3) + Duration::seconds(30),
+            -Duration::days(1) - Duration::nanoseconds(5),
+        ];
+        for duration in durations {
+            assert_eq!(parse(&duration.to_string()), Some(duration), "{duration}");
+        }
+        assert_eq!(
+            parse("-P1DT30S"),
+            Some(-(Duration::days(1) + Duration::seconds(30)))
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_durations() {
+        for value in ["", "1.5S", "PT1.5", "PT1.1234567890S", "PT1.-5S", "PxD"] {
+            assert_eq!(parse(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn converts_microsecond_intervals() {
+        let duration = Duration::microseconds(1_234_567);
+        assert_eq!(from_sql(into_sql(&duration)).unwrap(), duration);
+        assert!(from_sql(sql::Interval::Month(1)).is_err());
+    }
+}
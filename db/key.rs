@@ -2,15 +2,19 @@ use crate::*;
 
 use sql::Key;
 
+/// Conversion of primary keys into GlueSQL keys
+///
+/// Fallible since composite keys of tuples encode their parts, which breaks implementations written
+/// for the previous `fn into_sql_key(self) -> Key` signature
 pub trait IntoSqlKey {
-    fn into_sql_key(self) -> Key;
+    fn into_sql_key(self) -> Result<Key>;
 }
 
 macro_rules! into_key {
     ($type:tt, $variant:tt) => {
         impl IntoSqlKey for $type {
-            fn into_sql_key(self) -> Key {
-                Key::$variant(self)
+            fn into_sql_key(self) -> Result<Key> {
+                Ok(Key::$variant(self))
             }
         }
     };
@@ -31,32 +35,114 @@ into_key!(String, Str);
 into_key!(NaiveDateTime, Timestamp);
 into_key!(NaiveDate, Date);
 into_key!(NaiveTime, Time);
+into_key!(Decimal, Decimal);
+into_key!(IpAddr, Inet);
+
+impl IntoSqlKey for chrono::Duration {
+    fn into_sql_key(self) -> Result<Key> {
+        Ok(Key::Interval(interval::into_sql(&self)))
+    }
+}
 
 impl IntoSqlKey for Uuid {
-    fn into_sql_key(self) -> Key {
-        Key::Uuid(self.as_u128())
+    fn into_sql_key(self) -> Result<Key> {
+        Ok(Key::Uuid(self.as_u128()))
     }
 }
 
 impl IntoSqlKey for Vec<u8> {
-    fn into_sql_key(self) -> Key {
-        Key::Bytea(self)
+    fn into_sql_key(self) -> Result<Key> {
+        Ok(Key::Bytea(self))
     }
 }
 
 impl IntoSqlKey for f32 {
-    fn into_sql_key(self) -> Key {
-        Key::F32(sql::OrderedFloat(self))
+    fn into_sql_key(self) -> Result<Key> {
+        Ok(Key::F32(sql::OrderedFloat(self)))
     }
 }
 
 impl IntoSqlKey for f64 {
-    fn into_sql_key(self) -> Key {
-        Key::F64(sql::OrderedFloat(self))
+    fn into_sql_key(self) -> Result<Key> {
+        Ok(Key::F64(sql::OrderedFloat(self)))
+    }
+}
+
+macro_rules! into_composite_key {
+    ($($type:ident: $index:tt),+) => {
+        impl<$($type: IntoSqlKey),+> IntoSqlKey for ($($type,)+) {
+            fn into_sql_key(self) -> Result<Key> {
+                composite_key([$(self.$index.into_sql_key()?),+])
+            }
+        }
+    };
+}
+
+into_composite_key!(A: 0, B: 1);
+into_composite_key!(A: 0, B: 1, C: 2);
+into_composite_key!(A: 0, B: 1, C: 2, D: 3);
+into_composite_key!(A: 0, B: 1, C: 2, D: 3, E: 4);
+
+/// Combines multiple keys into a single ordered [`Key::Bytea`]
+///
+/// Each part is encoded with `to_cmp_be_bytes`, zero bytes are escaped as `0x00 0xFF` and every part
+/// is terminated with `0x00 0x01` so that byte-wise ordering of the result matches the ordering of tuples
+/// even for variable-length parts like strings.
+pub fn composite_key(parts: impl IntoIterator<Item = Key>) -> Result<Key> {
+    let mut bytes = vec![];
+    for part in parts {
+        let encoded = part
+            .to_cmp_be_bytes()
+            .map_err(|e| e!("can't encode {part:?} as a part of a composite key: {e}"))?;
+        for byte in encoded {
+            bytes.push(byte);
+            if byte == 0 {
+                bytes.push(0xFF);
+            }
+        }
+        bytes.extend([0x00, 0x01]);
     }
+    Ok(Key::Bytea(bytes))
 }
 
-// TODO: remaining possible key types
-// Decimal(v) => Ok(Key::Decimal(v)),
-// Inet(v) => Ok(Key::Inet(v)),
-// Interval(v) => Ok(Key::Interval(v)),
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(key: Key) -> Vec<u8> {
+        let Key::Bytea(bytes) = key else {
+            panic!("composite keys are bytea")
+        };
+        bytes
+    }
+
+    #[test]
+    fn composite_keys_are_ordered_like_tuples() {
+        let ordered = [
+            ("a".to_owned(), 2u32),
+            ("a".to_owned(), 10),
+            ("a\0".to_owned(), 1),
+            ("ab".to_owned(), 0),
+            ("b".to_owned(), 0),
+        ];
+        let keys: Vec<_> = ordered
+            .into_iter()
+            .map(|t| bytes(t.into_sql_key().unwrap()))
+            .collect();
+        for pair in keys.windows(2) {
+            assert!(
+                pair[0] < pair[1],
+                "{:?} should go before {:?}",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+
+    #[test]
+    fn composite_keys_of_different_parts_dont_collide() {
+        let joined = composite_key([Key::Str("ab".to_owned()), Key::Str("c".to_owned())]).unwrap();
+        let split = composite_key([Key::Str("a".to_owned()), Key::Str("bc".to_owned())]).unwrap();
+        assert_ne!(bytes(joined), bytes(split));
+    }
+}
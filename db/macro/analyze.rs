@@ -11,8 +11,51 @@ pub fn from_field(field: Field) -> Column {
         .attrs
         .iter()
         .find(|a| a.path().to_token_stream().to_string() == "unique")
-        .is_some()
-        || pkey;
+        .is_some();
+
+    let index = field
+        .attrs
+        .iter()
+        .find(|a| a.path().to_token_stream().to_string() == "index")
+        .is_some();
+
+    let references = field
+        .attrs
+        .iter()
+        .find(|a| a.path().is_ident("references"))
+        .map(reference);
+
+    let storage = field
+        .attrs
+        .iter()
+        .find(|a| a.path().is_ident("storage"))
+        .map(structured_type);
+
+    let default = field
+        .attrs
+        .iter()
+        .find(|a| a.path().is_ident("default"))
+        .map(|a| match &a.meta {
+            syn::Meta::NameValue(meta) => meta.value.clone(),
+            _ => panic!("default value should be set like #[default = expr]"),
+        });
+
+    let validations = field
+        .attrs
+        .iter()
+        .filter(|a| a.path().is_ident("validate"))
+        .flat_map(validations)
+        .collect::<Vec<_>>();
+
+    let created_at = field.attrs.iter().any(|a| a.path().is_ident("created_at"));
+    let updated_at = field.attrs.iter().any(|a| a.path().is_ident("updated_at"));
+    let expires_at = field.attrs.iter().any(|a| a.path().is_ident("expires_at"));
+
+    let searchable = field
+        .attrs
+        .iter()
+        .find(|a| a.path().is_ident("searchable"))
+        .map(search);
 
     let field_name = field.ident.expect("only named structs");
     let field_name_str = field_name.to_string();
@@ -33,22 +76,80 @@ pub fn from_field(field: Field) -> Column {
             (type_str, false, false)
         };
 
+    // structured values are stored whole, including Vec<...> ones
+    let (inner_type_str, list) = match storage.is_some() && list {
+        true => (type_str, false),
+        false => (inner_type_str, list),
+    };
+
     if pkey && optional || pkey && list {
-        panic!("Primary Key (first attribute by default) cannot be Option<...> or Vec<...>")
+        panic!("Primary Key (first attribute by default) or its parts cannot be Option<...> or Vec<...>")
+    }
+
+    if references.is_some() && list {
+        panic!("Vec<...> columns cannot reference other tables")
     }
 
     let inner_type: syn::Type = syn::parse_str(inner_type_str).unwrap();
 
-    let serialized = match inner_type_str {
-        "Uuid" | "String" | "NaiveDateTime" | "bool" | "u128" | "u64" | "u32" | "u16" | "u8"
-        | "i128" | "i64" | "i32" | "i16" | "i8" | "f64" | "f32" => false,
-        _ => true,
+    // paths like chrono::Duration are recognized by their last segment, except for std::time::Duration
+    let type_name = match inner_type_str.contains('<') || inner_type_str.contains("time ::") {
+        true => inner_type_str,
+        false => inner_type_str.rsplit("::").next().unwrap().trim(),
+    };
+
+    let primitive = matches!(
+        type_name,
+        "Uuid"
+            | "String"
+            | "NaiveDateTime"
+            | "NaiveDate"
+            | "NaiveTime"
+            | "Duration"
+            | "TimeDelta"
+            | "Decimal"
+            | "IpAddr"
+            | "bool"
+            | "u128"
+            | "u64"
+            | "u32"
+            | "u16"
+            | "u8"
+            | "i128"
+            | "i64"
+            | "i32"
+            | "i16"
+            | "i8"
+            | "f64"
+            | "f32"
+    );
+
+    // maps and tuples are stored natively without annotations, other types are encoded unless annotated
+    let storage = match storage {
+        Some(_) if primitive => panic!("{field_name} has a primitive type and is stored as is"),
+        Some(sql_type) => Some(sql_type),
+        None if list || primitive => None,
+        None => detect_structured(&inner_type),
     };
+    let structured = storage.is_some();
+    let serialized = !primitive && !structured;
+
+    if structured && pkey {
+        panic!("Primary Key or its parts cannot be structured values, {field_name} is")
+    }
+    if structured && references.is_some() {
+        panic!("columns referencing other tables should have the type of their primary keys")
+    }
 
     use SqlType::*;
-    let sql_type = match inner_type_str {
+    let sql_type = match type_name {
         "Uuid" => Uuid,
         "NaiveDateTime" => Timestamp,
+        "NaiveDate" => Date,
+        "NaiveTime" => Time,
+        "Duration" | "TimeDelta" => Interval,
+        "Decimal" => Decimal,
+        "IpAddr" => Inet,
         "bool" => Boolean,
         "u128" => Uint128,
         "u64" => Uint64,
@@ -63,11 +164,32 @@ pub fn from_field(field: Field) -> Column {
         "f32" => Float32,
         "f64" => Float,
         "String" => Text,
+        _ if structured => storage.unwrap(),
         _ if serialized => Bytea,
         _ => panic!("Unsupported inner type str = {inner_type_str}"),
         // _ => Text, // fallback?
     };
 
+    if (created_at || updated_at) && (sql_type != Timestamp || list) {
+        panic!(
+            "{field_name} should be NaiveDateTime or Option<NaiveDateTime> to be set automatically"
+        )
+    }
+    if expires_at && (sql_type != Timestamp || list) {
+        panic!("{field_name} should be NaiveDateTime or Option<NaiveDateTime> to be #[expires_at]")
+    }
+    if created_at && updated_at {
+        panic!("{field_name} can't be both #[created_at] and #[updated_at]")
+    }
+
+    if searchable.is_some() && (sql_type != Text || structured || list) {
+        panic!("{field_name} should be String or Option<String> to be #[searchable]")
+    }
+
+    if pkey && sql_type == Interval {
+        panic!("Primary Key (first attribute by default) or its parts cannot be intervals")
+    }
+
     Column {
         full_type_str: type_str.replace(' ', ""),
         sql_type,
@@ -79,6 +201,127 @@ pub fn from_field(field: Field) -> Column {
         optional,
         list,
         unique,
+        index,
         serialized,
+        structured,
+        references,
+        default,
+        validations,
+        created_at,
+        updated_at,
+        expires_at,
+        searchable,
+    }
+}
+
+/// Parses `#[searchable]` with optional `stem`
+fn search(attr: &syn::Attribute) -> Search {
+    let mut stem = false;
+    if let syn::Meta::List(_) = &attr.meta {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("stem") {
+                stem = true;
+                Ok(())
+            } else {
+                Err(meta.error("unsupported searchable attribute, expected stem"))
+            }
+        })
+        .expect("searchable attribute should be valid");
+    }
+    Search { stem }
+}
+
+/// Parses `#[validate(len(min = .., max = ..), range(min = .., max = ..), email)]`
+fn validations(attr: &syn::Attribute) -> Vec<Validation> {
+    let mut validations = vec![];
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("email") {
+            validations.push(Validation::Email);
+            return Ok(());
+        }
+        let bounded = meta.path.is_ident("len") || meta.path.is_ident("range");
+        if !bounded {
+            return Err(meta.error("supported validations are len, range and email"));
+        }
+        let (mut min, mut max) = (None, None);
+        meta.parse_nested_meta(|bound| {
+            if bound.path.is_ident("min") {
+                min = Some(bound.value()?.parse()?);
+            } else if bound.path.is_ident("max") {
+                max = Some(bound.value()?.parse()?);
+            } else {
+                return Err(bound.error("expected min or max"));
+            }
+            Ok(())
+        })?;
+        validations.push(match meta.path.is_ident("len") {
+            true => Validation::Len { min, max },
+            false => Validation::Range { min, max },
+        });
+        Ok(())
+    })
+    .expect("validate attribute should be valid");
+    validations
+}
+
+/// Parses `#[storage(text | map | list)]` of the column
+fn structured_type(attr: &syn::Attribute) -> SqlType {
+    let mut sql_type = None;
+    attr.parse_nested_meta(|meta| {
+        sql_type = Some(
+            match meta.path.get_ident().map(|i| i.to_string()).as_deref() {
+                Some("text") => Text,
+                Some("map") => Map,
+                Some("list") => List,
+                _ => return Err(meta.error("column storage should be text, map or list")),
+            },
+        );
+        Ok(())
+    })
+    .expect("storage attribute should be valid");
+    sql_type.expect("storage attribute should specify the type like #[storage(text)]")
+}
+
+/// Picks native storage for the types which can be recognized by their syntax
+fn detect_structured(ty: &Type) -> Option<SqlType> {
+    match ty {
+        Type::Tuple(tuple) if !tuple.elems.is_empty() => Some(List),
+        Type::Array(_) => Some(List),
+        Type::Path(path) => match path.path.segments.last()?.ident.to_string().as_str() {
+            "HashMap" | "BTreeMap" | "IndexMap" => Some(Map),
+            "HashSet" | "BTreeSet" | "IndexSet" | "VecDeque" => Some(List),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Parses `#[references(Table)]` with optional `on_delete = restrict | cascade`
+fn reference(attr: &syn::Attribute) -> Reference {
+    let mut table = None;
+    let mut on_delete = ident("Keep");
+    attr.parse_nested_meta(|meta| {
+        if meta.path.is_ident("on_delete") {
+            let action: Ident = meta.value()?.parse()?;
+            on_delete = match action.to_string().as_str() {
+                "keep" => ident("Keep"),
+                "restrict" => ident("Restrict"),
+                "cascade" => ident("Cascade"),
+                _ => return Err(meta.error("on_delete should be keep, restrict or cascade")),
+            };
+            Ok(())
+        } else if table.is_none() {
+            table = Some(meta.path);
+            Ok(())
+        } else {
+            Err(meta.error("unsupported references attribute"))
+        }
+    })
+    .expect("references attribute should be valid");
+
+    Reference {
+        table: table
+            .expect("references attribute should specify the table like #[references(User)]"),
+        on_delete,
     }
 }
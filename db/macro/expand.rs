@@ -1,13 +1,19 @@
 use super::{from_glue_value::from_glue_value, into_glue_expr::into_glue_expr, *};
 use proc_macro2::TokenStream;
 
-pub fn impl_table(struct_ident: Ident, table_name: String, columns: Vec<Column>) -> TokenStream {
+pub fn impl_table(
+    struct_ident: Ident,
+    vis: syn::Visibility,
+    table_name: String,
+    columns: Vec<Column>,
+    struct_attrs: StructAttrs,
+) -> TokenStream {
     let fields_idents = columns.iter().map(|col| col.field_name.clone());
     let schema = columns.iter().map(column_schema);
     let from_row_extractions = columns.iter().enumerate().rev().map(from_glue_value);
     let into_row_items = columns.iter().rev().map(|c| {
         let name = c.field_name.clone();
-        into_row_item(c, q!(self.#name))
+        into_row_item(c, timestamped(c, q!(self.#name)))
     });
     let into_expr_list_items = columns.iter().rev().map(into_expr_list_item);
     let find_fns = columns.iter().map(select_by);
@@ -19,31 +25,133 @@ pub fn impl_table(struct_ident: Ident, table_name: String, columns: Vec<Column>)
         .map(update);
     let get_all_as_strings = columns.iter().map(get_as_string);
     let range_fns = columns.iter().filter(|col| col.comparable()).map(in_range);
+    let query_fns = columns
+        .iter()
+        .filter(|col| !col.list && !col.serialized)
+        .map(query_filter);
+    let col_fields = columns.iter().map(|col| {
+        let name = &col.field_name;
+        q!(pub #name: prest::Col<#struct_ident>,)
+    });
+    let col_values = columns.iter().map(col_value);
+    let aggregate_fns = columns
+        .iter()
+        .filter(|col| !col.list && !col.serialized && !col.structured)
+        .map(aggregates);
+    let decode_serialized_fn = decode_serialized(&columns);
+    let default_fns = columns
+        .iter()
+        .filter(|col| col.default.is_some())
+        .map(default_fn);
+    let validate_fn = validate(&columns);
+    let referenced_fns = columns
+        .iter()
+        .filter(|col| col.references.is_some())
+        .map(referenced);
+    let referencing_traits = columns
+        .iter()
+        .filter(|col| col.references.is_some())
+        .map(|col| referencing(&struct_ident, &vis, &columns, col));
 
     let pk_index = columns.iter().position(|c| c.pkey).unwrap();
-    let pkey = columns.get(pk_index).unwrap();
-    let Column {
-        field_name: key_name_ident,
-        field_name_str: key_name_str,
-        full_type: key_type_token,
-        ..
-    } = &pkey;
-    let id_from_str = if pkey.full_type_str != "String" {
-        let key_type = pkey.full_type.clone();
-        Some(q! {
-            use std::str::FromStr;
-            let id = #key_type::from_str(&id)?;
-        })
-    } else {
-        None
+    let pkeys = columns.iter().filter(|c| c.pkey).collect::<Vec<_>>();
+
+    let (key_type, get_pkey, pk_filter_sql_node, pkey_from_id, pkey_to_id) = match pkeys[..] {
+        [pkey] => {
+            let Column {
+                field_name: key_name_ident,
+                field_name_str: key_name_str,
+                full_type: key_type,
+                ..
+            } = pkey;
+            let pkey_from_id = if pkey.full_type_str != "String" {
+                q! {
+                    use std::str::FromStr;
+                    let pkey = #key_type::from_str(&id)?;
+                }
+            } else {
+                q!(let pkey = id.clone();)
+            };
+            let pkey_expr = into_glue_expr(pkey, q!(pkey), true, false);
+            (
+                q!(#key_type),
+                q!(self.#key_name_ident.clone()),
+                q!(sql::col(#key_name_str).eq(#pkey_expr)),
+                pkey_from_id,
+                q!(value.get_pkey().to_string()),
+            )
+        }
+        _ => {
+            let key_types = pkeys.iter().map(|c| &c.full_type);
+            let key_names = pkeys.iter().map(|c| &c.field_name);
+            let mut filters = pkeys.iter().enumerate().map(|(i, col)| {
+                let name = &col.field_name_str;
+                let index = syn::Index::from(i);
+                let expr = into_glue_expr(col, q!(pkey.#index), false, false);
+                q!(sql::col(#name).eq(#expr))
+            });
+            let first_filter = filters.next().unwrap();
+            (
+                q!((#(#key_types,)*)),
+                q!((#(self.#key_names.clone(),)*)),
+                q!(#first_filter #(.and(#filters))*),
+                q!(let pkey = prest::from_json_str(&id)?;),
+                q!(prest::to_json_string(&value.get_pkey())?),
+            )
+        }
     };
 
-    let pkey_expr = into_glue_expr(pkey, q!(pkey), true, false);
-    let pk_filter_sql_node = q!(sql::col(#key_name_str).eq(#pkey_expr));
-
-    let pk_range_fn = pk_range(pkey);
+    let pk_range_fn = pk_range(&pkeys, &key_type);
 
     let schema_name = ident(&format!("{}Schema", struct_ident.to_string()));
+    let query_name = ident(&format!("{}Query", struct_ident.to_string()));
+    let cols_name = ident(&format!("{}Cols", struct_ident.to_string()));
+
+    let migrations_fn = struct_attrs.migrations.map(|path| {
+        q! {
+            fn migrations(&self) -> Vec<prest::MigrationStep> {
+                #path()
+            }
+        }
+    });
+
+    let search_fn = columns.iter().any(|c| c.searchable.is_some()).then(|| {
+        q! {
+            /// Rows with the most relevant matches of the query in the `#[searchable]` columns, ranked by BM25
+            pub async fn search(query: &str, limit: usize) -> prest::Result<Vec<Self>> {
+                Self::from_rows(prest::DB.search(#table_name, query, limit).await?)
+            }
+        }
+    });
+
+    let history_fn = struct_attrs.history.then(|| {
+        q! {
+            fn history(&self) -> bool {
+                true
+            }
+        }
+    });
+    let soft_delete_fn = struct_attrs.soft_delete.then(|| {
+        q! {
+            fn soft_delete(&self) -> bool {
+                true
+            }
+        }
+    });
+    let sync_fn = struct_attrs.sync.then(|| {
+        q! {
+            fn sync(&self) -> bool {
+                true
+            }
+        }
+    });
+    let ttl_fn = struct_attrs.ttl.map(|secs| {
+        q! {
+            fn ttl(&self) -> Option<std::time::Duration> {
+                Some(std::time::Duration::from_secs(#secs))
+            }
+        }
+    });
 
     let relative_path = format!("/table/{table_name}");
     let full_path = format!("/admin/db{relative_path}");
@@ -53,8 +161,12 @@ pub fn impl_table(struct_ident: Ident, table_name: String, columns: Vec<Column>)
     let fields_idents4 = fields_idents.clone();
     let fields_idents5 = fields_idents.clone();
     let fields_idents6 = fields_idents.clone();
+    let fields_idents7 = fields_idents.clone();
+    let fields_idents8 = fields_idents.clone();
     let get_all_as_strings2 = get_all_as_strings.clone();
     let get_all_as_strings3 = get_all_as_strings.clone();
+    let get_all_as_strings4 = get_all_as_strings.clone();
+    let get_all_as_strings5 = get_all_as_strings.clone();
 
     q! {
         struct #schema_name;
@@ -72,6 +184,12 @@ pub fn impl_table(struct_ident: Ident, table_name: String, columns: Vec<Column>)
             fn full_path(&self) -> &'static str {
                 #full_path
             }
+            #migrations_fn
+            #history_fn
+            #soft_delete_fn
+            #ttl_fn
+            #sync_fn
+            #decode_serialized_fn
             async fn get_all_as_strings(&self) -> prest::Result<Vec<Vec<String>>> {
                 let mut rows = vec![];
                 for item in #struct_ident::get_all().await? {
@@ -97,24 +215,54 @@ pub fn impl_table(struct_ident: Ident, table_name: String, columns: Vec<Column>)
                 Ok((rows, has_more))
             }
             async fn get_as_strings_by_id(&self, id: String) -> prest::Result<Vec<String>> {
-                #id_from_str
-                let Some(#struct_ident { #(#fields_idents4 ,)* }) = #struct_ident::get_by_pkey(id.clone()).await? else {
+                #pkey_from_id
+                let Some(#struct_ident { #(#fields_idents4 ,)* }) = #struct_ident::get_by_pkey(pkey).await? else {
                     return Err(prest::e!("expected to find a row by id = {id}"))
                 };
                 let mut row = vec![];
                 #(#get_all_as_strings2)*
                 Ok(row)
             }
+            async fn history_as_strings(&self, limit: usize) -> prest::Result<Vec<prest::HistoryRow>> {
+                let mut rows = vec![];
+                for version in #struct_ident::recent_history(limit).await? {
+                    let #struct_ident { #(#fields_idents7 ,)* } = version.value;
+                    let mut row = vec![];
+                    #(#get_all_as_strings4)*
+                    rows.push(prest::HistoryRow {
+                        tx_id: version.tx_id,
+                        timestamp: version.timestamp,
+                        user: version.user,
+                        action: version.action,
+                        row,
+                    });
+                }
+                Ok(rows)
+            }
+            async fn search_as_strings(&self, query: &str, limit: usize) -> prest::Result<Vec<Vec<String>>> {
+                let mut rows = vec![];
+                for item in prest::DB.search(#table_name, query, limit).await? {
+                    let #struct_ident { #(#fields_idents8 ,)* } = #struct_ident::from_row(item)?;
+                    let mut row = vec![];
+                    #(#get_all_as_strings5)*
+                    rows.push(row);
+                }
+                Ok(rows)
+            }
             async fn save(&self, req: Request) -> prest::Result<String> {
                 let value: #struct_ident = Vals::from_request(req, &()).await?.0;
                 value.save().await?;
-                Ok(value.get_pkey().to_string())
+                Ok(#pkey_to_id)
             }
             async fn remove(&self, req: Request) -> prest::Result {
                 let value: #struct_ident = Vals::from_request(req, &()).await?.0;
                 value.remove().await?;
                 Ok(())
             }
+            fn validate_row(&self, row: Vec<prest::sql::Value>) -> prest::Result {
+                let value = <#struct_ident as prest::Storage>::from_row(row)?;
+                prest::Storage::validate(&value)
+            }
         }
 
         #[prest::async_trait]
@@ -122,16 +270,18 @@ pub fn impl_table(struct_ident: Ident, table_name: String, columns: Vec<Column>)
             const STRUCT_NAME: &'static str = #table_name;
             const FIELD_SCHEMAS: prest::FieldSchemas = &[#(#schema),*];
             const PK_INDEX: usize = #pk_index;
-            type Key = #key_type_token;
+            type Key = #key_type;
 
-            fn get_pkey(&self) -> &Self::Key {
-                &self.#key_name_ident
+            fn get_pkey(&self) -> Self::Key {
+                #get_pkey
             }
 
             fn pk_filter_sql_node<'a, 'b>(pkey: &'a Self::Key) -> prest::sql::ExprNode<'b> { #pk_filter_sql_node }
 
             fn schema() -> &'static dyn StructSchemaTrait { &#schema_name }
 
+            #validate_fn
+
             fn from_row(mut row: Vec<prest::sql::Value>) -> prest::Result<Self> {
                 #(#from_row_extractions)*
                 Ok(Self { #(#fields_idents ,)* })
@@ -148,7 +298,46 @@ pub fn impl_table(struct_ident: Ident, table_name: String, columns: Vec<Column>)
             }
         }
 
+        /// Columns of the table for ordering typed queries like `.order_by(MyStruct::COLS.field)`
+        #vis struct #cols_name {
+            #(#col_fields)*
+        }
+
+        /// Typed query over the rows of the table
+        #vis struct #query_name(prest::TypedQuery<#struct_ident>);
+
+        impl prest::StorageQuery for #query_name {
+            type Row = #struct_ident;
+
+            fn query_mut(&mut self) -> &mut prest::TypedQuery<#struct_ident> {
+                &mut self.0
+            }
+            fn into_query(self) -> prest::TypedQuery<#struct_ident> {
+                self.0
+            }
+        }
+
+        impl #query_name {
+            #(#query_fns)*
+            #(#aggregate_fns)*
+        }
+
+        #(#referencing_traits)*
+
         impl #struct_ident {
+            pub const COLS: #cols_name = #cols_name {
+                #(#col_values)*
+            };
+
+            pub fn query() -> #query_name {
+                #query_name(Default::default())
+            }
+
+            /// Number of the rows matching filters like `Todo::count_where(|q| q.where_done().eq(false))`
+            pub async fn count_where(filter: impl FnOnce(#query_name) -> #query_name) -> prest::Result<u64> {
+                prest::StorageQuery::count(filter(Self::query())).await
+            }
+
             pub async fn get_paginated(offset: usize, limit: usize) -> prest::Result<Vec<Self>> {
                 Self::select()
                     .offset(offset as i64)
@@ -157,11 +346,14 @@ pub fn impl_table(struct_ident: Ident, table_name: String, columns: Vec<Column>)
                     .await
             }
 
+            #(#default_fns)*
             #(#find_fns)*
+            #(#referenced_fns)*
             #(#range_fns)*
             #(#update_fns)*
             #(#check_fns)*
             #pk_range_fn
+            #search_fn
         }
     }
 }
@@ -174,15 +366,25 @@ fn select_by(column: &Column) -> TokenStream {
         inner_type,
         optional,
         unique,
+        index,
         ..
     } = column;
 
-    let select = q!(Self::select().filter(filter).rows().await);
+    // indexed columns are looked up with typed values, others are filtered by gluesql
+    let select = |value: TokenStream| match index {
+        true => {
+            q!(Self::select_in_index_range(#field_name_str, Some(#value.clone()), Some(#value)).await)
+        }
+        false => q!(Self::select().filter(sql::col(#field_name_str).eq(#value)).rows().await),
+    };
 
     let find_null_fn = if *optional {
         let fn_name = select_by_null_(column);
-        let filter = q!(sql::col(#field_name_str).eq(sql::null()));
-        q!( pub async fn #fn_name() -> prest::Result<Vec<Self>> { let filter = #filter; #select } )
+        let select = match index {
+            true => select(q!(prest::sql::Value::Null)),
+            false => select(q!(sql::null())),
+        };
+        q!( pub async fn #fn_name() -> prest::Result<Vec<Self>> { #select } )
     } else {
         q!()
     };
@@ -192,6 +394,12 @@ fn select_by(column: &Column) -> TokenStream {
         true => q!(Result<Option<Self>>),
         false => q!(Result<Vec<Self>>),
     };
+
+    let value = match index {
+        true => into_value(column, q!(#field_name)),
+        false => into_glue_expr(column, q!(#field_name), true, true),
+    };
+    let select = select(q!(value));
     let result = match unique {
         true => q!(Ok(#select?.pop())),
         false => select,
@@ -199,12 +407,9 @@ fn select_by(column: &Column) -> TokenStream {
 
     let fn_arg = if *optional { inner_type } else { full_type };
 
-    let filter_expr = into_glue_expr(column, q!(#field_name), true, true);
-    let filter = q!(sql::col(#field_name_str).eq(#filter_expr));
-
     q! {
         pub async fn #fn_name(#field_name: &#fn_arg) -> #fn_value {
-            let filter = #filter;
+            let value = #value;
             #result
         }
         #find_null_fn
@@ -215,30 +420,353 @@ fn in_range(col: &Column) -> TokenStream {
     let Column {
         field_name_str,
         inner_type,
-        sql_type,
+        index,
         ..
     } = col;
     let fn_name = find_in_range_(col);
-    let filter_str = if sql_type.quoted() { "'{}'" } else { "{}" };
-    let filter = q!(sql::col(#field_name_str)
-        .gte(sql::expr(format!(#filter_str, min)))
-        .and(sql::col(#field_name_str).lte(sql::expr(format!(#filter_str, max)))));
 
-    let values = q!(Self::select().filter(#filter).rows().await);
+    let values = if *index {
+        let min = into_value(col, q!(min));
+        let max = into_value(col, q!(max));
+        q!(Self::select_in_index_range(#field_name_str, Some(#min), Some(#max)).await)
+    } else {
+        let min = into_glue_expr(col, q!(min), true, true);
+        let max = into_glue_expr(col, q!(max), true, true);
+        let filter =
+            q!(sql::col(#field_name_str).gte(#min).and(sql::col(#field_name_str).lte(#max)));
+        q!(Self::select().filter(#filter).rows().await)
+    };
 
     q! { pub async fn #fn_name(min: &#inner_type, max: &#inner_type) -> Result<Vec<Self>> { #values } }
 }
 
-fn pk_range(col: &Column) -> TokenStream {
-    let Column { full_type, .. } = col;
-    let fn_name = get_in_range_(col);
+/// Typed filter of the column, comparisons are available only for comparable types
+fn query_filter(col: &Column) -> TokenStream {
+    let Column {
+        field_name,
+        field_name_str,
+        inner_type,
+        ..
+    } = col;
+    let filter = match col.comparable() {
+        true => q!(prest::CmpFilter),
+        false => q!(prest::Filter),
+    };
+    let into_expr = match col.structured {
+        true => {
+            let sql_type = sql_type_variant(&col.sql_type);
+            q!(prest::into_sql_expr(v, #sql_type).expect("filter value should be serializable"))
+        }
+        false => into_glue_expr(col, q!(v), true, true),
+    };
+    // prefixed to avoid collisions with the methods of StorageQuery like `limit` or `count`
+    let fn_name = ident(&format!("where_{field_name_str}"));
+    q! {
+        pub fn #fn_name(self) -> #filter<Self, #inner_type> {
+            #filter::new(self, #field_name_str, |v: &#inner_type| -> prest::sql::ExprNode<'static> { #into_expr })
+        }
+    }
+}
+
+/// Getter of the row referenced by the column like `todo.owner().await?`
+fn referenced(col: &Column) -> TokenStream {
+    let Column {
+        field_name,
+        optional,
+        references,
+        ..
+    } = col;
+    let table = &references.as_ref().unwrap().table;
+
+    let get = match optional {
+        true => q! {
+            match &self.#field_name {
+                Some(key) => <#table as prest::Storage>::get_by_pkey(key.clone()).await,
+                None => Ok(None),
+            }
+        },
+        false => q!(<#table as prest::Storage>::get_by_pkey(self.#field_name.clone()).await),
+    };
+    q! {
+        pub async fn #field_name(&self) -> prest::Result<Option<#table>> {
+            #get
+        }
+    }
+}
+
+/// Trait with the getter of the referencing rows like `user.todos().await?`, implemented for
+/// the referenced table which might be defined in another crate
+fn referencing(
+    struct_ident: &Ident,
+    vis: &syn::Visibility,
+    columns: &[Column],
+    col: &Column,
+) -> TokenStream {
+    let Column {
+        field_name_str,
+        serialized,
+        references,
+        ..
+    } = col;
+    if *serialized {
+        panic!("columns referencing other tables should have the type of their primary keys");
+    }
+    let table = &references.as_ref().unwrap().table;
+    let table_str = table.to_token_stream().to_string();
+    let where_fn = ident(&format!("where_{field_name_str}"));
+
+    // tables referenced by multiple columns get getters for each of them
+    let same_table_refs = columns
+        .iter()
+        .filter_map(|c| c.references.as_ref())
+        .filter(|r| r.table.to_token_stream().to_string() == table_str)
+        .count();
+    let plural = plural(&snake_case(&struct_ident.to_string()));
+    let fn_name = match same_table_refs {
+        1 => ident(&plural),
+        _ => ident(&format!("{plural}_by_{field_name_str}")),
+    };
+    let trait_name = ident(&format!("{struct_ident}By{}", pascal_case(field_name_str)));
+    let doc = format!("Rows of `{struct_ident}` referencing `{table_str}` by `{field_name_str}`");
+
+    q! {
+        #[doc = #doc]
+        #vis trait #trait_name {
+            fn #fn_name(&self) -> impl std::future::Future<Output = prest::Result<Vec<#struct_ident>>> + Send;
+        }
+
+        impl #trait_name for #table {
+            fn #fn_name(&self) -> impl std::future::Future<Output = prest::Result<Vec<#struct_ident>>> + Send {
+                let query = #struct_ident::query().#where_fn().eq(prest::Storage::get_pkey(self));
+                prest::StorageQuery::all(query)
+            }
+        }
+    }
+}
+
+fn snake_case(name: &str) -> String {
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+fn pascal_case(name: &str) -> String {
+    name.split('_')
+        .map(|part| {
+            let mut chars = part.chars();
+            match chars.next() {
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+fn plural(name: &str) -> String {
+    if name.ends_with('s') || name.ends_with('x') || name.ends_with("sh") || name.ends_with("ch") {
+        format!("{name}es")
+    } else if name.ends_with('y')
+        && !name.ends_with("ay")
+        && !name.ends_with("ey")
+        && !name.ends_with("oy")
+    {
+        format!("{}ies", &name[..name.len() - 1])
+    } else {
+        format!("{name}s")
+    }
+}
+
+/// Grouped counts of the column along with sums, averages and extremes of numeric ones
+fn aggregates(col: &Column) -> TokenStream {
+    let Column {
+        field_name_str,
+        inner_type,
+        sql_type,
+        optional,
+        ..
+    } = col;
+
+    let count_by = if col.numeric() || matches!(sql_type, SqlType::Boolean | SqlType::Text) {
+        let fn_name = ident(&format!("count_by_{field_name_str}"));
+        let projection = format!("{field_name_str}, COUNT(*)");
+        let groups = q!(self.0.count_by::<#inner_type>(#field_name_str, #projection).await);
+        match optional {
+            true => q! {
+                pub async fn #fn_name(self) -> prest::Result<Vec<(Option<#inner_type>, u64)>> {
+                    #groups
+                }
+            },
+            false => q! {
+                pub async fn #fn_name(self) -> prest::Result<Vec<(#inner_type, u64)>> {
+                    #groups?
+                        .into_iter()
+                        .map(|(value, count)| Ok((value.ok_or(prest::e!("unexpected NULL in {}", #field_name_str))?, count)))
+                        .collect()
+                }
+            },
+        }
+    } else {
+        q!()
+    };
+
+    if !col.numeric() {
+        return count_by;
+    }
+
+    let [sum_fn, avg_fn, min_fn, max_fn] =
+        ["sum", "avg", "min", "max"].map(|f| ident(&format!("{f}_{field_name_str}")));
+    let [sum, avg, min, max] =
+        ["SUM", "AVG", "MIN", "MAX"].map(|f| format!("{f}({field_name_str})"));
+    q! {
+        #count_by
+
+        pub async fn #sum_fn(self) -> prest::Result<#inner_type> {
+            Ok(self.0.aggregate(#sum).await?.unwrap_or_default())
+        }
+
+        pub async fn #avg_fn(self) -> prest::Result<Option<f64>> {
+            self.0.aggregate(#avg).await
+        }
+
+        pub async fn #min_fn(self) -> prest::Result<Option<#inner_type>> {
+            self.0.aggregate(#min).await
+        }
+
+        pub async fn #max_fn(self) -> prest::Result<Option<#inner_type>> {
+            self.0.aggregate(#max).await
+        }
+    }
+}
+
+/// Restores bitcode values of the columns which were serialized before they became structured
+fn decode_serialized(columns: &[Column]) -> Option<TokenStream> {
+    let arms = columns.iter().filter(|col| col.structured).map(|col| {
+        let Column {
+            field_name_str,
+            inner_type,
+            ..
+        } = col;
+        let sql_type = sql_type_variant(&col.sql_type);
+        q! {
+            (#field_name_str, prest::sql::Value::Bytea(bytes)) => {
+                prest::into_sql_value(&prest::from_bitcode::<#inner_type>(&bytes)?, #sql_type)
+            }
+        }
+    });
+    let arms = arms.collect::<Vec<_>>();
+    if arms.is_empty() {
+        return None;
+    }
+    Some(q! {
+        fn decode_serialized(&self, column: &str, value: prest::sql::Value) -> prest::Result<prest::sql::Value> {
+            match (column, value) {
+                (_, prest::sql::Value::Null) => Ok(prest::sql::Value::Null),
+                #(#arms)*
+                (column, value) => Err(prest::e!("unexpected serialized value {value:?} of {column}")),
+            }
+        }
+    })
+}
+
+/// Value of the `#[default = expr]` column, also usable with `#[serde(default = "Struct::default_field")]`
+fn default_fn(col: &Column) -> TokenStream {
+    let Column {
+        field_name_str,
+        full_type,
+        default,
+        ..
+    } = col;
+    let fn_name = ident(&format!("default_{field_name_str}"));
     q! {
-        pub async fn #fn_name(min: #full_type, max: #full_type) -> prest::Result<Vec<Self>> {
+        pub fn #fn_name() -> #full_type {
+            #default
+        }
+    }
+}
+
+/// Overrides `Storage::validate` if any of the columns has `#[validate(...)]` attributes
+fn validate(columns: &[Column]) -> Option<TokenStream> {
+    let checks = columns
+        .iter()
+        .filter(|col| !col.validations.is_empty())
+        .map(|col| {
+            let name = &col.field_name;
+            validation_checks(col, q!(&self.#name))
+        })
+        .collect::<Vec<_>>();
+    if checks.is_empty() {
+        return None;
+    }
+    Some(q! {
+        fn validate(&self) -> prest::Result {
+            let mut errors = prest::ValidationErrors::default();
+            #(#checks)*
+            errors.into_result()
+        }
+    })
+}
+
+/// Records failed validations of the referenced value into `errors`, skips `None`s
+fn validation_checks(col: &Column, value: TokenStream) -> TokenStream {
+    let name = &col.field_name_str;
+    let bound = |bound: &Option<syn::Expr>| match bound {
+        Some(bound) => q!(Some(#bound)),
+        None => q!(None),
+    };
+    let checks = col.validations.iter().map(|validation| match validation {
+        Validation::Len { min, max } => {
+            let (min, max) = (bound(min), bound(max));
+            q!(errors.check(#name, prest::validate::len(v, #min, #max));)
+        }
+        Validation::Range { min, max } => {
+            let (min, max) = (bound(min), bound(max));
+            q!(errors.check(#name, prest::validate::range(v, #min, #max));)
+        }
+        Validation::Email => q!(errors.check(#name, prest::validate::email(v));),
+    });
+    match col.optional {
+        true => q!(if let Some(v) = #value { #(#checks)* }),
+        false => q!({ let v = #value; #(#checks)* }),
+    }
+}
+
+/// Current time for `#[updated_at]` and unset `#[created_at]` columns, the value as is for others
+fn timestamped(col: &Column, path: TokenStream) -> TokenStream {
+    let now = q!(prest::Utc::now().naive_utc());
+    match (col.created_at, col.updated_at, col.optional) {
+        (true, _, true) => q!(#path.or_else(|| Some(#now))),
+        (true, _, false) => {
+            q!((if #path == prest::NaiveDateTime::default() { #now } else { #path }))
+        }
+        (_, true, true) => q!(Some(#now)),
+        (_, true, false) => now,
+        _ => path,
+    }
+}
+
+fn col_value(col: &Column) -> TokenStream {
+    let Column {
+        field_name,
+        field_name_str,
+        ..
+    } = col;
+    q!(#field_name: prest::Col::new(#field_name_str),)
+}
+
+fn pk_range(pkeys: &[&Column], key_type: &TokenStream) -> TokenStream {
+    let fn_name = get_in_range_(pkeys);
+    q! {
+        pub async fn #fn_name(min: #key_type, max: #key_type) -> prest::Result<Vec<Self>> {
             let payload = prest::DB
                 .read(prest::Query::PKRange {
                     name: Self::STRUCT_NAME,
-                    pkey_min: min.into_sql_key(),
-                    pkey_max: max.into_sql_key(),
+                    pkey_min: min.into_sql_key()?,
+                    pkey_max: max.into_sql_key()?,
                 })
                 .await?;
 
@@ -264,10 +792,22 @@ fn update((index, col): (usize, &Column)) -> TokenStream {
     let fn_name = update_(col);
     let arg_name = ident(&format!("new_{field_name_str}"));
     let into_row_item = into_row_item(col, q!(#arg_name));
+    let validation = match col.validations.is_empty() {
+        true => q!(),
+        false => {
+            let checks = validation_checks(col, q!(&#arg_name));
+            q! {
+                let mut errors = prest::ValidationErrors::default();
+                #checks
+                errors.into_result()?;
+            }
+        }
+    };
     q! {
         pub async fn #fn_name(&mut self, #arg_name: #full_type) -> prest::Result<&mut Self> {
+            #validation
             #into_row_item
-            let pkey = self.get_pkey().clone().into_sql_key();
+            let pkey = self.get_pkey().into_sql_key()?;
             let payload = prest::DB
                 .write(prest::Transaction::UpdateField {
                     name: Self::STRUCT_NAME,
@@ -295,7 +835,7 @@ fn check(col: &Column) -> TokenStream {
     let fn_name = check_(col);
     q! {
         pub async fn #fn_name(&self, value: #full_type) -> prest::Result<bool> {
-            if let Some(item) = Self::get_by_pkey(self.get_pkey().clone()).await? {
+            if let Some(item) = Self::get_by_pkey(self.get_pkey()).await? {
                 Ok(item.#field_name == value)
             } else {
                 Err(prest::Error::NotFound)
@@ -313,8 +853,17 @@ fn get_as_string(col: &Column) -> TokenStream {
         ..
     } = col;
 
-    let preprocessing = (*serialized || *list || *optional)
-        .then(|| q!(let #field_name = prest::to_json_string(&#field_name)?;));
+    let preprocessing = if col.structured {
+        Some(q!(let #field_name = prest::readable_string(&#field_name)?;))
+    } else if *optional && col.sql_type == SqlType::Interval {
+        // durations are serialized only through prest::interval
+        Some(
+            q!(let #field_name = prest::to_json_string(&#field_name.as_ref().map(|v| v.to_string()))?;),
+        )
+    } else {
+        (*serialized || *list || *optional)
+            .then(|| q!(let #field_name = prest::to_json_string(&#field_name)?;))
+    };
 
     q! {
         #preprocessing
@@ -323,7 +872,7 @@ fn get_as_string(col: &Column) -> TokenStream {
     }
 }
 
-fn into_row_item(column: &Column, path: TokenStream) -> TokenStream {
+pub(crate) fn into_row_item(column: &Column, path: TokenStream) -> TokenStream {
     let Column {
         field_name,
         optional,
@@ -341,18 +890,23 @@ fn into_row_item(column: &Column, path: TokenStream) -> TokenStream {
         ););
     }
 
-    let transform = match column.value_transform() {
-        ValueTransform::UuidU128 => q!(let v = #path.as_u128()),
-        ValueTransform::SerDe => q!(let v = prest::into_bitcode(&#path)?),
-        ValueTransform::None => q!(let v = #path.clone()),
+    if column.structured {
+        let sql_type = sql_type_variant(&column.sql_type);
+        return q!(let #field_name: prest::sql::Value = prest::into_sql_value(&#path, #sql_type)?;);
+    }
+
+    // converts each item of lists and values of options
+    let (transform, item) = match column.value_transform() {
+        ValueTransform::UuidU128 => (q!(let v = #path.clone()), q!(v.as_u128())),
+        ValueTransform::Interval => (q!(let v = #path.clone()), q!(prest::interval::into_sql(&v))),
+        ValueTransform::SerDe => (q!(let v = prest::into_bitcode(&#path)?), q!(v)),
+        ValueTransform::Structured | ValueTransform::None => (q!(let v = #path.clone()), q!(v)),
     };
 
     let value_variant = ident(column.value_variant());
-    let mut value = q!(sql::Value::#value_variant(v));
+    let mut value = q!(sql::Value::#value_variant(#item));
 
-    if *list && *serialized {
-        value = q!( prest::sql::Value::List(v.into_iter().map(|v| #value).collect()) )
-    } else if *list {
+    if *list {
         value = q!( prest::sql::Value::List(v.into_iter().map(|v| #value).collect()) )
     }
 
@@ -366,6 +920,22 @@ fn into_row_item(column: &Column, path: TokenStream) -> TokenStream {
     )
 }
 
+/// Converts a reference to the (inner) value of the column into its `sql::Value`
+fn into_value(column: &Column, path: TokenStream) -> TokenStream {
+    if column.structured {
+        let sql_type = sql_type_variant(&column.sql_type);
+        return q!(prest::into_sql_value(#path, #sql_type)?);
+    }
+    let transform = match column.value_transform() {
+        ValueTransform::UuidU128 => q!(#path.as_u128()),
+        ValueTransform::Interval => q!(prest::interval::into_sql(#path)),
+        ValueTransform::SerDe => q!(prest::into_bitcode(#path)?),
+        ValueTransform::Structured | ValueTransform::None => q!(#path.clone()),
+    };
+    let value_variant = ident(column.value_variant());
+    q!(prest::sql::Value::#value_variant(#transform))
+}
+
 fn into_expr_list_item(column: &Column) -> TokenStream {
     let Column { field_name, .. } = column;
 
@@ -396,8 +966,12 @@ fn find_in_range_(col: &Column) -> Ident {
     ident(&format!("find_in_range_{}", col.field_name_str))
 }
 
-fn get_in_range_(col: &Column) -> Ident {
-    ident(&format!("get_in_{}_range", col.field_name_str))
+fn get_in_range_(pkeys: &[&Column]) -> Ident {
+    let names = pkeys
+        .iter()
+        .map(|col| col.field_name_str.as_str())
+        .collect::<Vec<_>>();
+    ident(&format!("get_in_{}_range", names.join("_")))
 }
 
 fn update_(col: &Column) -> Ident {
@@ -11,14 +11,6 @@ pub fn from_glue_value((index, col): (usize, &Column)) -> TokenStream {
         ..
     } = col;
 
-    let value_variant = ident(col.value_variant());
-
-    let transform = match col.value_transform() {
-        ValueTransform::UuidU128 => q!(let v = prest::Uuid::from_u128(v)),
-        ValueTransform::SerDe => q!(let v = prest::from_bitcode(&v)?),
-        ValueTransform::None => q!(),
-    };
-
     let error_arms = q!(
         Some(other) => {
             let column = &Self::FIELD_SCHEMAS[#index];
@@ -30,6 +22,36 @@ pub fn from_glue_value((index, col): (usize, &Column)) -> TokenStream {
         }
     );
 
+    if col.structured {
+        let value = match optional {
+            true => q!(Some(prest::from_sql_value(v)?)),
+            false => q!(prest::from_sql_value(v)?),
+        };
+        let null_arm = match optional {
+            true => q!( Some(prest::sql::Value::Null) => None, ),
+            false => q!(),
+        };
+        return q! {
+            let #field_name = match row.pop() {
+                #null_arm
+                Some(v) => #value,
+                None => {
+                    let column = &Self::FIELD_SCHEMAS[#index];
+                    return Err(prest::e!("row too short, missing {column:?}"))
+                }
+            };
+        };
+    }
+
+    let value_variant = ident(col.value_variant());
+
+    let transform = match col.value_transform() {
+        ValueTransform::UuidU128 => q!(let v = prest::Uuid::from_u128(v)),
+        ValueTransform::Interval => q!(let v = prest::interval::from_sql(v)?),
+        ValueTransform::SerDe => q!(let v = prest::from_bitcode(&v)?),
+        ValueTransform::Structured | ValueTransform::None => q!(),
+    };
+
     if *list {
         let err = q!(
             let column = &Self::FIELD_SCHEMAS[#index];
@@ -50,6 +72,21 @@ pub fn from_glue_value((index, col): (usize, &Column)) -> TokenStream {
                 };
                 list.push(v);
             )
+        } else if matches!(sql_type, SqlType::Float | SqlType::Float32) {
+            // list literals are evaluated into F64 or I64 values
+            let float = match sql_type {
+                SqlType::Float32 => q!(F32(v) => v, F64(v) => v as f32,),
+                _ => q!(F64(v) => v, F32(v) => v as f64,),
+            };
+            q!(
+                use prest::sql::Value::*;
+                let v = match item {
+                    #float
+                    I64(v) => v as #inner_type,
+                    item => { #err }
+                };
+                list.push(v);
+            )
         } else {
             q!(
                 if let prest::sql::Value::#value_variant(v) = item {
@@ -10,15 +10,32 @@ pub fn into_glue_expr(column: &Column, path: TokenStream, deref: bool, inner: bo
         ..
     } = column;
     let optional = *optional && !inner;
+
+    // nulls of optional values are handled by serde as well
+    if column.structured {
+        let sql_type = sql_type_variant(sql_type);
+        return q!(prest::into_sql_expr(&#path, #sql_type)?);
+    }
+
     match (list, optional, serialized) {
         (true, false, _) => {
             let literal = |ts: TokenStream| q!(sql::Expr::Literal(sql::AstLiteral::#ts));
+            let typed = |data_type: TokenStream| q!(sql::Expr::TypedString { data_type: sql::DataType::#data_type, value: item.to_string() });
 
             let item_into_expr = match sql_type {
                 _ if *serialized => literal(q!(HexString(prest::hex::encode(prest::into_bitcode(item)?)))),
                 _ if sql_type.integer() => literal(q!(Number(item.into()))),
                 SqlType::Boolean => literal(q!(Boolean(*item))),
                 SqlType::Text => literal(q!(QuotedString(item.to_string()))),
+                SqlType::Float | SqlType::Float32 => {
+                    literal(q!(Number(prest::_Somehow::somehow(item.to_string().parse())?)))
+                }
+                SqlType::Uuid => typed(q!(Uuid)),
+                SqlType::Timestamp => typed(q!(Timestamp)),
+                SqlType::Date => typed(q!(Date)),
+                SqlType::Time => typed(q!(Time)),
+                SqlType::Decimal => typed(q!(Decimal)),
+                SqlType::Inet => typed(q!(Inet)),
                 _ => unimplemented!("Vec of this type is not currently supported due to complexities related to the untyped nature of gluesql lists"),
             };
 
@@ -37,18 +54,20 @@ pub fn into_glue_expr(column: &Column, path: TokenStream, deref: bool, inner: bo
         ),
         (false, true, false) => {
             let inner = match sql_type {
+                _ if has_value_node(sql_type) => value_node(sql_type, q!(v)),
                 SqlType::Text => q!(sql::text(v.clone())),
                 SqlType::Uuid => q!(sql::uuid(v.to_string())),
                 SqlType::Boolean => node_literal(q!(Boolean(*v))),
                 // these do not implement Into<NumNode>
                 SqlType::Int128 | SqlType::Uint128 => q!(sql::num(v.to_string())),
                 _ if sql_type.numeric() => q!(sql::num(*v)),
-                _ => q!(sql::expr(v.to_string())),
+                _ => node_literal(q!(QuotedString(v.to_string()))),
             };
             q!( if let Some(v) = &#path { #inner } else { sql::null() } )
         }
         (false, false, false) => {
             match sql_type {
+                _ if has_value_node(sql_type) => value_node(sql_type, path),
                 SqlType::Boolean => node_literal(q!(Boolean(#path.clone()))),
                 SqlType::Text => q!(sql::text(#path.clone())),
                 SqlType::Uuid => q!(sql::uuid(#path.to_string())),
@@ -56,7 +75,7 @@ pub fn into_glue_expr(column: &Column, path: TokenStream, deref: bool, inner: bo
                 SqlType::Int128 | SqlType::Uint128 => q!(sql::num(#path.to_string())),
                 _ if sql_type.numeric() && deref => q!(sql::num(*#path)),
                 _ if sql_type.numeric() => q!(sql::num(#path)),
-                _ => q!(sql::expr(format!("'{}'", &#path))),
+                _ => node_literal(q!(QuotedString(#path.to_string()))),
             }
         }
         (true, true, _) => {
@@ -73,3 +92,23 @@ fn node_literal(variant: TokenStream) -> TokenStream {
 fn expr_literal(variant: TokenStream) -> TokenStream {
     q!(prest::sql::Expr::Literal(prest::sql::AstLiteral::#variant))
 }
+
+fn has_value_node(sql_type: &SqlType) -> bool {
+    matches!(
+        sql_type,
+        SqlType::Date | SqlType::Time | SqlType::Decimal | SqlType::Inet | SqlType::Interval
+    )
+}
+
+/// Literal of the types which don't have ast builder helpers
+fn value_node(sql_type: &SqlType, path: TokenStream) -> TokenStream {
+    let value = match sql_type {
+        SqlType::Date => q!(Date(#path.clone())),
+        SqlType::Time => q!(Time(#path.clone())),
+        SqlType::Decimal => q!(Decimal(#path.clone())),
+        SqlType::Inet => q!(Inet(#path.clone())),
+        SqlType::Interval => q!(Interval(prest::interval::into_sql(&#path))),
+        _ => unreachable!("{sql_type} has ast builder helpers"),
+    };
+    q!(prest::value_node(prest::sql::Value::#value))
+}
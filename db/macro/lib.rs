@@ -12,11 +12,19 @@ pub(crate) use gluesql_core::ast::DataType as SqlType;
 use SqlType::*;
 
 /// Generates schema and helper functions to use struct as a table in the embedded database
-#[proc_macro_derive(Storage, attributes(pkey, unique))]
+#[proc_macro_derive(
+    Storage,
+    attributes(
+        pkey, unique, index, references, storage, default, validate, created_at, updated_at,
+        expires_at, searchable
+    )
+)]
 pub fn table_derive(input: TokenStream) -> TokenStream {
     let ast = parse_macro_input!(input as DeriveInput);
     let struct_ident = ast.ident;
+    let vis = ast.vis;
     let table_name = struct_ident.to_string();
+    let struct_attrs = StructAttrs::from_attrs(&ast.attrs);
 
     // supports only struct with named fields
     let fields = match ast.data {
@@ -30,18 +38,110 @@ pub fn table_derive(input: TokenStream) -> TokenStream {
     // decompose
     let mut columns: Vec<Column> = fields.named.into_iter().map(analyze::from_field).collect();
 
-    // can't have multiple primary keys
+    // single primary key is unique by itself, while columns of a composite key are unique only together
     match columns.iter().filter(|c| c.pkey).count() {
         0 => {
             columns[0].pkey = true;
             columns[0].unique = true;
         }
-        1 => {}
-        _ => panic!("Storage macro doesn't support more than one pkey at the moment"),
+        1 => columns
+            .iter_mut()
+            .filter(|c| c.pkey)
+            .for_each(|c| c.unique = true),
+        _ => {}
     };
 
+    // unique columns are indexed to lookup values efficiently, pkeys are ordered by themselves,
+    // referencing columns are indexed to find the rows affected by removals of the referenced ones
+    for col in columns.iter_mut() {
+        if col.index && col.list {
+            panic!("Vec<...> columns cannot be indexed");
+        }
+        if (col.index || col.unique) && col.structured && col.sql_type != Text {
+            panic!("only #[storage(text)] structured columns can be indexed");
+        }
+        let referencing = col.references.is_some() && !(col.structured && col.sql_type != Text);
+        col.index = (col.index || col.unique || referencing) && !col.pkey && !col.list;
+    }
+
+    if struct_attrs.ttl.is_some() && !columns.iter().any(|c| c.created_at || c.updated_at) {
+        panic!(
+            "#[storage(ttl = ...)] requires a #[created_at] or #[updated_at] column to count from"
+        );
+    }
+
     // expand
-    TokenStream::from(expand::impl_table(struct_ident, table_name, columns))
+    TokenStream::from(expand::impl_table(
+        struct_ident,
+        vis,
+        table_name,
+        columns,
+        struct_attrs,
+    ))
+}
+
+/// Table-level options declared with `#[storage(...)]`
+#[derive(Default)]
+struct StructAttrs {
+    // fn() -> Vec<MigrationStep>
+    migrations: Option<syn::Path>,
+    // records versions of rows on every write
+    history: bool,
+    // keeps removed rows as tombstones
+    soft_delete: bool,
+    // seconds after the last update or creation when rows expire
+    ttl: Option<u64>,
+    // synchronized between the service worker and the host
+    sync: bool,
+}
+
+impl StructAttrs {
+    fn from_attrs(attrs: &[syn::Attribute]) -> Self {
+        let mut struct_attrs = Self::default();
+        for attr in attrs.iter().filter(|a| a.path().is_ident("storage")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("migrations") {
+                    struct_attrs.migrations = Some(meta.value()?.parse()?);
+                    Ok(())
+                } else if meta.path.is_ident("history") {
+                    struct_attrs.history = true;
+                    Ok(())
+                } else if meta.path.is_ident("soft_delete") {
+                    struct_attrs.soft_delete = true;
+                    Ok(())
+                } else if meta.path.is_ident("sync") {
+                    struct_attrs.sync = true;
+                    Ok(())
+                } else if meta.path.is_ident("ttl") {
+                    let ttl: syn::LitStr = meta.value()?.parse()?;
+                    struct_attrs.ttl = Some(parse_ttl(&ttl.value()).ok_or_else(|| {
+                        meta.error("ttl should be a number with s, m, h, d or w unit like \"30d\"")
+                    })?);
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported storage attribute"))
+                }
+            })
+            .expect("storage attribute should be valid");
+        }
+        struct_attrs
+    }
+}
+
+/// Parses durations like `90s`, `15m`, `12h`, `30d` or `2w` into seconds
+fn parse_ttl(ttl: &str) -> Option<u64> {
+    let ttl = ttl.trim();
+    let unit = ttl.chars().last()?;
+    let amount: u64 = ttl[..ttl.len() - unit.len_utf8()].trim().parse().ok()?;
+    let secs = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    amount.checked_mul(secs).filter(|secs| *secs > 0)
 }
 
 struct Column {
@@ -53,7 +153,7 @@ struct Column {
     inner_type: Type,
     // type in sql syntax (gluesql_core::ast::DataType)
     sql_type: SqlType,
-    // is primary pkey
+    // is primary key or a part of the composite one
     pkey: bool,
     // is Option<...>
     optional: bool,
@@ -61,14 +161,57 @@ struct Column {
     list: bool,
     // should be UNIQUE
     unique: bool,
-    // requires serialization/deserialization
+    // has a secondary index
+    index: bool,
+    // requires serialization/deserialization into bitcode
     serialized: bool,
+    // stored as TEXT, MAP or LIST value through serde, #[storage(text | map | list)]
+    structured: bool,
+    // stores the primary key of another table, #[references(Table, on_delete = ...)]
+    references: Option<Reference>,
+    // #[default = expr]
+    default: Option<syn::Expr>,
+    // #[validate(...)]
+    validations: Vec<Validation>,
+    // #[created_at]
+    created_at: bool,
+    // #[updated_at]
+    updated_at: bool,
+    // #[expires_at]
+    expires_at: bool,
+    // #[searchable] or #[searchable(stem)]
+    searchable: Option<Search>,
+}
+
+struct Search {
+    // reduce words to their stems
+    stem: bool,
+}
+
+enum Validation {
+    Len {
+        min: Option<syn::Expr>,
+        max: Option<syn::Expr>,
+    },
+    Range {
+        min: Option<syn::Expr>,
+        max: Option<syn::Expr>,
+    },
+    Email,
+}
+
+struct Reference {
+    table: syn::Path,
+    // variant of prest::OnDelete
+    on_delete: Ident,
 }
 
 impl Column {
     fn value_transform(&self) -> ValueTransform {
         match self.sql_type {
+            _ if self.structured => ValueTransform::Structured,
             Uuid => ValueTransform::UuidU128,
+            Interval => ValueTransform::Interval,
             _ if self.serialized => ValueTransform::SerDe,
             _ => ValueTransform::None,
         }
@@ -79,6 +222,11 @@ impl Column {
             Uuid => "Uuid",
             Text => "Str",
             Timestamp => "Timestamp",
+            Date => "Date",
+            Time => "Time",
+            Interval => "Interval",
+            Decimal => "Decimal",
+            Inet => "Inet",
             Boolean => "Bool",
             Uint128 => "U128",
             Uint64 => "U64",
@@ -100,7 +248,9 @@ impl Column {
 
 enum ValueTransform {
     UuidU128,
+    Interval,
     SerDe,
+    Structured,
     None,
 }
 
@@ -109,18 +259,13 @@ fn ident(name: &str) -> Ident {
 }
 
 trait TypeProps {
-    fn impl_into_exprnode(&self) -> bool;
     fn int_or_smaller(&self) -> bool;
     fn integer(&self) -> bool;
     fn numeric(&self) -> bool;
     fn comparable(&self) -> bool;
-    fn quoted(&self) -> bool;
 }
 
 impl TypeProps for SqlType {
-    fn impl_into_exprnode(&self) -> bool {
-        matches!(self, Boolean | Int)
-    }
     fn int_or_smaller(&self) -> bool {
         matches!(self, Uint32 | Uint16 | Uint8 | Int | Int32 | Int16 | Int8)
     }
@@ -128,20 +273,14 @@ impl TypeProps for SqlType {
         self.int_or_smaller() || matches!(self, Uint128 | Uint64 | Int128)
     }
     fn numeric(&self) -> bool {
-        self.integer() || matches!(self, Float | Float32)
+        self.integer() || matches!(self, Float | Float32 | Decimal)
     }
     fn comparable(&self) -> bool {
         self.numeric() || matches!(self, Timestamp | Date | Time)
     }
-    fn quoted(&self) -> bool {
-        !self.impl_into_exprnode() && !self.numeric()
-    }
 }
 
 impl TypeProps for Column {
-    fn impl_into_exprnode(&self) -> bool {
-        self.sql_type.impl_into_exprnode()
-    }
     fn int_or_smaller(&self) -> bool {
         self.sql_type.int_or_smaller()
     }
@@ -154,9 +293,6 @@ impl TypeProps for Column {
     fn comparable(&self) -> bool {
         self.sql_type.comparable()
     }
-    fn quoted(&self) -> bool {
-        self.sql_type.quoted()
-    }
 }
 
 fn column_schema(col: &Column) -> proc_macro2::TokenStream {
@@ -166,28 +302,84 @@ fn column_schema(col: &Column) -> proc_macro2::TokenStream {
         sql_type,
         pkey,
         unique,
+        index,
         list,
         optional,
         serialized,
+        structured,
+        created_at,
+        updated_at,
+        expires_at,
         ..
     } = col;
+    let searchable = col.searchable.is_some();
+    let stemmed = col.searchable.as_ref().map_or(false, |s| s.stem);
+    let default = match col.default.is_some() {
+        true => {
+            let fn_name = ident(&format!("default_{field_name_str}"));
+            let value = expand::into_row_item(col, q!(value));
+            let field_name = &col.field_name;
+            q!(Some(|| {
+                let value = Self::#fn_name();
+                #value
+                Ok(#field_name)
+            }))
+        }
+        false => q!(None),
+    };
+    let constant_default = col.default.as_ref().map_or(false, is_literal);
+    let references = match &col.references {
+        Some(Reference { table, .. }) => q!(Some(<#table as prest::Storage>::STRUCT_NAME)),
+        None => q!(None),
+    };
+    let on_delete = match &col.references {
+        Some(Reference { on_delete, .. }) => on_delete.clone(),
+        None => ident("Keep"),
+    };
     let numeric = sql_type.numeric();
     let comparable = sql_type.comparable();
-    let sql_type_str = sql_type.to_string();
-    let (first_char, rest) = sql_type_str.split_at(1);
-    let sql_type = ident(&(first_char.to_owned() + &rest.to_ascii_lowercase()));
+    let sql_type = sql_type_variant(sql_type);
     q! {
         FieldSchema {
             name: #field_name_str,
             rust_type: #full_type_str,
-            sql_type: prest::sql::DataType::#sql_type,
+            sql_type: #sql_type,
             unique: #unique,
+            index: #index,
             pkey: #pkey,
             list: #list,
             optional: #optional,
             serialized: #serialized,
+            structured: #structured,
+            default: #default,
+            constant_default: #constant_default,
+            created_at: #created_at,
+            updated_at: #updated_at,
+            expires_at: #expires_at,
+            searchable: #searchable,
+            stemmed: #stemmed,
             numeric: #numeric,
             comparable: #comparable,
+            references: #references,
+            on_delete: prest::OnDelete::#on_delete,
         }
     }
 }
+
+/// Path to the `prest::sql::DataType` variant like `prest::sql::DataType::Text`
+fn sql_type_variant(sql_type: &SqlType) -> proc_macro2::TokenStream {
+    let sql_type_str = sql_type.to_string();
+    let (first_char, rest) = sql_type_str.split_at(1);
+    let variant = ident(&(first_char.to_owned() + &rest.to_ascii_lowercase()));
+    q!(prest::sql::DataType::#variant)
+}
+
+/// Whether the default value is a literal which evaluates the same way every time
+fn is_literal(expr: &syn::Expr) -> bool {
+    match expr {
+        syn::Expr::Lit(_) => true,
+        syn::Expr::Unary(unary) => matches!(unary.op, syn::UnOp::Neg(_)) && is_literal(&unary.expr),
+        syn::Expr::Paren(paren) => is_literal(&paren.expr),
+        _ => false,
+    }
+}
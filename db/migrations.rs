@@ -0,0 +1,365 @@
+use crate::*;
+
+/// User-declared step to migrate rows persisted with a previous version of a [`Storage`] struct
+///
+/// Declared with `#[storage(migrations = fn_name)]` where `fn fn_name() -> Vec<MigrationStep>`.
+/// Steps are used only when the difference between persisted and current schemas requires them,
+/// so they can be kept around after the migration was applied.
+#[derive(Clone)]
+pub enum MigrationStep {
+    /// Moves values of the `from` column into the `to` column
+    RenameColumn {
+        from: &'static str,
+        to: &'static str,
+    },
+    /// Allows dropping values of the removed column
+    DropColumn(&'static str),
+    /// Computes values of the column from the row persisted with the previous schema
+    Transform {
+        column: &'static str,
+        transform: fn(&MigrationRow) -> Result<sql::Value>,
+    },
+}
+
+/// Row persisted with the previous version of the schema
+pub struct MigrationRow {
+    pub(crate) columns: Vec<String>,
+    pub(crate) values: Vec<sql::Value>,
+}
+
+impl MigrationRow {
+    pub fn get(&self, column: &str) -> Option<&sql::Value> {
+        self.columns
+            .iter()
+            .position(|c| c == column)
+            .and_then(|i| self.values.get(i))
+    }
+}
+
+/// Persisted description of the table columns used to detect schema changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredSchema {
+    pub version: u32,
+    pub columns: Vec<StoredColumn>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StoredColumn {
+    pub name: String,
+    pub rust_type: String,
+    pub sql_type: sql::DataType,
+    pub optional: bool,
+    pub list: bool,
+    pub serialized: bool,
+}
+
+impl StoredColumn {
+    pub(crate) fn from_schema(schema: StructSchema) -> Vec<Self> {
+        schema
+            .fields()
+            .iter()
+            .map(|f| StoredColumn {
+                name: f.name.to_owned(),
+                rust_type: f.rust_type.to_owned(),
+                sql_type: f.sql_type.clone(),
+                optional: f.optional,
+                list: f.list,
+                serialized: f.serialized,
+            })
+            .collect()
+    }
+}
+
+/// Dry-run description of changes required to migrate the table
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MigrationReport {
+    pub table: String,
+    pub stored_version: Option<u32>,
+    pub changes: Vec<String>,
+    pub blocking: Vec<String>,
+}
+
+impl MigrationReport {
+    pub fn pending(&self) -> bool {
+        !self.changes.is_empty() || !self.blocking.is_empty()
+    }
+}
+
+pub(crate) enum ColumnSource {
+    Existing(usize),
+    Transform(fn(&MigrationRow) -> Result<sql::Value>),
+    Decode {
+        index: usize,
+        column: &'static str,
+        schema: StructSchema,
+    },
+    Default(fn() -> Result<sql::Value>),
+    Now,
+    Null,
+    EmptyList,
+}
+
+pub(crate) struct MigrationPlan {
+    pub sources: Vec<ColumnSource>,
+    pub report: MigrationReport,
+}
+
+impl MigrationPlan {
+    /// Compares persisted columns with the current schema, `None` if they match
+    pub fn new(stored: &StoredSchema, schema: StructSchema) -> Option<Self> {
+        let current = StoredColumn::from_schema(schema);
+        if stored.columns == current {
+            return None;
+        }
+
+        let steps = schema.migrations();
+        let position = |name: &str| stored.columns.iter().position(|c| c.name == name);
+
+        let mut used = vec![false; stored.columns.len()];
+        let mut sources = vec![];
+        let mut changes = vec![];
+        let mut blocking = vec![];
+
+        for (col, field) in current.iter().zip(schema.fields()) {
+            let name = col.name.as_str();
+
+            let transform = steps.iter().find_map(|step| match step {
+                MigrationStep::Transform { column, transform } if *column == name => {
+                    Some(*transform)
+                }
+                _ => None,
+            });
+            let renamed_from = steps.iter().find_map(|step| match step {
+                MigrationStep::RenameColumn { from, to } if *to == name => position(from),
+                _ => None,
+            });
+
+            if let Some(transform) = transform {
+                if let Some(i) = position(name) {
+                    used[i] = true;
+                }
+                changes.push(format!("compute values of {name}"));
+                sources.push(ColumnSource::Transform(transform));
+            } else if let Some(i) = position(name) {
+                used[i] = true;
+                let old = &stored.columns[i];
+                if old.serialized && !old.list && field.structured && old.rust_type == col.rust_type
+                {
+                    changes.push(format!("convert serialized {name} into {}", col.sql_type));
+                    sources.push(ColumnSource::Decode {
+                        index: i,
+                        column: field.name,
+                        schema,
+                    });
+                    continue;
+                } else if old.sql_type != col.sql_type || old.list != col.list {
+                    blocking.push(format!(
+                        "type of {name} changed from {} to {}, declare MigrationStep::Transform",
+                        old.rust_type, col.rust_type
+                    ));
+                } else if old.optional && !col.optional {
+                    blocking.push(format!(
+                        "{name} is not optional anymore, declare MigrationStep::Transform"
+                    ));
+                } else if old.rust_type != col.rust_type {
+                    changes.push(format!(
+                        "type of {name} changed from {} to {}",
+                        old.rust_type, col.rust_type
+                    ));
+                }
+                sources.push(ColumnSource::Existing(i));
+            } else if let Some(i) = renamed_from {
+                used[i] = true;
+                changes.push(format!("rename {} into {name}", stored.columns[i].name));
+                sources.push(ColumnSource::Existing(i));
+            } else if let Some(default) = field.default {
+                changes.push(format!("add column {name} with the default value"));
+                sources.push(ColumnSource::Default(default));
+            } else if field.created_at || field.updated_at {
+                changes.push(format!("add timestamp column {name}"));
+                sources.push(ColumnSource::Now);
+            } else if col.optional {
+                changes.push(format!("add nullable column {name}"));
+                sources.push(ColumnSource::Null);
+            } else if col.list {
+                changes.push(format!("add list column {name}"));
+                sources.push(ColumnSource::EmptyList);
+            } else {
+                blocking.push(format!(
+                    "column {name} was added, make it Option<...> or declare MigrationStep::Transform"
+                ));
+                sources.push(ColumnSource::Null);
+            }
+        }
+
+        for (old, used) in stored.columns.iter().zip(used) {
+            if used {
+                continue;
+            }
+            let name = old.name.as_str();
+            let droppable = steps
+                .iter()
+                .any(|step| matches!(step, MigrationStep::DropColumn(column) if *column == name));
+            match droppable {
+                true => changes.push(format!("drop column {name}")),
+                false => blocking.push(format!(
+                    "column {name} was removed, declare MigrationStep::DropColumn or RenameColumn"
+                )),
+            }
+        }
+
+        if changes.is_empty() && blocking.is_empty() {
+            changes.push("reorder columns".to_owned());
+        }
+
+        Some(Self {
+            sources,
+            report: MigrationReport {
+                table: schema.name().to_owned(),
+                stored_version: Some(stored.version),
+                changes,
+                blocking,
+            },
+        })
+    }
+
+    pub fn apply(&self, row: &MigrationRow) -> Result<Vec<sql::Value>> {
+        self.sources
+            .iter()
+            .map(|source| match source {
+                ColumnSource::Existing(i) => row
+                    .values
+                    .get(*i)
+                    .cloned()
+                    .ok_or_else(|| e!("row is too short for column {i}")),
+                ColumnSource::Transform(transform) => transform(row),
+                ColumnSource::Decode {
+                    index,
+                    column,
+                    schema,
+                } => {
+                    let value = row
+                        .values
+                        .get(*index)
+                        .cloned()
+                        .ok_or_else(|| e!("row is too short for column {index}"))?;
+                    schema.decode_serialized(column, value)
+                }
+                ColumnSource::Default(default) => default(),
+                ColumnSource::Now => Ok(sql::Value::Timestamp(Utc::now().naive_utc())),
+                ColumnSource::Null => Ok(sql::Value::Null),
+                ColumnSource::EmptyList => Ok(sql::Value::List(vec![])),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Storage, Debug, Clone, Serialize, Deserialize)]
+    struct Before {
+        id: u32,
+        title: String,
+        count: u32,
+        legacy: bool,
+    }
+
+    #[derive(Storage, Debug, Clone, Serialize, Deserialize)]
+    #[storage(migrations = renamed_steps)]
+    struct Renamed {
+        id: u32,
+        name: String,
+        count: String,
+    }
+
+    fn renamed_steps() -> Vec<MigrationStep> {
+        vec![MigrationStep::RenameColumn {
+            from: "title",
+            to: "name",
+        }]
+    }
+
+    #[derive(Storage, Debug, Clone, Serialize, Deserialize)]
+    #[storage(migrations = converted_steps)]
+    struct Converted {
+        id: u32,
+        name: String,
+        count: String,
+    }
+
+    fn converted_steps() -> Vec<MigrationStep> {
+        vec![
+            MigrationStep::RenameColumn {
+                from: "title",
+                to: "name",
+            },
+            MigrationStep::Transform {
+                column: "count",
+                transform: |row| match row.get("count") {
+                    Some(sql::Value::U32(count)) => Ok(sql::Value::Str(count.to_string())),
+                    other => Err(e!("unexpected count {other:?}")),
+                },
+            },
+            MigrationStep::DropColumn("legacy"),
+        ]
+    }
+
+    fn stored() -> StoredSchema {
+        StoredSchema {
+            version: 1,
+            columns: StoredColumn::from_schema(Before::schema()),
+        }
+    }
+
+    #[test]
+    fn unchanged_schemas_dont_need_migrations() {
+        assert!(MigrationPlan::new(&stored(), Before::schema()).is_none());
+    }
+
+    #[test]
+    fn type_changes_and_removed_columns_block_migrations() {
+        let plan = MigrationPlan::new(&stored(), Renamed::schema()).unwrap();
+        assert_eq!(plan.report.changes, ["rename title into name"]);
+        assert_eq!(plan.report.blocking.len(), 2, "{:?}", plan.report.blocking);
+        assert!(plan.report.blocking[0].starts_with("type of count changed"));
+        assert!(plan.report.blocking[1].starts_with("column legacy was removed"));
+    }
+
+    #[test]
+    fn declared_steps_rename_convert_and_drop_columns() {
+        let plan = MigrationPlan::new(&stored(), Converted::schema()).unwrap();
+        assert!(
+            plan.report.blocking.is_empty(),
+            "{:?}",
+            plan.report.blocking
+        );
+        assert_eq!(
+            plan.report.changes,
+            [
+                "rename title into name",
+                "compute values of count",
+                "drop column legacy"
+            ]
+        );
+
+        let row = MigrationRow {
+            columns: stored().columns.into_iter().map(|c| c.name).collect(),
+            values: vec![
+                sql::Value::U32(1),
+                sql::Value::Str("first".to_owned()),
+                sql::Value::U32(3),
+                sql::Value::Bool(true),
+            ],
+        };
+        assert_eq!(
+            plan.apply(&row).unwrap(),
+            [
+                sql::Value::U32(1),
+                sql::Value::Str("first".to_owned()),
+                sql::Value::Str("3".to_owned()),
+            ]
+        );
+    }
+}
@@ -4,43 +4,378 @@ mod storage;
 pub use storage::*;
 
 mod key;
-pub use key::IntoSqlKey;
+pub use key::{composite_key, IntoSqlKey};
+
+mod backup;
+pub use backup::{
+    BackupFrame, BackupReader, BackupRow, BackupWriter, BACKUP_FORMAT_VERSION, BACKUP_FRAME_ROWS,
+};
+
+#[cfg(host)]
+mod changes;
+#[cfg(host)]
+pub use changes::{Change, Changes, RowChange};
+
+mod params;
+#[doc(hidden)]
+pub use params::value_node;
+
+pub mod interval;
+
+mod structured;
+pub use structured::{from_sql_value, into_sql_value};
+#[doc(hidden)]
+pub use structured::{into_sql_expr, readable_string};
+
+mod validation;
+pub use validation::{validate, FieldError, ValidationErrors};
+
+mod history;
+pub(crate) use history::acting_user;
+#[cfg(host)]
+pub use history::as_user;
+#[doc(hidden)]
+pub use history::HistoryRow;
+pub use history::{HistoryAction, HistoryEntry, Version};
+
+mod sync;
+pub use sync::{SyncChange, SyncRequest, SyncResponse, SYNC_ROUTE};
+
+mod glue;
+pub(crate) use glue::{GlueDb, WrittenRow};
+
+mod query;
+pub use query::{CmpFilter, Col, Filter, StorageQuery, TypedQuery};
+
+mod migrations;
+pub(crate) use migrations::MigrationPlan;
+pub use migrations::{MigrationReport, MigrationRow, MigrationStep, StoredColumn, StoredSchema};
 
 use gluesql_core::{ast_builder::Build as BuildSQL, prelude::Glue};
+#[cfg(host)]
+use std::sync::mpsc::{SyncSender, TrySendError};
 use std::sync::{
-    atomic::{AtomicBool, AtomicU64},
-    mpsc::{Receiver, SyncSender},
+    atomic::{AtomicU64, AtomicUsize, Ordering},
+    mpsc::Receiver,
 };
 
 /// re-export of GlueSQL core AST builder and other utils
 pub mod sql {
     pub use gluesql_core::ast::*;
     pub use gluesql_core::ast_builder::*;
-    pub use gluesql_core::data::{Key, Value};
+    pub use gluesql_core::data::{Interval, Key, Value};
     pub use gluesql_core::executor::Payload;
     pub use gluesql_core::store::DataRow;
     pub use ordered_float::OrderedFloat;
 }
 pub use prest_db_macro::Storage;
+pub use rust_decimal::Decimal;
+pub use std::net::IpAddr;
 
-type Returner = async_oneshot_channel::Sender<Result<Payload>>;
-pub(crate) type DbReadMessage = (Query, Returner);
-pub(crate) type DbWriteMessage = (Transaction, Returner);
+pub(crate) type Returner = async_oneshot_channel::Sender<Result<Payload>>;
+/// Query, returner of its result and the moment it was queued
+#[cfg(host)]
+pub(crate) type DbReadMessage = (Query, Returner, std::time::Instant);
+/// Transaction, returner of its result, the user who initiated it, the primary's id of the replicated write
+/// and the moment it was queued
+#[cfg(host)]
+pub(crate) type DbWriteMessage = (
+    Transaction,
+    Returner,
+    Option<Uuid>,
+    Option<u64>,
+    std::time::Instant,
+);
 
 pub(crate) const DB_DIRECTORY_NAME: &str = "db";
 
-pub(crate) static TX_ID: AtomicU64 = AtomicU64::new(0);
-pub(crate) static TX_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
+static GLOBAL_DB: Lazy<Db> = Lazy::new(Db::init);
+
+thread_local! {
+    static SCOPED_DB: std::cell::Cell<Option<&'static Db>> = const { std::cell::Cell::new(None) };
+}
+
+/// Number of the held [`TestDb`]s, the app's DB isn't used while there are any
+#[cfg(host)]
+static LIVE_TEST_DBS: AtomicUsize = AtomicUsize::new(0);
+
+/// Embedded database of the app, replaced with an isolated one on threads holding a [`TestDb`]
+pub static DB: DbRef = DbRef;
+
+#[doc(hidden)]
+pub struct DbRef;
+
+impl DbRef {
+    #[doc(hidden)]
+    pub fn _init(&self) -> Result {
+        #[cfg(host)]
+        DbBackend::selected()?;
+        Lazy::force(&GLOBAL_DB);
+        // jobs aren't spawned by the lazy initialization which can run on any thread touching the DB
+        #[cfg(host)]
+        crate::host::db::schedule_slow_query_flush();
+        OK
+    }
+
+    #[cfg(host)]
+    #[doc(hidden)]
+    pub fn _init_with(&self, backend: DbBackend) -> Result {
+        backend.configure();
+        self._init()
+    }
+}
 
-state!(DB: Db = { Db::init() });
+impl std::ops::Deref for DbRef {
+    type Target = Db;
+
+    fn deref(&self) -> &Db {
+        match SCOPED_DB.get() {
+            Some(db) => db,
+            // prest's own tests never touch the app's DB, even from threads without their TestDb
+            #[cfg(test)]
+            None => panic!("DB is used outside of a TestDb, hold one on the thread which uses it"),
+            #[cfg(not(test))]
+            None => {
+                // threads of the tests which don't hold their TestDb would write into the app's files
+                #[cfg(host)]
+                if LIVE_TEST_DBS.load(Ordering::SeqCst) > 0 {
+                    panic!("DB is used on a thread without a TestDb while tests hold them, hold one on every thread which uses it");
+                }
+                &GLOBAL_DB
+            }
+        }
+    }
+}
+
+/// Isolated throwaway DB used instead of the global one by the current thread while it's held
+///
+/// `#[tokio::test]` runs tests on their own threads with the current thread runtime, so every test
+/// can hold its own DB on the backend set with the `DB_BACKEND` env variable, sled by default.
+/// While any test DB is held other threads like the ones of `spawn_blocking` or multi-threaded runtimes
+/// can't use the app's DB and panic instead of writing into its files.
+/// Tables used by the test should be passed to [`TestDb::new`] because
+/// the `init` macro which registers them isn't used in tests:
+/// ```rust,ignore
+/// #[tokio::test]
+/// async fn saves_todos() {
+///     let _db = TestDb::new([Todo::schema()]).await;
+///     todo.save().await.unwrap();
+///     assert_eq!(Todo::get_all().await.unwrap().len(), 1);
+/// }
+/// ```
+#[cfg(host)]
+pub struct TestDb {
+    /// Owned DB which is freed on drop, bound to the thread which holds the test DB
+    db: *mut Db,
+}
+
+#[cfg(host)]
+impl TestDb {
+    pub async fn new(schemas: impl IntoIterator<Item = StructSchema>) -> Self {
+        let backend = DbBackend::selected().unwrap_or_else(|e| panic!("{e}"));
+        Self::with_backend(backend, schemas).await
+    }
+
+    /// Throwaway DB on the specified backend
+    pub async fn with_backend(
+        backend: DbBackend,
+        schemas: impl IntoIterator<Item = StructSchema>,
+    ) -> Self {
+        // references to a freed DB would stay reachable if nested test DBs were dropped out of order
+        if SCOPED_DB.get().is_some() {
+            panic!("a TestDb is already held on this thread");
+        }
+        let owned = Box::into_raw(Box::new(Db::open(backend, false)));
+        // SAFETY: freed only on drop, after the thread stops using it
+        let db: &'static Db = unsafe { &*owned };
+        LIVE_TEST_DBS.fetch_add(1, Ordering::SeqCst);
+        for schema in schemas {
+            db._register_schema(schema);
+        }
+        SCOPED_DB.set(Some(db));
+        db.migrate()
+            .await
+            .expect("test DB migration should be successful");
+        Self { db: owned }
+    }
+}
+
+#[cfg(host)]
+impl Drop for TestDb {
+    fn drop(&mut self) {
+        SCOPED_DB.set(None);
+        // SAFETY: the DB was allocated by `with_backend` and was reachable only through `DB` on this thread,
+        // which isn't scoped to it anymore, and its threads are joined by the shutdown
+        let db = unsafe { Box::from_raw(self.db) };
+        if let Err(e) = db.shutdown() {
+            warn!(target: "db", "failed to shutdown test DB: {e}");
+        }
+        LIVE_TEST_DBS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
 
 pub struct Db {
-    pub(crate) read: SyncSender<DbReadMessage>,
-    pub(crate) write: SyncSender<DbWriteMessage>,
-    // shutdown: Sender ? + fn shutdown() -> Result ?
-    pub(crate) internal_schemas: Arc<Vec<StructSchema>>,
-    pub(crate) custom_schemas: Arc<std::sync::RwLock<Vec<StructSchema>>>,
-    pub(crate) handles: Arc<Vec<std::thread::JoinHandle<Result>>>,
+    #[cfg(host)]
+    pub(crate) read: DbSender<DbReadMessage>,
+    #[cfg(host)]
+    pub(crate) write: DbSender<DbWriteMessage>,
+    pub(crate) schemas: Schemas,
+    /// Threads of the DB joined on [`Db::shutdown`]
+    #[cfg(host)]
+    pub(crate) handles: std::sync::Mutex<Vec<std::thread::JoinHandle<Result>>>,
+    pub(crate) read_metrics: Arc<ReadMetrics>,
+    /// Per-table query stats and slow queries waiting to be saved
+    #[cfg(host)]
+    pub(crate) profiler: Arc<crate::host::db::QueryProfiler>,
+    /// Keeps the change feed open while there are no subscribers
+    #[cfg(host)]
+    pub(crate) changes: async_broadcast::InactiveReceiver<Arc<RowChange>>,
+    /// Id of the latest committed write, awaited by the followers tailing the replication log
+    #[cfg(host)]
+    pub(crate) committed: tokio::sync::watch::Receiver<u64>,
+    #[cfg(host)]
+    pub(crate) replication: crate::host::db::ReplicationConfig,
+    /// Trees of the sled tables released once the threads are stopped
+    #[cfg(host)]
+    pub(crate) tables: crate::host::db::TablesCache,
+    /// Rollback of the write interrupted by a crash performed when the DB was opened
+    #[cfg(host)]
+    pub(crate) recovery: Option<RecoveryReport>,
+    // removed on shutdown because global statics aren't dropped
+    pub(crate) temp_path: Option<std::path::PathBuf>,
+}
+
+/// Sender of the messages to the DB threads, closed on [`Db::shutdown`] so that they stop
+#[cfg(host)]
+pub(crate) struct DbSender<T>(std::sync::RwLock<Option<SyncSender<T>>>);
+
+#[cfg(host)]
+impl<T> DbSender<T> {
+    pub fn new(sender: SyncSender<T>) -> Self {
+        Self(std::sync::RwLock::new(Some(sender)))
+    }
+
+    pub fn get(&self) -> Result<SyncSender<T>> {
+        self.0.read().unwrap().clone().ok_or(e!("DB is shut down"))
+    }
+
+    pub fn close(&self) {
+        self.0.write().unwrap().take();
+    }
+}
+
+#[cfg(host)]
+impl<T: Send + 'static> DbSender<T> {
+    /// Queues the message without blocking the executor while the channel is full,
+    /// returns whether it had to wait for a free slot
+    pub async fn send(&self, msg: T) -> Result<bool> {
+        let sender = self.get()?;
+        let msg = match sender.try_send(msg) {
+            Ok(()) => return Ok(false),
+            Err(TrySendError::Disconnected(_)) => return Err(e!("DB threads are disconnected")),
+            Err(TrySendError::Full(msg)) => msg,
+        };
+        tokio::task::spawn_blocking(move || sender.send(msg))
+            .await
+            .somehow()?
+            .map_err(|_| e!("DB threads are disconnected"))?;
+        Ok(true)
+    }
+}
+
+/// Load of the reader threads, updated by [`Db::read`] and the readers
+#[derive(Debug, Default)]
+pub(crate) struct ReadMetrics {
+    pub readers: AtomicUsize,
+    pub queued: AtomicUsize,
+    pub max_queued: AtomicUsize,
+    pub reads: AtomicU64,
+    /// Times reads found the channel full and had to wait for a free slot
+    pub backpressured: AtomicU64,
+    pub total_wait_us: AtomicU64,
+    pub max_wait_us: AtomicU64,
+}
+
+impl ReadMetrics {
+    pub fn queue(&self) {
+        let queued = self.queued.fetch_add(1, Ordering::Relaxed) + 1;
+        self.max_queued.fetch_max(queued, Ordering::Relaxed);
+    }
+    /// Records that one of the readers picked up the query queued at the provided moment
+    pub fn dequeue(&self, queued_at: std::time::Instant) {
+        let waited = queued_at.elapsed().as_micros() as u64;
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.reads.fetch_add(1, Ordering::Relaxed);
+        self.total_wait_us.fetch_add(waited, Ordering::Relaxed);
+        self.max_wait_us.fetch_max(waited, Ordering::Relaxed);
+    }
+}
+
+/// Snapshot of the reader threads load since the start
+#[derive(Debug, Clone, Serialize)]
+pub struct ReadPoolStats {
+    pub readers: usize,
+    /// Reads waiting for a free reader right now
+    pub queued: usize,
+    pub max_queued: usize,
+    pub reads: u64,
+    /// Reads which found the queue full
+    pub backpressured: u64,
+    /// Average time reads spent in the queue
+    pub avg_wait: std::time::Duration,
+    pub max_wait: std::time::Duration,
+}
+
+/// Registered table schemas, shared with the DB threads
+#[derive(Clone, Default)]
+pub(crate) struct Schemas {
+    internal: Arc<Vec<StructSchema>>,
+    custom: Arc<std::sync::RwLock<Vec<StructSchema>>>,
+}
+
+impl Schemas {
+    pub fn new(internal: Vec<StructSchema>) -> Self {
+        Self {
+            internal: Arc::new(internal),
+            custom: Default::default(),
+        }
+    }
+    pub fn register(&self, schema: StructSchema) {
+        let mut custom = self.custom.write().unwrap();
+        if !custom.iter().any(|s| s.name() == schema.name()) {
+            custom.push(schema);
+        }
+    }
+    pub fn custom(&self) -> Vec<StructSchema> {
+        self.custom.read().unwrap().clone()
+    }
+    pub fn all(&self) -> Vec<StructSchema> {
+        let mut schemas = (*self.internal).clone();
+        schemas.extend(self.custom());
+        schemas
+    }
+    pub fn fetch_struct_schema(&self, table_name: &str) -> Option<StructSchema> {
+        if let Some(schema) = self.internal.iter().find(|s| s.name() == table_name) {
+            Some(*schema)
+        } else {
+            self.custom
+                .read()
+                .unwrap()
+                .iter()
+                .find(|s| s.name() == table_name)
+                .copied()
+        }
+    }
+    pub fn fetch_glue_schema(&self, table_name: &str) -> Option<gluesql_core::data::Schema> {
+        self.fetch_struct_schema(table_name)
+            .map(|schema| into_glue_schema(schema, self))
+    }
+    pub fn fetch_all_glue_schemas(&self) -> Vec<gluesql_core::data::Schema> {
+        self.all()
+            .into_iter()
+            .map(|schema| into_glue_schema(schema, self))
+            .collect()
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -56,6 +391,49 @@ pub enum Query {
         pkey_min: sql::Key,
         pkey_max: sql::Key,
     },
+    IndexRange {
+        name: &'static str,
+        index: &'static str,
+        min: Option<sql::Value>,
+        max: Option<sql::Value>,
+    },
+    MigrationReports,
+    /// Streams an archive of all the tables from a consistent snapshot into the sink
+    Export {
+        #[cfg(host)]
+        #[serde(skip)]
+        sink: ExportSink,
+    },
+    /// Number of rows maintained by the storage
+    Count {
+        name: &'static str,
+    },
+    /// Versions of the row or of all the rows if there is no pkey, the latest `limit` ones if set
+    History {
+        name: &'static str,
+        pkey: Option<sql::Key>,
+        limit: Option<usize>,
+    },
+    /// Tombstones of the rows removed from a soft delete table
+    Deleted {
+        name: &'static str,
+    },
+    /// Rows matching the query in the `#[searchable]` columns, most relevant first
+    Search {
+        name: &'static str,
+        query: String,
+        limit: usize,
+    },
+    /// Changes of the `#[storage(sync)]` rows committed after the cursor
+    SyncChanges {
+        since: u64,
+    },
+    /// Serialized committed writes starting from the id, served to the followers
+    ReplicationLog {
+        from: u64,
+    },
+    /// Id of the primary's write that the follower will apply next
+    ReplicaPosition,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -82,16 +460,89 @@ pub enum Transaction {
         name: &'static str,
         key: sql::Key,
     },
+    /// Moves the row removed from a soft delete table back from its tombstone
+    Restore {
+        name: &'static str,
+        key: sql::Key,
+    },
+    /// Removes rows found expired by the sweeper unless they were already removed
+    Expire {
+        rows: Vec<(&'static str, sql::Key)>,
+    },
+    /// Stores values of the rows modified by the primary's write as they are, `None` removes the row
+    Replicate {
+        rows: Vec<(&'static str, sql::Key, Option<Vec<sql::Value>>)>,
+    },
+    SyncIndexes {
+        name: &'static str,
+    },
+    Migrate {
+        name: &'static str,
+    },
+    /// Applied atomically: either all of the writes are persisted or none of them
+    Batch(Vec<Transaction>),
+    /// Restores a chunk of the backed up rows persisted with the columns
+    ImportRows {
+        name: &'static str,
+        columns: Vec<StoredColumn>,
+        rows: Vec<BackupRow>,
+    },
+    /// Removes up to `limit` rows of the restored table which weren't written after the `since` write
+    PruneImported {
+        name: &'static str,
+        since: u64,
+        limit: usize,
+    },
     #[cfg(feature = "experimental")]
     Nuke,
 }
 
+/// Rollback of the write interrupted by a crash, performed on startup
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecoveryReport {
+    pub tx_id: u64,
+    pub rows: usize,
+    pub index_entries: usize,
+    pub recovered_at: NaiveDateTime,
+}
+
+/// Queue of writes that will be applied atomically by [`Db::transaction`]
+#[derive(Debug, Clone, Default)]
+pub struct Tx(Arc<std::sync::Mutex<Vec<Transaction>>>);
+
+impl Tx {
+    pub fn push(&self, op: Transaction) {
+        self.0.lock().unwrap().push(op);
+    }
+
+    pub fn write_sql(&self, sql: &str) {
+        self.push(Transaction::SqlString(sql.to_owned()));
+    }
+
+    /// Queues the SQL with `$1`, `$2`, ... placeholders bound to the params
+    pub fn write_sql_params(&self, sql: &str, params: &[sql::Value]) -> Result {
+        self.push(Transaction::SqlStatement(params::bind(sql, params)?));
+        OK
+    }
+
+    fn take(&self) -> Vec<Transaction> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Payload {
     Success,
     Rows(Vec<Vec<sql::Value>>),
     Affected(usize),
     Sql(sql::Payload),
+    Migrations(Vec<MigrationReport>),
+    Count(u64),
+    History(Vec<HistoryEntry>),
+    Sync(SyncResponse),
+    /// Ids and serialized writes from the replication log
+    Replication(Vec<(u64, Vec<u8>)>),
+    Cursor(u64),
 }
 
 impl From<sql::Payload> for Payload {
@@ -109,92 +560,243 @@ impl From<sql::Payload> for Payload {
 impl Db {
     pub async fn read(&self, query: Query) -> Result<Payload> {
         let (returner, result) = async_oneshot_channel::oneshot::<Result<Payload>>();
-        self.read.send((query, returner));
+        #[cfg(sw)]
+        crate::service_worker::db::read(query, returner);
+        #[cfg(host)]
+        {
+            let msg = (query, returner, std::time::Instant::now());
+            self.read_metrics.queue();
+            if let Err(e) = self.send_read(msg).await {
+                self.read_metrics.queued.fetch_sub(1, Ordering::Relaxed);
+                return Err(e);
+            }
+        }
         result.recv().await.ok_or(e!("missing db return"))?
     }
+
+    /// Queues the read without blocking the executor while the channel is full
+    #[cfg(host)]
+    async fn send_read(&self, msg: DbReadMessage) -> Result {
+        if self.read.send(msg).await? {
+            self.read_metrics
+                .backpressured
+                .fetch_add(1, Ordering::Relaxed);
+        }
+        OK
+    }
+
+    /// Load of the reader threads since the start
+    pub fn read_pool_stats(&self) -> ReadPoolStats {
+        let m = &self.read_metrics;
+        let reads = m.reads.load(Ordering::Relaxed);
+        let total_wait_us = m.total_wait_us.load(Ordering::Relaxed);
+        ReadPoolStats {
+            readers: m.readers.load(Ordering::Relaxed),
+            queued: m.queued.load(Ordering::Relaxed),
+            max_queued: m.max_queued.load(Ordering::Relaxed),
+            reads,
+            backpressured: m.backpressured.load(Ordering::Relaxed),
+            avg_wait: std::time::Duration::from_micros(total_wait_us / reads.max(1)),
+            max_wait: std::time::Duration::from_micros(m.max_wait_us.load(Ordering::Relaxed)),
+        }
+    }
     pub async fn write(&self, tx: Transaction) -> Result<Payload> {
         let (returner, result) = async_oneshot_channel::oneshot::<Result<Payload>>();
-        self.write.send((tx, returner));
+        #[cfg(sw)]
+        crate::service_worker::db::write(tx, returner);
+        #[cfg(host)]
+        self.write
+            .send((tx, returner, acting_user(), None, std::time::Instant::now()))
+            .await?;
         result.recv().await.ok_or(e!("missing db return"))?
     }
 
     pub async fn read_sql(&self, sql: &str) -> Result<Payload> {
-        let (returner, result) = async_oneshot_channel::oneshot::<Result<Payload>>();
-        self.read.send((Query::SqlString(sql.to_owned()), returner));
-        result.recv().await.ok_or(e!("missing db return"))?
+        self.read(Query::SqlString(sql.to_owned())).await
     }
 
     pub async fn read_sql_rows<T: Storage>(&self, sql: &str) -> Result<Vec<T>> {
-        let (returner, result) = async_oneshot_channel::oneshot::<Result<Payload>>();
-        self.read.send((Query::SqlString(sql.to_owned()), returner));
-        match result.recv().await.ok_or(e!("missing value"))?? {
+        match self.read(Query::SqlString(sql.to_owned())).await? {
+            Payload::Rows(rows) => rows.into_iter().map(T::from_row).collect(),
+            p => Err(e!("Got {p:?} instead of rows")),
+        }
+    }
+
+    /// Runs the query with `$1`, `$2`, ... placeholders bound to the params through the AST
+    ///
+    /// ```rust,ignore
+    /// DB.read_sql_params("SELECT * FROM Todo WHERE task = $1", &[sql::Value::Str(task)]).await?;
+    /// ```
+    pub async fn read_sql_params(&self, sql: &str, params: &[sql::Value]) -> Result<Payload> {
+        self.read(Query::SqlStatement(params::bind(sql, params)?))
+            .await
+    }
+
+    pub async fn read_sql_rows_params<T: Storage>(
+        &self,
+        sql: &str,
+        params: &[sql::Value],
+    ) -> Result<Vec<T>> {
+        match self.read_sql_params(sql, params).await? {
             Payload::Rows(rows) => rows.into_iter().map(T::from_row).collect(),
             p => Err(e!("Got {p:?} instead of rows")),
         }
     }
 
     pub async fn write_sql(&self, sql: &str) -> Result<Payload> {
-        let (returner, result) = async_oneshot_channel::oneshot::<Result<Payload>>();
-        self.write
-            .send((Transaction::SqlString(sql.to_owned()), returner));
-        result.recv().await.ok_or(e!("missing db return"))?
+        self.write(Transaction::SqlString(sql.to_owned())).await
+    }
+
+    /// Runs the write with `$1`, `$2`, ... placeholders bound to the params through the AST
+    pub async fn write_sql_params(&self, sql: &str, params: &[sql::Value]) -> Result<Payload> {
+        self.write(Transaction::SqlStatement(params::bind(sql, params)?))
+            .await
     }
 
     #[cfg(feature = "experimental")]
     pub async fn nuke(&self) -> Result<Payload> {
-        let (returner, result) = async_oneshot_channel::oneshot::<Result<Payload>>();
         warn!("nuking the database");
-        self.write.send((Transaction::Nuke, returner));
-        result.recv().await.ok_or(e!("missing db return"))?
+        self.write(Transaction::Nuke).await
     }
 
     pub fn _register_schema(&self, schema: StructSchema) {
-        self.custom_schemas.write().unwrap().push(schema);
+        self.schemas.register(schema);
+    }
+    /// Registers the table which isn't collected by `#[init]`, like the ones used in the service worker
+    pub fn register_table<T: Storage>(&self) {
+        self.schemas.register(T::schema());
     }
     pub(crate) fn custom_schemas(&self) -> Vec<StructSchema> {
-        self.custom_schemas.read().unwrap().clone()
+        self.schemas.custom()
     }
-    pub(crate) fn fetch_glue_schema(&self, table_name: &str) -> Option<gluesql_core::data::Schema> {
-        if let Some(schema) = self
-            .internal_schemas
-            .iter()
-            .find(|s| s.name() == table_name)
-        {
-            Some(into_glue_schema(*schema))
-        } else if let Some(schema) = self
-            .custom_schemas
-            .read()
-            .unwrap()
-            .iter()
-            .find(|s| s.name() == table_name)
-        {
-            Some(into_glue_schema(*schema))
-        } else {
-            None
+    /// Runs the closure and atomically applies writes it queued into the [`Tx`]
+    ///
+    /// Queued writes are applied after the closure returns, so reads inside of it don't see them.
+    /// If any of them fails all the others are rolled back:
+    /// ```rust,ignore
+    /// DB.transaction(|tx| async move {
+    ///     from.save_in(&tx)?;
+    ///     to.save_in(&tx)?;
+    ///     OK
+    /// })
+    /// .await?;
+    /// ```
+    pub async fn transaction<T, F, Fut>(&self, f: F) -> Result<T>
+    where
+        F: FnOnce(Tx) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let tx = Tx::default();
+        let value = f(tx.clone()).await?;
+        let ops = tx.take();
+        if !ops.is_empty() {
+            self.write(Transaction::Batch(ops)).await?;
         }
+        Ok(value)
     }
-    pub(crate) fn fetch_all_glue_schemas(&self) -> Vec<gluesql_core::data::Schema> {
-        let mut schemas = self
-            .internal_schemas
-            .iter()
-            .map(|s| into_glue_schema(*s))
-            .collect::<Vec<_>>();
-        schemas.extend(
-            self.custom_schemas
-                .read()
-                .unwrap()
-                .iter()
-                .map(|s| into_glue_schema(*s)),
-        );
-        schemas
-    }
+
+    /// Migrates persisted rows of all the tables to their current schemas and syncs indexes
+    ///
+    /// With `DB_MIGRATIONS=dry-run` env variable pending migrations are only reported
     pub async fn migrate(&self) -> Result {
-        let mut all_tables = (*self.internal_schemas).clone();
-        all_tables.extend(self.custom_schemas().into_iter());
+        let dry_run = env_var("DB_MIGRATIONS").map_or(false, |v| v == "dry-run");
+        let reports = self.migration_reports().await?;
+
+        for table in self.schemas.all() {
+            let name = table.name();
+            let pending = reports.iter().find(|r| r.table == name && r.pending());
+            if let (Some(report), true) = (pending, dry_run) {
+                warn!(target: "db", "pending migration of {name}: {report:?}");
+                continue;
+            }
+            self.write(Transaction::Migrate { name }).await?;
+            self.write(Transaction::SyncIndexes { name }).await?;
+        }
         Ok(())
     }
+
+    /// Number of rows in the table, kept by the storage so it doesn't scan them
+    pub async fn count(&self, name: &'static str) -> Result<u64> {
+        match self.read(Query::Count { name }).await? {
+            Payload::Count(count) => Ok(count),
+            p => Err(e!("Got {p:?} instead of count")),
+        }
+    }
+
+    /// Versions of the row recorded by a table with `#[storage(history)]`, oldest first
+    ///
+    /// Without the pkey returns versions of all the rows, limited to the latest ones if `limit` is set
+    pub async fn history(
+        &self,
+        name: &'static str,
+        pkey: Option<sql::Key>,
+        limit: Option<usize>,
+    ) -> Result<Vec<HistoryEntry>> {
+        match self.read(Query::History { name, pkey, limit }).await? {
+            Payload::History(entries) => Ok(entries),
+            p => Err(e!("Got {p:?} instead of history")),
+        }
+    }
+
+    /// Rows with the most relevant matches of the query in the `#[searchable]` columns of the table
+    pub async fn search(
+        &self,
+        name: &'static str,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<Vec<sql::Value>>> {
+        let query = query.to_owned();
+        match self.read(Query::Search { name, query, limit }).await? {
+            Payload::Rows(rows) => Ok(rows),
+            p => Err(e!("Got {p:?} instead of search results")),
+        }
+    }
+
+    /// Changes of the `#[storage(sync)]` rows committed after the cursor, all their rows for `0`
+    pub async fn sync_changes(&self, since: u64) -> Result<SyncResponse> {
+        match self.read(Query::SyncChanges { since }).await? {
+            Payload::Sync(response) => Ok(response),
+            p => Err(e!("Got {p:?} instead of sync changes")),
+        }
+    }
+
+    /// Tombstones of the rows removed from a table with `#[storage(soft_delete)]`
+    pub async fn deleted(&self, name: &'static str) -> Result<Vec<HistoryEntry>> {
+        match self.read(Query::Deleted { name }).await? {
+            Payload::History(entries) => Ok(entries),
+            p => Err(e!("Got {p:?} instead of tombstones")),
+        }
+    }
+
+    /// Describes changes required to migrate persisted rows to the current schemas
+    pub async fn migration_reports(&self) -> Result<Vec<MigrationReport>> {
+        match self.read(Query::MigrationReports).await? {
+            Payload::Migrations(reports) => Ok(reports),
+            p => Err(e!("Got {p:?} instead of migration reports")),
+        }
+    }
+    /// Stops the DB threads once they handle the queued queries, then removes files of the throwaway DB
+    ///
+    /// Queries sent after the shutdown fail
     pub fn shutdown(&self) -> Result {
-        // TODO: stop receiving on the write thread and flush?
+        #[cfg(host)]
+        {
+            self.read.close();
+            self.write.close();
+            let handles = std::mem::take(&mut *self.handles.lock().unwrap());
+            for handle in handles {
+                match handle.join() {
+                    Ok(Err(e)) => warn!(target: "db", "DB thread stopped with an error: {e}"),
+                    Err(_) => warn!(target: "db", "DB thread panicked"),
+                    Ok(Ok(())) => {}
+                }
+            }
+            // sled keeps the files open until all the trees are dropped
+            self.tables.release();
+        }
+        if let Some(path) = &self.temp_path {
+            std::fs::remove_dir_all(path).somehow()?;
+        }
         OK
     }
 }
@@ -0,0 +1,294 @@
+use crate::*;
+use gluesql_core::{
+    ast::{
+        AstLiteral, DataType, DateTimeField, Expr, Query, Select, SelectItem, SetExpr, Statement,
+        Values,
+    },
+    data::{Interval, Value},
+    parse_sql::parse,
+    translate::translate,
+};
+
+/// Prefix of identifiers that stand in for `$N` placeholders until the values are bound
+const PARAM_PREFIX: &str = "__prest_param_";
+
+/// Parses the SQL with `$1`, `$2`, ... placeholders and binds values into its AST as literals
+pub(crate) fn bind(sql: &str, params: &[Value]) -> Result<Statement> {
+    let (sql, placeholders) = replace_placeholders(sql, params.len())?;
+
+    let mut parsed = parse(&sql)?;
+    if parsed.len() != 1 {
+        return Err(e!("expected a single statement, got {}", parsed.len()));
+    }
+    let mut statement = translate(&parsed.remove(0))?;
+
+    let mut binder = Binder { params, bound: 0 };
+    binder.statement(&mut statement)?;
+    if binder.bound != placeholders {
+        return Err(e!(
+            "parameters are supported only in expressions of SELECT, INSERT, UPDATE and DELETE statements"
+        ));
+    }
+    Ok(statement)
+}
+
+/// Swaps placeholders outside of quoted strings and identifiers with [`PARAM_PREFIX`]ed identifiers
+fn replace_placeholders(sql: &str, params: usize) -> Result<(String, usize)> {
+    let mut replaced = String::with_capacity(sql.len());
+    let mut placeholders = 0;
+    let mut quote = None;
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {}
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '$') if chars.peek().is_some_and(char::is_ascii_digit) => {
+                let mut index = String::new();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    index.push(digit);
+                }
+                let index: usize = index.parse().somehow()?;
+                if index == 0 || index > params {
+                    return Err(e!("parameter ${index} is not provided"));
+                }
+                replaced.push_str(PARAM_PREFIX);
+                replaced.push_str(&index.to_string());
+                placeholders += 1;
+                continue;
+            }
+            _ => {}
+        }
+        replaced.push(c);
+    }
+
+    Ok((replaced, placeholders))
+}
+
+struct Binder<'a> {
+    params: &'a [Value],
+    bound: usize,
+}
+
+impl Binder<'_> {
+    fn statement(&mut self, statement: &mut Statement) -> Result {
+        match statement {
+            Statement::Query(query) => self.query(query),
+            Statement::Insert { source, .. } => self.query(source),
+            Statement::Update {
+                assignments,
+                selection,
+                ..
+            } => {
+                for assignment in assignments {
+                    self.expr(&mut assignment.value)?;
+                }
+                self.optional(selection)
+            }
+            Statement::Delete { selection, .. } => self.optional(selection),
+            _ => OK,
+        }
+    }
+
+    fn query(&mut self, query: &mut Query) -> Result {
+        match &mut query.body {
+            SetExpr::Select(select) => self.select(select)?,
+            SetExpr::Values(Values(rows)) => {
+                for expr in rows.iter_mut().flatten() {
+                    self.expr(expr)?;
+                }
+            }
+        }
+        for order in &mut query.order_by {
+            self.expr(&mut order.expr)?;
+        }
+        self.optional(&mut query.limit)?;
+        self.optional(&mut query.offset)
+    }
+
+    fn select(&mut self, select: &mut Select) -> Result {
+        for item in &mut select.projection {
+            if let SelectItem::Expr { expr, .. } = item {
+                self.expr(expr)?;
+            }
+        }
+        self.optional(&mut select.selection)?;
+        for expr in &mut select.group_by {
+            self.expr(expr)?;
+        }
+        self.optional(&mut select.having)
+    }
+
+    fn optional(&mut self, expr: &mut Option<Expr>) -> Result {
+        match expr {
+            Some(expr) => self.expr(expr),
+            None => OK,
+        }
+    }
+
+    fn expr(&mut self, expr: &mut Expr) -> Result {
+        match expr {
+            Expr::Identifier(name) => {
+                let Some(index) = name.strip_prefix(PARAM_PREFIX) else {
+                    return OK;
+                };
+                let index: usize = index.parse().somehow()?;
+                *expr = value_expr(&self.params[index - 1])?;
+                self.bound += 1;
+                OK
+            }
+            Expr::IsNull(inner) | Expr::IsNotNull(inner) | Expr::Nested(inner) => self.expr(inner),
+            Expr::UnaryOp { expr, .. } => self.expr(expr),
+            Expr::BinaryOp { left, right, .. } => {
+                self.expr(left)?;
+                self.expr(right)
+            }
+            Expr::Between {
+                expr, low, high, ..
+            } => {
+                self.expr(expr)?;
+                self.expr(low)?;
+                self.expr(high)
+            }
+            Expr::Like { expr, pattern, .. } | Expr::ILike { expr, pattern, .. } => {
+                self.expr(expr)?;
+                self.expr(pattern)
+            }
+            Expr::InList { expr, list, .. } => {
+                self.expr(expr)?;
+                for item in list {
+                    self.expr(item)?;
+                }
+                OK
+            }
+            Expr::InSubquery { expr, subquery, .. } => {
+                self.expr(expr)?;
+                self.query(subquery)
+            }
+            Expr::Exists { subquery, .. } | Expr::Subquery(subquery) => self.query(subquery),
+            Expr::Case {
+                operand,
+                when_then,
+                else_result,
+            } => {
+                if let Some(operand) = operand {
+                    self.expr(operand)?;
+                }
+                for (when, then) in when_then {
+                    self.expr(when)?;
+                    self.expr(then)?;
+                }
+                if let Some(else_result) = else_result {
+                    self.expr(else_result)?;
+                }
+                OK
+            }
+            Expr::Array { elem } => {
+                for item in elem {
+                    self.expr(item)?;
+                }
+                OK
+            }
+            _ => OK,
+        }
+    }
+}
+
+/// Literal expression of the value, typed where the text form is ambiguous
+pub(crate) fn value_expr(value: &Value) -> Result<Expr> {
+    let typed = |data_type: DataType, value: String| Expr::TypedString { data_type, value };
+    let number = |value: String| -> Result<Expr> {
+        Ok(Expr::Literal(AstLiteral::Number(value.parse().somehow()?)))
+    };
+
+    Ok(match value {
+        Value::Null => Expr::Literal(AstLiteral::Null),
+        Value::Bool(v) => Expr::Literal(AstLiteral::Boolean(*v)),
+        Value::I8(v) => number(v.to_string())?,
+        Value::I16(v) => number(v.to_string())?,
+        Value::I32(v) => number(v.to_string())?,
+        Value::I64(v) => number(v.to_string())?,
+        Value::I128(v) => number(v.to_string())?,
+        Value::U8(v) => number(v.to_string())?,
+        Value::U16(v) => number(v.to_string())?,
+        Value::U32(v) => number(v.to_string())?,
+        Value::U64(v) => number(v.to_string())?,
+        Value::U128(v) => number(v.to_string())?,
+        Value::F32(v) => number(v.to_string())?,
+        Value::F64(v) => number(v.to_string())?,
+        Value::Decimal(v) => typed(DataType::Decimal, v.to_string()),
+        Value::Str(v) => Expr::Literal(AstLiteral::QuotedString(v.clone())),
+        Value::Bytea(v) => Expr::Literal(AstLiteral::HexString(hex::encode(v))),
+        Value::Inet(v) => typed(DataType::Inet, v.to_string()),
+        Value::Date(v) => typed(DataType::Date, v.to_string()),
+        Value::Timestamp(v) => typed(DataType::Timestamp, v.to_string()),
+        Value::Time(v) => typed(DataType::Time, v.to_string()),
+        Value::Uuid(v) => typed(DataType::Uuid, Uuid::from_u128(*v).to_string()),
+        Value::Interval(v) => {
+            let (value, field) = match v {
+                Interval::Month(months) => (months.to_string(), DateTimeField::Month),
+                Interval::Microsecond(micros) => {
+                    let seconds = rust_decimal::Decimal::new(*micros, 6).normalize();
+                    (seconds.to_string(), DateTimeField::Second)
+                }
+            };
+            Expr::Interval {
+                expr: Box::new(Expr::Literal(AstLiteral::QuotedString(value))),
+                leading_field: Some(field),
+                last_field: None,
+            }
+        }
+        Value::List(items) => Expr::Array {
+            elem: items.iter().map(value_expr).collect::<Result<_>>()?,
+        },
+        Value::Map(_) => typed(
+            DataType::Map,
+            super::structured::value_into_json(value.clone()).to_string(),
+        ),
+        other => return Err(e!("binding {other:?} as a parameter is not supported")),
+    })
+}
+
+/// Expression node of values which always have a literal form like dates, decimals or intervals
+pub fn value_node(value: Value) -> sql::ExprNode<'static> {
+    let expr = value_expr(&value).expect("value should have a literal form");
+    sql::ExprNode::Expr(std::borrow::Cow::Owned(expr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statement(sql: &str) -> Statement {
+        translate(&parse(sql).unwrap().remove(0)).unwrap()
+    }
+
+    #[test]
+    fn binds_values_as_literals() {
+        let bound = bind(
+            "SELECT * FROM t WHERE id = $1 AND name = $2 AND note = '$1'",
+            &[Value::I64(5), Value::Str("it's".to_owned())],
+        )
+        .unwrap();
+        let expected = statement("SELECT * FROM t WHERE id = 5 AND name = 'it''s' AND note = '$1'");
+        assert_eq!(bound, expected);
+    }
+
+    #[test]
+    fn placeholders_need_provided_values() {
+        let missing = bind("SELECT * FROM t WHERE id = $2", &[Value::I64(1)]);
+        assert!(missing
+            .unwrap_err()
+            .to_string()
+            .contains("$2 is not provided"));
+        let zero = bind("SELECT * FROM t WHERE id = $0", &[Value::I64(1)]);
+        assert!(zero.unwrap_err().to_string().contains("$0 is not provided"));
+    }
+
+    #[test]
+    fn placeholders_outside_of_supported_expressions_fail() {
+        let unbound = bind("SELECT * FROM a JOIN b ON a.id = $1", &[Value::I64(1)]);
+        assert!(unbound.unwrap_err().to_string().contains("supported only"));
+    }
+}
@@ -0,0 +1,422 @@
+use crate::{sql::Build, *};
+use std::marker::PhantomData;
+
+/// Column of a [`Storage`] table, generated as `MyStruct::COLS.field_name` for ordering typed queries
+pub struct Col<T> {
+    pub name: &'static str,
+    table: PhantomData<fn() -> T>,
+}
+
+impl<T> Col<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            table: PhantomData,
+        }
+    }
+
+    fn order(self, asc: bool) -> sql::OrderByExpr {
+        sql::OrderByExpr {
+            expr: sql::Expr::Identifier(self.name.to_owned()),
+            asc: Some(asc),
+        }
+    }
+}
+
+impl<T> Clone for Col<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+impl<T> Copy for Col<T> {}
+
+/// Filters, ordering and pagination of a typed query, wrapped by the generated `MyStructQuery`
+pub struct TypedQuery<T> {
+    filters: Vec<sql::Expr>,
+    order: Vec<sql::OrderByExpr>,
+    offset: usize,
+    limit: Option<usize>,
+    error: Option<String>,
+    table: PhantomData<fn() -> T>,
+}
+
+impl<T> Default for TypedQuery<T> {
+    fn default() -> Self {
+        Self {
+            filters: vec![],
+            order: vec![],
+            offset: 0,
+            limit: None,
+            error: None,
+            table: PhantomData,
+        }
+    }
+}
+
+impl<T: Storage> TypedQuery<T> {
+    pub fn filter(&mut self, node: sql::ExprNode<'static>) {
+        match sql::Expr::try_from(node) {
+            Ok(expr) => self.filters.push(expr),
+            Err(e) => {
+                self.error.get_or_insert(e.to_string());
+            }
+        }
+    }
+
+    /// Combined filters of the query
+    fn filter_node(&mut self) -> Result<sql::ExprNode<'static>> {
+        if let Some(e) = self.error.take() {
+            return Err(e!("invalid {} query: {e}", T::STRUCT_NAME));
+        }
+
+        Ok(std::mem::take(&mut self.filters)
+            .into_iter()
+            .map(|expr| sql::ExprNode::Expr(std::borrow::Cow::Owned(expr)))
+            .reduce(|all, next| all.and(next))
+            .unwrap_or_else(|| sql::expr("TRUE")))
+    }
+
+    /// Compiles the query into the GlueSQL select statement
+    pub fn build(mut self) -> Result<sql::Statement> {
+        let paged = T::select()
+            .filter(self.filter_node()?)
+            .offset(self.offset as i64);
+        let mut statement = match self.limit {
+            Some(limit) => paged.limit(limit as i64).build()?,
+            None => paged.build()?,
+        };
+        // ordering is set on the AST so that column names aren't parsed back from SQL text
+        if let sql::Statement::Query(query) = &mut statement {
+            query.order_by = self.order;
+        }
+        Ok(statement)
+    }
+
+    /// Number of the filtered rows, unfiltered queries use the counter of the table instead of scanning
+    pub async fn count(mut self) -> Result<u64> {
+        if self.filters.is_empty() && self.error.is_none() {
+            return T::count().await;
+        }
+        let count: Option<i64> = self.aggregate("COUNT(*)").await?;
+        Ok(count.unwrap_or(0) as u64)
+    }
+
+    /// Result of the aggregate `projection` like `SUM(field)` over the filtered rows, `None` if it's `NULL`
+    ///
+    /// Ordering and pagination are ignored
+    pub async fn aggregate<V>(mut self, projection: &'static str) -> Result<Option<V>>
+    where
+        V: for<'v> TryFrom<&'v sql::Value>,
+    {
+        let statement = T::select()
+            .filter(self.filter_node()?)
+            .project(projection)
+            .build()?;
+        let value = match DB.read(Query::SqlStatement(statement)).await? {
+            Payload::Rows(mut rows) => rows.pop().and_then(|mut row| row.pop()),
+            p => return Err(e!("Got {p:?} instead of rows")),
+        };
+        match value {
+            None | Some(sql::Value::Null) => Ok(None),
+            Some(value) => V::try_from(&value)
+                .map(Some)
+                .map_err(|_| e!("unexpected {projection} of {}: {value:?}", T::STRUCT_NAME)),
+        }
+    }
+
+    /// Numbers of the filtered rows grouped by the values of the `column`, with `projection` like `column, COUNT(*)`
+    ///
+    /// Ordering and pagination are ignored
+    pub async fn count_by<V>(
+        mut self,
+        column: &'static str,
+        projection: &'static str,
+    ) -> Result<Vec<(Option<V>, u64)>>
+    where
+        V: for<'v> TryFrom<&'v sql::Value>,
+    {
+        let statement = T::select()
+            .filter(self.filter_node()?)
+            .group_by(column)
+            .project(projection)
+            .build()?;
+        let rows = match DB.read(Query::SqlStatement(statement)).await? {
+            Payload::Rows(rows) => rows,
+            p => return Err(e!("Got {p:?} instead of rows")),
+        };
+
+        let mut groups = vec![];
+        for row in rows {
+            let [value, count] = &row[..] else {
+                return Err(e!("unexpected {projection} of {}: {row:?}", T::STRUCT_NAME));
+            };
+            let value = match value {
+                sql::Value::Null => None,
+                value => Some(
+                    V::try_from(value)
+                        .map_err(|_| e!("unexpected {column} of {}: {value:?}", T::STRUCT_NAME))?,
+                ),
+            };
+            let count = i64::try_from(count)
+                .map_err(|_| e!("unexpected count of {}: {count:?}", T::STRUCT_NAME))?;
+            groups.push((value, count as u64));
+        }
+        Ok(groups)
+    }
+}
+
+/// Ordering, pagination and execution of the generated typed queries like `MyStruct::query()`
+///
+/// ```rust,ignore
+/// let todos = Todo::query()
+///     .where_done().eq(false)
+///     .where_owner().eq(user.id)
+///     .order_by_desc(Todo::COLS.created)
+///     .limit(20)
+///     .all()
+///     .await?;
+/// ```
+pub trait StorageQuery: Sized + Send {
+    type Row: Storage;
+
+    fn query_mut(&mut self) -> &mut TypedQuery<Self::Row>;
+    fn into_query(self) -> TypedQuery<Self::Row>;
+
+    fn order_by(mut self, col: Col<Self::Row>) -> Self {
+        self.query_mut().order.push(col.order(true));
+        self
+    }
+
+    fn order_by_desc(mut self, col: Col<Self::Row>) -> Self {
+        self.query_mut().order.push(col.order(false));
+        self
+    }
+
+    fn offset(mut self, offset: usize) -> Self {
+        self.query_mut().offset = offset;
+        self
+    }
+
+    fn limit(mut self, limit: usize) -> Self {
+        self.query_mut().limit = Some(limit);
+        self
+    }
+
+    fn build(self) -> Result<sql::Statement> {
+        self.into_query().build()
+    }
+
+    fn all(self) -> impl Future<Output = Result<Vec<Self::Row>>> + Send {
+        let statement = self.build();
+        async move {
+            match DB.read(Query::SqlStatement(statement?)).await? {
+                Payload::Rows(rows) => Self::Row::from_rows(rows),
+                p => Err(e!("Got {p:?} instead of rows")),
+            }
+        }
+    }
+
+    /// Number of the rows matching filters, ignoring pagination
+    fn count(self) -> impl Future<Output = Result<u64>> + Send {
+        self.into_query().count()
+    }
+
+    fn first(self) -> impl Future<Output = Result<Option<Self::Row>>> + Send {
+        let rows = self.limit(1).all();
+        async move { Ok(rows.await?.pop()) }
+    }
+}
+
+/// Filter on a column which supports only equality checks, returned by `MyStructQuery::where_field_name()`
+pub struct Filter<Q, V> {
+    query: Q,
+    column: &'static str,
+    into_expr: fn(&V) -> sql::ExprNode<'static>,
+}
+
+impl<Q: StorageQuery, V> Filter<Q, V> {
+    pub fn new(
+        query: Q,
+        column: &'static str,
+        into_expr: fn(&V) -> sql::ExprNode<'static>,
+    ) -> Self {
+        Self {
+            query,
+            column,
+            into_expr,
+        }
+    }
+
+    fn push(mut self, node: impl FnOnce(sql::ExprNode<'static>) -> sql::ExprNode<'static>) -> Q {
+        self.query.query_mut().filter(node(sql::col(self.column)));
+        self.query
+    }
+
+    pub fn eq(self, value: impl Into<V>) -> Q {
+        let value = (self.into_expr)(&value.into());
+        self.push(|col| col.eq(value))
+    }
+
+    pub fn ne(self, value: impl Into<V>) -> Q {
+        let value = (self.into_expr)(&value.into());
+        self.push(|col| col.neq(value))
+    }
+
+    pub fn is_null(self) -> Q {
+        self.push(|col| col.is_null())
+    }
+
+    pub fn is_not_null(self) -> Q {
+        self.push(|col| col.is_not_null())
+    }
+}
+
+/// Filter on a comparable column, returned by `MyStructQuery::where_field_name()`
+pub struct CmpFilter<Q, V>(Filter<Q, V>);
+
+impl<Q: StorageQuery, V> CmpFilter<Q, V> {
+    pub fn new(
+        query: Q,
+        column: &'static str,
+        into_expr: fn(&V) -> sql::ExprNode<'static>,
+    ) -> Self {
+        Self(Filter::new(query, column, into_expr))
+    }
+
+    pub fn eq(self, value: impl Into<V>) -> Q {
+        self.0.eq(value)
+    }
+
+    pub fn ne(self, value: impl Into<V>) -> Q {
+        self.0.ne(value)
+    }
+
+    pub fn is_null(self) -> Q {
+        self.0.is_null()
+    }
+
+    pub fn is_not_null(self) -> Q {
+        self.0.is_not_null()
+    }
+
+    pub fn gt(self, value: impl Into<V>) -> Q {
+        let value = (self.0.into_expr)(&value.into());
+        self.0.push(|col| col.gt(value))
+    }
+
+    pub fn gte(self, value: impl Into<V>) -> Q {
+        let value = (self.0.into_expr)(&value.into());
+        self.0.push(|col| col.gte(value))
+    }
+
+    pub fn lt(self, value: impl Into<V>) -> Q {
+        let value = (self.0.into_expr)(&value.into());
+        self.0.push(|col| col.lt(value))
+    }
+
+    pub fn lte(self, value: impl Into<V>) -> Q {
+        let value = (self.0.into_expr)(&value.into());
+        self.0.push(|col| col.lte(value))
+    }
+
+    /// Inclusive range
+    pub fn between(self, min: impl Into<V>, max: impl Into<V>) -> Q {
+        let min = (self.0.into_expr)(&min.into());
+        let max = (self.0.into_expr)(&max.into());
+        self.0.push(|col| col.between(min, max))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Task {
+        id: u8,
+        name: String,
+        priority: u8,
+        done: bool,
+    }
+
+    fn names(tasks: Vec<Task>) -> Vec<String> {
+        tasks.into_iter().map(|t| t.name).collect()
+    }
+
+    #[test]
+    fn builds_ordering_as_ast_nodes() {
+        let statement = Task::query()
+            .where_done()
+            .eq(false)
+            .order_by_desc(Task::COLS.priority)
+            .order_by(Task::COLS.name)
+            .limit(2)
+            .build()
+            .unwrap();
+        let sql::Statement::Query(query) = statement else {
+            panic!("select expected");
+        };
+        let order = query
+            .order_by
+            .iter()
+            .map(|o| (o.expr.clone(), o.asc))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            order,
+            [
+                (sql::Expr::Identifier("priority".to_owned()), Some(false)),
+                (sql::Expr::Identifier("name".to_owned()), Some(true)),
+            ]
+        );
+        assert!(query.limit.is_some());
+    }
+
+    #[tokio::test]
+    async fn filters_orders_and_pages_rows() {
+        let _db = TestDb::new([Task::schema()]).await;
+        let tasks = [
+            ("a", 1, false),
+            ("b", 3, false),
+            ("c", 3, false),
+            ("d", 2, true),
+            ("e", 2, false),
+        ];
+        for (id, (name, priority, done)) in tasks.into_iter().enumerate() {
+            let name = name.to_owned();
+            let id = id as u8;
+            Task {
+                id,
+                name,
+                priority,
+                done,
+            }
+            .save()
+            .await
+            .unwrap();
+        }
+
+        let open = || Task::query().where_done().eq(false);
+        let by_priority = open()
+            .order_by_desc(Task::COLS.priority)
+            .order_by(Task::COLS.name)
+            .all()
+            .await
+            .unwrap();
+        assert_eq!(names(by_priority), ["b", "c", "e", "a"]);
+
+        let page = open()
+            .order_by_desc(Task::COLS.priority)
+            .order_by_desc(Task::COLS.name)
+            .offset(1)
+            .limit(2)
+            .all()
+            .await
+            .unwrap();
+        assert_eq!(names(page), ["b", "e"]);
+
+        let urgent = open().where_priority().gte(2).count().await.unwrap();
+        assert_eq!(urgent, 3);
+        let lowest = open().order_by(Task::COLS.priority).first().await.unwrap();
+        assert_eq!(lowest.map(|t| t.name), Some("a".to_owned()));
+    }
+}
@@ -2,7 +2,7 @@ use crate::*;
 
 use db::key::IntoSqlKey;
 use gluesql_core::{
-    ast::{ColumnDef, ColumnUniqueOption, Expr},
+    ast::{ColumnDef, ColumnUniqueOption, Expr, ForeignKey, ReferentialAction},
     ast_builder::{DeleteNode, InsertNode, SelectNode, UpdateNode},
     data::{Schema as GlueSchema, SchemaIndex, SchemaIndexOrd},
 };
@@ -19,17 +19,51 @@ pub struct FieldSchema {
     pub rust_type: &'static str,
     pub sql_type: sql::DataType,
     pub unique: bool,
+    pub index: bool,
     pub pkey: bool,
     pub list: bool,
     pub optional: bool,
     pub serialized: bool,
+    /// Stored as a TEXT, MAP or LIST value through serde instead of bitcode
+    pub structured: bool,
     pub numeric: bool,
     pub comparable: bool,
+    /// Value used when the column is added by migrations or omitted in SQL inserts if it's constant, set with `#[default = expr]`
+    #[serde(skip)]
+    pub default: Option<fn() -> Result<sql::Value>>,
+    /// Default is a literal, so it's also kept in the table schema for SQL inserts
+    pub constant_default: bool,
+    /// Set to the time of the first save, `#[created_at]`
+    pub created_at: bool,
+    /// Set to the time of every write, `#[updated_at]`
+    pub updated_at: bool,
+    /// Row is removed by the sweeper after this time, `#[expires_at]`
+    pub expires_at: bool,
+    /// Words of the text are indexed for full-text search, `#[searchable]`
+    pub searchable: bool,
+    /// Searched words are reduced to their stems, `#[searchable(stem)]`
+    pub stemmed: bool,
+    /// Name of the table whose primary key is stored in this column, set with `#[references(Table)]`
+    pub references: Option<&'static str>,
+    pub on_delete: OnDelete,
+}
+
+/// Handling of rows referencing the deleted one, set with `#[references(Table, on_delete = ...)]`
+///
+/// `Restrict` and `Cascade` also make writes fail if the referenced row doesn't exist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum OnDelete {
+    /// Relation isn't enforced, referencing rows are kept as is
+    Keep,
+    /// Deletion of the referenced row fails
+    Restrict,
+    /// Referencing rows are deleted as well
+    Cascade,
 }
 
 pub type StructSchema = &'static dyn StructSchemaTrait;
 
-pub fn into_glue_schema(schema: StructSchema) -> GlueSchema {
+pub(crate) fn into_glue_schema(schema: StructSchema, schemas: &Schemas) -> GlueSchema {
     let pkey = schema
         .fields()
         .iter()
@@ -44,7 +78,12 @@ pub fn into_glue_schema(schema: StructSchema) -> GlueSchema {
             name: c.name.to_owned(),
             data_type: c.sql_type.clone(),
             nullable: c.optional,
-            default: None,
+            // other defaults like `Utc::now()` would be frozen at the time of the schema creation
+            default: c
+                .default
+                .filter(|_| c.constant_default)
+                .and_then(|default| default().ok())
+                .and_then(|value| db::params::value_expr(&value).ok()),
             unique: if c.unique {
                 Some(ColumnUniqueOption { is_primary: c.pkey })
             } else {
@@ -54,12 +93,43 @@ pub fn into_glue_schema(schema: StructSchema) -> GlueSchema {
         })
         .collect::<Vec<_>>();
 
+    let indexes = schema
+        .fields()
+        .iter()
+        .filter(|c| c.index)
+        .map(|c| SchemaIndex {
+            name: c.name.to_owned(),
+            expr: Expr::Identifier(c.name.to_owned()),
+            order: SchemaIndexOrd::Both,
+            created: Default::default(),
+        })
+        .collect::<Vec<_>>();
+
+    // only enforced relations are checked by gluesql which doesn't support cascades
+    let foreign_keys = schema
+        .fields()
+        .iter()
+        .filter(|c| c.on_delete != OnDelete::Keep)
+        .filter_map(|c| {
+            let referenced = schemas.fetch_struct_schema(c.references?)?;
+            let referenced_pkey = referenced.fields().iter().find(|f| f.pkey)?;
+            Some(ForeignKey {
+                name: format!("fk_{}_{}", schema.name(), c.name),
+                referencing_column_name: c.name.to_owned(),
+                referenced_table_name: referenced.name().to_owned(),
+                referenced_column_name: referenced_pkey.name.to_owned(),
+                on_delete: ReferentialAction::NoAction,
+                on_update: ReferentialAction::NoAction,
+            })
+        })
+        .collect::<Vec<_>>();
+
     GlueSchema {
         table_name: schema.name().to_owned(),
         column_defs: Some(columns),
-        indexes: vec![],
+        indexes,
         engine: None,
-        foreign_keys: vec![],
+        foreign_keys,
         comment: None,
     }
 }
@@ -78,8 +148,40 @@ pub trait StructSchemaTrait: Sync {
         limit: usize,
     ) -> Result<(Vec<Vec<String>>, bool)>;
     async fn get_as_strings_by_id(&self, id: String) -> Result<Vec<String>>;
+    /// Latest versions of the rows with formatted values, newest first
+    async fn history_as_strings(&self, limit: usize) -> Result<Vec<HistoryRow>>;
+    /// Rows most relevant to the query in the `#[searchable]` columns with formatted values
+    async fn search_as_strings(&self, query: &str, limit: usize) -> Result<Vec<Vec<String>>>;
     async fn save(&self, req: Request) -> Result<String>;
     async fn remove(&self, req: Request) -> Result;
+    /// Checks `#[validate(...)]` attributes of the row's values, see [`Storage::validate`]
+    fn validate_row(&self, row: Vec<sql::Value>) -> Result;
+    fn migrations(&self) -> Vec<MigrationStep> {
+        vec![]
+    }
+    /// Versions of the rows are recorded on every write, `#[storage(history)]`
+    fn history(&self) -> bool {
+        false
+    }
+    /// Removed rows are kept as tombstones which can be restored, `#[storage(soft_delete)]`
+    fn soft_delete(&self) -> bool {
+        false
+    }
+    /// Rows are removed by the sweeper this long after their last update or creation, `#[storage(ttl = "30d")]`
+    fn ttl(&self) -> Option<std::time::Duration> {
+        None
+    }
+    /// Rows are synchronized between the service worker and the host, `#[storage(sync)]`
+    fn sync(&self) -> bool {
+        false
+    }
+    /// Converts bitcode of a column that became structured into its native value
+    fn decode_serialized(&self, column: &str, _value: sql::Value) -> Result<sql::Value> {
+        Err(e!("{column} of {} isn't structured", self.name()))
+    }
+    async fn count(&self) -> Result<u64> {
+        DB.count(self.name()).await
+    }
 }
 
 /// Derived interface to interact with structs as tables of their values
@@ -89,7 +191,11 @@ pub trait Storage: Sized + Send + serde::de::DeserializeOwned {
     const FIELD_SCHEMAS: FieldSchemas;
     const PK_INDEX: usize;
 
-    type Key: std::fmt::Display + Send + Clone + IntoSqlKey;
+    /// Type of the primary key, a tuple of the `#[pkey]` fields if there are several of them
+    ///
+    /// Bound by `Debug` rather than `Display` since tuples don't implement the latter, so code
+    /// formatting keys with `{}` has to switch to `{:?}`
+    type Key: std::fmt::Debug + Send + Clone + IntoSqlKey;
 
     fn schema() -> StructSchema;
 
@@ -100,9 +206,11 @@ pub trait Storage: Sized + Send + serde::de::DeserializeOwned {
         rows.into_iter().map(Self::from_row).collect()
     }
 
-    fn get_pkey(&self) -> &Self::Key;
+    /// Returns the key by value instead of `&Self::Key` since composite keys are assembled
+    /// from their fields, so callers that kept the reference should clone or borrow the field
+    fn get_pkey(&self) -> Self::Key;
     async fn get_by_pkey(pkey: Self::Key) -> Result<Option<Self>> {
-        let pkey = pkey.into_sql_key();
+        let pkey = pkey.into_sql_key()?;
         let prest::db::Payload::Rows(mut rows) = DB
             .read(prest::Query::GetByPKey {
                 pkey,
@@ -117,6 +225,32 @@ pub trait Storage: Sized + Send + serde::de::DeserializeOwned {
 
     fn pk_filter_sql_node<'a, 'b>(pkey: &'a Self::Key) -> sql::ExprNode<'b>;
 
+    /// Checks `#[validate(...)]` attributes of the fields, runs before values are sent to the DB
+    fn validate(&self) -> Result {
+        OK
+    }
+
+    /// Selects rows with values of the indexed column in the inclusive range, unbounded if `None`
+    async fn select_in_index_range(
+        index: &'static str,
+        min: Option<sql::Value>,
+        max: Option<sql::Value>,
+    ) -> Result<Vec<Self>> {
+        let payload = DB
+            .read(prest::Query::IndexRange {
+                name: Self::STRUCT_NAME,
+                index,
+                min,
+                max,
+            })
+            .await?;
+
+        let prest::db::Payload::Rows(rows) = payload else {
+            panic!("unexpected DB select_in_index_range return payload: {payload:?}")
+        };
+        Self::from_rows(rows)
+    }
+
     fn select() -> SelectNode<'static> {
         sql::table(Self::STRUCT_NAME).select()
     }
@@ -137,8 +271,15 @@ pub trait Storage: Sized + Send + serde::de::DeserializeOwned {
         Self::select().rows().await
     }
 
-    async fn insert_self(&self) -> Result {
-        let pkey = self.get_pkey().clone().into_sql_key();
+    /// Number of rows in the table without scanning them
+    async fn count() -> Result<u64> {
+        DB.count(Self::STRUCT_NAME).await
+    }
+
+    /// Inserts the row, returns it as stored with the timestamps set by the write
+    async fn insert_self(&self) -> Result<Self> {
+        self.validate()?;
+        let pkey = self.get_pkey().into_sql_key()?;
         let row = self.into_row()?;
         let payload = DB
             .write(prest::Transaction::Insert {
@@ -148,14 +289,17 @@ pub trait Storage: Sized + Send + serde::de::DeserializeOwned {
             })
             .await?;
 
-        let prest::db::Payload::Success = payload else {
+        let prest::db::Payload::Rows(mut rows) = payload else {
             panic!("unexpected DB insert_self return payload: {payload:?}")
         };
-        OK
+        Self::from_row(rows.pop().ok_or(e!("missing inserted row"))?)
     }
 
-    async fn save(&self) -> Result<&Self> {
-        let pkey = self.get_pkey().clone().into_sql_key();
+    /// Saves the row, returns it as stored with the timestamps set by the write
+    /// and `#[created_at]` kept from the previous version
+    async fn save(&self) -> Result<Self> {
+        self.validate()?;
+        let pkey = self.get_pkey().into_sql_key()?;
         let row = self.into_row()?;
         let payload = DB
             .write(prest::Transaction::Save {
@@ -165,17 +309,39 @@ pub trait Storage: Sized + Send + serde::de::DeserializeOwned {
             })
             .await?;
 
-        let prest::db::Payload::Success = payload else {
+        let prest::db::Payload::Rows(mut rows) = payload else {
             panic!("unexpected DB save return payload: {payload:?}")
         };
-        Ok(self)
+        Self::from_row(rows.pop().ok_or(e!("missing saved row"))?)
+    }
+
+    /// Queues insertion into the transaction, fails on commit if the pkey is already taken
+    fn insert_in(&self, tx: &Tx) -> Result {
+        self.validate()?;
+        tx.push(prest::Transaction::Insert {
+            name: Self::STRUCT_NAME,
+            key: self.get_pkey().into_sql_key()?,
+            row: self.into_row()?,
+        });
+        OK
+    }
+
+    /// Queues saving into the transaction
+    fn save_in(&self, tx: &Tx) -> Result {
+        self.validate()?;
+        tx.push(prest::Transaction::Save {
+            name: Self::STRUCT_NAME,
+            key: self.get_pkey().into_sql_key()?,
+            row: self.into_row()?,
+        });
+        OK
     }
 
     async fn delete_by_pkey(pkey: Self::Key) -> Result {
         let payload = DB
             .write(prest::Transaction::Delete {
                 name: Self::STRUCT_NAME,
-                key: pkey.into_sql_key(),
+                key: pkey.into_sql_key()?,
             })
             .await?;
 
@@ -186,6 +352,69 @@ pub trait Storage: Sized + Send + serde::de::DeserializeOwned {
     }
 
     async fn remove(&self) -> Result {
-        Self::delete_by_pkey(self.get_pkey().clone()).await
+        Self::delete_by_pkey(self.get_pkey()).await
+    }
+
+    /// Versions of the row recorded with `#[storage(history)]`, oldest first
+    async fn history(pkey: Self::Key) -> Result<Vec<Version<Self>>> {
+        DB.history(Self::STRUCT_NAME, Some(pkey.into_sql_key()?), None)
+            .await?
+            .into_iter()
+            .map(HistoryEntry::into_version)
+            .collect()
+    }
+
+    /// Latest versions of all the rows recorded with `#[storage(history)]`, newest first
+    async fn recent_history(limit: usize) -> Result<Vec<Version<Self>>> {
+        DB.history(Self::STRUCT_NAME, None, Some(limit))
+            .await?
+            .into_iter()
+            .rev()
+            .map(HistoryEntry::into_version)
+            .collect()
+    }
+
+    /// Rows removed from the table with `#[storage(soft_delete)]` which can be restored
+    async fn deleted() -> Result<Vec<Version<Self>>> {
+        DB.deleted(Self::STRUCT_NAME)
+            .await?
+            .into_iter()
+            .map(HistoryEntry::into_version)
+            .collect()
+    }
+
+    /// Moves the removed row back into the table with `#[storage(soft_delete)]`
+    async fn restore(pkey: Self::Key) -> Result {
+        let payload = DB
+            .write(prest::Transaction::Restore {
+                name: Self::STRUCT_NAME,
+                key: pkey.into_sql_key()?,
+            })
+            .await?;
+
+        let prest::db::Payload::Success = payload else {
+            panic!("unexpected DB restore return payload: {payload:?}")
+        };
+        OK
+    }
+
+    /// Queues deletion into the transaction, fails on commit if the row doesn't exist
+    fn delete_by_pkey_in(pkey: Self::Key, tx: &Tx) -> Result {
+        tx.push(prest::Transaction::Delete {
+            name: Self::STRUCT_NAME,
+            key: pkey.into_sql_key()?,
+        });
+        OK
+    }
+
+    /// Queues removal into the transaction
+    fn remove_in(&self, tx: &Tx) -> Result {
+        Self::delete_by_pkey_in(self.get_pkey(), tx)
+    }
+
+    /// Stream of committed inserts, saves, field updates and deletions of rows of this table
+    #[cfg(host)]
+    fn subscribe() -> Changes<Self> {
+        DB.subscribe()
     }
 }
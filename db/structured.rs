@@ -0,0 +1,199 @@
+use crate::*;
+use serde_json::{Map as JsonMap, Number, Value as JsonValue};
+
+/// Converts a serializable value into a native GlueSQL value of the column type
+///
+/// `Text` holds unit enum variants as their names and other values as JSON,
+/// `Map` holds structs and maps (unit variants become `{"Variant": null}`), `List` holds sequences and tuples
+pub fn into_sql_value<T: Serialize + ?Sized>(
+    value: &T,
+    sql_type: sql::DataType,
+) -> Result<sql::Value> {
+    let json = serde_json::to_value(value)?;
+    Ok(match (sql_type, json) {
+        (_, JsonValue::Null) => sql::Value::Null,
+        (sql::DataType::Text, JsonValue::String(s)) => sql::Value::Str(s),
+        (sql::DataType::Text, json) => sql::Value::Str(json.to_string()),
+        (sql::DataType::Map, JsonValue::String(variant)) => {
+            sql::Value::Map([(variant, sql::Value::Null)].into())
+        }
+        (sql::DataType::Map, json @ JsonValue::Object(_)) => json_into_value(json),
+        (sql::DataType::List, json @ JsonValue::Array(_)) => json_into_value(json),
+        (sql_type, json) => return Err(e!("{json} can't be stored as {sql_type}")),
+    })
+}
+
+/// Restores a value stored by [`into_sql_value`]
+pub fn from_sql_value<T: serde::de::DeserializeOwned>(value: sql::Value) -> Result<T> {
+    match value {
+        // names of unit variants and plain strings, otherwise JSON of the value
+        sql::Value::Str(s) => match serde_json::from_value(JsonValue::String(s.clone())) {
+            Ok(v) => Ok(v),
+            Err(_) => Ok(serde_json::from_str(&s)?),
+        },
+        value => Ok(serde_json::from_value(value_into_json(value))?),
+    }
+}
+
+/// Filter expression of the value stored by [`into_sql_value`]
+pub fn into_sql_expr<T: Serialize + ?Sized>(
+    value: &T,
+    sql_type: sql::DataType,
+) -> Result<sql::ExprNode<'static>> {
+    let expr = super::params::value_expr(&into_sql_value(value, sql_type)?)?;
+    Ok(sql::ExprNode::Expr(std::borrow::Cow::Owned(expr)))
+}
+
+/// Names of unit variants and strings as is, other values as JSON
+pub fn readable_string<T: Serialize + ?Sized>(value: &T) -> Result<String> {
+    Ok(match serde_json::to_value(value)? {
+        JsonValue::String(s) => s,
+        json => json.to_string(),
+    })
+}
+
+pub(crate) fn json_into_value(json: JsonValue) -> sql::Value {
+    match json {
+        JsonValue::Null => sql::Value::Null,
+        JsonValue::Bool(v) => sql::Value::Bool(v),
+        JsonValue::Number(n) => match (n.as_i64(), n.as_u64(), n.as_f64()) {
+            (Some(v), _, _) => sql::Value::I64(v),
+            (_, Some(v), _) => sql::Value::U64(v),
+            (_, _, Some(v)) => sql::Value::F64(v),
+            _ => sql::Value::Null,
+        },
+        JsonValue::String(s) => sql::Value::Str(s),
+        JsonValue::Array(items) => {
+            sql::Value::List(items.into_iter().map(json_into_value).collect())
+        }
+        JsonValue::Object(entries) => sql::Value::Map(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k, json_into_value(v)))
+                .collect(),
+        ),
+    }
+}
+
+pub(crate) fn value_into_json(value: sql::Value) -> JsonValue {
+    use sql::Value::*;
+    match value {
+        Null => JsonValue::Null,
+        Bool(v) => JsonValue::Bool(v),
+        I8(v) => v.into(),
+        I16(v) => v.into(),
+        I32(v) => v.into(),
+        I64(v) => v.into(),
+        U8(v) => v.into(),
+        U16(v) => v.into(),
+        U32(v) => v.into(),
+        U64(v) => v.into(),
+        F32(v) => Number::from_f64(v as f64).map_or(JsonValue::Null, JsonValue::Number),
+        F64(v) => Number::from_f64(v).map_or(JsonValue::Null, JsonValue::Number),
+        Str(s) => JsonValue::String(s),
+        Uuid(v) => JsonValue::String(crate::Uuid::from_u128(v).to_string()),
+        List(items) => JsonValue::Array(items.into_iter().map(value_into_json).collect()),
+        Map(entries) => JsonValue::Object(
+            entries
+                .into_iter()
+                .map(|(k, v)| (k, value_into_json(v)))
+                .collect::<JsonMap<_, _>>(),
+        ),
+        other => JsonValue::String(String::from(&other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::collections::HashMap;
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    enum Priority {
+        Low,
+        High,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Address {
+        city: String,
+        zip: u32,
+    }
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Contact {
+        id: u8,
+        #[storage(text)]
+        priority: Priority,
+        #[storage(map)]
+        address: Address,
+        tags: Vec<String>,
+        location: (i32, i32),
+        scores: HashMap<String, u32>,
+    }
+
+    fn contact(id: u8, priority: Priority) -> Contact {
+        Contact {
+            id,
+            priority,
+            address: Address {
+                city: "Oslo".to_owned(),
+                zip: id as u32,
+            },
+            tags: vec!["friend".to_owned()],
+            location: (id as i32, -1),
+            scores: [("chess".to_owned(), 10)].into(),
+        }
+    }
+
+    #[test]
+    fn converts_values_into_native_ones() {
+        let text = into_sql_value(&Priority::High, sql::DataType::Text).unwrap();
+        assert!(matches!(&text, sql::Value::Str(s) if s == "High"));
+        assert_eq!(from_sql_value::<Priority>(text).unwrap(), Priority::High);
+
+        let address = contact(1, Priority::Low).address;
+        let map = into_sql_value(&address, sql::DataType::Map).unwrap();
+        assert!(matches!(&map, sql::Value::Map(entries) if entries.len() == 2));
+        assert_eq!(from_sql_value::<Address>(map).unwrap(), address);
+
+        let list = into_sql_value(&(1, 2), sql::DataType::List).unwrap();
+        assert!(matches!(&list, sql::Value::List(items) if items.len() == 2));
+        assert_eq!(from_sql_value::<(i32, i32)>(list).unwrap(), (1, 2));
+
+        assert!(into_sql_value(&1, sql::DataType::Map).is_err());
+        assert_eq!(readable_string(&Priority::Low).unwrap(), "Low");
+        assert_eq!(readable_string(&(1, 2)).unwrap(), "[1,2]");
+    }
+
+    #[tokio::test]
+    async fn filters_and_renders_structured_columns() {
+        let _db = TestDb::new([Contact::schema()]).await;
+        for (id, priority) in [(1, Priority::High), (2, Priority::Low), (3, Priority::High)] {
+            contact(id, priority).save().await.unwrap();
+        }
+
+        assert_eq!(
+            Contact::get_by_pkey(2).await.unwrap(),
+            Some(contact(2, Priority::Low))
+        );
+        let mut urgent = Contact::query()
+            .where_priority()
+            .eq(Priority::High)
+            .all()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|c| c.id)
+            .collect::<Vec<_>>();
+        urgent.sort();
+        assert_eq!(urgent, [1, 3]);
+
+        let row = Contact::schema()
+            .get_as_strings_by_id("1".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(row[1], "High");
+        assert_eq!(row[2], r#"{"city":"Oslo","zip":1}"#);
+    }
+}
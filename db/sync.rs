@@ -0,0 +1,45 @@
+use crate::*;
+
+/// Route of the host which exchanges changes of `#[storage(sync)]` tables with service workers
+pub const SYNC_ROUTE: &str = "/db/sync";
+
+/// Write of a synchronized row made either in the service worker or on the host
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncChange {
+    pub table: String,
+    pub key: sql::Key,
+    /// Values after the write, `None` if the row was removed
+    pub row: Option<Vec<sql::Value>>,
+    /// Values of the removed row, known only to the host to check its visibility and never sent to clients
+    #[serde(skip)]
+    pub removed: Option<Vec<sql::Value>>,
+    /// Moment of the write, compared by the default last-writer-wins policy
+    pub timestamp: NaiveDateTime,
+}
+
+impl SyncChange {
+    /// Written row as the struct of its table, `None` for removals
+    pub fn value<T: Storage>(&self) -> Result<Option<T>> {
+        self.row.clone().map(T::from_row).transpose()
+    }
+
+    /// Removed row as the struct of its table, `None` for other writes and for the changes of clients
+    pub fn removed<T: Storage>(&self) -> Result<Option<T>> {
+        self.removed.clone().map(T::from_row).transpose()
+    }
+}
+
+/// Local changes of the service worker made since it pulled the cursor
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncRequest {
+    pub cursor: u64,
+    pub changes: Vec<SyncChange>,
+}
+
+/// Changes of the host committed after the requested cursor along with the new cursor
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SyncResponse {
+    /// Id of the last write included into the changes
+    pub cursor: u64,
+    pub changes: Vec<SyncChange>,
+}
@@ -0,0 +1,199 @@
+use crate::*;
+
+/// Failed checks of `#[validate(...)]` attributes of a [`Storage`], responds with 422 Unprocessable Entity
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct ValidationErrors {
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: &'static str,
+    pub message: String,
+}
+
+impl ValidationErrors {
+    /// Records the message of the failed check if there is one
+    pub fn check(&mut self, field: &'static str, message: Option<String>) {
+        if let Some(message) = message {
+            self.errors.push(FieldError { field, message });
+        }
+    }
+
+    pub fn into_result(self) -> Result {
+        match self.errors.is_empty() {
+            true => OK,
+            false => Err(Error::Validation(self)),
+        }
+    }
+}
+
+impl std::fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let errors = self
+            .errors
+            .iter()
+            .map(|e| format!("{} {}", e.field, e.message))
+            .collect::<Vec<_>>();
+        write!(f, "invalid values: {}", errors.join(", "))
+    }
+}
+
+/// Checks used by the derived validations which return messages of failures
+#[doc(hidden)]
+pub mod validate {
+    use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+    use std::fmt::Display;
+
+    /// Length of strings in characters and of collections in items
+    pub trait Length {
+        fn length(&self) -> usize;
+    }
+
+    impl Length for String {
+        fn length(&self) -> usize {
+            self.chars().count()
+        }
+    }
+
+    impl<T> Length for Vec<T> {
+        fn length(&self) -> usize {
+            self.len()
+        }
+    }
+
+    impl<K, V> Length for HashMap<K, V> {
+        fn length(&self) -> usize {
+            self.len()
+        }
+    }
+
+    impl<K, V> Length for BTreeMap<K, V> {
+        fn length(&self) -> usize {
+            self.len()
+        }
+    }
+
+    impl<T> Length for HashSet<T> {
+        fn length(&self) -> usize {
+            self.len()
+        }
+    }
+
+    impl<T> Length for BTreeSet<T> {
+        fn length(&self) -> usize {
+            self.len()
+        }
+    }
+
+    pub fn len<T: Length>(value: &T, min: Option<usize>, max: Option<usize>) -> Option<String> {
+        let len = value.length();
+        match (min, max) {
+            (Some(min), _) if len < min => {
+                Some(format!("should have at least {min} characters or items"))
+            }
+            (_, Some(max)) if len > max => {
+                Some(format!("should have at most {max} characters or items"))
+            }
+            _ => None,
+        }
+    }
+
+    pub fn range<T: PartialOrd + Display>(
+        value: &T,
+        min: Option<T>,
+        max: Option<T>,
+    ) -> Option<String> {
+        match (min, max) {
+            (Some(min), _) if *value < min => Some(format!("should be at least {min}")),
+            (_, Some(max)) if *value > max => Some(format!("should be at most {max}")),
+            _ => None,
+        }
+    }
+
+    pub fn email(value: &str) -> Option<String> {
+        let valid = match value.split_once('@') {
+            Some((local, domain)) => {
+                !local.is_empty()
+                    && !domain.contains('@')
+                    && domain.contains('.')
+                    && !domain.starts_with('.')
+                    && !domain.ends_with('.')
+                    && !value.chars().any(char::is_whitespace)
+            }
+            None => false,
+        };
+        (!valid).then(|| "should be a valid email".to_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Profile {
+        id: u8,
+        #[validate(len(min = 1, max = 5))]
+        name: String,
+        #[validate(email)]
+        email: String,
+        #[validate(range(min = 0, max = 120))]
+        age: i32,
+        #[default = 5]
+        credits: i64,
+    }
+
+    fn profile(name: &str, email: &str, age: i32) -> Profile {
+        Profile {
+            id: 1,
+            name: name.to_owned(),
+            email: email.to_owned(),
+            age,
+            credits: 0,
+        }
+    }
+
+    fn invalid_fields(result: Result<impl std::fmt::Debug>) -> Vec<&'static str> {
+        match result {
+            Err(Error::Validation(errors)) => errors.errors.iter().map(|e| e.field).collect(),
+            other => panic!("validation error expected, got {other:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_values_before_writes() {
+        let _db = TestDb::new([Profile::schema()]).await;
+        let invalid = profile("too long", "not an email", -1);
+        assert_eq!(
+            invalid_fields(invalid.save().await),
+            ["name", "email", "age"]
+        );
+        assert_eq!(Profile::count().await.unwrap(), 0);
+
+        let mut valid = profile("ann", "ann@example.com", 30);
+        valid.save().await.unwrap();
+        assert_eq!(invalid_fields(valid.update_age(121).await), ["age"]);
+        assert_eq!(Profile::get_by_pkey(1).await.unwrap().unwrap().age, 30);
+
+        let response = valid
+            .update_name(String::new())
+            .await
+            .unwrap_err()
+            .into_response();
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[tokio::test]
+    async fn fills_defaults_of_omitted_columns() {
+        let _db = TestDb::new([Profile::schema()]).await;
+        assert_eq!(Profile::default_credits(), 5);
+        let insert = format!(
+            "INSERT INTO {} (id, name, email, age) VALUES (1, 'ann', 'ann@example.com', 30)",
+            Profile::STRUCT_NAME
+        );
+        DB.write_sql(&insert).await.unwrap();
+        let stored = Profile::get_by_pkey(1).await.unwrap().unwrap();
+        assert_eq!(stored.credits, 5);
+    }
+}
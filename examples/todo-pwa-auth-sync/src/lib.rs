@@ -1,5 +1,39 @@
 use prest::*;
 
+#[derive(Storage, Debug, Clone, Default, Serialize, Deserialize)]
+#[storage(sync)]
+#[serde(default)]
+pub struct Todo {
+    #[serde(default = "Uuid::now_v7")]
+    pub id: Uuid,
+    /// Assigned by the host when the todo is synced
+    #[serde(default)]
+    pub owner: Uuid,
+    pub task: String,
+    pub done: bool,
+}
+
+impl Render for Todo {
+    fn render(&self) -> Markup {
+        html! {
+            $"flex justify-between items-center" swap-this vals=(json!(self)) {
+                input type="checkbox" patch="/todos" checked[self.done] {}
+                label $"ml-4 text-lg" {(self.task)}
+                button $"ml-auto" delete="/todos" {"Delete"}
+            }
+        }
+    }
+}
+
+pub fn todos_form() -> Markup {
+    html!(
+        form put="/todos" into-end-of="#list" after-request="this.reset()" {
+            input $"border rounded-md" type="text" name="task" {}
+            button $"ml-4" type="submit" {"Add"}
+        }
+    )
+}
+
 pub fn shared_routes() -> Router {
     route("/", get(home))
 }
@@ -22,8 +56,30 @@ pub async fn into_page(content: Markup) -> Markup {
     }}
 }
 
+/// Todos in the service worker are read and written locally and synced with the host in the background
+#[cfg(sw)]
+fn local_routes() -> Router {
+    route(
+        "/todos",
+        get(|| async {
+            ok(html!(
+                (todos_form())
+                div #list $"w-full" {(Todo::get_all().await?)}
+                a $"mt-8 text-sm" href="/account" {"Account"}
+            ))
+        })
+        .put(|todo: Vals<Todo>| async move { ok(todo.save().await?.render()) })
+        .delete(|todo: Vals<Todo>| async move { ok(todo.remove().await?) })
+        .patch(|Vals(mut todo): Vals<Todo>| async move {
+            ok(todo.update_done(!todo.done).await?.render())
+        }),
+    )
+    .wrap_non_htmx(into_page)
+}
+
 #[cfg(sw)]
 #[wasm_bindgen(start)]
 pub fn main() {
-    shared_routes().handle_fetch_events()
+    DB.register_table::<Todo>();
+    shared_routes().merge(local_routes()).handle_fetch_events()
 }
@@ -1,38 +1,61 @@
 use prest::*;
-use todo_pwa_auth_sync::{into_page, shared_routes};
+use todo_pwa_auth_sync::{into_page, shared_routes, Todo};
 
 embed_build_output_as!(BuiltAssets);
 
-state!(TODO_UPDATES: SseBroadcast<Option<Todo>> = { SseBroadcast::default() });
+/// Lets signed in users sync only their own todos
+struct OwnersOnly;
 
-#[derive(Storage, Debug, Clone, Default, Serialize, Deserialize)]
-#[serde(default)]
-struct Todo {
-    #[serde(default = "Uuid::now_v7")]
-    pub id: Uuid,
-    #[serde(default)]
-    pub owner: Uuid,
-    pub task: String,
-    pub done: bool,
+#[async_trait]
+impl SyncPolicy for OwnersOnly {
+    async fn accept(&self, user: Option<Uuid>, change: &mut SyncChange) -> Result<bool> {
+        let (Some(user), sql::Key::Uuid(id)) = (user, &change.key) else {
+            return Ok(false);
+        };
+        if let Some(todo) = Todo::get_by_pkey(Uuid::from_u128(*id)).await? {
+            if todo.owner != user {
+                return Ok(false);
+            }
+        }
+        if let Some(mut todo) = change.value::<Todo>()? {
+            todo.owner = user;
+            change.row = Some(todo.into_row()?);
+        }
+        Ok(true)
+    }
+
+    async fn visible(&self, user: Option<Uuid>, change: &SyncChange) -> Result<bool> {
+        let todo = change.value::<Todo>()?.or(change.removed::<Todo>()?);
+        Ok(todo.is_some_and(|todo| Some(todo.owner) == user))
+    }
 }
 
-impl Todo {
-    fn render_for(&self, maybe_user: &Option<User>) -> Markup {
-        let owned = maybe_user
-            .as_ref()
-            .map(|u| u.id == self.owner)
-            .unwrap_or(false);
+fn render_for(todo: &Todo, maybe_user: &Option<User>) -> Markup {
+    let owned = maybe_user
+        .as_ref()
+        .map(|u| u.id == todo.owner)
+        .unwrap_or(false);
 
-        html! {
-            $"flex justify-between items-center" sse-swap=(self.id) vals=(json!(self)) {
-                input type="checkbox" patch="/todos" disabled[!owned] checked[self.done] {}
-                label $"ml-4 text-lg" {(self.task)}
-                button $"ml-auto" delete="/todos" disabled[!owned] {"Delete"}
-            }
+    html! {
+        $"flex justify-between items-center" sse-swap=(todo.id) vals=(json!(todo)) {
+            input type="checkbox" patch="/todos" disabled[!owned] checked[todo.done] {}
+            label $"ml-4 text-lg" {(todo.task)}
+            button $"ml-auto" delete="/todos" disabled[!owned] {"Delete"}
         }
     }
 }
 
+fn login_form() -> Markup {
+    html!(
+        form $"flex flex-col gap-4 items-center" method="POST" action=(LOGIN_ROUTE) {
+            input $"border rounded-md mx-4" type="text" name="username" placeholder="username" {}
+            input $"border rounded-md mx-4" type="password" name="password" placeholder="password" {}
+            input type="hidden" name="signup" value="true" {}
+            button $"ml-4" type="submit" {"Sign in / Sign up"}
+        }
+    )
+}
+
 #[init]
 async fn main() -> Result {
     shared_routes()
@@ -40,56 +63,68 @@ async fn main() -> Result {
             "/todos",
             get(|auth: Auth| async move {
                 ok(html!(
-                    @if auth.user.is_some() {
+                    @if let Some(user) = &auth.user {
                         form put="/todos" swap-none after-request="this.reset()" {
                             input $"border rounded-md" type="text" name="task" {}
                             button $"ml-4" type="submit" {"Add"}
                         }
-                    } @else {
-                        form $"flex flex-col gap-4 items-center" method="POST" action=(LOGIN_ROUTE) {
-                            input $"border rounded-md mx-4" type="text" name="username" placeholder="username" {}
-                            input $"border rounded-md mx-4" type="password" name="password" placeholder="password" {}
-                            input type="hidden" name="signup" value="true" {}
-                            button $"ml-4" type="submit" {"Sign in / Sign up"}
+                        div #"todos" $"w-full" sse="/todos/subscribe" sse-msg="inserted" swap-beforeend {
+                            @for todo in Todo::query().where_owner().eq(user.id).all().await? {
+                                (render_for(&todo, &auth.user))
+                            }
                         }
-                    }
-                    div #"todos" $"w-full" sse="/todos/subscribe" sse-msg="add" swap-beforeend {
-                        @for item in Todo::get_all().await? {(item.render_for(&auth.user))}
+                    } @else {
+                        (login_form())
                     }
                 ))
             })
-                .put(|user: User, Vals(mut todo): Vals<Todo>| async move {
-                    todo.owner = user.id;
-                    todo.save().await?;
-                    TODO_UPDATES.send("add", Some(todo)).await?;
-                    OK
-                })
-                .patch(|user: User, Vals(mut todo): Vals<Todo>| async move {
-                    if !todo.check_owner(user.id).await? {
-                        return Err(Error::Unauthorized);
-                    }
-                    todo.update_done(!todo.done).await?;
-                    TODO_UPDATES.send(todo.id.to_string(), Some(todo)).await?;
-                    OK
-                })
-                .delete(|user: User, Vals(todo): Vals<Todo>| async move {
-                    if !todo.check_owner(user.id).await? {
-                        return Err(Error::Unauthorized);
-                    }
-                    todo.remove().await?;
-                    TODO_UPDATES.send(todo.id.to_string(), None).await?;
-                    OK
-                }),
+            .put(|user: User, Vals(mut todo): Vals<Todo>| async move {
+                todo.owner = user.id;
+                todo.save().await?;
+                OK
+            })
+            .patch(|user: User, Vals(mut todo): Vals<Todo>| async move {
+                if !todo.check_owner(user.id).await? {
+                    return Err(Error::Unauthorized);
+                }
+                todo.update_done(!todo.done).await?;
+                OK
+            })
+            .delete(|user: User, Vals(todo): Vals<Todo>| async move {
+                if !todo.check_owner(user.id).await? {
+                    return Err(Error::Unauthorized);
+                }
+                todo.remove().await
+            }),
+        )
+        .route(
+            "/account",
+            get(|auth: Auth| async move {
+                match auth.user {
+                    Some(user) => html!(
+                        div {"Signed in as " (user.username.unwrap_or_default())}
+                        a $"mt-4" href=(LOGOUT_ROUTE) {"Sign out"}
+                    ),
+                    None => login_form(),
+                }
+            }),
         )
         .wrap_non_htmx(into_page)
         .route(
             "/todos/subscribe",
-            get(|auth: Auth| async {
-                TODO_UPDATES.stream_and_render(move |_event, todo| {
-                    todo.map(|t| t.render_for(&auth.user)).unwrap_or_default()
+            // changes made on the host and synced from the service workers alike
+            get(|auth: Auth| async move {
+                Todo::subscribe().stream_and_render(move |_event, change| match change {
+                    Change::Inserted(todo) | Change::Updated { new: todo, .. }
+                        if auth.user.as_ref().is_some_and(|u| u.id == todo.owner) =>
+                    {
+                        render_for(&todo, &auth.user)
+                    }
+                    _ => html!(),
                 })
             }),
         )
+        .sync_db(OwnersOnly)
         .embed(BuiltAssets)
         .run()
         .await
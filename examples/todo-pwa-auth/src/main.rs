@@ -5,13 +5,18 @@ embed_build_output_as!(BuiltAssets);
 
 #[derive(Storage, Default, Serialize, Deserialize)]
 #[serde(default)]
+#[storage(history)]
 struct Todo {
     #[serde(default = "Uuid::now_v7")]
     pub id: Uuid,
     #[serde(default)]
+    #[references(User, on_delete = cascade)]
     pub owner: Uuid,
+    #[validate(len(min = 1, max = 200))]
     pub task: String,
     pub done: bool,
+    #[created_at]
+    pub created_at: NaiveDateTime,
 }
 
 impl Render for Todo {
@@ -38,7 +43,7 @@ async fn main() -> Result {
                             input $"border rounded-md" type="text" name="task" {}
                             button $"ml-4" type="submit" {"Add"}
                         }
-                        div #list $"w-full" {(Todo::select_by_owner(&user.id).await?)}
+                        div #list $"w-full" {(user.todos().await?)}
                     } @else {
                         @if *WITH_GOOGLE_AUTH {
                             a $"p-4 border rounded-md" href=(GOOGLE_LOGIN_ROUTE) {"Login with Google"}
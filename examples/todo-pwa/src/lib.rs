@@ -1,7 +1,46 @@
 use prest::*;
 
+#[derive(Storage, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Todo {
+    #[serde(default = "Uuid::now_v7")]
+    pub id: Uuid,
+    pub task: String,
+    pub done: bool,
+}
+
+impl Render for Todo {
+    fn render(&self) -> Markup {
+        html! {
+            $"flex justify-between items-center" swap-this vals=(json!(self)) {
+                input type="checkbox" patch="/todos" checked[self.done] {}
+                label $"ml-4 text-lg" {(self.task)}
+                button $"ml-auto" delete="/todos" {"Delete"}
+            }
+        }
+    }
+}
+
 pub fn shared_routes() -> Router {
     route("/", get(home))
+        .route(
+            "/todos",
+            get(|| async {
+                ok(html!(
+                    form put="/todos" into-end-of="#list" after-request="this.reset()" {
+                        input $"border rounded-md" type="text" name="task" {}
+                        button $"ml-4" type="submit" {"Add"}
+                    }
+                    div #list $"w-full" {(Todo::get_all().await?)}
+                ))
+            })
+            .put(|todo: Vals<Todo>| async move { ok(todo.save().await?.render()) })
+            .delete(|todo: Vals<Todo>| async move { ok(todo.remove().await?) })
+            .patch(|Vals(mut todo): Vals<Todo>| async move {
+                ok(todo.update_done(!todo.done).await?.render())
+            }),
+        )
+        .wrap_non_htmx(into_page)
 }
 
 async fn home() -> Markup {
@@ -25,5 +64,6 @@ pub async fn into_page(content: Markup) -> Markup {
 #[cfg(wasm)]
 #[wasm_bindgen(start)]
 pub fn main() {
+    DB.register_table::<Todo>();
     shared_routes().handle_fetch_events()
 }
@@ -4,6 +4,7 @@ use crate::*;
 struct TableDescription {
     name: String,
     fields: Vec<FieldSchema>,
+    history: bool,
 }
 
 #[derive(Deserialize)]
@@ -12,6 +13,12 @@ struct TableQueryParams {
     limit: Option<usize>,
 }
 
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    limit: Option<usize>,
+}
+
 pub(crate) async fn schema() -> impl IntoResponse {
     let descriptions = DB
         .custom_schemas()
@@ -19,6 +26,7 @@ pub(crate) async fn schema() -> impl IntoResponse {
         .map(|s| TableDescription {
             name: s.name().to_owned(),
             fields: s.fields().to_vec(),
+            history: s.history(),
         })
         .collect::<Vec<_>>();
     Json(descriptions)
@@ -30,24 +38,90 @@ struct TableData {
     fields: Vec<FieldSchema>,
     rows: Vec<Vec<String>>,
     has_more: bool,
-    total_pages: Option<usize>,
+    total_rows: u64,
+    total_pages: usize,
 }
 
 // table_data_json function removed - now handled within table_routes()
 
 pub(crate) async fn db_page() -> Markup {
     html! {
+        @if let Some(report) = DB.recovery_report() {
+            $"w-full text-xs md:text-sm font-mono text-yellow-400" {
+                "Recovered from unfinished write "(report.tx_id)" at "(report.recovered_at)": rolled back "
+                (report.rows)" rows and "(report.index_entries)" index entries"
+            }
+        }
         a _="on load call loadSchema() then remove me" {}
+        a get="/admin/db/migrations" trigger="load" swap-this {}
+        a get="/admin/db/backups" trigger="load" swap-this {}
         div #db-container {
             // React component will be rendered here
         }
     }
 }
 
+pub(crate) async fn migrations() -> Result<Markup> {
+    let pending = DB
+        .migration_reports()
+        .await?
+        .into_iter()
+        .filter(|r| r.pending())
+        .collect::<Vec<_>>();
+
+    Ok(html! {
+        @if !pending.is_empty() {
+            $"w-full text-xs md:text-sm font-mono" {
+                $"font-bold text-lg" {"Pending migrations"}
+                @for report in pending {
+                    @let version = report.stored_version.map_or("-".to_owned(), |v| v.to_string());
+                    $"w-full" {b{(report.table)}" (schema v"(version)")"}
+                    @for change in report.changes {
+                        p{"- "(change)}
+                    }
+                    @for blocking in report.blocking {
+                        p $"text-red-400" {"! "(blocking)}
+                    }
+                }
+            }
+        }
+    })
+}
+
 pub(crate) fn table_routes() -> Router {
     let mut router = Router::new();
     for table in DB.custom_schemas() {
         let table_name = table.name().to_owned();
+        if table.fields().iter().any(|f| f.searchable) {
+            let table_name = table_name.clone();
+            router = router.route(
+                &format!("{}/search", table.relative_path()),
+                get(move |Vals(params): Vals<SearchParams>| async move {
+                    let table = DB
+                        .custom_schemas()
+                        .into_iter()
+                        .find(|t| t.name() == table_name)
+                        .ok_or_else(|| e!("Table not found: {}", table_name))?;
+                    let limit = params.limit.unwrap_or(20).max(1);
+                    ok(Json(table.search_as_strings(&params.q, limit).await?))
+                }),
+            );
+        }
+        if table.history() {
+            let table_name = table_name.clone();
+            router = router.route(
+                &format!("{}/history", table.relative_path()),
+                get(move |Vals(params): Vals<TableQueryParams>| async move {
+                    let table = DB
+                        .custom_schemas()
+                        .into_iter()
+                        .find(|t| t.name() == table_name)
+                        .ok_or_else(|| e!("Table not found: {}", table_name))?;
+                    let limit = params.limit.unwrap_or(100).max(1);
+                    ok(Json(table.history_as_strings(limit).await?))
+                }),
+            );
+        }
         router = router.route(
             table.relative_path(),
             get({
@@ -60,16 +134,18 @@ pub(crate) fn table_routes() -> Router {
                         .ok_or_else(|| e!("Table not found: {}", table_name))?;
 
                     let offset = params.offset.unwrap_or(0);
-                    let limit = params.limit.unwrap_or(20);
+                    let limit = params.limit.unwrap_or(20).max(1);
 
                     let (rows, has_more) = table.get_as_strings_paginated(offset, limit).await?;
+                    let total_rows = table.count().await?;
 
                     ok(Json(TableData {
                         name: table.name().to_owned(),
                         fields: table.fields().to_vec(),
                         rows,
                         has_more,
-                        total_pages: None, // We don't calculate total pages for performance
+                        total_rows,
+                        total_pages: (total_rows as usize).div_ceil(limit),
                     }))
                 }
             })
@@ -112,3 +188,76 @@ pub(crate) fn table_routes() -> Router {
     }
     router
 }
+
+pub(crate) async fn backups() -> Result<Markup> {
+    let stored = Db::stored_backups().await?;
+    ok(html! {
+        $"w-full text-xs md:text-sm font-mono" #db-backups {
+            $"font-bold text-lg" {"Backups"}
+            $"flex gap-4 items-center" {
+                a href="/admin/db/backup" download boost="false" $"underline" {"Download current"}
+                button post="/admin/db/backups" target="#db-backups" swap-full $"underline" {"Save into backups directory"}
+                label $"underline cursor-pointer" {
+                    "Restore from file"
+                    input type="file" $"hidden"
+                        _="on change fetch /admin/db/restore with method:'POST', body:my.files[0]
+                           then put it into #db-restore-result" {}
+                }
+            }
+            div #db-restore-result {}
+            @for backup in stored {
+                @let size = format!("{:.1} MB", backup.size as f64 / 1_000_000.0);
+                $"flex gap-4 items-center" {
+                    a href={"/admin/db/backups/"(backup.name)} download boost="false" $"underline" {(backup.name)}
+                    span {(size)}
+                    button post={"/admin/db/backups/"(backup.name)"/restore"} target="#db-restore-result"
+                        hx-confirm={"Replace current data with "(backup.name)"?"} $"underline" {"restore"}
+                }
+            }
+        }
+    })
+}
+
+pub(crate) async fn save_backup() -> Result<Markup> {
+    DB.backup_to_file().await?;
+    backups().await
+}
+
+fn backup_download(name: &str, bytes: Vec<u8>) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_owned()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("attachment; filename=\"{name}\""),
+            ),
+        ],
+        bytes,
+    )
+        .into_response()
+}
+
+pub(crate) async fn download_backup() -> Result<Response> {
+    let bytes = DB.export_bytes().await?;
+    let name = format!(
+        "{}-{}.prestdb",
+        APP_CONFIG.name,
+        Utc::now().format("%Y%m%d-%H%M%S")
+    );
+    Ok(backup_download(&name, bytes))
+}
+
+pub(crate) async fn download_stored_backup(Path(name): Path<String>) -> Result<Response> {
+    let bytes = Db::read_stored(&name).await?;
+    Ok(backup_download(&name, bytes))
+}
+
+pub(crate) async fn restore_backup(body: axum::body::Bytes) -> Result<String> {
+    let restored = DB.import(&body[..]).await?;
+    Ok(format!("Restored {restored} rows"))
+}
+
+pub(crate) async fn restore_stored_backup(Path(name): Path<String>) -> Result<String> {
+    let restored = DB.import_stored(&name).await?;
+    Ok(format!("Restored {restored} rows from {name}"))
+}
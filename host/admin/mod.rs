@@ -4,6 +4,7 @@ mod analytics;
 mod db;
 mod logs;
 mod monitoring;
+mod queries;
 mod remote;
 mod schedule;
 
@@ -11,6 +12,7 @@ const ADMIN_SVG: PreEscaped<&str> = PreEscaped(include_str!("assets/admin.svg"))
 const DB_SVG: PreEscaped<&str> = PreEscaped(include_str!("assets/db.svg"));
 const LOGS_SVG: PreEscaped<&str> = PreEscaped(include_str!("assets/logs.svg"));
 const ANALYTICS_SVG: PreEscaped<&str> = PreEscaped(include_str!("assets/analytics.svg"));
+const QUERIES_SVG: PreEscaped<&str> = PreEscaped(include_str!("assets/queries.svg"));
 const LOADER_SVG: PreEscaped<&str> = PreEscaped(include_str!("assets/loader.svg"));
 
 pub(crate) async fn routes() -> Router {
@@ -30,11 +32,18 @@ pub(crate) async fn routes() -> Router {
     .route("/traces", get(logs::traces_explorer))
     .route("/schedule", get(schedule::full))
     .route("/analytics", get(analytics::full))
+    .route("/queries", get(queries::full))
     .route("/db", get(db::db_page))
+    .route("/db/migrations", get(db::migrations))
+    .route("/db/backups", get(db::backups).post(db::save_backup))
     .nest("/remote", remote::routes())
     .wrap_non_htmx(into_page)
     .nest("/db", db::table_routes())
     .route("/db/schema", get(db::schema))
+    .route("/db/backup", get(db::download_backup))
+    .route("/db/backups/:name", get(db::download_stored_backup))
+    .route("/db/backups/:name/restore", post(db::restore_stored_backup))
+    .route("/db/restore", post(db::restore_backup))
     .route("/traces/:period", get(logs::traces))
     .route("/monitoring/data", get(monitoring::data))
 }
@@ -48,6 +57,7 @@ async fn into_page(content: Markup) -> impl IntoResponse {
                 button get="/admin" {$"w-6" {(ADMIN_SVG)}}
                 button get="/admin/analytics" {$"w-6" {(ANALYTICS_SVG)}}
                 button get="/admin/traces" {$"w-6" {(LOGS_SVG)}}
+                button get="/admin/queries" {$"w-6" {(QUERIES_SVG)}}
                 @if DB.custom_schemas().len() > 0 {
                     button get="/admin/db" {$"w-6" {(DB_SVG)}}
                 }
@@ -69,9 +69,25 @@ pub(crate) async fn container() -> Result<Markup> {
         $"h-[300px]" { canvas #"stats-chart" {} }
 
         (disk_stats().await?)
+        (db_reads_stats())
     ))
 }
 
+fn db_reads_stats() -> Markup {
+    let stats = DB.read_pool_stats();
+    let avg_wait = format!("{:.2} ms", stats.avg_wait.as_secs_f64() * 1000.0);
+    let max_wait = format!("{:.2} ms", stats.max_wait.as_secs_f64() * 1000.0);
+    html!(
+        $"w-full items-center text-[0.6rem] md:text-sm lg:text-base mt-2" {
+            $"font-bold" {"DB reads: "}
+            div $"text-xs" {
+                (stats.reads)" reads by "(stats.readers)" readers, queued: "(stats.queued)" (max "(stats.max_queued)"), "
+                "waited in queue: "(avg_wait)" avg, "(max_wait)" max, found queue full "(stats.backpressured)" times"
+            }
+        }
+    )
+}
+
 async fn disk_stats() -> Result<Markup> {
     let used_disk = SYSTEM_INFO.used_disk.read().await;
     let total_disk = SYSTEM_INFO.total_disk;
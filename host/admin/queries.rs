@@ -0,0 +1,54 @@
+use crate::*;
+
+/// Latest slow queries shown in the log
+const SLOW_QUERIES_SHOWN: usize = 100;
+
+pub(crate) async fn full() -> Result<Markup> {
+    let ms = |duration: std::time::Duration| format!("{:.1}ms", duration.as_secs_f64() * 1000.0);
+    let stats = DB.query_stats();
+
+    let mut slow_queries = SlowQuery::get_all().await?;
+    slow_queries.sort_by(|a, b| b.recorded_at.cmp(&a.recorded_at));
+    slow_queries.truncate(SLOW_QUERIES_SHOWN);
+
+    Ok(html! {
+        $"font-bold text-lg" {"Queries by table"}
+        $"hidden md:block italic text-xs" {"since the start, times include waiting in the queue"}
+        table $"w-full text-xs md:text-sm font-mono" {
+            tr $"text-left" {
+                th {"table"} th {"reads"} th {"writes"} th {"avg"} th {"max"} th {"slow"} th {"full scans"} th {"scanned"} th {"returned"}
+            }
+            @for table in stats {
+                tr {
+                    td {(table.table)}
+                    td {(table.reads)}
+                    td {(table.writes)}
+                    td {(ms(table.avg_time()))}
+                    td {(ms(table.max_time))}
+                    td {(table.slow)}
+                    td {(table.full_scans)}
+                    td {(table.scanned)}
+                    td {(table.returned)}
+                }
+            }
+        }
+        $"font-bold text-lg" {"Slow queries"}
+        @if slow_queries.is_empty() {
+            $"italic text-xs" {"no queries took longer than DB_SLOW_QUERY_MS in the last 7 days"}
+        }
+        table $"w-full text-xs md:text-sm font-mono" {
+            @for query in slow_queries {
+                @let failed = if query.failed { " (failed)" } else { "" };
+                tr {
+                    td $"w-[15%]" {(query.recorded_at.format("%m-%d %H:%M:%S"))}
+                    td $"w-[15%]" {(query.table_name)}
+                    td $"w-[10%]" {(query.kind)(failed)}
+                    td $"w-[10%]" {(query.plan)}
+                    td $"w-[10%]" {(format!("{:.1}+{:.1}ms", query.queued_ms, query.exec_ms))}
+                    td $"w-[10%]" {(query.scanned)"/"(query.returned)}
+                    td $"w-[30%] break-all" {(query.statement)}
+                }
+            }
+        }
+    })
+}
@@ -69,7 +69,10 @@ fn record_response_metrics(
         true => trace!(target: "response", latency = %short_latency, code = %status.as_u16()),
         false => {
             debug!(target: "response", latency = %short_latency, code = %status.as_u16());
-            RT.spawn(RouteStat::record(req_method, req_path, latency));
+            // followers don't write their own stats
+            if !DB.is_replica() {
+                RT.spawn(RouteStat::record(req_method, req_path, latency));
+            }
         }
     }
 }
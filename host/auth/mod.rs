@@ -25,6 +25,7 @@ pub type OAuthCode = String;
 pub struct User {
     pub id: Uuid,
     pub permissions: Vec<String>,
+    #[storage(text)]
     pub group: UserGroup,
     #[unique]
     pub username: Option<String>,
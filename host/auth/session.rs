@@ -9,17 +9,31 @@ use tower_sessions::{
 pub struct SessionRow {
     pub id: i128,
     pub record: Vec<u8>,
+    /// Removed by the DB sweeper once the session is over
+    #[expires_at]
+    #[default = Utc::now().naive_utc() + chrono::Duration::days(30)]
+    pub expires_at: NaiveDateTime,
 }
 
 #[async_trait]
 impl SessionStore for Prest {
     async fn save(&self, record: &Record) -> SessionResult<()> {
         let id = record.id.0;
+        let expires_at = chrono::DateTime::from_timestamp(record.expiry_date.unix_timestamp(), 0)
+            .map(|expiry| expiry.naive_utc())
+            .unwrap_or_else(|| Utc::now().naive_utc());
         let record = match bitcode::serialize(record) {
             Ok(s) => s,
             Err(e) => return Err(SessionError::Encode(format!("{e}"))),
         };
-        match (SessionRow { id, record }).save().await {
+        match (SessionRow {
+            id,
+            record,
+            expires_at,
+        })
+        .save()
+        .await
+        {
             Ok(_) => Ok(()),
             Err(e) => Err(SessionError::Backend(format!("Session save error: {e}"))),
         }
@@ -38,6 +52,10 @@ impl SessionStore for Prest {
         let Some(session_row) = search else {
             return Ok(None);
         };
+        // might not be swept yet
+        if session_row.expires_at <= Utc::now().naive_utc() {
+            return Ok(None);
+        }
         match bitcode::deserialize(&session_row.record) {
             Ok(record) => Ok(Some(record)),
             Err(e) => Err(SessionError::Decode(format!("Session load error: {e}"))),
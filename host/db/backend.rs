@@ -0,0 +1,638 @@
+use {
+    super::{
+        expiry::{sweep_interval, ExpiryIndex},
+        internal_schemas, QueryInfo, QueryProfiler, QueryTimer, ReplicationConfig,
+    },
+    crate::*,
+    gluesql_core::store::{DataRow, GStore, GStoreMut},
+    gluesql_shared_memory_storage::SharedMemoryStorage,
+    std::{
+        sync::{
+            atomic::Ordering,
+            mpsc::{sync_channel, RecvTimeoutError},
+            OnceLock,
+        },
+        time::Instant,
+    },
+};
+
+#[cfg(feature = "sqlite")]
+use super::sqlite::{SqliteStorage, SQLITE_FILE_NAME};
+
+/// Storage of the [`DB`], selected with `#[init(db = "memory")]` or the `DB_BACKEND` env variable which takes precedence
+///
+/// All backends run the same derived queries, batches, change feeds, unique values, relations and expiration.
+/// The rest is supported only by the default sled backend and unavailable on others:
+/// - secondary indexes, so that range queries scan the tables instead
+/// - `#[storage(history)]` and `#[storage(soft_delete)]` tables which fail to register
+/// - full-text search, service worker sync changes, exports and imports whose queries return errors
+/// - replication which is ignored with a warning, and scheduled backups which aren't made
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DbBackend {
+    /// Persistent multi-version storage in the app's data directory
+    Sled,
+    /// GlueSQL shared memory storage which starts empty every time, handy for tests and demos
+    Memory,
+    /// `db.sqlite` file in the app's data directory, requires the `sqlite` feature
+    #[cfg(feature = "sqlite")]
+    Sqlite,
+}
+
+static CONFIGURED: OnceLock<DbBackend> = OnceLock::new();
+
+impl DbBackend {
+    /// Sets the backend used unless `DB_BACKEND` env variable is set, called by the `init` macro
+    pub(crate) fn configure(self) {
+        if CONFIGURED.set(self).is_err() {
+            warn!(target: "db", "DB backend is already configured");
+        }
+    }
+
+    /// Backend named by the `DB_BACKEND` env variable or the configured one, sled by default
+    pub(crate) fn selected() -> Result<DbBackend> {
+        match env_var("DB_BACKEND") {
+            Ok(name) => name.parse().map_err(|e| e!("invalid DB_BACKEND: {e}")),
+            Err(_) => Ok(CONFIGURED.get().copied().unwrap_or(DbBackend::Sled)),
+        }
+    }
+}
+
+impl std::str::FromStr for DbBackend {
+    type Err = Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "sled" => Ok(DbBackend::Sled),
+            "memory" => Ok(DbBackend::Memory),
+            #[cfg(feature = "sqlite")]
+            "sqlite" => Ok(DbBackend::Sqlite),
+            other => Err(e!("unknown DB backend {other}")),
+        }
+    }
+}
+
+/// Storage which runs the queries one by one on its own thread
+///
+/// Implemented by GlueSQL storages wrapped into [`GlueDb`], while the sled backend runs
+/// its own readers concurrently with the writer
+#[async_trait(?Send)]
+pub(crate) trait StorageBackend: 'static {
+    async fn read(&mut self, query: Query) -> Result<Payload>;
+    /// Applies all parts of the write or none of them and returns changes of the rows
+    async fn write(&mut self, tx: Transaction) -> Result<(Payload, Vec<RowChange>)>;
+    /// Keys of the rows which expired by now, see `#[expires_at]` and `#[storage(ttl = "..")]`
+    async fn expired_rows(&mut self) -> Result<Vec<(&'static str, sql::Key)>>;
+}
+
+#[async_trait(?Send)]
+impl<S: GStore + GStoreMut + 'static> StorageBackend for GlueDb<S> {
+    async fn read(&mut self, query: Query) -> Result<Payload> {
+        self.sync_tables().await?;
+        GlueDb::read(self, query).await
+    }
+
+    async fn write(&mut self, tx: Transaction) -> Result<(Payload, Vec<RowChange>)> {
+        self.sync_tables().await?;
+        let (payload, written) = GlueDb::write(self, tx).await?;
+        let changes = written
+            .into_iter()
+            .map(|row| RowChange {
+                table: row.table,
+                old: row.old,
+                new: row.new,
+            })
+            .collect();
+        Ok((payload, changes))
+    }
+
+    async fn expired_rows(&mut self) -> Result<Vec<(&'static str, sql::Key)>> {
+        self.sync_tables().await?;
+        let now = Utc::now().naive_utc();
+        let mut expired = vec![];
+        for schema in self.schemas.all() {
+            let Some(expiry) = ExpiryIndex::of(schema) else {
+                continue;
+            };
+            for (key, row) in self.scan(schema.name()).await? {
+                if expiry
+                    .expiration(&DataRow::Vec(row))
+                    .is_some_and(|at| at <= now)
+                {
+                    expired.push((schema.name(), key));
+                }
+            }
+        }
+        Ok(expired)
+    }
+}
+
+enum BackendMessage {
+    Read(DbReadMessage),
+    Write(DbWriteMessage),
+}
+
+impl Db {
+    /// Opens the DB on the backend, throwaway unless it's persistent
+    pub(crate) fn open(backend: DbBackend, persistent: bool) -> Db {
+        if backend != DbBackend::Sled {
+            let replication = ReplicationConfig::from_env();
+            if replication.token.is_some() || replication.primary.is_some() {
+                warn!(target: "db", "replication is supported only by the sled backend, {backend:?} DB isn't replicated");
+            }
+        }
+        match backend {
+            DbBackend::Sled if persistent => Self::persistent(),
+            DbBackend::Sled => Self::temporary(),
+            DbBackend::Memory => Self::start_backend(|schemas| {
+                Ok(GlueDb::new(
+                    SharedMemoryStorage::new(),
+                    schemas,
+                    "memory backend",
+                    true,
+                ))
+            }),
+            #[cfg(feature = "sqlite")]
+            DbBackend::Sqlite => Self::start_backend(move |schemas| {
+                if persistent {
+                    std::fs::create_dir_all(&APP_CONFIG.data_dir).somehow()?;
+                }
+                let path = persistent.then(|| APP_CONFIG.data_dir.join(SQLITE_FILE_NAME));
+                let storage = SqliteStorage::open(path.as_deref())?;
+                Ok(GlueDb::new(storage, schemas, "sqlite backend", !persistent))
+            }),
+        }
+    }
+
+    /// Runs the backend opened by the closure on its own thread
+    fn start_backend<B: StorageBackend>(
+        open: impl FnOnce(Schemas) -> Result<B> + Send + 'static,
+    ) -> Db {
+        let schemas = internal_schemas();
+        let (write_sender, writes) = sync_channel::<DbWriteMessage>(10);
+        let (read_sender, reads) = sync_channel::<DbReadMessage>(100);
+        // the backend runs queries one by one so reads and writes are merged into a single queue
+        let (queue, messages) = std::sync::mpsc::channel::<BackendMessage>();
+
+        let reads_queue = queue.clone();
+        let read_forwarder = std::thread::Builder::new()
+            .name("DB reads".to_string())
+            .spawn(move || {
+                for message in reads {
+                    if reads_queue.send(BackendMessage::Read(message)).is_err() {
+                        break;
+                    }
+                }
+                OK
+            })
+            .expect("DB reads thread should spawn");
+        let write_forwarder = std::thread::Builder::new()
+            .name("DB writes".to_string())
+            .spawn(move || {
+                for message in writes {
+                    if queue.send(BackendMessage::Write(message)).is_err() {
+                        break;
+                    }
+                }
+                OK
+            })
+            .expect("DB writes thread should spawn");
+
+        let (mut changes, changes_receiver) = async_broadcast::broadcast(1000);
+        // slow subscribers skip the oldest changes instead of blocking the backend
+        changes.set_overflow(true);
+        let (committed, committed_receiver) = tokio::sync::watch::channel(0);
+        let read_metrics = Arc::new(ReadMetrics::default());
+        read_metrics.readers.store(1, Ordering::Relaxed);
+
+        let profiler = Arc::new(QueryProfiler::default());
+
+        let backend_schemas = schemas.clone();
+        let backend_metrics = read_metrics.clone();
+        let backend_profiler = profiler.clone();
+        let backend_thread = std::thread::Builder::new()
+            .name("DB backend".to_string())
+            .spawn(move || {
+                let rt = tokio::runtime::Builder::new_current_thread()
+                    .build()
+                    .unwrap();
+                let mut backend = open(backend_schemas).expect("DB backend should open");
+                let mut tx_id = 0;
+                let sweep_interval = sweep_interval();
+                let mut next_sweep = Instant::now() + sweep_interval;
+                loop {
+                    if Instant::now() >= next_sweep {
+                        let swept = rt.block_on(async {
+                            let rows = backend.expired_rows().await?;
+                            if rows.is_empty() {
+                                return Ok(vec![]);
+                            }
+                            Ok::<_, Error>(backend.write(Transaction::Expire { rows }).await?.1)
+                        });
+                        match swept {
+                            Ok(rows) if rows.is_empty() => {}
+                            Ok(rows) => {
+                                tx_id += 1;
+                                committed.send_replace(tx_id);
+                                debug!(target: "db", "expired {} rows", rows.len());
+                                for change in rows {
+                                    let _ = changes.try_broadcast(Arc::new(change));
+                                }
+                            }
+                            Err(e) => warn!(target: "db", "failed to expire rows: {e}"),
+                        }
+                        next_sweep = Instant::now() + sweep_interval;
+                    }
+                    let timeout = next_sweep.saturating_duration_since(Instant::now());
+                    let message = match messages.recv_timeout(timeout) {
+                        Ok(message) => message,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    let (result, returner) = match message {
+                        BackendMessage::Read((query, returner, queued_at)) => {
+                            backend_metrics.dequeue(queued_at);
+                            let timer = QueryTimer::start(QueryInfo::of_read(&query), queued_at);
+                            let result = rt.block_on(backend.read(query));
+                            timer.finish(&backend_profiler, &result);
+                            (result, returner)
+                        }
+                        BackendMessage::Write((tx, returner, _, _, queued_at)) => {
+                            let timer = QueryTimer::start(QueryInfo::of_write(&tx), queued_at);
+                            let result = rt.block_on(backend.write(tx)).map(|(payload, rows)| {
+                                tx_id += 1;
+                                committed.send_replace(tx_id);
+                                for change in rows {
+                                    // fails only without active subscribers
+                                    let _ = changes.try_broadcast(Arc::new(change));
+                                }
+                                payload
+                            });
+                            timer.finish(&backend_profiler, &result);
+                            (result, returner)
+                        }
+                    };
+                    if let Err(e) = returner.send(result) {
+                        warn!("failed to return DB result: {e:?}");
+                    }
+                }
+                OK
+            })
+            .expect("DB backend thread should spawn");
+
+        Db {
+            read: DbSender::new(read_sender),
+            write: DbSender::new(write_sender),
+            schemas,
+            handles: std::sync::Mutex::new(vec![backend_thread, read_forwarder, write_forwarder]),
+            read_metrics,
+            profiler,
+            changes: changes_receiver.deactivate(),
+            committed: committed_receiver,
+            replication: ReplicationConfig::default(),
+            tables: Default::default(),
+            recovery: None,
+            temp_path: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod conformance {
+    use crate::*;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Note {
+        id: Uuid,
+        text: String,
+        done: bool,
+    }
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Author {
+        id: u32,
+        #[unique]
+        email: String,
+        #[index]
+        age: u8,
+    }
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Post {
+        #[pkey]
+        author: u32,
+        #[pkey]
+        seq: u32,
+        title: String,
+    }
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Comment {
+        id: u32,
+        #[references(Author, on_delete = cascade)]
+        author: u32,
+    }
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Review {
+        id: u32,
+        #[references(Author, on_delete = restrict)]
+        author: u32,
+    }
+
+    fn author(id: u32, age: u8) -> Author {
+        Author {
+            id,
+            email: format!("{id}@example.com"),
+            age,
+        }
+    }
+
+    fn note(text: &str) -> Note {
+        Note {
+            id: Uuid::now_v7(),
+            text: text.to_owned(),
+            done: false,
+        }
+    }
+
+    /// Runs the check on a fresh DB of every backend
+    async fn on_every_backend<F: Future<Output = ()>>(check: impl Fn(DbBackend) -> F) {
+        let mut backends = vec![DbBackend::Sled, DbBackend::Memory];
+        #[cfg(feature = "sqlite")]
+        backends.push(DbBackend::Sqlite);
+
+        for backend in backends {
+            let schemas = [
+                Note::schema(),
+                Author::schema(),
+                Post::schema(),
+                Comment::schema(),
+                Review::schema(),
+            ];
+            let _db = TestDb::with_backend(backend, schemas).await;
+            check(backend).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn inserts_and_gets_rows() {
+        on_every_backend(|backend| async move {
+            let note = note("insert");
+            note.insert_self().await.unwrap();
+            let found = Note::get_by_pkey(note.id).await.unwrap();
+            assert_eq!(found, Some(note.clone()), "{backend:?}");
+            assert!(
+                note.insert_self().await.is_err(),
+                "{backend:?} allowed a duplicate"
+            );
+            assert_eq!(
+                Note::get_by_pkey(Uuid::now_v7()).await.unwrap(),
+                None,
+                "{backend:?}"
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn saves_and_updates_rows() {
+        on_every_backend(|backend| async move {
+            let mut note = note("save");
+            note.save().await.unwrap();
+            note.update_text("updated".to_owned()).await.unwrap();
+            note.done = true;
+            note.save().await.unwrap();
+            let found = Note::get_by_pkey(note.id).await.unwrap().unwrap();
+            assert_eq!(found.text, "updated", "{backend:?}");
+            assert!(found.done, "{backend:?}");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn removes_rows() {
+        on_every_backend(|backend| async move {
+            let note = note("remove");
+            note.save().await.unwrap();
+            note.remove().await.unwrap();
+            assert_eq!(
+                Note::get_by_pkey(note.id).await.unwrap(),
+                None,
+                "{backend:?}"
+            );
+            assert_eq!(Note::count().await.unwrap(), 0, "{backend:?}");
+            assert!(
+                note.remove().await.is_err(),
+                "{backend:?} removed a missing row"
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn lists_and_counts_rows() {
+        on_every_backend(|backend| async move {
+            for text in ["a", "b", "c"] {
+                note(text).save().await.unwrap();
+            }
+            assert_eq!(Note::get_all().await.unwrap().len(), 3, "{backend:?}");
+            assert_eq!(Note::count().await.unwrap(), 3, "{backend:?}");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn rolls_back_failed_batches() {
+        on_every_backend(|backend| async move {
+            let existing = note("existing");
+            existing.save().await.unwrap();
+            let added = note("added");
+            let result = DB
+                .transaction(|tx| {
+                    let (added, existing) = (added.clone(), existing.clone());
+                    async move {
+                        added.insert_in(&tx)?;
+                        existing.insert_in(&tx)?;
+                        OK
+                    }
+                })
+                .await;
+            assert!(result.is_err(), "{backend:?} applied a duplicate insert");
+            assert_eq!(
+                Note::get_by_pkey(added.id).await.unwrap(),
+                None,
+                "{backend:?}"
+            );
+            assert_eq!(Note::count().await.unwrap(), 1, "{backend:?}");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn streams_committed_changes() {
+        on_every_backend(|backend| async move {
+            let mut changes = Note::subscribe();
+            let mut note = note("subscribe");
+            note.save().await.unwrap();
+            note.update_done(true).await.unwrap();
+            note.remove().await.unwrap();
+            assert!(
+                matches!(changes.next().await, Some(Change::Inserted(_))),
+                "{backend:?}"
+            );
+            assert!(
+                matches!(changes.next().await, Some(Change::Updated { new, .. }) if new.done),
+                "{backend:?}"
+            );
+            assert!(
+                matches!(changes.next().await, Some(Change::Deleted(_))),
+                "{backend:?}"
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn filters_typed_queries() {
+        on_every_backend(|backend| async move {
+            for (id, age) in [(1, 20), (2, 30), (3, 40)] {
+                author(id, age).save().await.unwrap();
+            }
+            let adults = Author::query()
+                .where_age()
+                .gte(30)
+                .order_by_desc(Author::COLS.age)
+                .all()
+                .await
+                .unwrap();
+            let ids = adults.iter().map(|a| a.id).collect::<Vec<_>>();
+            assert_eq!(ids, [3, 2], "{backend:?}");
+            let count = Author::query().where_age().lt(40).count().await.unwrap();
+            assert_eq!(count, 2, "{backend:?}");
+            let first = Author::query()
+                .order_by(Author::COLS.age)
+                .first()
+                .await
+                .unwrap();
+            assert_eq!(first.map(|a| a.id), Some(1), "{backend:?}");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn selects_key_and_index_ranges() {
+        on_every_backend(|backend| async move {
+            for (id, age) in [(1, 20), (2, 30), (3, 40)] {
+                author(id, age).save().await.unwrap();
+            }
+            let ids = |authors: Vec<Author>| {
+                let mut ids = authors.iter().map(|a| a.id).collect::<Vec<_>>();
+                ids.sort();
+                ids
+            };
+            // the upper bound of key ranges is exclusive while index ranges include both
+            let in_keys = Author::get_in_id_range(1, 3).await.unwrap();
+            assert_eq!(ids(in_keys), [1, 2], "{backend:?}");
+            let in_index = Author::find_in_range_age(&30, &40).await.unwrap();
+            assert_eq!(ids(in_index), [2, 3], "{backend:?}");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn handles_composite_keys() {
+        on_every_backend(|backend| async move {
+            for (author, seq) in [(1, 1), (1, 2), (2, 1)] {
+                let title = format!("{author}/{seq}");
+                Post { author, seq, title }.save().await.unwrap();
+            }
+            let post = Post::get_by_pkey((1, 2)).await.unwrap().unwrap();
+            assert_eq!(post.title, "1/2", "{backend:?}");
+            let first_author = Post::get_in_author_seq_range((1, 0), (2, 0)).await.unwrap();
+            let seqs = first_author
+                .iter()
+                .map(|p| (p.author, p.seq))
+                .collect::<Vec<_>>();
+            assert_eq!(seqs, [(1, 1), (1, 2)], "{backend:?}");
+            post.remove().await.unwrap();
+            assert_eq!(Post::count().await.unwrap(), 2, "{backend:?}");
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn rejects_unique_violations() {
+        on_every_backend(|backend| async move {
+            let first = author(1, 20);
+            first.save().await.unwrap();
+            let taken = Author {
+                id: 2,
+                ..first.clone()
+            };
+            assert!(
+                taken.save().await.is_err(),
+                "{backend:?} allowed a taken email"
+            );
+            assert_eq!(Author::get_by_pkey(2).await.unwrap(), None, "{backend:?}");
+            // rows keep their own values
+            Author { age: 21, ..first }.save().await.unwrap();
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn enforces_references() {
+        on_every_backend(|backend| async move {
+            let missing = Comment { id: 1, author: 1 };
+            assert!(
+                missing.save().await.is_err(),
+                "{backend:?} referenced a missing row"
+            );
+
+            author(1, 20).save().await.unwrap();
+            author(2, 30).save().await.unwrap();
+            Comment { id: 1, author: 1 }.save().await.unwrap();
+            Review { id: 1, author: 2 }.save().await.unwrap();
+
+            author(1, 20).remove().await.unwrap();
+            assert_eq!(
+                Comment::get_by_pkey(1).await.unwrap(),
+                None,
+                "{backend:?} kept a cascaded row"
+            );
+            assert!(
+                author(2, 30).remove().await.is_err(),
+                "{backend:?} ignored a restriction"
+            );
+            assert!(
+                Author::get_by_pkey(2).await.unwrap().is_some(),
+                "{backend:?}"
+            );
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn binds_sql_params() {
+        on_every_backend(|backend| async move {
+            for (id, age) in [(1, 20), (2, 30)] {
+                author(id, age).save().await.unwrap();
+            }
+            let sql = format!(
+                "SELECT * FROM {} WHERE age > $1 AND email <> $2",
+                Author::STRUCT_NAME
+            );
+            let params = [sql::Value::U8(25), sql::Value::Str("'; DROP".to_owned())];
+            let found = DB
+                .read_sql_rows_params::<Author>(&sql, &params)
+                .await
+                .unwrap();
+            assert_eq!(found, [author(2, 30)], "{backend:?}");
+            assert!(
+                DB.read_sql_params(&sql, &params[..1]).await.is_err(),
+                "{backend:?}"
+            );
+        })
+        .await;
+    }
+}
@@ -0,0 +1,524 @@
+use {
+    super::{index_sync::IndexSync, row_values, AsStorageError, DbConn, Snapshot},
+    crate::*,
+    gluesql_core::{
+        error::{Error, Result as GlueResult},
+        store::DataRow,
+    },
+    sled::InlineArray,
+    std::{io::Write, path::PathBuf, sync::atomic::Ordering},
+};
+
+const BACKUPS_DIRECTORY_NAME: &str = "backups";
+const BACKUP_EXTENSION: &str = "prestdb";
+const DEFAULT_BACKUPS_RETAINED: usize = 7;
+
+/// Number of exported chunks buffered while the writer catches up
+const EXPORT_CHUNKS_BUFFERED: usize = 4;
+
+/// Receiver of the chunks of the archive streamed by [`Query::Export`]
+#[doc(hidden)]
+#[derive(Debug, Clone, Default)]
+pub struct ExportSink(Option<tokio::sync::mpsc::Sender<Vec<u8>>>);
+
+impl<'a> DbConn<'a> {
+    /// Streams visible rows of all the registered tables into the sink frame by frame
+    ///
+    /// Runs on a reader thread which can't repeat it on a fresh snapshot because chunks are sent as it goes,
+    /// so it fails as soon as writes overwrite the versions it reads
+    pub async fn export(&self, sink: &ExportSink) -> Result<usize> {
+        let Some(sink) = &sink.0 else {
+            return Err(e!("export without a sink"));
+        };
+        let mut archive = BackupWriter::new(vec![], Utc::now().naive_utc())?;
+        for schema in self.schemas.all() {
+            let name = schema.name();
+            // rows of tables with pending migrations are still in the stored format
+            let columns = match self.stored_schema(name)? {
+                Some(stored) => stored.columns,
+                None => StoredColumn::from_schema(schema),
+            };
+            archive.table(name, columns)?;
+
+            let mut rows = vec![];
+            for item in self.table(name)?.data.iter() {
+                let (key, value) = item.as_storage_err()?;
+                let snapshot: Snapshot<DataRow> = bitcode::deserialize(&value).as_storage_err()?;
+                if let Some(DataRow::Vec(values)) = self.visible(snapshot) {
+                    rows.push(BackupRow {
+                        key: key.to_vec(),
+                        values,
+                    });
+                }
+                if rows.len() == BACKUP_FRAME_ROWS {
+                    archive.rows(std::mem::take(&mut rows))?;
+                    self.send_chunk(sink, &mut archive).await?;
+                }
+            }
+            archive.rows(rows)?;
+            self.send_chunk(sink, &mut archive).await?;
+        }
+        archive.finish()
+    }
+
+    async fn send_chunk(
+        &self,
+        sink: &tokio::sync::mpsc::Sender<Vec<u8>>,
+        archive: &mut BackupWriter<Vec<u8>>,
+    ) -> Result {
+        if self.stale.load(Ordering::Relaxed) {
+            return Err(e!("export snapshot went stale"));
+        }
+        sink.send(archive.take_bytes())
+            .await
+            .map_err(|_| e!("export was cancelled"))
+    }
+
+    /// Restores the chunk of backed up rows, migrating them from the columns they were persisted with
+    pub async fn import_rows(
+        &mut self,
+        name: &str,
+        columns: Vec<StoredColumn>,
+        rows: Vec<BackupRow>,
+        changes: &mut Vec<RowChange>,
+    ) -> GlueResult<usize> {
+        let schema = self.fetch_struct_schema(name)?;
+        let table_name = schema.name();
+
+        let stored = StoredSchema {
+            version: 0,
+            columns,
+        };
+        let plan = MigrationPlan::new(&stored, schema);
+        if let Some(plan) = &plan {
+            if !plan.report.blocking.is_empty() {
+                return Err(Error::StorageMsg(format!(
+                    "backup of {table_name} requires explicit migration steps: {}",
+                    plan.report.blocking.join("; ")
+                )));
+            }
+        }
+        let stored_columns = stored
+            .columns
+            .iter()
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>();
+
+        let index_sync = IndexSync::new(self, table_name)?;
+        let trees = self.table(table_name)?;
+        let restored = rows.len();
+        let mut inserted = 0;
+
+        for row in rows {
+            let values = match &plan {
+                Some(plan) => plan
+                    .apply(&MigrationRow {
+                        columns: stored_columns.clone(),
+                        values: row.values,
+                    })
+                    .as_storage_err()?,
+                None => row.values,
+            };
+            let new_row = DataRow::Vec(values.clone());
+            let key = InlineArray::from_iter(row.key);
+
+            let (snapshot, old) = match trees.data.get(&key).as_storage_err()? {
+                Some(snapshot) => {
+                    let mut snapshot: Snapshot<DataRow> =
+                        bitcode::deserialize(&snapshot).as_storage_err()?;
+                    let old = snapshot.data.clone();
+                    match &old {
+                        Some(old_row) => index_sync.update(&key, old_row, &new_row).await?,
+                        None => {
+                            index_sync.insert(&key, &new_row).await?;
+                            inserted += 1;
+                        }
+                    }
+                    snapshot.update(self.state, new_row);
+                    (snapshot, old)
+                }
+                None => {
+                    index_sync.insert(&key, &new_row).await?;
+                    inserted += 1;
+                    (Snapshot::new(self.state.tx_id, new_row), None)
+                }
+            };
+            let snapshot = bitcode::serialize(&snapshot).as_storage_err()?;
+            trees.data.insert(&key, snapshot).as_storage_err()?;
+            self.touch_row(table_name, &key);
+            changes.push(RowChange {
+                table: table_name,
+                old: row_values(old),
+                new: Some(values),
+            });
+        }
+        self.adjust_count(trees, inserted)?;
+        Ok(restored)
+    }
+
+    /// Removes up to `limit` rows of the restored table which weren't written after the `since` write,
+    /// returns the number of removed rows
+    pub async fn prune_imported(
+        &mut self,
+        name: &str,
+        since: u64,
+        limit: usize,
+        changes: &mut Vec<RowChange>,
+    ) -> GlueResult<usize> {
+        let schema = self.fetch_struct_schema(name)?;
+        let table_name = schema.name();
+        let index_sync = IndexSync::new(self, table_name)?;
+        let trees = self.table(table_name)?;
+        let mut removed = 0;
+
+        for item in trees.data.iter() {
+            if removed == limit {
+                break;
+            }
+            let (key, value) = item.as_storage_err()?;
+            let snapshot: Snapshot<DataRow> = bitcode::deserialize(&value).as_storage_err()?;
+            if snapshot.data_txid > since {
+                continue;
+            }
+            let Some(old_row) = snapshot.data.clone() else {
+                continue;
+            };
+            index_sync.delete(&key, &old_row).await?;
+            self.touch_row(table_name, &key);
+            match snapshot.delete(self.state) {
+                Some(updated) => {
+                    let updated = bitcode::serialize(&updated).as_storage_err()?;
+                    trees.data.insert(&key, updated).as_storage_err()?;
+                }
+                None => {
+                    trees.data.remove(&key).as_storage_err()?;
+                }
+            }
+            let Some(old) = row_values(Some(old_row)) else {
+                continue;
+            };
+            if schema.soft_delete() {
+                self.bury(table_name, &old)?;
+            }
+            changes.push(RowChange {
+                table: table_name,
+                old: Some(old),
+                new: None,
+            });
+            removed += 1;
+        }
+        self.adjust_count(trees, -(removed as i64))?;
+        Ok(removed)
+    }
+}
+
+/// Backup archive saved in the backups directory
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredBackup {
+    pub name: String,
+    pub size: u64,
+}
+
+impl Db {
+    pub fn backups_dir() -> PathBuf {
+        APP_CONFIG.data_dir.join(BACKUPS_DIRECTORY_NAME)
+    }
+
+    /// Restores the tables in the archive, returns the number of restored rows
+    ///
+    /// Rows persisted with previous schemas are migrated on the fly. Every table is restored with
+    /// writes of up to [`BACKUP_FRAME_ROWS`] rows followed by removals of the rows missing from the backup,
+    /// so an interrupted import leaves some of the tables restored and can be repeated.
+    /// These writes record histories, sync changes and change feed events like any others,
+    /// while tables missing from the archive are left untouched.
+    pub async fn import(&self, reader: impl std::io::Read) -> Result<usize> {
+        let mut archive = BackupReader::new(reader)?;
+        // rows which weren't restored or written since then are missing from the backup
+        let since = *self.committed.borrow();
+        let mut created_at = None;
+        let mut started = false;
+        let mut table: Option<(&'static str, Vec<StoredColumn>)> = None;
+        let mut restored = 0;
+        while let Some(frame) = archive.frame()? {
+            match frame {
+                BackupFrame::Created(at) => created_at = Some(at),
+                BackupFrame::Table { name, columns } => {
+                    if let Some((name, _)) = table.take() {
+                        self.prune_imported(name, since).await?;
+                    }
+                    started = true;
+                    match self.schemas.fetch_struct_schema(&name) {
+                        Some(schema) => table = Some((schema.name(), columns)),
+                        None => warn!(target: "db", "skipped backup of unknown table {name}"),
+                    }
+                }
+                BackupFrame::Rows(rows) => {
+                    if !started {
+                        return Err(e!("backup rows without a table"));
+                    }
+                    let Some((name, columns)) = &table else {
+                        continue;
+                    };
+                    let tx = Transaction::ImportRows {
+                        name: *name,
+                        columns: columns.clone(),
+                        rows,
+                    };
+                    match self.write(tx).await? {
+                        Payload::Affected(rows) => restored += rows,
+                        p => return Err(e!("Got {p:?} instead of the number of restored rows")),
+                    }
+                }
+            }
+        }
+        if let Some((name, _)) = table {
+            self.prune_imported(name, since).await?;
+        }
+
+        let created_at = created_at.ok_or(e!("backup without the creation time"))?;
+        info!(target: "db", "restored {restored} rows from the backup created at {created_at}");
+        Ok(restored)
+    }
+
+    async fn prune_imported(&self, name: &'static str, since: u64) -> Result {
+        loop {
+            let tx = Transaction::PruneImported {
+                name,
+                since,
+                limit: BACKUP_FRAME_ROWS,
+            };
+            match self.write(tx).await? {
+                Payload::Affected(0) => return OK,
+                Payload::Affected(_) => continue,
+                p => return Err(e!("Got {p:?} instead of the number of removed rows")),
+            }
+        }
+    }
+
+    /// Streams an archive of all the tables into the writer, returns the number of exported rows
+    ///
+    /// Export reads a consistent snapshot on one of the reader threads so that writes aren't blocked by it,
+    /// and fails if they overwrite rows before it reads them. Chunks are written on the calling task
+    pub async fn export<W: std::io::Write + Send>(&self, mut writer: W) -> Result<usize> {
+        let (exported, mut chunks) = self.export_chunks();
+        let written = async move {
+            while let Some(chunk) = chunks.recv().await {
+                writer.write_all(&chunk)?;
+            }
+            writer.flush()?;
+            OK
+        };
+        let (exported, written) = futures::join!(exported, written);
+        written?;
+        exported
+    }
+
+    /// Exports an archive into memory
+    pub async fn export_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.export(&mut bytes).await?;
+        Ok(bytes)
+    }
+
+    /// Starts streaming the archive from a reader thread, chunks end once the export is over
+    fn export_chunks(
+        &self,
+    ) -> (
+        impl std::future::Future<Output = Result<usize>> + '_,
+        tokio::sync::mpsc::Receiver<Vec<u8>>,
+    ) {
+        let (sink, chunks) = tokio::sync::mpsc::channel(EXPORT_CHUNKS_BUFFERED);
+        let exported = async move {
+            let sink = ExportSink(Some(sink));
+            match self.read(Query::Export { sink }).await? {
+                Payload::Affected(rows) => Ok(rows),
+                p => Err(e!("Got {p:?} instead of the number of exported rows")),
+            }
+        };
+        (exported, chunks)
+    }
+
+    /// Saves an archive into the backups directory and removes the oldest ones beyond retention
+    ///
+    /// Number of retained backups is configured with the `DB_BACKUPS_RETAINED` env variable, 0 keeps all of them
+    pub async fn backup_to_file(&self) -> Result<PathBuf> {
+        let dir = Self::backups_dir();
+        let created = dir.clone();
+        blocking(move || Ok(std::fs::create_dir_all(created)?)).await?;
+
+        // random suffix keeps backups saved within the same second apart
+        let id = Uuid::new_v4().simple().to_string();
+        let name = format!(
+            "backup-{}-{}.{BACKUP_EXTENSION}",
+            Utc::now().format("%Y%m%d-%H%M%S"),
+            &id[..8]
+        );
+        // archive is listed only after it's complete
+        let partial = dir.join(format!(".{name}.tmp"));
+        let path = dir.join(name);
+        let file = {
+            let partial = partial.clone();
+            blocking(move || Ok(std::fs::File::create(partial)?)).await?
+        };
+        let (exported, mut chunks) = self.export_chunks();
+        let written = blocking(move || {
+            let mut file = std::io::BufWriter::new(file);
+            while let Some(chunk) = chunks.blocking_recv() {
+                file.write_all(&chunk)?;
+            }
+            file.flush()?;
+            OK
+        });
+        let (exported, written) = futures::join!(exported, written);
+        let rows = match written.and(exported) {
+            Ok(rows) => rows,
+            Err(e) => {
+                let _ = blocking(move || Ok(std::fs::remove_file(partial)?)).await;
+                return Err(e);
+            }
+        };
+        let renamed = path.clone();
+        blocking(move || Ok(std::fs::rename(partial, renamed)?)).await?;
+        info!(target: "db", "saved backup with {rows} rows into {}", path.display());
+
+        let retained = match env_var("DB_BACKUPS_RETAINED") {
+            Ok(v) => v.parse::<usize>().unwrap_or(DEFAULT_BACKUPS_RETAINED),
+            Err(_) => DEFAULT_BACKUPS_RETAINED,
+        };
+        if retained > 0 {
+            let backups = Self::stored_backups().await?;
+            let outdated = backups.into_iter().skip(retained).collect::<Vec<_>>();
+            blocking(move || {
+                for backup in outdated {
+                    std::fs::remove_file(dir.join(&backup.name))?;
+                    debug!(target: "gc", "removed outdated backup {}", backup.name);
+                }
+                OK
+            })
+            .await?;
+        }
+
+        Ok(path)
+    }
+
+    /// Archives in the backups directory, newest first
+    pub async fn stored_backups() -> Result<Vec<StoredBackup>> {
+        let dir = Self::backups_dir();
+        blocking(move || {
+            if !dir.exists() {
+                return Ok(vec![]);
+            }
+
+            let mut backups = vec![];
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if !name.ends_with(BACKUP_EXTENSION) {
+                    continue;
+                }
+                backups.push(StoredBackup {
+                    name,
+                    size: entry.metadata()?.len(),
+                });
+            }
+            // names start with creation timestamps
+            backups.sort_by(|a, b| b.name.cmp(&a.name));
+            Ok(backups)
+        })
+        .await
+    }
+
+    /// Contents of one of the archives in the backups directory
+    pub async fn read_stored(name: &str) -> Result<Vec<u8>> {
+        if !Self::stored_backups().await?.iter().any(|b| b.name == name) {
+            return Err(e!("backup {name} not found"));
+        }
+        let path = Self::backups_dir().join(name);
+        blocking(move || Ok(std::fs::read(path)?)).await
+    }
+
+    /// Restores one of the archives from the backups directory
+    pub async fn import_stored(&self, name: &str) -> Result<usize> {
+        if !Self::stored_backups().await?.iter().any(|b| b.name == name) {
+            return Err(e!("backup {name} not found"));
+        }
+        let path = Self::backups_dir().join(name);
+        let file = blocking(move || Ok(std::fs::File::open(path)?)).await?;
+        self.import(std::io::BufReader::new(file)).await
+    }
+}
+
+/// Runs file operations on the blocking pool to keep them off the async executor
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f).await.somehow()?
+}
+
+/// Starts saving backups every `DB_BACKUP_INTERVAL_HOURS` if it's set
+pub(crate) fn schedule_backups() {
+    let Ok(interval) = env_var("DB_BACKUP_INTERVAL_HOURS") else {
+        return;
+    };
+    let Ok(interval @ 1..) = interval.parse::<u32>() else {
+        warn!(target: "db", "DB_BACKUP_INTERVAL_HOURS should be a positive number of hours, backups are disabled");
+        return;
+    };
+
+    RT.every(interval).hours().schedule("DB backup", || async {
+        DB.backup_to_file().await.map(|_| ())
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        id: u8,
+        balance: i64,
+    }
+
+    async fn accounts() -> Vec<Account> {
+        let mut accounts = Account::get_all().await.unwrap();
+        accounts.sort_by_key(|a| a.id);
+        accounts
+    }
+
+    #[tokio::test]
+    async fn imports_restore_exported_tables() {
+        let db = TestDb::new([Account::schema()]).await;
+        for (id, balance) in [(1, 100), (2, 200)] {
+            Account { id, balance }.save().await.unwrap();
+        }
+        let archive = DB.export_bytes().await.unwrap();
+        let exported = accounts().await;
+
+        Account { id: 1, balance: 0 }.save().await.unwrap();
+        Account { id: 2, balance: 0 }.remove().await.unwrap();
+        Account { id: 3, balance: 0 }.save().await.unwrap();
+        assert_eq!(DB.import(&archive[..]).await.unwrap(), 2);
+        assert_eq!(accounts().await, exported);
+        assert_eq!(Account::count().await.unwrap(), 2);
+
+        // and into a fresh DB
+        drop(db);
+        let _db = TestDb::new([Account::schema()]).await;
+        assert_eq!(DB.import(&archive[..]).await.unwrap(), 2);
+        assert_eq!(accounts().await, exported);
+    }
+
+    #[tokio::test]
+    async fn truncated_archives_are_rejected() {
+        let _db = TestDb::new([Account::schema()]).await;
+        Account {
+            id: 1,
+            balance: 100,
+        }
+        .save()
+        .await
+        .unwrap();
+        let archive = DB.export_bytes().await.unwrap();
+        assert!(DB.import(&archive[..archive.len() - 1]).await.is_err());
+        assert!(DB.import(&b"not a backup"[..]).await.is_err());
+    }
+}
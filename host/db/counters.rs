@@ -0,0 +1,207 @@
+use {
+    super::{trees::TableTrees, AsStorageError, DbConn, Snapshot, WriteState, WRITE_STATE_KEY},
+    crate::*,
+    gluesql_core::{error::Result, store::DataRow},
+};
+
+/// Snapshot of the number of rows in the table's meta tree
+pub(crate) const COUNT_KEY: &[u8] = b"count";
+
+impl<'a> DbConn<'a> {
+    /// Number of rows in the table visible to the connection, doesn't scan the rows
+    pub fn count_rows(&self, table_name: &str) -> Result<u64> {
+        let Some(counter) = self
+            .table(table_name)?
+            .meta
+            .get(COUNT_KEY)
+            .as_storage_err()?
+        else {
+            return Ok(0);
+        };
+        let counter: Snapshot<u64> = bitcode::deserialize(&counter).as_storage_err()?;
+        Ok(self.visible(counter).unwrap_or(0))
+    }
+
+    /// Adds rows inserted or removed by the current write to the counter
+    pub(super) fn adjust_count(&self, trees: &TableTrees, delta: i64) -> Result<()> {
+        if delta == 0 {
+            return Ok(());
+        }
+        let counter = match trees.meta.get(COUNT_KEY).as_storage_err()? {
+            Some(counter) => {
+                let mut counter: Snapshot<u64> = bitcode::deserialize(&counter).as_storage_err()?;
+                let count = counter.data.unwrap_or(0).saturating_add_signed(delta);
+                counter.update(self.state, count);
+                counter
+            }
+            None => Snapshot::new(self.state.tx_id, 0u64.saturating_add_signed(delta)),
+        };
+        let counter = bitcode::serialize(&counter).as_storage_err()?;
+        trees.meta.insert(COUNT_KEY, counter).as_storage_err()?;
+        Ok(())
+    }
+
+    /// Sets the counter to the number of rows written by the current write, used after bulk rewrites
+    pub(super) fn recount(&self, table_name: &str) -> Result<()> {
+        let trees = self.table(table_name)?;
+        let mut count = 0u64;
+        for item in trees.data.iter() {
+            let (_, value) = item.as_storage_err()?;
+            let snapshot: Snapshot<DataRow> = bitcode::deserialize(&value).as_storage_err()?;
+            if snapshot.data.is_some() {
+                count += 1;
+            }
+        }
+        let current = self.written_count(trees)?;
+        self.adjust_count(trees, count as i64 - current as i64)
+    }
+
+    /// Latest value of the counter including changes of the current write
+    fn written_count(&self, trees: &TableTrees) -> Result<u64> {
+        let Some(counter) = trees.meta.get(COUNT_KEY).as_storage_err()? else {
+            return Ok(0);
+        };
+        let counter: Snapshot<u64> = bitcode::deserialize(&counter).as_storage_err()?;
+        Ok(counter.data.unwrap_or(0))
+    }
+
+    /// Restores the counter modified by the current write, returns whether it was
+    pub(super) fn rollback_count(&self, trees: &TableTrees) -> Result<bool> {
+        let Some(counter) = trees.meta.get(COUNT_KEY).as_storage_err()? else {
+            return Ok(false);
+        };
+        let counter: Snapshot<u64> = bitcode::deserialize(&counter).as_storage_err()?;
+        match counter.rollback(self.state) {
+            Some(Some(restored)) => {
+                let restored = bitcode::serialize(&restored).as_storage_err()?;
+                trees.meta.insert(COUNT_KEY, restored).as_storage_err()?;
+            }
+            Some(None) => {
+                trees.meta.remove(COUNT_KEY).as_storage_err()?;
+            }
+            None => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Counts committed rows of the tables stored before counters were introduced
+    pub(super) fn init_row_counters(&self) -> Result<()> {
+        // rows of an unfinished write are rolled back after the upgrade
+        let committed = match self.tree.get(WRITE_STATE_KEY).as_storage_err()? {
+            Some(state) => match bitcode::deserialize::<WriteState>(&state).as_storage_err()? {
+                WriteState {
+                    tx_id,
+                    in_progress: true,
+                } => tx_id.saturating_sub(1),
+                WriteState { tx_id, .. } => tx_id,
+            },
+            None => self.view(),
+        };
+
+        for table_name in self.stored_tables()? {
+            let trees = self.table(&table_name)?;
+            let mut count = 0u64;
+            for item in trees.data.iter() {
+                let (_, value) = item.as_storage_err()?;
+                let snapshot: Snapshot<DataRow> = bitcode::deserialize(&value).as_storage_err()?;
+                if snapshot.take(committed).is_some() {
+                    count += 1;
+                }
+            }
+            // counted before any of the writes so that rollbacks keep it
+            let counter = bitcode::serialize(&Snapshot::new(0, count)).as_storage_err()?;
+            trees.meta.insert(COUNT_KEY, counter).as_storage_err()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Purchase {
+        id: u8,
+        amount: i64,
+        paid: bool,
+    }
+
+    #[tokio::test]
+    async fn counts_rows_through_writes_and_rollbacks() {
+        let _db = TestDb::new([Purchase::schema()]).await;
+        for (id, amount) in [(1, 10), (2, 20), (3, 40)] {
+            let paid = amount != 20;
+            Purchase { id, amount, paid }.save().await.unwrap();
+        }
+        assert_eq!(Purchase::count().await.unwrap(), 3);
+
+        // updates don't change the count
+        Purchase {
+            id: 1,
+            amount: 15,
+            paid: true,
+        }
+        .save()
+        .await
+        .unwrap();
+        assert_eq!(Purchase::count().await.unwrap(), 3);
+
+        let failed = DB
+            .transaction(|tx| async move {
+                Purchase {
+                    id: 4,
+                    amount: 0,
+                    paid: false,
+                }
+                .insert_in(&tx)?;
+                tx.push(Transaction::SqlString(
+                    "DELETE FROM missing_table".to_owned(),
+                ));
+                OK
+            })
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(Purchase::count().await.unwrap(), 3);
+
+        Purchase::get_by_pkey(2)
+            .await
+            .unwrap()
+            .unwrap()
+            .remove()
+            .await
+            .unwrap();
+        assert_eq!(Purchase::count().await.unwrap(), 2);
+        assert_eq!(DB.count(Purchase::STRUCT_NAME).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn aggregates_filtered_rows() {
+        let _db = TestDb::new([Purchase::schema()]).await;
+        for (id, amount, paid) in [(1, 10, true), (2, 20, false), (3, 40, true)] {
+            Purchase { id, amount, paid }.save().await.unwrap();
+        }
+
+        let paid = || Purchase::query().where_paid().eq(true);
+        assert_eq!(
+            Purchase::count_where(|q| q.where_paid().eq(true))
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(Purchase::query().sum_amount().await.unwrap(), 70);
+        assert_eq!(paid().avg_amount().await.unwrap(), Some(25.0));
+        assert_eq!(paid().min_amount().await.unwrap(), Some(10));
+        assert_eq!(paid().max_amount().await.unwrap(), Some(40));
+
+        let mut by_paid = Purchase::query().count_by_paid().await.unwrap();
+        by_paid.sort();
+        assert_eq!(by_paid, [(false, 1), (true, 2)]);
+
+        // aggregates of no rows
+        let none = || Purchase::query().where_amount().gt(100);
+        assert_eq!(none().sum_amount().await.unwrap(), 0);
+        assert_eq!(none().avg_amount().await.unwrap(), None);
+        assert_eq!(none().count().await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,296 @@
+use {
+    super::{
+        index_sync::{build_index_key, build_index_key_prefix},
+        AsStorageError, DbConn, DbCore, Snapshot, WriteState,
+    },
+    crate::*,
+    gluesql_core::{
+        error::Result,
+        store::{DataRow, Store},
+    },
+};
+
+/// Default interval between sweeps of expired rows, configurable with `DB_SWEEP_INTERVAL_SECS`
+const DEFAULT_SWEEP_INTERVAL_SECS: u64 = 60;
+
+/// Moments when rows of the table expire, see `#[expires_at]` and `#[storage(ttl = "..")]`
+///
+/// Rows are kept in the table's index tree as `{index}/{expiration}` => `Vec<Snapshot<data key>>`
+/// just like values of the regular indexes so that the sweeper finds expired rows with a range scan
+#[derive(Debug, Clone)]
+pub(crate) struct ExpiryIndex {
+    pub index_name: String,
+    /// Positions of the `#[expires_at]` columns
+    expires_at: Vec<usize>,
+    /// Position of the `#[updated_at]` or `#[created_at]` column and the ttl after it
+    ttl: Option<(usize, chrono::Duration)>,
+}
+
+impl ExpiryIndex {
+    pub fn of(schema: StructSchema) -> Option<Self> {
+        let fields = schema.fields();
+        let expires_at = fields
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| field.expires_at)
+            .map(|(position, _)| position)
+            .collect::<Vec<_>>();
+        let ttl = schema.ttl().and_then(|ttl| {
+            let reference = fields
+                .iter()
+                .position(|f| f.updated_at)
+                .or_else(|| fields.iter().position(|f| f.created_at))?;
+            Some((reference, chrono::Duration::from_std(ttl).ok()?))
+        });
+        if expires_at.is_empty() && ttl.is_none() {
+            return None;
+        }
+
+        // changes of the expiration rules rebuild the index under the new name
+        let mut rules = expires_at
+            .iter()
+            .map(|&position| fields[position].name.to_owned())
+            .collect::<Vec<_>>();
+        if let Some((reference, ttl)) = ttl {
+            rules.push(format!("{}+{}s", fields[reference].name, ttl.num_seconds()));
+        }
+        Some(Self {
+            index_name: format!("#expiry:{}", rules.join(",")),
+            expires_at,
+            ttl,
+        })
+    }
+
+    /// The earliest of the row's expiration moments, `None` if it doesn't expire
+    pub fn expiration(&self, row: &DataRow) -> Option<NaiveDateTime> {
+        let DataRow::Vec(values) = row else {
+            return None;
+        };
+        let timestamp = |position: usize| match values.get(position) {
+            Some(sql::Value::Timestamp(timestamp)) => Some(*timestamp),
+            _ => None,
+        };
+        let ttl = self
+            .ttl
+            .and_then(|(reference, ttl)| Some(timestamp(reference)? + ttl));
+        self.expires_at
+            .iter()
+            .filter_map(|&position| timestamp(position))
+            .chain(ttl)
+            .min()
+    }
+
+    pub fn key(&self, row: &DataRow) -> Result<Option<Vec<u8>>> {
+        self.expiration(row)
+            .map(|expiration| build_index_key(&self.index_name, sql::Value::Timestamp(expiration)))
+            .transpose()
+    }
+}
+
+impl<'a> DbConn<'a> {
+    /// Keys of the rows which expired by now in all the tables
+    pub fn expired_rows(&self) -> Result<Vec<(&'static str, sql::Key)>> {
+        let now = Utc::now().naive_utc();
+        let mut expired = vec![];
+        for schema in self.schemas.all() {
+            let Some(expiry) = ExpiryIndex::of(schema) else {
+                continue;
+            };
+            let trees = self.table(schema.name())?;
+            let start = build_index_key_prefix(&expiry.index_name);
+            let end = build_index_key(&expiry.index_name, sql::Value::Timestamp(now))?;
+            for item in trees.index.range(start..end) {
+                let (_, value) = item.as_storage_err()?;
+                let data_keys: Vec<Snapshot<Vec<u8>>> =
+                    bitcode::deserialize(&value).as_storage_err()?;
+                for data_key in data_keys.into_iter().filter_map(|s| self.visible(s)) {
+                    expired.push((schema.name(), sql::Key::Bytea(data_key)));
+                }
+            }
+        }
+        Ok(expired)
+    }
+
+    /// Deletes the expired rows which weren't removed since they were found
+    pub async fn expire(
+        &mut self,
+        rows: Vec<(&'static str, sql::Key)>,
+        changes: &mut Vec<RowChange>,
+    ) -> crate::Result<usize> {
+        let mut expired = 0;
+        for (name, key) in rows {
+            if self.fetch_data(name, &key).await?.is_none() {
+                continue;
+            }
+            self.delete_row(name, key, changes).await?;
+            expired += 1;
+        }
+        Ok(expired)
+    }
+}
+
+/// Interval between sweeps of expired rows
+pub(super) fn sweep_interval() -> std::time::Duration {
+    let secs = env_var("DB_SWEEP_INTERVAL_SECS")
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|secs| *secs > 0)
+        .unwrap_or(DEFAULT_SWEEP_INTERVAL_SECS);
+    std::time::Duration::from_secs(secs)
+}
+
+/// Removes the expired rows, runs on the writer thread between writes
+pub(super) async fn sweep(core: &DbCore) {
+    let committed = *core.committed.borrow();
+    let conn = DbConn {
+        state: WriteState {
+            tx_id: committed,
+            in_progress: false,
+        },
+        committed,
+        stale: Default::default(),
+        readonly: true,
+        user: None,
+        touched: None,
+        tree: &core.tree,
+        schemas: &core.schemas,
+        tables: &core.tables,
+    };
+    let rows = match conn.expired_rows() {
+        Ok(rows) if rows.is_empty() => return,
+        Ok(rows) => rows,
+        Err(e) => {
+            warn!(target: "db", "failed to find expired rows: {e}");
+            return;
+        }
+    };
+
+    match super::write(core, Transaction::Expire { rows }, None, None).await {
+        Ok(Payload::Affected(expired)) => debug!(target: "db", "expired {expired} rows"),
+        Ok(payload) => warn!(target: "db", "unexpected expiration result: {payload:?}"),
+        Err(e) => warn!(target: "db", "failed to expire rows: {e}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::db::TablesCache;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[storage(ttl = "1h")]
+    struct Token {
+        id: u8,
+        #[updated_at]
+        updated: NaiveDateTime,
+        #[expires_at]
+        expires: NaiveDateTime,
+    }
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Session {
+        id: u8,
+        #[expires_at]
+        expires: NaiveDateTime,
+    }
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        id: u8,
+    }
+
+    #[test]
+    fn expires_rows_at_the_earliest_moment() {
+        let expiry = ExpiryIndex::of(Token::schema()).unwrap();
+        let now = Utc::now().naive_utc();
+        let row = |updated, expires| {
+            DataRow::Vec(vec![
+                sql::Value::U8(1),
+                sql::Value::Timestamp(updated),
+                sql::Value::Timestamp(expires),
+            ])
+        };
+        let hour = chrono::Duration::hours(1);
+        let minute = chrono::Duration::minutes(1);
+        assert_eq!(
+            expiry.expiration(&row(now, now + minute)),
+            Some(now + minute)
+        );
+        assert_eq!(
+            expiry.expiration(&row(now, now + hour * 2)),
+            Some(now + hour)
+        );
+        assert!(ExpiryIndex::of(Account::schema()).is_none());
+    }
+
+    #[tokio::test]
+    async fn sweeps_expired_rows() {
+        let path = std::env::temp_dir().join(format!("prest-db-{}", Uuid::now_v7()));
+        let tree = sled::Config::default()
+            .path(path.clone())
+            .flush_every_ms(None)
+            .open::<1024>()
+            .unwrap();
+        let db = Db::start(tree.clone(), Some(path), Default::default());
+        db._register_schema(Session::schema());
+        db.migrate().await.unwrap();
+
+        let now = Utc::now().naive_utc();
+        let minute = chrono::Duration::minutes(1);
+        for (id, expires) in [(1, now - minute), (2, now + minute)] {
+            let session = Session { id, expires };
+            let save = Transaction::Save {
+                name: Session::STRUCT_NAME,
+                key: session.get_pkey().into_sql_key().unwrap(),
+                row: session.into_row().unwrap(),
+            };
+            db.write(save).await.unwrap();
+        }
+
+        // the sweeper's lookup on the committed state
+        let committed = *db.committed.borrow();
+        let (schemas, tables) = (db.schemas.clone(), TablesCache::default());
+        let conn = DbConn {
+            state: WriteState {
+                tx_id: committed,
+                in_progress: false,
+            },
+            committed,
+            stale: Default::default(),
+            readonly: true,
+            user: None,
+            touched: None,
+            tree: &tree,
+            schemas: &schemas,
+            tables: &tables,
+        };
+        let rows = conn.expired_rows().unwrap();
+        assert_eq!(rows.len(), 1);
+
+        let expired = db.write(Transaction::Expire { rows: rows.clone() }).await;
+        assert!(matches!(expired, Ok(Payload::Affected(1))));
+        // already removed rows are skipped
+        let repeated = db.write(Transaction::Expire { rows }).await;
+        assert!(matches!(repeated, Ok(Payload::Affected(0))));
+        let Payload::Rows(rows) = db
+            .read(Query::SqlString(format!(
+                "SELECT * FROM {}",
+                Session::STRUCT_NAME
+            )))
+            .await
+            .unwrap()
+        else {
+            panic!("rows expected");
+        };
+        let ids = Session::from_rows(rows)
+            .unwrap()
+            .into_iter()
+            .map(|s| s.id)
+            .collect::<Vec<_>>();
+        assert_eq!(ids, [2]);
+
+        tables.release();
+        drop(tree);
+        db.shutdown().unwrap();
+    }
+}
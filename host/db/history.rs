@@ -0,0 +1,335 @@
+use {
+    super::{trees::TableTrees, AsStorageError, DbConn, Snapshot},
+    crate::*,
+    gluesql_core::{
+        error::{Error, Result},
+        store::{DataRow, Store, StoreMut},
+    },
+    sled::InlineArray,
+};
+
+/// Size of the `{tx_id}{seq}` suffix of the history keys
+const VERSION_SUFFIX_LEN: usize = 12;
+
+impl<'a> DbConn<'a> {
+    /// Appends versions of the changed rows into the histories of the tables which keep them
+    pub fn record_history(&self, changes: &[RowChange]) -> Result<()> {
+        let timestamp = Utc::now().naive_utc();
+        for (seq, change) in changes.iter().enumerate() {
+            let schema = self.fetch_struct_schema(change.table)?;
+            if !schema.history() {
+                continue;
+            }
+            let (action, row) = match (&change.old, &change.new) {
+                (None, Some(new)) => (HistoryAction::Insert, new),
+                (Some(_), Some(new)) => (HistoryAction::Update, new),
+                (Some(old), None) => (HistoryAction::Delete, old),
+                (None, None) => continue,
+            };
+            let entry = self.entry(schema, action, row.clone(), timestamp);
+
+            let mut version = self.state.tx_id.to_be_bytes().to_vec();
+            version.extend((seq as u32).to_be_bytes());
+            let mut key = history_prefix(&row_key(schema, row)?);
+            key.extend(&version);
+            let entry = bitcode::serialize(&entry).as_storage_err()?;
+            let trees = self.table(change.table)?;
+            // logged first so that versions of a write interrupted by a crash are still found by the rollback
+            trees
+                .history_log
+                .insert(version, key.clone())
+                .as_storage_err()?;
+            trees.history.insert(key, entry).as_storage_err()?;
+        }
+        Ok(())
+    }
+
+    /// Versions of the row or of all the rows in the order of writes, the latest `limit` ones if set,
+    /// skips ones of the write in progress
+    pub fn history(
+        &self,
+        name: &str,
+        pkey: Option<sql::Key>,
+        limit: Option<usize>,
+    ) -> Result<Vec<HistoryEntry>> {
+        let trees = self.table(name)?;
+        let limit = limit.unwrap_or(usize::MAX);
+        let mut entries = vec![];
+        match pkey {
+            // versions of a row are ordered by the `{tx_id}{seq}` suffixes of their keys
+            Some(pkey) => {
+                let prefix = history_prefix(&super::sled_key(pkey)?);
+                for item in trees.history.scan_prefix(prefix).rev() {
+                    if entries.len() == limit {
+                        break;
+                    }
+                    let (_, value) = item.as_storage_err()?;
+                    let entry: HistoryEntry = bitcode::deserialize(&value).as_storage_err()?;
+                    if entry.tx_id <= self.view() {
+                        entries.push(entry);
+                    }
+                }
+            }
+            None => {
+                let visible = (self.view() + 1).to_be_bytes();
+                for item in trees.history_log.range(..visible).rev() {
+                    if entries.len() == limit {
+                        break;
+                    }
+                    let (_, key) = item.as_storage_err()?;
+                    let Some(value) = trees.history.get(&key).as_storage_err()? else {
+                        continue;
+                    };
+                    entries.push(bitcode::deserialize(&value).as_storage_err()?);
+                }
+            }
+        }
+        entries.reverse();
+        Ok(entries)
+    }
+
+    /// Keeps the row removed from a soft delete table so that it can be restored
+    pub fn bury(&self, name: &str, row: &[sql::Value]) -> Result<()> {
+        let schema = self.fetch_struct_schema(name)?;
+        let key = row_key(schema, row)?;
+        let entry = self.entry(
+            schema,
+            HistoryAction::Delete,
+            row.to_vec(),
+            Utc::now().naive_utc(),
+        );
+        let trees = self.table(name)?;
+        let tombstone = match trees.tombstones.get(&key).as_storage_err()? {
+            Some(tombstone) => {
+                let mut tombstone: Snapshot<HistoryEntry> =
+                    bitcode::deserialize(&tombstone).as_storage_err()?;
+                tombstone.update(self.state, entry);
+                tombstone
+            }
+            None => Snapshot::new(self.state.tx_id, entry),
+        };
+        let tombstone = bitcode::serialize(&tombstone).as_storage_err()?;
+        trees.tombstones.insert(&key, tombstone).as_storage_err()?;
+        self.touch_row(name, &key);
+        Ok(())
+    }
+
+    /// Removes the tombstone of the row once it's written again
+    pub fn unbury(&self, name: &str, key: &sql::Key) -> Result<Option<HistoryEntry>> {
+        let trees = self.table(name)?;
+        let key = super::sled_key(key.clone())?;
+        let Some(tombstone) = trees.tombstones.get(&key).as_storage_err()? else {
+            return Ok(None);
+        };
+        let tombstone: Snapshot<HistoryEntry> =
+            bitcode::deserialize(&tombstone).as_storage_err()?;
+        let entry = tombstone.get(self.view());
+        self.touch_row(name, &key);
+        match tombstone.delete(self.state) {
+            Some(updated) => {
+                let updated = bitcode::serialize(&updated).as_storage_err()?;
+                trees.tombstones.insert(&key, updated).as_storage_err()?;
+            }
+            None => {
+                trees.tombstones.remove(&key).as_storage_err()?;
+            }
+        }
+        Ok(entry)
+    }
+
+    /// Tombstones of the rows removed from a soft delete table
+    pub fn tombstones(&self, name: &str) -> Result<Vec<HistoryEntry>> {
+        let mut entries = vec![];
+        for item in self.table(name)?.tombstones.iter() {
+            let (_, value) = item.as_storage_err()?;
+            let tombstone: Snapshot<HistoryEntry> =
+                bitcode::deserialize(&value).as_storage_err()?;
+            entries.extend(self.visible(tombstone));
+        }
+        Ok(entries)
+    }
+
+    /// Moves the row back from its tombstone into the table
+    pub async fn restore_row(
+        &mut self,
+        name: &'static str,
+        key: sql::Key,
+        changes: &mut Vec<RowChange>,
+    ) -> crate::Result {
+        if self.fetch_data(name, &key).await?.is_some() {
+            return Err(e!("restoring existing row {key:?}"));
+        }
+        let Some(entry) = self.unbury(name, &key)? else {
+            return Err(e!("no removed {name} row {key:?} to restore"));
+        };
+        let row = entry.current_row(self.fetch_struct_schema(name)?.fields());
+        self.check_references(name, &row).await?;
+        self.insert_data(name, vec![(key, DataRow::Vec(row.clone()))])
+            .await?;
+        changes.push(RowChange {
+            table: name,
+            old: None,
+            new: Some(row),
+        });
+        OK
+    }
+
+    /// Removes history entries and restores tombstones modified by the current write,
+    /// only tombstones of the provided rows are checked if they are known
+    pub(super) fn rollback_history(
+        &self,
+        trees: &TableTrees,
+        rows: Option<&std::collections::BTreeSet<Vec<u8>>>,
+    ) -> Result<()> {
+        for item in trees
+            .history_log
+            .scan_prefix(self.state.tx_id.to_be_bytes())
+        {
+            let (version, key) = item.as_storage_err()?;
+            trees.history.remove(&key).as_storage_err()?;
+            trees.history_log.remove(&version).as_storage_err()?;
+        }
+
+        let keys: Vec<Vec<u8>> = match rows {
+            Some(rows) => rows.iter().cloned().collect(),
+            None => trees
+                .tombstones
+                .iter()
+                .map(|item| item.map(|(key, _)| key.to_vec()).as_storage_err())
+                .collect::<Result<_>>()?,
+        };
+        for key in keys {
+            let Some(value) = trees.tombstones.get(&key).as_storage_err()? else {
+                continue;
+            };
+            let tombstone: Snapshot<HistoryEntry> =
+                bitcode::deserialize(&value).as_storage_err()?;
+            match tombstone.rollback(self.state) {
+                Some(Some(restored)) => {
+                    let restored = bitcode::serialize(&restored).as_storage_err()?;
+                    trees.tombstones.insert(&key, restored).as_storage_err()?;
+                }
+                Some(None) => {
+                    trees.tombstones.remove(&key).as_storage_err()?;
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Orders versions recorded before the history logs were introduced
+    pub(super) fn init_history_logs(&self) -> Result<()> {
+        for table_name in self.stored_tables()? {
+            let trees = self.table(&table_name)?;
+            for item in trees.history.iter() {
+                let (key, _) = item.as_storage_err()?;
+                let Some(version) = key.len().checked_sub(VERSION_SUFFIX_LEN).map(|i| &key[i..])
+                else {
+                    continue;
+                };
+                trees.history_log.insert(version, &*key).as_storage_err()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn entry(
+        &self,
+        schema: StructSchema,
+        action: HistoryAction,
+        row: Vec<sql::Value>,
+        timestamp: NaiveDateTime,
+    ) -> HistoryEntry {
+        HistoryEntry {
+            tx_id: self.state.tx_id,
+            timestamp,
+            user: self.user,
+            action,
+            columns: schema.fields().iter().map(|f| f.name.to_owned()).collect(),
+            row,
+        }
+    }
+}
+
+/// Storage key of the row assembled from its primary key columns
+fn row_key(schema: StructSchema, row: &[sql::Value]) -> Result<InlineArray> {
+    super::sled_key(row_pkey(schema, row)?)
+}
+
+/// Primary key of the row, composite if it has several pkey columns
+pub(crate) fn row_pkey(schema: StructSchema, row: &[sql::Value]) -> Result<sql::Key> {
+    let mut parts = schema
+        .fields()
+        .iter()
+        .zip(row)
+        .filter(|(field, _)| field.pkey)
+        .map(|(_, value)| Ok(sql::Key::try_from(value.clone())?))
+        .collect::<Result<Vec<_>>>()?;
+    let key = match parts.len() {
+        1 => parts.remove(0),
+        0 => {
+            return Err(Error::StorageMsg(format!(
+                "{} row without pkey",
+                schema.name()
+            )))
+        }
+        _ => crate::composite_key(parts).map_err(|e| Error::StorageMsg(e.to_string()))?,
+    };
+    Ok(key)
+}
+
+/// Length-prefixed key of the row so that versions of rows with longer keys don't match
+fn history_prefix(key: &[u8]) -> Vec<u8> {
+    let mut prefix = (key.len() as u32).to_be_bytes().to_vec();
+    prefix.extend_from_slice(key);
+    prefix
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[storage(history, soft_delete)]
+    struct Note {
+        id: u8,
+        text: String,
+    }
+
+    #[tokio::test]
+    async fn keeps_authors_of_versions_and_tombstones() {
+        let _db = TestDb::new([Note::schema()]).await;
+        let author = Uuid::now_v7();
+        let note = Note {
+            id: 1,
+            text: "draft".to_owned(),
+        };
+        as_user(author, note.save()).await.unwrap();
+        Note {
+            id: 1,
+            text: "final".to_owned(),
+        }
+        .save()
+        .await
+        .unwrap();
+
+        let versions = Note::history(1).await.unwrap();
+        let authors = versions.iter().map(|v| v.user).collect::<Vec<_>>();
+        assert_eq!(authors, [Some(author), None]);
+        assert_eq!(versions[0].value, note);
+
+        let remover = Uuid::now_v7();
+        as_user(remover, Note::delete_by_pkey(1)).await.unwrap();
+        assert_eq!(Note::get_by_pkey(1).await.unwrap(), None);
+        let deleted = Note::deleted().await.unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert_eq!(deleted[0].value.text, "final");
+        assert_eq!(deleted[0].user, Some(remover));
+
+        Note::restore(1).await.unwrap();
+        let restored = Note::get_by_pkey(1).await.unwrap().unwrap();
+        assert_eq!(restored.text, "final");
+        assert!(Note::deleted().await.unwrap().is_empty());
+    }
+}
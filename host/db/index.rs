@@ -1,7 +1,8 @@
 use {
     super::{
+        accessed,
         index_sync::{build_index_key, build_index_key_prefix},
-        AsStorageError, DbConn, Snapshot,
+        AsStorageError, DbConn, QueryPlan, Snapshot,
     },
     async_trait::async_trait,
     futures::stream::iter,
@@ -13,7 +14,10 @@ use {
     },
     iter_enum::{DoubleEndedIterator, Iterator},
     sled::InlineArray,
-    std::iter::{empty, once},
+    std::{
+        iter::{empty, once},
+        ops::Bound,
+    },
 };
 
 #[async_trait(?Send)]
@@ -25,6 +29,8 @@ impl<'a> Index for DbConn<'a> {
         asc: Option<bool>,
         cmp_value: Option<(&IndexOperator, Value)>,
     ) -> Result<RowIter> {
+        accessed(QueryPlan::Index, 0);
+        let trees = self.table(table_name)?;
         let data_keys = {
             #[derive(Iterator, DoubleEndedIterator)]
             enum DataIds<I1, I2, I3, I4> {
@@ -35,58 +41,42 @@ impl<'a> Index for DbConn<'a> {
             }
 
             let map = |item: std::result::Result<_, _>| item.map(|(_, v)| v);
+            let index_tree = &trees.index;
 
             match cmp_value {
                 None => {
-                    let prefix = build_index_key_prefix(table_name, index_name);
+                    let prefix = build_index_key_prefix(index_name);
 
-                    DataIds::Full(self.tree.scan_prefix(prefix).map(map))
+                    DataIds::Full(index_tree.scan_prefix(prefix).map(map))
                 }
                 Some((op, value)) => {
-                    let incr = |key: Vec<u8>| {
-                        let mut key = key
-                            .into_iter()
-                            .rev()
-                            .fold((false, Vec::new()), |(added, mut upper), v| {
-                                let (added, _) = match (added, v) {
-                                    (true, _) => (added, upper.push(v)),
-                                    (false, u8::MAX) => (added, upper.push(v)),
-                                    (false, _) => (true, upper.push(v + 1)),
-                                };
-                                (added, upper)
-                            })
-                            .1;
-                        key.reverse();
-                        key
-                    };
-                    let lower = || build_index_key_prefix(table_name, index_name);
-                    let upper = || incr(build_index_key_prefix(table_name, index_name));
-                    let key = build_index_key(table_name, index_name, value)?;
+                    let lower = || build_index_key_prefix(index_name);
+                    let upper = || incr(build_index_key_prefix(index_name));
+                    let key = build_index_key(index_name, value)?;
 
                     match op {
-                        IndexOperator::Eq => match self.tree.get(&key).transpose() {
+                        IndexOperator::Eq => match index_tree.get(&key).transpose() {
                             Some(v) => DataIds::Once(once(v)),
                             None => DataIds::Empty(empty()),
                         },
                         IndexOperator::Gt => {
-                            DataIds::Range(self.tree.range(incr(key)..upper()).map(map))
+                            DataIds::Range(index_tree.range(incr(key)..upper()).map(map))
                         }
                         IndexOperator::GtEq => {
-                            DataIds::Range(self.tree.range(key..upper()).map(map))
+                            DataIds::Range(index_tree.range(key..upper()).map(map))
+                        }
+                        IndexOperator::Lt => {
+                            DataIds::Range(index_tree.range(lower()..key).map(map))
                         }
-                        IndexOperator::Lt => DataIds::Range(self.tree.range(lower()..key).map(map)),
                         IndexOperator::LtEq => {
-                            DataIds::Range(self.tree.range(lower()..=key).map(map))
+                            DataIds::Range(index_tree.range(lower()..=key).map(map))
                         }
                     }
                 }
             }
         };
 
-        // let txid = self.state.txid;
-
-        let prefix_len = build_index_key_prefix(table_name, index_name).len();
-        let tree = self.tree.clone();
+        let tree = &trees.data;
         let flat_map = move |keys: Result<InlineArray>| {
             #[derive(Iterator)]
             enum Rows<I1, I2> {
@@ -109,11 +99,11 @@ impl<'a> Index for DbConn<'a> {
             let keys: Vec<Snapshot<Vec<u8>>> =
                 try_into!(bitcode::deserialize(&keys).as_storage_err());
 
-            let tree2 = tree.clone();
+            let tree2 = tree;
             let rows = keys
                 .into_iter()
                 .map(move |key_snapshot| -> Result<_> {
-                    let key = match key_snapshot.take(self.state) {
+                    let key = match self.visible(key_snapshot) {
                         Some(key) => key,
                         None => {
                             return Ok(None);
@@ -126,8 +116,8 @@ impl<'a> Index for DbConn<'a> {
                         .ok_or(IndexError::ConflictOnEmptyIndexValueScan)?;
                     let snapshot: Snapshot<DataRow> =
                         bitcode::deserialize(&value).as_storage_err()?;
-                    let row = snapshot.take(self.state);
-                    let key = key.into_iter().skip(prefix_len).collect();
+                    let row = self.visible(snapshot);
+                    accessed(QueryPlan::Index, row.is_some() as u64);
                     let item = row.map(|row| (Key::Bytea(key), row));
 
                     Ok(item)
@@ -145,3 +135,80 @@ impl<'a> Index for DbConn<'a> {
         })
     }
 }
+
+impl<'a> DbConn<'a> {
+    /// Fetches rows with indexed values in the inclusive `min..=max` range, unbounded if `None`
+    pub async fn index_range(
+        &self,
+        table_name: &str,
+        index_name: &str,
+        min: Option<Value>,
+        max: Option<Value>,
+    ) -> Result<Vec<Vec<Value>>> {
+        let rows = self.index_entries(table_name, index_name, min, max)?;
+        Ok(rows.into_iter().map(|(_, values)| values).collect())
+    }
+
+    /// Data keys and values of the rows with indexed values in the inclusive `min..=max` range
+    pub fn index_entries(
+        &self,
+        table_name: &str,
+        index_name: &str,
+        min: Option<Value>,
+        max: Option<Value>,
+    ) -> Result<Vec<(Vec<u8>, Vec<Value>)>> {
+        let trees = self.table(table_name)?;
+        let prefix = build_index_key_prefix(index_name);
+        let lower = match min {
+            Some(value) => build_index_key(index_name, value)?,
+            None => prefix.clone(),
+        };
+        let upper = match max {
+            Some(value) => Bound::Included(build_index_key(index_name, value)?),
+            None => Bound::Excluded(incr(prefix)),
+        };
+
+        let mut rows = vec![];
+        for item in trees
+            .index
+            .range::<Vec<u8>, _>((Bound::Included(lower), upper))
+        {
+            let (_, keys) = item.as_storage_err()?;
+            let keys: Vec<Snapshot<Vec<u8>>> = bitcode::deserialize(&keys).as_storage_err()?;
+            for key_snapshot in keys {
+                let Some(key) = self.visible(key_snapshot) else {
+                    continue;
+                };
+                let value = trees
+                    .data
+                    .get(&key)
+                    .as_storage_err()?
+                    .ok_or(IndexError::ConflictOnEmptyIndexValueScan)?;
+                let snapshot: Snapshot<DataRow> = bitcode::deserialize(&value).as_storage_err()?;
+                if let Some(DataRow::Vec(values)) = self.visible(snapshot) {
+                    rows.push((key, values));
+                }
+            }
+        }
+        accessed(QueryPlan::Index, rows.len() as u64);
+        Ok(rows)
+    }
+}
+
+/// Smallest key that is greater than all the keys starting with provided prefix
+fn incr(key: Vec<u8>) -> Vec<u8> {
+    let mut key = key
+        .into_iter()
+        .rev()
+        .fold((false, Vec::new()), |(added, mut upper), v| {
+            let (added, _) = match (added, v) {
+                (true, _) => (added, upper.push(v)),
+                (false, u8::MAX) => (added, upper.push(v)),
+                (false, _) => (true, upper.push(v + 1)),
+            };
+            (added, upper)
+        })
+        .1;
+    key.reverse();
+    key
+}
@@ -1,16 +1,18 @@
 use {
-    super::{index_sync::IndexSync, AsStorageError, DbConn},
+    super::{
+        index_sync::{
+            build_index_key_prefix, build_index_state_key, IndexSync, INDEX_STATE_PREFIX,
+        },
+        trees::TableTrees,
+        AsStorageError, DbConn, Snapshot,
+    },
     crate::*,
     async_trait::async_trait,
-    futures::stream::TryStreamExt,
     gluesql_core::{
         ast::OrderByExpr,
-        chrono::Utc,
-        data::{Schema, SchemaIndex, SchemaIndexOrd},
-        error::{Error, IndexError, Result},
-        store::{IndexMut, Store},
+        error::{Error, Result},
+        store::{DataRow, IndexMut},
     },
-    std::iter::once,
 };
 
 #[async_trait(?Send)]
@@ -21,169 +23,142 @@ impl<'a> IndexMut for DbConn<'a> {
         _index_name: &str,
         _column: &OrderByExpr,
     ) -> Result<()> {
-        // TODO
-
-        // let rows = self
-        //     .scan_data(table_name)
-        //     .await?
-        //     .try_collect::<Vec<_>>()
-        //     .await?;
-
-        // let txid = self.txid;
-
-        // self.tree
-        //     .transaction(move |tree| {
-        //         let index_expr = &column.expr;
-
-        //         let (schema_key, schema_snapshot) = fetch_schema(tree, table_name)?;
-        //         let schema_snapshot = schema_snapshot
-        //             .ok_or_else(|| IndexError::TableNotFound(table_name.to_owned()).into())
-        //             .map_err(ConflictableTransactionError::Abort)?;
-
-        //         let (schema_snapshot, schema) = schema_snapshot.delete(txid);
-        //         let Schema {
-        //             column_defs,
-        //             indexes,
-        //             engine,
-        //             foreign_keys,
-        //             comment,
-        //             ..
-        //         } = schema
-        //             .ok_or_else(|| IndexError::ConflictTableNotFound(table_name.to_owned()).into())
-        //             .map_err(ConflictableTransactionError::Abort)?;
-
-        //         if indexes.iter().any(|index| index.name == index_name) {
-        //             return Err(ConflictableTransactionError::Abort(
-        //                 IndexError::IndexNameAlreadyExists(index_name.to_owned()).into(),
-        //             ));
-        //         }
-
-        //         let index = SchemaIndex {
-        //             name: index_name.to_owned(),
-        //             expr: index_expr.clone(),
-        //             order: SchemaIndexOrd::Both,
-        //             created: Utc::now().naive_utc(),
-        //         };
-
-        //         let indexes = indexes
-        //             .into_iter()
-        //             .chain(once(index.clone()))
-        //             .collect::<Vec<_>>();
-
-        //         let schema = Schema {
-        //             table_name: table_name.to_owned(),
-        //             column_defs,
-        //             indexes,
-        //             engine,
-        //             foreign_keys,
-        //             comment,
-        //         };
-
-        //         let index_sync = IndexSync::from_schema(tree, txid, &schema);
-
-        //         let schema_snapshot = schema_snapshot.update(txid, schema.clone());
-        //         let schema_snapshot = bitcode::serialize(&schema_snapshot)
-        //             .as_storage_err()
-        //             .map_err(ConflictableTransactionError::Abort)?;
-
-        //         async {
-        //             for (data_key, row) in rows.iter() {
-        //                 let data_key = data_key
-        //                     .to_cmp_be_bytes()
-        //                     .map_err(ConflictableTransactionError::Abort)
-        //                     .map(|key| key::sled_key(table_name, key))?;
-
-        //                 index_sync.insert_index(&index, &data_key, row).await?;
-        //             }
-
-        //             Ok(()) as ConflictableTransactionResult<(), Error>
-        //         }
-        //         .await_blocking()?;
-
-        //         tree.insert(schema_key.as_bytes(), schema_snapshot)?;
-        //         Ok(())
-        //     })
-        //     .map_err(tx_err_into)?;
-
-        Ok(())
+        Err(Error::StorageMsg(
+            "indexes are defined by #[index] and #[unique] attributes of Storage structs"
+                .to_owned(),
+        ))
     }
 
     async fn drop_index(&mut self, _table_name: &str, _index_name: &str) -> Result<()> {
-        // let rows = self
-        //     .scan_data(table_name)
-        //     .await?
-        //     .try_collect::<Vec<_>>()
-        //     .await?;
-
-        // let txid = self.txid;
-
-        // self.tree
-        //     .transaction(move |tree| {
-        //         let (schema_key, schema_snapshot) = fetch_schema(tree, table_name)?;
-        //         let schema_snapshot = schema_snapshot
-        //             .ok_or_else(|| IndexError::TableNotFound(table_name.to_owned()).into())
-        //             .map_err(ConflictableTransactionError::Abort)?;
-
-        //         let (schema_snapshot, schema) = schema_snapshot.delete(txid);
-        //         let Schema {
-        //             column_defs,
-        //             indexes,
-        //             engine,
-        //             foreign_keys,
-        //             comment,
-        //             ..
-        //         } = schema
-        //             .ok_or_else(|| IndexError::ConflictTableNotFound(table_name.to_owned()).into())
-        //             .map_err(ConflictableTransactionError::Abort)?;
-
-        //         let (index, indexes): (Vec<_>, _) = indexes
-        //             .into_iter()
-        //             .partition(|index| index.name == index_name);
-
-        //         let index = match index.into_iter().next() {
-        //             Some(index) => index,
-        //             None => {
-        //                 return Err(ConflictableTransactionError::Abort(
-        //                     IndexError::IndexNameDoesNotExist(index_name.to_owned()).into(),
-        //                 ));
-        //             }
-        //         };
-
-        //         let schema = Schema {
-        //             table_name: table_name.to_owned(),
-        //             column_defs,
-        //             indexes,
-        //             engine,
-        //             foreign_keys,
-        //             comment,
-        //         };
-
-        //         let index_sync = IndexSync::from_schema(tree, txid, &schema);
-
-        //         let schema_snapshot = schema_snapshot.update(txid, schema.clone());
-        //         let schema_snapshot = bitcode::serialize(&schema_snapshot)
-        //             .as_storage_err()
-        //             .map_err(ConflictableTransactionError::Abort)?;
-
-        //         async {
-        //             for (data_key, row) in rows.iter() {
-        //                 let data_key = data_key
-        //                     .to_cmp_be_bytes()
-        //                     .map_err(ConflictableTransactionError::Abort)
-        //                     .map(|key| key::sled_key(table_name, key))?;
-
-        //                 index_sync.delete_index(&index, &data_key, row).await?;
-        //             }
-
-        //             Ok(()) as ConflictableTransactionResult<(), Error>
-        //         }
-        //         .await_blocking()?;
-
-        //         tree.insert(schema_key.as_bytes(), schema_snapshot)?;
-        //         Ok(())
-        //     })
-        //     .map_err(tx_err_into)?;
+        Err(Error::StorageMsg(
+            "indexes are defined by #[index] and #[unique] attributes of Storage structs"
+                .to_owned(),
+        ))
+    }
+}
+
+impl<'a> DbConn<'a> {
+    /// Builds indexes that were added to the table since the last run and drops the removed ones
+    pub async fn sync_indexes(&mut self, table_name: &str) -> Result<()> {
+        let index_sync = IndexSync::new(self, table_name)?;
+        let trees = self.table(table_name)?;
+
+        for item in trees.meta.scan_prefix(INDEX_STATE_PREFIX.as_bytes()) {
+            let (state_key, _) = item.as_storage_err()?;
+            let index_name =
+                String::from_utf8_lossy(&state_key[INDEX_STATE_PREFIX.len()..]).to_string();
+            let kept = index_sync.indexes().iter().any(|i| i.name == index_name)
+                || index_sync
+                    .search_columns()
+                    .iter()
+                    .any(|c| c.index_name == index_name)
+                || index_sync
+                    .expiry()
+                    .is_some_and(|e| e.index_name == index_name);
+            if kept {
+                continue;
+            }
+            clear_index(trees, &index_name)?;
+            trees.meta.remove(&state_key).as_storage_err()?;
+            warn!(target: "db", "dropped index {index_name} of {table_name}");
+        }
+
+        for index in index_sync.indexes() {
+            let state_key = build_index_state_key(&index.name);
+            if trees.meta.get(&state_key).as_storage_err()?.is_some() {
+                continue;
+            }
+            // leftovers of an interrupted build
+            clear_index(trees, &index.name)?;
+
+            let mut indexed = 0;
+            for item in trees.data.iter() {
+                let (key, value) = item.as_storage_err()?;
+                let snapshot: Snapshot<DataRow> = bitcode::deserialize(&value).as_storage_err()?;
+                if let Some(row) = snapshot.data {
+                    index_sync.insert_index(index, &key, &row).await?;
+                    indexed += 1;
+                }
+            }
+
+            trees
+                .meta
+                .insert(&state_key, Vec::<u8>::new())
+                .as_storage_err()?;
+            info!(target: "db", "built index {} of {table_name} with {indexed} rows", index.name);
+        }
+
+        for column in index_sync.search_columns() {
+            let state_key = build_index_state_key(&column.index_name);
+            if trees.meta.get(&state_key).as_storage_err()?.is_some() {
+                continue;
+            }
+            clear_index(trees, &column.index_name)?;
+
+            let mut indexed = 0;
+            for item in trees.data.iter() {
+                let (key, value) = item.as_storage_err()?;
+                let snapshot: Snapshot<DataRow> = bitcode::deserialize(&value).as_storage_err()?;
+                if let Some(row) = snapshot.data {
+                    index_sync.insert_tokens(column, &key, &row)?;
+                    indexed += 1;
+                }
+            }
+
+            trees
+                .meta
+                .insert(&state_key, Vec::<u8>::new())
+                .as_storage_err()?;
+            info!(target: "db", "built search index {} of {table_name} with {indexed} rows", column.index_name);
+        }
+
+        if let Some(expiry) = index_sync.expiry() {
+            let state_key = build_index_state_key(&expiry.index_name);
+            if trees.meta.get(&state_key).as_storage_err()?.is_none() {
+                clear_index(trees, &expiry.index_name)?;
+
+                let mut indexed = 0;
+                for item in trees.data.iter() {
+                    let (key, value) = item.as_storage_err()?;
+                    let snapshot: Snapshot<DataRow> =
+                        bitcode::deserialize(&value).as_storage_err()?;
+                    if let Some(row) = snapshot.data {
+                        index_sync.insert_expiration(expiry, &key, &row)?;
+                        indexed += 1;
+                    }
+                }
+
+                trees
+                    .meta
+                    .insert(&state_key, Vec::<u8>::new())
+                    .as_storage_err()?;
+                info!(target: "db", "built expiry index {} of {table_name} with {indexed} rows", expiry.index_name);
+            }
+        }
 
         Ok(())
     }
 }
+
+impl DbConn<'_> {
+    /// Marks indexes of all the tables as unbuilt so that [`DbConn::sync_indexes`] rebuilds them
+    pub(super) fn reset_index_states(&self) -> Result<()> {
+        for table_name in self.stored_tables()? {
+            let trees = self.table(&table_name)?;
+            for item in trees.meta.scan_prefix(INDEX_STATE_PREFIX.as_bytes()) {
+                let (state_key, _) = item.as_storage_err()?;
+                trees.meta.remove(&state_key).as_storage_err()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn clear_index(trees: &TableTrees, index_name: &str) -> Result<()> {
+    let prefix = build_index_key_prefix(index_name);
+    for item in trees.index.scan_prefix(&prefix) {
+        let (key, _) = item.as_storage_err()?;
+        trees.index.remove(&key).as_storage_err()?;
+    }
+    Ok(())
+}
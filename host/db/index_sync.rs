@@ -1,59 +1,53 @@
 use {
-    super::{AsStorageError, Snapshot, WriteState},
+    super::{
+        expiry::ExpiryIndex, search::SearchColumn, transaction::TouchedKeys, trees::TableTrees,
+        AsStorageError, DbConn, Snapshot, WriteState,
+    },
     gluesql_core::{
         ast::Expr,
-        data::schema::{Schema, SchemaIndex},
+        data::{
+            schema::{Schema, SchemaIndex},
+            Key,
+        },
         error::{Error, IndexError, Result},
         executor::evaluate_stateless,
         prelude::Value,
         store::DataRow,
     },
-    sled::{Db, InlineArray},
-    std::borrow::Cow,
+    sled::InlineArray,
+    std::sync::{Arc, Mutex},
 };
 
+/// Prefix of the keys in the table's meta tree which mark built indexes
+pub(crate) const INDEX_STATE_PREFIX: &str = "index/";
+
+/// Keeps secondary indexes of the table in sync with its rows
+///
+/// Every index value is stored in the table's index tree as `{index}/{value}` => `Vec<Snapshot<data key>>`
+/// so that index entries can be rolled back together with the rows they point to.
+/// Tokens of `#[searchable]` columns are kept the same way as `{search index}/{token}`
+/// and expiration moments of the rows as `{expiry index}/{timestamp}`.
 pub struct IndexSync<'a> {
-    tree: &'a Db,
+    trees: &'static TableTrees,
     state: WriteState,
+    committed: u64,
+    touched: Option<Arc<Mutex<TouchedKeys>>>,
     table_name: &'a str,
     columns: Option<Vec<String>>,
-    indexes: Cow<'a, Vec<SchemaIndex>>,
+    indexes: Vec<SchemaIndex>,
+    unique: Vec<&'static str>,
+    search: Vec<SearchColumn>,
+    expiry: Option<ExpiryIndex>,
 }
 
 impl<'a> IndexSync<'a> {
-    pub fn from_schema(tree: &'a Db, state: WriteState, schema: &'a Schema) -> Self {
-        let Schema {
-            table_name,
-            column_defs,
-            indexes,
-            ..
-        } = schema;
-
-        let columns = column_defs.as_ref().map(|column_defs| {
-            column_defs
-                .iter()
-                .map(|column_def| column_def.name.to_owned())
-                .collect::<Vec<_>>()
-        });
-
-        let indexes = Cow::Borrowed(indexes);
-
-        Self {
-            tree,
-            state,
-            table_name,
-            columns,
-            indexes,
-        }
-    }
-
-    pub fn new(tree: &'a Db, state: WriteState, table_name: &'a str) -> Result<Self, Error> {
-        // crate::info!("IndexSync::new table_name={table_name} txid={txid}");
+    pub fn new(conn: &DbConn<'a>, table_name: &'a str) -> Result<Self, Error> {
         let Schema {
             column_defs,
             indexes,
             ..
-        } = crate::DB
+        } = conn
+            .schemas
             .fetch_glue_schema(table_name)
             .ok_or_else(|| IndexError::ConflictTableNotFound(table_name.to_owned()))?;
 
@@ -64,20 +58,85 @@ impl<'a> IndexSync<'a> {
                 .collect::<Vec<_>>()
         });
 
+        let struct_schema = conn.schemas.fetch_struct_schema(table_name);
+        let unique = struct_schema
+            .map(|schema| {
+                schema
+                    .fields()
+                    .iter()
+                    .filter(|f| f.unique && f.index)
+                    .map(|f| f.name)
+                    .collect()
+            })
+            .unwrap_or_default();
+        let search = struct_schema.map(SearchColumn::of).unwrap_or_default();
+        let expiry = struct_schema.and_then(ExpiryIndex::of);
+
         Ok(Self {
-            tree,
-            state,
+            trees: conn.table(table_name)?,
+            state: conn.state,
+            committed: conn.committed,
+            touched: conn.touched.clone(),
             table_name,
             columns,
-            indexes: Cow::Owned(indexes),
+            indexes,
+            unique,
+            search,
+            expiry,
         })
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.indexes.is_empty() && self.search.is_empty() && self.expiry.is_none()
+    }
+
+    pub fn indexes(&self) -> &[SchemaIndex] {
+        &self.indexes
+    }
+
+    pub fn search_columns(&self) -> &[SearchColumn] {
+        &self.search
+    }
+
+    pub fn expiry(&self) -> Option<&ExpiryIndex> {
+        self.expiry.as_ref()
+    }
+
     pub async fn insert(&self, data_key: &InlineArray, row: &DataRow) -> Result<()> {
         for index in self.indexes.iter() {
             self.insert_index(index, data_key, row).await?;
         }
+        for column in self.search.iter() {
+            self.insert_tokens(column, data_key, row)?;
+        }
+        if let Some(expiry) = self.expiry.as_ref() {
+            self.insert_expiration(expiry, data_key, row)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn insert_tokens(
+        &self,
+        column: &SearchColumn,
+        data_key: &InlineArray,
+        row: &DataRow,
+    ) -> Result<()> {
+        for token in column.token_set(row) {
+            self.insert_index_data(&column.key(&token), data_key, false)?;
+        }
+        Ok(())
+    }
 
+    pub fn insert_expiration(
+        &self,
+        expiry: &ExpiryIndex,
+        data_key: &InlineArray,
+        row: &DataRow,
+    ) -> Result<()> {
+        if let Some(expiry_key) = expiry.key(row)? {
+            self.insert_index_data(&expiry_key, data_key, false)?;
+        }
         Ok(())
     }
 
@@ -87,22 +146,10 @@ impl<'a> IndexSync<'a> {
         data_key: &InlineArray,
         row: &DataRow,
     ) -> Result<()> {
-        let SchemaIndex {
-            name: index_name,
-            expr: index_expr,
-            ..
-        } = index;
-
-        let index_key = &evaluate_index_key(
-            self.table_name,
-            index_name,
-            index_expr,
-            self.columns.as_deref(),
-            row,
-        )
-        .await?;
+        let (index_key, value) = self.evaluate(index, row).await?;
 
-        self.insert_index_data(index_key, data_key)?;
+        let check_unique = !matches!(value, Value::Null) && self.is_unique(index);
+        self.insert_index_data(&index_key, data_key, check_unique)?;
 
         Ok(())
     }
@@ -114,32 +161,40 @@ impl<'a> IndexSync<'a> {
         new_row: &DataRow,
     ) -> Result<()> {
         for index in self.indexes.iter() {
-            let SchemaIndex {
-                name: index_name,
-                expr: index_expr,
-                ..
-            } = index;
-
-            let old_index_key = &evaluate_index_key(
-                self.table_name,
-                index_name,
-                index_expr,
-                self.columns.as_deref(),
-                old_row,
-            )
-            .await?;
-
-            let new_index_key = &evaluate_index_key(
-                self.table_name,
-                index_name,
-                index_expr,
-                self.columns.as_deref(),
-                new_row,
-            )
-            .await?;
-
-            self.delete_index_data(old_index_key, data_key)?;
-            self.insert_index_data(new_index_key, data_key)?;
+            let (old_index_key, _) = self.evaluate(index, old_row).await?;
+            let (new_index_key, value) = self.evaluate(index, new_row).await?;
+
+            if old_index_key == new_index_key {
+                continue;
+            }
+
+            let check_unique = !matches!(value, Value::Null) && self.is_unique(index);
+            self.delete_index_data(&old_index_key, data_key)?;
+            self.insert_index_data(&new_index_key, data_key, check_unique)?;
+        }
+
+        for column in self.search.iter() {
+            let old_tokens = column.token_set(old_row);
+            let new_tokens = column.token_set(new_row);
+            for token in old_tokens.difference(&new_tokens) {
+                self.delete_index_data(&column.key(token), data_key)?;
+            }
+            for token in new_tokens.difference(&old_tokens) {
+                self.insert_index_data(&column.key(token), data_key, false)?;
+            }
+        }
+
+        if let Some(expiry) = self.expiry.as_ref() {
+            let old_expiry_key = expiry.key(old_row)?;
+            let new_expiry_key = expiry.key(new_row)?;
+            if old_expiry_key != new_expiry_key {
+                if let Some(old_expiry_key) = old_expiry_key {
+                    self.delete_index_data(&old_expiry_key, data_key)?;
+                }
+                if let Some(new_expiry_key) = new_expiry_key {
+                    self.insert_index_data(&new_expiry_key, data_key, false)?;
+                }
+            }
         }
 
         Ok(())
@@ -149,6 +204,20 @@ impl<'a> IndexSync<'a> {
         for index in self.indexes.iter() {
             self.delete_index(index, data_key, row).await?;
         }
+        for column in self.search.iter() {
+            for token in column.token_set(row) {
+                self.delete_index_data(&column.key(&token), data_key)?;
+            }
+        }
+        if let Some(expiry_key) = self
+            .expiry
+            .as_ref()
+            .map(|e| e.key(row))
+            .transpose()?
+            .flatten()
+        {
+            self.delete_index_data(&expiry_key, data_key)?;
+        }
 
         Ok(())
     }
@@ -159,29 +228,31 @@ impl<'a> IndexSync<'a> {
         data_key: &InlineArray,
         row: &DataRow,
     ) -> Result<()> {
+        let (index_key, _) = self.evaluate(index, row).await?;
+
+        self.delete_index_data(&index_key, data_key)?;
+
+        Ok(())
+    }
+
+    fn is_unique(&self, index: &SchemaIndex) -> bool {
+        self.unique.iter().any(|name| *name == index.name)
+    }
+
+    async fn evaluate(&self, index: &SchemaIndex, row: &DataRow) -> Result<(Vec<u8>, Value)> {
         let SchemaIndex {
             name: index_name,
             expr: index_expr,
             ..
         } = index;
 
-        let index_key = &evaluate_index_key(
-            self.table_name,
-            index_name,
-            index_expr,
-            self.columns.as_deref(),
-            row,
-        )
-        .await?;
-
-        self.delete_index_data(index_key, data_key)?;
-
-        Ok(())
+        evaluate_index_key(index_name, index_expr, self.columns.as_deref(), row).await
     }
 
-    fn insert_index_data(&self, index_key: &[u8], data_key: &InlineArray) -> Result<()> {
-        let mut data_keys: Vec<Snapshot<Vec<u8>>> = self
-            .tree
+    fn load_index_data(&self, index_key: &[u8]) -> Result<Vec<Snapshot<Vec<u8>>>> {
+        let data_keys: Vec<Snapshot<Vec<u8>>> = self
+            .trees
+            .index
             .get(index_key)
             .as_storage_err()?
             .map(|v| bitcode::deserialize(&v))
@@ -189,75 +260,159 @@ impl<'a> IndexSync<'a> {
             .as_storage_err()?
             .unwrap_or_default();
 
-        let key_snapshot = Snapshot::<Vec<u8>>::new(self.state.tx_id, data_key.to_vec());
-        data_keys.push(key_snapshot);
-        let data_keys = bitcode::serialize(&Vec::from(data_keys)).as_storage_err()?;
+        // entries removed by older writes aren't needed for rollbacks and snapshots anymore,
+        // ones removed by the latest committed write are kept for readers which started before it
+        Ok(data_keys
+            .into_iter()
+            .filter(|s| s.data.is_some() || s.data_txid >= self.committed)
+            .collect())
+    }
 
-        // let temp_key = super::temp_index(self.state.txid, index_key);
+    fn store_index_data(&self, index_key: &[u8], data_keys: Vec<Snapshot<Vec<u8>>>) -> Result<()> {
+        if let Some(touched) = &self.touched {
+            let mut touched = touched.lock().expect("touched keys lock isn't poisoned");
+            let table = touched.entry(self.table_name.to_owned()).or_default();
+            table.index.insert(index_key.to_vec());
+        }
+        if data_keys.is_empty() {
+            self.trees.index.remove(index_key).as_storage_err()?;
+        } else {
+            let data_keys = bitcode::serialize(&data_keys).as_storage_err()?;
+            self.trees
+                .index
+                .insert(index_key, data_keys)
+                .as_storage_err()?;
+        }
+        Ok(())
+    }
 
-        self.tree.insert(index_key, data_keys).as_storage_err()?;
-        // self.tree.insert(temp_key, index_key).as_storage_err()?;
+    fn insert_index_data(
+        &self,
+        index_key: &[u8],
+        data_key: &InlineArray,
+        check_unique: bool,
+    ) -> Result<()> {
+        let mut data_keys = self.load_index_data(index_key)?;
 
-        Ok(())
+        if data_keys
+            .iter()
+            .any(|s| s.data.as_deref() == Some(data_key.as_ref()))
+        {
+            return Ok(());
+        }
+
+        if check_unique && data_keys.iter().any(|s| s.data.is_some()) {
+            return Err(Error::StorageMsg(format!(
+                "unique constraint violation in {}",
+                self.table_name
+            )));
+        }
+
+        data_keys.push(Snapshot::new(self.state.tx_id, data_key.to_vec()));
+
+        self.store_index_data(index_key, data_keys)
     }
 
     fn delete_index_data(&self, index_key: &[u8], data_key: &InlineArray) -> Result<()> {
-        let data_keys: Vec<Snapshot<Vec<u8>>> = self
-            .tree
-            .get(index_key)
-            .as_storage_err()?
-            .map(|v| bitcode::deserialize(&v))
-            .transpose()
-            .as_storage_err()?
-            .ok_or(Error::StorageMsg("index not found".into()))?;
-        // .as_storage_err()?;
-
-        let data_keys = data_keys
+        let data_keys = self
+            .load_index_data(index_key)?
             .into_iter()
-            .map(|snapshot| {
-                todo!("fix this stuff (state isn't here)");
-                // let key = snapshot.get(state);
-
-                // if Some(data_key) == key.map(InlineArray::from).as_ref() {
-                //     snapshot.delete(self.state)
-                // } else {
-                //     Some(snapshot)
-                // }
+            .filter_map(|snapshot| {
+                if snapshot.data.as_deref() == Some(data_key.as_ref()) {
+                    snapshot.delete(self.state)
+                } else {
+                    Some(snapshot)
+                }
             })
             .collect::<Vec<_>>();
 
-        let data_keys = bitcode::serialize(&data_keys).as_storage_err()?;
-
-        // let temp_key = super::temp_index(self.state.txid, index_key);
-
-        self.tree.insert(index_key, data_keys).as_storage_err()?;
-        // self.tree.insert(temp_key, index_key).as_storage_err()?;
-
-        Ok(())
+        self.store_index_data(index_key, data_keys)
     }
 }
 
 async fn evaluate_index_key(
-    table_name: &str,
     index_name: &str,
     index_expr: &Expr,
     columns: Option<&[String]>,
     row: &DataRow,
-) -> Result<Vec<u8>> {
+) -> Result<(Vec<u8>, Value)> {
     let context = Some(row.as_context(columns));
     let evaluated = evaluate_stateless(context, index_expr).await?;
     let value: Value = evaluated.try_into()?;
 
-    build_index_key(table_name, index_name, value)
+    Ok((build_index_key(index_name, value.clone())?, value))
 }
 
-pub fn build_index_key_prefix(table_name: &str, index_name: &str) -> Vec<u8> {
-    format!("index/{}/{}/", table_name, index_name).into_bytes()
+pub fn build_index_key_prefix(index_name: &str) -> Vec<u8> {
+    format!("{index_name}/").into_bytes()
 }
 
-pub fn build_index_key(table_name: &str, index_name: &str, value: Value) -> Result<Vec<u8>> {
-    Ok(build_index_key_prefix(table_name, index_name)
+pub fn build_index_key(index_name: &str, value: Value) -> Result<Vec<u8>> {
+    Ok(build_index_key_prefix(index_name)
         .into_iter()
-        .chain(value.to_cmp_be_bytes()?)
+        .chain(index_value_bytes(value)?)
         .collect::<Vec<_>>())
 }
+
+pub fn build_index_state_key(index_name: &str) -> Vec<u8> {
+    format!("{INDEX_STATE_PREFIX}{index_name}").into_bytes()
+}
+
+/// Numbers are normalized so that SQL literals (evaluated as `I64`/`F64`) match values of any numeric column
+fn index_value_bytes(value: Value) -> Result<Vec<u8>> {
+    use Value::*;
+    let value = match value {
+        I8(v) => I128(v as i128),
+        I16(v) => I128(v as i128),
+        I32(v) => I128(v as i128),
+        I64(v) => I128(v as i128),
+        U8(v) => I128(v as i128),
+        U16(v) => I128(v as i128),
+        U32(v) => I128(v as i128),
+        U64(v) => I128(v as i128),
+        U128(v) => match i128::try_from(v) {
+            Ok(v) => I128(v),
+            Err(_) => return large_u128_bytes(v),
+        },
+        F32(v) => F64(v as f64),
+        other => other,
+    };
+    Key::try_from(value)?.to_cmp_be_bytes()
+}
+
+/// Continues the order of non-negative `I128` keys past `i128::MAX`: their encoding ends with
+/// the big-endian value after the prefix they share, so it's swapped for the wider value
+fn large_u128_bytes(value: u128) -> Result<Vec<u8>> {
+    let mut bytes = Key::I128(i128::MAX).to_cmp_be_bytes()?;
+    bytes.truncate(bytes.len() - std::mem::size_of::<u128>());
+    bytes.extend(value.to_be_bytes());
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_integers_of_all_widths() {
+        let values = [
+            Value::I128(i128::MIN),
+            Value::I64(-1),
+            Value::U8(0),
+            Value::I32(7),
+            Value::U64(u64::MAX),
+            Value::I128(i128::MAX),
+            Value::U128(i128::MAX as u128 + 1),
+            Value::U128(u128::MAX),
+        ];
+        let encoded = values
+            .into_iter()
+            .map(|v| index_value_bytes(v).unwrap())
+            .collect::<Vec<_>>();
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        assert_eq!(
+            index_value_bytes(Value::U128(7)).unwrap(),
+            index_value_bytes(Value::I64(7)).unwrap()
+        );
+    }
+}
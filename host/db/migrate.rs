@@ -0,0 +1,138 @@
+use {
+    super::{index_sync::INDEX_STATE_PREFIX, AsStorageError, DbConn, Snapshot},
+    crate::{MigrationPlan, MigrationReport, MigrationRow, StoredColumn, StoredSchema},
+    gluesql_core::{
+        error::{Error, Result},
+        store::DataRow,
+    },
+};
+
+/// Key of the persisted schema in the table's meta tree
+pub(crate) const SCHEMA_KEY: &[u8] = b"schema";
+/// Prefix of the persisted schemas in the default tree before per-table trees
+pub(crate) const LEGACY_SCHEMA_PREFIX: &str = "schema/";
+
+impl<'a> DbConn<'a> {
+    pub fn stored_schema(&self, table_name: &str) -> Result<Option<StoredSchema>> {
+        self.table(table_name)?
+            .meta
+            .get(SCHEMA_KEY)
+            .as_storage_err()?
+            .map(|v| bitcode::deserialize(&v))
+            .transpose()
+            .as_storage_err()
+    }
+
+    fn store_schema(&self, table_name: &str, schema: &StoredSchema) -> Result<()> {
+        let value = bitcode::serialize(schema).as_storage_err()?;
+        self.table(table_name)?
+            .meta
+            .insert(SCHEMA_KEY, value)
+            .as_storage_err()?;
+        Ok(())
+    }
+
+    pub fn migration_reports(&self) -> Result<Vec<MigrationReport>> {
+        let mut reports = vec![];
+        for schema in self.schemas.all() {
+            let stored = self.stored_schema(schema.name())?;
+            let report = match stored.as_ref().map(|s| (s, MigrationPlan::new(s, schema))) {
+                Some((_, Some(plan))) => plan.report,
+                Some((stored, None)) => MigrationReport {
+                    table: schema.name().to_owned(),
+                    stored_version: Some(stored.version),
+                    changes: vec![],
+                    blocking: vec![],
+                },
+                None => MigrationReport {
+                    table: schema.name().to_owned(),
+                    stored_version: None,
+                    changes: vec![],
+                    blocking: vec![],
+                },
+            };
+            reports.push(report);
+        }
+        Ok(reports)
+    }
+
+    /// Rewrites rows persisted with the previous schema of the table and records the current one
+    ///
+    /// Tables without persisted schemas are assumed to match the current one
+    pub fn migrate_table(&mut self, table_name: &str) -> Result<()> {
+        let schema = self.fetch_struct_schema(table_name)?;
+        let columns = StoredColumn::from_schema(schema);
+
+        let Some(stored) = self.stored_schema(table_name)? else {
+            return self.store_schema(
+                table_name,
+                &StoredSchema {
+                    version: 1,
+                    columns,
+                },
+            );
+        };
+
+        let Some(plan) = MigrationPlan::new(&stored, schema) else {
+            return Ok(());
+        };
+
+        if !plan.report.blocking.is_empty() {
+            return Err(Error::StorageMsg(format!(
+                "migration of {table_name} requires explicit steps: {}",
+                plan.report.blocking.join("; ")
+            )));
+        }
+
+        let stored_columns = stored
+            .columns
+            .iter()
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>();
+
+        let trees = self.table(table_name)?;
+        let mut migrated = 0;
+        for item in trees.data.iter() {
+            let (key, value) = item.as_storage_err()?;
+            let mut snapshot: Snapshot<DataRow> = bitcode::deserialize(&value).as_storage_err()?;
+            let Some(DataRow::Vec(values)) = snapshot.data.clone() else {
+                continue;
+            };
+
+            let row = MigrationRow {
+                columns: stored_columns.clone(),
+                values,
+            };
+            let values = plan.apply(&row).as_storage_err()?;
+
+            // previous version is kept as a backup until the migration is committed
+            snapshot.update(self.state, DataRow::Vec(values));
+            let snapshot = bitcode::serialize(&snapshot).as_storage_err()?;
+            trees.data.insert(&key, snapshot).as_storage_err()?;
+            self.touch_row(table_name, &key);
+            migrated += 1;
+        }
+
+        // values of indexed columns might have changed so indexes are rebuilt by sync_indexes
+        for item in trees.meta.scan_prefix(INDEX_STATE_PREFIX.as_bytes()) {
+            let (key, _) = item.as_storage_err()?;
+            trees.meta.remove(&key).as_storage_err()?;
+        }
+
+        self.store_schema(
+            table_name,
+            &StoredSchema {
+                version: stored.version + 1,
+                columns,
+            },
+        )?;
+
+        crate::info!(
+            target: "db",
+            "migrated {migrated} rows of {table_name} to schema v{}: {}",
+            stored.version + 1,
+            plan.report.changes.join(", ")
+        );
+        Ok(())
+    }
+}
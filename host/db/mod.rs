@@ -1,5 +1,4 @@
 use gluesql_core::{
-    ast_builder::Build,
     prelude::Glue,
     store::{Store, StoreMut},
 };
@@ -7,25 +6,45 @@ use gluesql_core::{
 use crate::*;
 
 mod alter_table;
+mod backend;
+mod backup;
+mod counters;
+mod expiry;
+mod history;
 mod index;
 mod index_mut;
 mod index_sync;
+mod migrate;
+mod profiling;
+mod relations;
+mod replication;
+mod search;
 mod snapshot;
+#[cfg(feature = "sqlite")]
+mod sqlite;
 mod store;
 mod store_mut;
+mod sync;
+mod timestamps;
 mod transaction;
+mod trees;
 
-use std::{
-    borrow::BorrowMut,
-    cell::RefCell,
-    sync::mpsc::{RecvError, TryRecvError},
+pub use backend::DbBackend;
+pub use backup::ExportSink;
+pub(crate) use history::row_pkey;
+pub(crate) use profiling::{
+    accessed, schedule_slow_query_flush, QueryInfo, QueryProfiler, QueryTimer,
 };
+pub use profiling::{QueryPlan, SlowQuery, TableQueryStats};
+pub(crate) use replication::ReplicationConfig;
+use std::{sync::mpsc::RecvTimeoutError, time::Instant};
+pub(crate) use trees::TablesCache;
+
 use {
-    self::snapshot::Snapshot,
+    self::{replication::ReplicationLog, snapshot::Snapshot, transaction::TouchedKeys},
     gluesql_core::store::{CustomFunction, CustomFunctionMut, Metadata},
     sled::InlineArray,
     std::sync::atomic::{AtomicBool, AtomicU64, Ordering},
-    tokio::sync::Notify,
 };
 
 pub(crate) const WRITE_STATE_KEY: &[u8] = b"state/write";
@@ -36,20 +55,87 @@ pub(crate) struct WriteState {
     pub in_progress: bool,
 }
 
-#[derive(Debug, Clone)]
+/// Ids of writes and the number of committed ones, shared by the reader and writer threads
+#[derive(Debug, Default)]
+pub(crate) struct WriteTracker {
+    next_id: AtomicU64,
+    /// Incremented after the id of the committed write is published, see [`read`]
+    commits: AtomicU64,
+}
+
+/// Resources used by the reader and writer threads
+#[derive(Clone)]
+struct DbCore {
+    tree: sled::Db,
+    tracker: Arc<WriteTracker>,
+    schemas: Schemas,
+    tables: TablesCache,
+    changes: async_broadcast::Sender<Arc<RowChange>>,
+    /// Id of the latest committed write
+    committed: Arc<tokio::sync::watch::Sender<u64>>,
+    /// Committed writes served to followers, see `DB_REPLICATION_TOKEN`
+    log: Option<Arc<ReplicationLog>>,
+    /// Rejects writes which weren't replicated from the primary, see `DB_REPLICATE_FROM`
+    replica: bool,
+    profiler: Arc<QueryProfiler>,
+}
+
+#[derive(Clone)]
 pub(crate) struct DbConn<'a> {
     pub state: WriteState,
+    /// Id of the latest write committed before the connection was opened
+    pub committed: u64,
+    /// Set when the reader meets versions overwritten after its snapshot, see [`DbConn::visible`]
+    pub stale: Arc<AtomicBool>,
     pub readonly: bool,
+    /// User who initiated the write, recorded in histories and tombstones
+    pub user: Option<Uuid>,
+    /// Keys modified by the write, `None` if they aren't tracked like in reads and recoveries
+    pub touched: Option<Arc<std::sync::Mutex<TouchedKeys>>>,
     pub tree: &'a sled::Db,
+    pub schemas: &'a Schemas,
+    pub tables: &'a TablesCache,
+}
+
+impl DbConn<'_> {
+    /// Id of the latest write visible to the connection: readers see committed writes and get
+    /// backups of rows modified after them while the writer sees its own changes
+    pub fn view(&self) -> u64 {
+        self.state.tx_id
+    }
+
+    /// Version of the snapshot visible to the connection, marks the read as stale
+    /// if it was already overwritten by writes which started after its snapshot
+    pub fn visible<T: Clone + std::fmt::Debug>(&self, snapshot: Snapshot<T>) -> Option<T> {
+        if snapshot.outdated(self.view()) {
+            self.stale.store(true, Ordering::Relaxed);
+        }
+        snapshot.take(self.view())
+    }
+
+    pub fn fetch_struct_schema(
+        &self,
+        table_name: &str,
+    ) -> Result<StructSchema, gluesql_core::error::Error> {
+        self.schemas.fetch_struct_schema(table_name).ok_or_else(|| {
+            gluesql_core::error::Error::StorageMsg(format!("table {table_name} not found"))
+        })
+    }
 }
 
 impl Db {
     pub(crate) fn init() -> Db {
+        // checked on startup by the init macro
+        let backend = DbBackend::selected().unwrap_or_else(|e| {
+            error!(target: "db", "{e}, falling back to sled");
+            DbBackend::Sled
+        });
+        Self::open(backend, APP_CONFIG.persistent)
+    }
+
+    fn persistent() -> Db {
         let mut db_path = APP_CONFIG.data_dir.clone();
         db_path.push(DB_DIRECTORY_NAME);
-        if !APP_CONFIG.persistent {
-            todo!("global statics aren't dropped => sled's temporary key doesn't work (doesn't Drop), need another way to deal with tmp storages")
-        }
 
         let storage = sled::Config::default()
             .path(db_path.clone())
@@ -57,133 +143,381 @@ impl Db {
             .open::<1024>()
             .expect(&format!("DB path ({db_path:?}) should be available"));
 
-        // ALTERNATIVE OPTIMIZATION: Separate trees per table for maximum performance
-        // This would eliminate the need for prefix scanning entirely but requires more changes:
-        //
-        // let route_stats_tree = storage.open_tree("RouteStat").expect("RouteStat tree");
-        // let system_stats_tree = storage.open_tree("SystemStat").expect("SystemStat tree");
-        // let job_records_tree = storage.open_tree("ScheduledJobRecord").expect("JobRecord tree");
-        //
-        // Benefits:
-        // - No scanning through millions of irrelevant entries
-        // - Independent table performance
-        // - Better memory locality
-        // - Easier to implement table-specific optimizations
-        //
-        // Changes needed:
-        // - Modify storage operations to use table-specific trees
-        // - Update sled_key() function to not use table prefixes
-        // - Adjust Store trait implementation
-
-        if let Some(state_bytes) = storage.get(WRITE_STATE_KEY).unwrap() {
+        let db = Self::start(storage, None, ReplicationConfig::from_env());
+        backup::schedule_backups();
+        db
+    }
+
+    /// Opens DB in a unique temporary directory which is removed on [`Db::shutdown`]
+    pub(crate) fn temporary() -> Db {
+        let db_path = std::env::temp_dir().join(format!("prest-db-{}", Uuid::now_v7()));
+
+        let storage = sled::Config::default()
+            .path(db_path.clone())
+            .cache_capacity_bytes(64 * 1024 * 1024)
+            // nothing to persist while background flushes would fail once the directory is removed
+            .flush_every_ms(None)
+            .open::<1024>()
+            .expect(&format!(
+                "temporary DB path ({db_path:?}) should be available"
+            ));
+
+        // temporary DBs are never replicated
+        Self::start(storage, Some(db_path), ReplicationConfig::default())
+    }
+
+    fn start(
+        storage: sled::Db,
+        temp_path: Option<std::path::PathBuf>,
+        replication: ReplicationConfig,
+    ) -> Db {
+        let (mut changes, changes_receiver) = async_broadcast::broadcast(1000);
+        // slow subscribers skip the oldest changes instead of blocking the writer
+        changes.set_overflow(true);
+        let (committed, committed_receiver) = tokio::sync::watch::channel(0);
+
+        let mut core = DbCore {
+            tree: storage,
+            tracker: Default::default(),
+            schemas: internal_schemas(),
+            tables: Default::default(),
+            changes,
+            committed: Arc::new(committed),
+            log: None,
+            replica: replication.primary.is_some(),
+            profiler: Default::default(),
+        };
+
+        DbConn {
+            state: WriteState {
+                tx_id: 0,
+                in_progress: false,
+            },
+            committed: 0,
+            stale: Default::default(),
+            readonly: false,
+            user: None,
+            touched: None,
+            tree: &core.tree,
+            schemas: &core.schemas,
+            tables: &core.tables,
+        }
+        .upgrade_format()
+        .expect("DB format should be upgraded");
+
+        // ids start from 1 so that the initial watermark doesn't cover any of the writes
+        core.tracker.next_id.store(1, Ordering::SeqCst);
+        let mut unfinished = None;
+        if let Some(state_bytes) = core.tree.get(WRITE_STATE_KEY).unwrap() {
             let state: WriteState = bitcode::deserialize(&state_bytes).unwrap();
-            if state.in_progress {
-                crate::warn!("detected unfinished write, rolling it back");
-                TX_ID.store(state.tx_id, Ordering::SeqCst);
-                // TODO
-                // DbConn {
-                //     lock: Some(lock.clone()),
-                //     tree: storage.clone(),
-                //     state,
-                // }
-                // .rollback_self()?;
-            }
+            // snapshots rely on ids of new writes being greater than the persisted ones
+            core.tracker
+                .next_id
+                .store(state.tx_id + 1, Ordering::SeqCst);
+            core.committed.send_replace(state.tx_id);
+            unfinished = state.in_progress.then_some(state);
+        }
+
+        // opened before the recovery so that it removes the unfinished write from the log too
+        if replication.token.is_some() {
+            let log = ReplicationLog::open(&core).expect("replication log should open");
+            core.log = Some(Arc::new(log));
         }
+        if core.replica {
+            replication::init_replica(&core).expect("replica position should be recorded");
+        }
+
+        let recovery = unfinished.map(|state| {
+            crate::warn!(target: "db", "detected unfinished write {}, rolling it back", state.tx_id);
+            let report = recover(&core, state).expect("unfinished write should be rolled back");
+            crate::warn!(target: "db", "recovered from unfinished write: {report:?}");
+            report
+        });
 
         let (write_sender, writes) = std::sync::mpsc::sync_channel::<DbWriteMessage>(10);
         let (read_sender, reads) = std::sync::mpsc::sync_channel::<DbReadMessage>(100);
+        // readers take turns receiving queries but run them concurrently
+        let reads = Arc::new(std::sync::Mutex::new(reads));
+        let read_metrics = Arc::new(ReadMetrics::default());
 
-        let write_storage = storage.clone();
-        let read_storage = storage.clone();
-
+        let write_core = core.clone();
         let write_thread = std::thread::Builder::new()
             .name("DB writer".to_string())
             .spawn(move || {
                 let rt = tokio::runtime::Builder::new_current_thread()
                     .build()
                     .unwrap();
+                let sweep_interval = expiry::sweep_interval();
+                let mut next_sweep = Instant::now() + sweep_interval;
                 loop {
-                    let Ok((tx, returner)) = writes.recv() else {
-                        panic!("DB is disconnected (write senders are dropped)")
-                    };
-                    let result = rt.block_on(write(&write_storage, tx));
+                    if Instant::now() >= next_sweep {
+                        // followers remove expired rows along with the primary
+                        if !write_core.replica {
+                            rt.block_on(expiry::sweep(&write_core));
+                        }
+                        if let Some(Err(e)) =
+                            write_core.log.as_ref().map(|log| log.trim(&write_core))
+                        {
+                            warn!(target: "db", "failed to trim replication log: {e}");
+                        }
+                        next_sweep = Instant::now() + sweep_interval;
+                    }
+                    let timeout = next_sweep.saturating_duration_since(Instant::now());
+                    let (tx, returner, user, replicated, queued_at) =
+                        match writes.recv_timeout(timeout) {
+                            Ok(message) => message,
+                            Err(RecvTimeoutError::Timeout) => continue,
+                            // senders are dropped by the shutdown
+                            Err(RecvTimeoutError::Disconnected) => return OK,
+                        };
+                    let timer = QueryTimer::start(QueryInfo::of_write(&tx), queued_at);
+                    let result = rt.block_on(write(&write_core, tx, user, replicated));
+                    timer.finish(&write_core.profiler, &result);
                     if let Err(e) = returner.send(result) {
                         warn!("failed to return write result: {e:?}");
                     }
                 }
             })
             .expect("DB writer thread should spawn");
-        let read_thread = std::thread::Builder::new()
-            .name("DB reader".to_string())
-            .spawn(move || {
-                let rt = tokio::runtime::Builder::new_current_thread()
-                    .build()
-                    .unwrap();
-                loop {
-                    let Ok(msg) = reads.recv() else {
-                        panic!("DB is disconnected (read senders are dropped)")
-                    };
-                    rt.block_on(read(&read_storage, msg));
-                }
-            })
-            .expect("DB reader thread should spawn");
 
-        use crate::host::analytics::RouteStat;
-        #[allow(unused_mut)]
-        let mut internal_schemas = vec![
-            ScheduledJobRecord::schema(),
-            RouteStat::schema(),
-            SystemStat::schema(),
-        ];
-        #[cfg(feature = "auth")]
-        {
-            internal_schemas.push(crate::host::auth::SessionRow::schema());
-            internal_schemas.push(crate::host::auth::User::schema());
+        let mut handles = vec![write_thread];
+        let readers = readers_num();
+        read_metrics.readers.store(readers, Ordering::Relaxed);
+        for i in 0..readers {
+            let read_core = core.clone();
+            let reads = reads.clone();
+            let read_metrics = read_metrics.clone();
+            let read_thread = std::thread::Builder::new()
+                .name(format!("DB reader {i}"))
+                .spawn(move || {
+                    let rt = tokio::runtime::Builder::new_current_thread()
+                        .build()
+                        .unwrap();
+                    loop {
+                        let Ok((query, returner, queued_at)) = reads.lock().unwrap().recv() else {
+                            return OK;
+                        };
+                        read_metrics.dequeue(queued_at);
+                        let timer = QueryTimer::start(QueryInfo::of_read(&query), queued_at);
+                        let result = rt.block_on(read(&read_core, query));
+                        timer.finish(&read_core.profiler, &result);
+                        if let Err(e) = returner.send(result) {
+                            warn!("failed to return read result: {e:?}");
+                        }
+                    }
+                })
+                .expect("DB reader thread should spawn");
+            handles.push(read_thread);
         }
 
         Db {
-            read: read_sender,
-            write: write_sender,
-            internal_schemas: Arc::new(internal_schemas),
-            custom_schemas: Default::default(),
-            handles: Arc::new(vec![write_thread, read_thread]),
+            read: DbSender::new(read_sender),
+            write: DbSender::new(write_sender),
+            schemas: core.schemas,
+            handles: std::sync::Mutex::new(handles),
+            read_metrics,
+            profiler: core.profiler,
+            changes: changes_receiver.deactivate(),
+            committed: committed_receiver,
+            replication,
+            tables: core.tables,
+            recovery,
+            temp_path,
         }
     }
 }
 
-async fn write<'a>(tree: &'a sled::Db, query: Transaction) -> Result<Payload> {
-    let tx_id = TX_ID.fetch_add(1, Ordering::Relaxed);
-    TX_IN_PROGRESS.store(true, Ordering::SeqCst);
+/// Schemas of the tables used by prest itself
+fn internal_schemas() -> Schemas {
+    let mut schemas = vec![
+        ScheduledJobRecord::schema(),
+        RouteStat::schema(),
+        SystemStat::schema(),
+        SlowQuery::schema(),
+    ];
+    #[cfg(feature = "auth")]
+    {
+        schemas.push(crate::host::auth::SessionRow::schema());
+        schemas.push(crate::host::auth::User::schema());
+    }
+    Schemas::new(schemas)
+}
+
+/// Number of reader threads, configurable with the `DB_READERS` env variable
+fn readers_num() -> usize {
+    let default = std::thread::available_parallelism().map_or(4, |n| n.get());
+    match env_var("DB_READERS") {
+        Ok(v) => v.parse::<usize>().unwrap_or(default).max(1),
+        Err(_) => default,
+    }
+}
+
+/// Rolls back rows and index entries modified by the write interrupted by a crash
+fn recover(core: &DbCore, state: WriteState) -> Result<RecoveryReport> {
+    let mut conn = DbConn {
+        state,
+        committed: state.tx_id.saturating_sub(1),
+        stale: Default::default(),
+        readonly: false,
+        user: None,
+        touched: None,
+        tree: &core.tree,
+        schemas: &core.schemas,
+        tables: &core.tables,
+    };
+    let (rows, index_entries) = conn.rollback_self()?;
+    replication::rollback(core, state.tx_id)?;
+
+    conn.state.in_progress = false;
+    conn.record_state()?;
+    core.tree.flush()?;
+
+    Ok(RecoveryReport {
+        tx_id: state.tx_id,
+        rows,
+        index_entries,
+        recovered_at: Utc::now().naive_utc(),
+    })
+}
+
+impl Db {
+    /// Describes the rollback of a write interrupted by a crash if it happened on startup
+    pub fn recovery_report(&self) -> Option<&RecoveryReport> {
+        self.recovery.as_ref()
+    }
+}
+
+async fn write(
+    core: &DbCore,
+    tx: Transaction,
+    user: Option<Uuid>,
+    replicated: Option<u64>,
+) -> Result<Payload> {
+    if core.replica && replicated.is_none() && !replication::allowed_on_replica(&tx) {
+        return Err(e!(
+            "DB is a read-only replica, writes are applied on the primary"
+        ));
+    }
+    // followers keep their schemas and indexes up to date themselves
+    let logged = core.log.is_some() && !replication::allowed_on_replica(&tx);
+
+    let tx_id = core.tracker.next_id.fetch_add(1, Ordering::SeqCst);
 
     let mut conn = DbConn {
         state: WriteState {
             tx_id,
             in_progress: true,
         },
+        committed: *core.committed.borrow(),
+        stale: Default::default(),
         readonly: false,
-        tree,
+        user,
+        touched: Some(Default::default()),
+        tree: &core.tree,
+        schemas: &core.schemas,
+        tables: &core.tables,
     };
 
-    let result = match query {
-        Transaction::SqlString(sql) => {
-            Ok(Glue::new(conn).execute(sql).await?.pop().unwrap().into())
+    let ops = match tx {
+        Transaction::Batch(ops) => ops,
+        op => vec![op],
+    };
+
+    let mut changes = vec![];
+    let mut result: Result<Payload> = conn
+        .record_state()
+        .map(|_| Payload::Success)
+        .map_err(Into::into);
+    for op in ops {
+        if result.is_err() {
+            break;
         }
+        result = apply(&mut conn, op, &mut changes).await;
+    }
+    if result.is_ok() {
+        if let Err(e) = conn.record_history(&changes) {
+            result = Err(e.into());
+        } else if let Err(e) = conn.record_sync(&changes) {
+            result = Err(e.into());
+        } else if let Err(e) = replication::record(core, &conn, tx_id, logged, replicated) {
+            result = Err(e);
+        }
+    }
+
+    if result.is_err() {
+        if let Err(e) = conn.rollback_self() {
+            error!("failed to rollback write {tx_id}: {e}");
+        }
+        if let Err(e) = replication::rollback(core, tx_id) {
+            error!("failed to rollback replication of write {tx_id}: {e}");
+        }
+    }
+
+    conn.state.in_progress = false;
+    if let Err(e) = conn.record_state() {
+        error!("failed to record the end of write {tx_id}: {e}");
+    }
+
+    if result.is_ok() {
+        // readers take snapshots in the reverse order so they never undercount commits after them
+        core.committed.send_replace(tx_id);
+        core.tracker.commits.fetch_add(1, Ordering::SeqCst);
+        for change in changes {
+            // fails only without active subscribers
+            let _ = core.changes.try_broadcast(Arc::new(change));
+        }
+    }
+    result
+}
+
+async fn apply<'a>(
+    conn: &mut DbConn<'a>,
+    op: Transaction,
+    changes: &mut Vec<RowChange>,
+) -> Result<Payload> {
+    match op {
+        Transaction::SqlString(sql) => Ok(Glue::new(conn.clone())
+            .execute(sql)
+            .await?
+            .pop()
+            .unwrap()
+            .into()),
         Transaction::SqlStatement(stmt) => {
-            let planned = gluesql_core::plan::plan(&conn, stmt).await?;
-            Ok(Glue::new(conn).execute_stmt(&planned).await?.into())
+            let planned = gluesql_core::plan::plan(&*conn, stmt).await?;
+            Ok(Glue::new(conn.clone()).execute_stmt(&planned).await?.into())
         }
         Transaction::Insert { name, key, row } => {
             if let Some(_) = conn.fetch_data(name, &key).await? {
                 return Err(e!("duplicate data insertion for {key:?}"));
             }
-            conn.insert_data(name, vec![(key, sql::DataRow::Vec(row))])
+            conn.check_references(name, &row).await?;
+            conn.unbury(name, &key)?;
+            conn.insert_data(name, vec![(key, sql::DataRow::Vec(row.clone()))])
                 .await?;
-            Ok(Payload::Success)
+            changes.push(RowChange {
+                table: name,
+                old: None,
+                new: Some(row.clone()),
+            });
+            Ok(Payload::Rows(vec![row]))
         }
-        Transaction::Save { name, key, row } => {
-            conn.insert_data(name, vec![(key, sql::DataRow::Vec(row))])
+        Transaction::Save { name, key, mut row } => {
+            conn.check_references(name, &row).await?;
+            let old = row_values(conn.fetch_data(name, &key).await?);
+            match &old {
+                Some(old) => conn.keep_created_at(name, old, &mut row),
+                None => {
+                    conn.unbury(name, &key)?;
+                }
+            }
+            conn.insert_data(name, vec![(key, sql::DataRow::Vec(row.clone()))])
                 .await?;
-            Ok(Payload::Success)
+            changes.push(RowChange {
+                table: name,
+                old,
+                new: Some(row.clone()),
+            });
+            Ok(Payload::Rows(vec![row]))
         }
         Transaction::UpdateField {
             name,
@@ -191,42 +525,123 @@ async fn write<'a>(tree: &'a sled::Db, query: Transaction) -> Result<Payload> {
             column,
             value,
         } => {
-            conn.update_cell(name, key, column, value).await?;
+            let old = row_values(conn.fetch_data(name, &key).await?);
+            conn.update_cell(name, key.clone(), column, value).await?;
+            conn.touch_updated_at(name, &key, column).await?;
+            let new = row_values(conn.fetch_data(name, &key).await?);
+            if let Some(new) = &new {
+                conn.check_references(name, new).await?;
+            }
+            changes.push(RowChange {
+                table: name,
+                old,
+                new,
+            });
             Ok(Payload::Success)
         }
         Transaction::Delete { name, key } => {
-            if let None = conn.fetch_data(name, &key).await? {
-                return Err(e!("deleting non-existent value {key:?}"));
+            conn.delete_row(name, key, changes).await?;
+            Ok(Payload::Success)
+        }
+        Transaction::Restore { name, key } => {
+            conn.restore_row(name, key, changes).await?;
+            Ok(Payload::Success)
+        }
+        Transaction::Expire { rows } => Ok(Payload::Affected(conn.expire(rows, changes).await?)),
+        Transaction::Replicate { rows } => {
+            for (name, key, values) in rows {
+                conn.replicate_row(name, key, values, changes).await?;
             }
-            conn.delete_data(name, vec![key]).await?;
             Ok(Payload::Success)
         }
+        Transaction::SyncIndexes { name } => {
+            conn.sync_indexes(name).await?;
+            Ok(Payload::Success)
+        }
+        Transaction::Migrate { name } => {
+            conn.migrate_table(name)?;
+            Ok(Payload::Success)
+        }
+        Transaction::Batch(_) => Err(e!("nested batches are not supported")),
+        Transaction::ImportRows {
+            name,
+            columns,
+            rows,
+        } => Ok(Payload::Affected(
+            conn.import_rows(name, columns, rows, changes).await?,
+        )),
+        Transaction::PruneImported { name, since, limit } => Ok(Payload::Affected(
+            conn.prune_imported(name, since, limit, changes).await?,
+        )),
         // Transaction::Flush => {
         //     conn.tree.flush()?;
         //     Ok(Payload::Success)
         // }
         #[cfg(feature = "experimental")]
         Transaction::Nuke => {
+            for table_name in conn.stored_tables()? {
+                let trees = conn.table(&table_name)?;
+                trees.data.clear()?;
+                trees.index.clear()?;
+                trees.meta.clear()?;
+                trees.history.clear()?;
+                trees.history_log.clear()?;
+                trees.tombstones.clear()?;
+                trees.sync.clear()?;
+            }
             conn.tree.clear()?;
-            conn.tree.flush()?;
+            // cleared registry should be refilled as tables are reopened
+            conn.tables.clear();
+            conn.upgrade_format()?;
             Ok(Payload::Success)
         }
-    };
-    TX_IN_PROGRESS.store(false, Ordering::SeqCst);
-    result
+    }
 }
 
-async fn read<'a>(tree: &'a sled::Db, (query, returner): DbReadMessage) -> Result {
-    let tx_id = TX_ID.load(Ordering::Relaxed);
-    let in_progress = TX_IN_PROGRESS.load(Ordering::Relaxed);
+/// Max number of attempts to read a consistent snapshot while writes overwrite its versions
+const READ_ATTEMPTS: usize = 16;
 
-    let mut conn = DbConn {
-        state: WriteState { tx_id, in_progress },
-        readonly: false,
-        tree,
-    };
+/// Runs the query on the snapshot of committed writes
+///
+/// Rows modified after the snapshot are read from their backups which keep only one previous version,
+/// so reads which overlapped with more than one later write are repeated on a fresh snapshot
+async fn read(core: &DbCore, query: Query) -> Result<Payload> {
+    for _ in 0..READ_ATTEMPTS {
+        let commits = core.tracker.commits.load(Ordering::SeqCst);
+        let committed = *core.committed.borrow();
+        let conn = DbConn {
+            state: WriteState {
+                tx_id: committed,
+                in_progress: false,
+            },
+            committed,
+            stale: Default::default(),
+            readonly: true,
+            user: None,
+            touched: None,
+            tree: &core.tree,
+            schemas: &core.schemas,
+            tables: &core.tables,
+        };
+        let stale = conn.stale.clone();
+
+        // exports stream rows as they go so they fail on stale snapshots instead of being repeated
+        if let Query::Export { .. } = query {
+            return read_snapshot(core, conn, query).await;
+        }
+        let result = read_snapshot(core, conn, query.clone()).await;
+        let overlapped = core.tracker.commits.load(Ordering::SeqCst) > commits + 1;
+        if !overlapped && !stale.load(Ordering::Relaxed) {
+            return result;
+        }
+    }
+    Err(e!(
+        "failed to read a consistent snapshot in {READ_ATTEMPTS} attempts due to concurrent writes"
+    ))
+}
 
-    let result = match query {
+async fn read_snapshot(core: &DbCore, conn: DbConn<'_>, query: Query) -> Result<Payload> {
+    match query {
         Query::SqlString(sql) => Ok(Glue::new(conn).execute(sql).await?.pop().unwrap().into()),
         Query::SqlStatement(stmt) => {
             let planned = gluesql_core::plan::plan(&conn, stmt).await?;
@@ -236,7 +651,9 @@ async fn read<'a>(tree: &'a sled::Db, (query, returner): DbReadMessage) -> Resul
             let rows = match conn.fetch_data(name, &pkey).await? {
                 Some(row) => match row {
                     sql::DataRow::Vec(vec) => vec![vec],
-                    sql::DataRow::Map(vec) => unimplemented!(),
+                    sql::DataRow::Map(_) => {
+                        return Err(e!("{name} row {pkey:?} isn't stored as a list of values"))
+                    }
                 },
                 None => vec![],
             };
@@ -250,28 +667,53 @@ async fn read<'a>(tree: &'a sled::Db, (query, returner): DbReadMessage) -> Resul
             let rows = conn.pk_range(name, pkey_min, pkey_max).await?;
             Ok(Payload::Rows(rows))
         }
-    };
-    returner.send(result);
-    OK
+        Query::IndexRange {
+            name,
+            index,
+            min,
+            max,
+        } => {
+            let rows = conn.index_range(name, index, min, max).await?;
+            Ok(Payload::Rows(rows))
+        }
+        Query::MigrationReports => Ok(Payload::Migrations(conn.migration_reports()?)),
+        Query::Export { sink } => Ok(Payload::Affected(conn.export(&sink).await?)),
+        Query::Count { name } => Ok(Payload::Count(conn.count_rows(name)?)),
+        Query::History { name, pkey, limit } => {
+            Ok(Payload::History(conn.history(name, pkey, limit)?))
+        }
+        Query::Deleted { name } => Ok(Payload::History(conn.tombstones(name)?)),
+        Query::Search { name, query, limit } => {
+            Ok(Payload::Rows(conn.search(name, &query, limit).await?))
+        }
+        Query::SyncChanges { since } => Ok(Payload::Sync(conn.sync_changes(since)?)),
+        Query::ReplicationLog { from } => {
+            let Some(log) = &core.log else {
+                return Err(e!("replication log is disabled"));
+            };
+            Ok(Payload::Replication(log.read(
+                core,
+                from,
+                conn.committed,
+            )?))
+        }
+        Query::ReplicaPosition => Ok(Payload::Cursor(replication::replica_position(core)?)),
+    }
+}
+
+fn row_values(row: Option<sql::DataRow>) -> Option<Vec<sql::Value>> {
+    match row {
+        Some(sql::DataRow::Vec(values)) => Some(values),
+        _ => None,
+    }
 }
 
 impl<'a> Metadata for DbConn<'a> {}
 impl<'a> CustomFunction for DbConn<'a> {}
 impl<'a> CustomFunctionMut for DbConn<'a> {}
 
-pub fn data_prefix(table_name: &str) -> String {
-    format!("/{table_name}/")
-}
-
-pub fn sled_key(
-    table_name: &str,
-    key: sql::Key,
-) -> Result<InlineArray, gluesql_core::error::Error> {
-    let key = key
-        .to_cmp_be_bytes()
-        .map(|key| data_prefix(table_name).into_bytes().into_iter().chain(key))?;
-
-    Ok(InlineArray::from_iter(key))
+pub fn sled_key(key: sql::Key) -> Result<InlineArray, gluesql_core::error::Error> {
+    Ok(InlineArray::from_iter(key.to_cmp_be_bytes()?))
 }
 
 trait AsStorageError<T, E: std::fmt::Display> {
@@ -286,3 +728,303 @@ impl<T, E: std::fmt::Display> AsStorageError<T, E> for Result<T, E> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[derive(Storage, Debug, Clone, Serialize, Deserialize)]
+    struct Account {
+        id: u8,
+        balance: i64,
+    }
+
+    #[derive(Storage, Debug, Clone, Serialize, Deserialize)]
+    #[storage(history)]
+    struct Ledger {
+        id: u8,
+        balance: i64,
+    }
+
+    #[derive(Storage, Debug, Clone, Serialize, Deserialize)]
+    struct Stamped {
+        id: u8,
+        #[created_at]
+        created: NaiveDateTime,
+        #[updated_at]
+        updated: NaiveDateTime,
+    }
+
+    #[tokio::test]
+    async fn reads_dont_see_partially_applied_writes() {
+        let _db = TestDb::new([Account::schema()]).await;
+        for id in [1, 2] {
+            Account { id, balance: 100 }.save().await.unwrap();
+        }
+
+        let writing = AtomicBool::new(true);
+        let transfers = async {
+            for i in 1..=200 {
+                DB.transaction(|tx| async move {
+                    Account {
+                        id: 1,
+                        balance: 100 - i,
+                    }
+                    .save_in(&tx)?;
+                    Account {
+                        id: 2,
+                        balance: 100 + i,
+                    }
+                    .save_in(&tx)?;
+                    OK
+                })
+                .await
+                .unwrap();
+            }
+            writing.store(false, Ordering::SeqCst);
+        };
+        let reads = async {
+            let mut checked = 0;
+            while writing.load(Ordering::SeqCst) {
+                let accounts = Account::get_all().await.unwrap();
+                let total: i64 = accounts.iter().map(|a| a.balance).sum();
+                assert_eq!(
+                    total, 200,
+                    "read a partially applied transfer: {accounts:?}"
+                );
+                checked += 1;
+            }
+            checked
+        };
+
+        let ((), checked) = futures::join!(transfers, reads);
+        assert!(checked > 0);
+    }
+
+    #[tokio::test]
+    async fn concurrent_reads_are_served_by_the_pool() {
+        let _db = TestDb::new([Account::schema()]).await;
+        Account {
+            id: 1,
+            balance: 100,
+        }
+        .save()
+        .await
+        .unwrap();
+
+        let before = DB.read_pool_stats();
+        // more reads than the queue fits
+        let reads = (0..300).map(|_| Account::get_all());
+        for accounts in futures::future::join_all(reads).await {
+            assert_eq!(accounts.unwrap().len(), 1);
+        }
+        let stats = DB.read_pool_stats();
+        assert!(stats.readers >= 1);
+        assert_eq!(stats.reads - before.reads, 300);
+        assert_eq!(stats.queued, 0);
+        assert!(stats.max_queued >= 1);
+    }
+
+    #[tokio::test]
+    async fn full_queues_wait_without_blocking_the_executor() {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(1);
+        let sender = DbSender::new(sender);
+        assert!(!sender.send(1).await.unwrap());
+
+        // deadlocks if the waiting send blocks the runtime which has to drain the queue
+        let drain = async {
+            tokio::task::yield_now().await;
+            receiver.recv().unwrap()
+        };
+        let (waited, first) = futures::join!(sender.send(2), drain);
+        assert!(waited.unwrap());
+        assert_eq!(first, 1);
+        assert_eq!(receiver.recv().unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_dbs_stop_and_remove_their_files() {
+        let db = TestDb::new([Account::schema()]).await;
+        Account {
+            id: 1,
+            balance: 100,
+        }
+        .save()
+        .await
+        .unwrap();
+        let path = DB.temp_path.clone().unwrap();
+        assert!(path.exists());
+
+        drop(db);
+        assert!(!path.exists());
+    }
+
+    #[tokio::test]
+    async fn failed_writes_restore_touched_rows() {
+        let _db = TestDb::new([Account::schema()]).await;
+        for id in [1, 2] {
+            Account { id, balance: 100 }.save().await.unwrap();
+        }
+
+        let overdraft = Account {
+            id: 1,
+            balance: -100,
+        };
+        let failed = DB
+            .write(Transaction::Batch(vec![
+                Transaction::Save {
+                    name: Account::STRUCT_NAME,
+                    key: overdraft.get_pkey().into_sql_key().unwrap(),
+                    row: overdraft.into_row().unwrap(),
+                },
+                Transaction::SqlString("DELETE FROM missing_table".to_owned()),
+            ]))
+            .await;
+        assert!(failed.is_err());
+
+        let balances: Vec<i64> = Account::get_all()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|a| a.balance)
+            .collect();
+        assert_eq!(balances, [100, 100]);
+        assert_eq!(DB.count(Account::STRUCT_NAME).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn history_is_ordered_by_writes_and_rolled_back_with_them() {
+        let _db = TestDb::new([Ledger::schema()]).await;
+        for (id, balance) in [(2, 10), (1, 20), (2, 30), (1, 40)] {
+            Ledger { id, balance }.save().await.unwrap();
+        }
+
+        let rolled_back = Ledger { id: 1, balance: 50 };
+        let failed = DB
+            .write(Transaction::Batch(vec![
+                Transaction::Save {
+                    name: Ledger::STRUCT_NAME,
+                    key: rolled_back.get_pkey().into_sql_key().unwrap(),
+                    row: rolled_back.into_row().unwrap(),
+                },
+                Transaction::SqlString("DELETE FROM missing_table".to_owned()),
+            ]))
+            .await;
+        assert!(failed.is_err());
+
+        let balances = |versions: Vec<Version<Ledger>>| {
+            versions
+                .into_iter()
+                .map(|v| v.value.balance)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            balances(Ledger::recent_history(3).await.unwrap()),
+            [20, 30, 40]
+        );
+        assert_eq!(balances(Ledger::history(1).await.unwrap()), [20, 40]);
+    }
+
+    #[tokio::test]
+    async fn rolls_back_writes_interrupted_by_crashes_on_startup() {
+        use super::{apply, DbConn, TablesCache, WriteState, WRITE_STATE_KEY};
+
+        let path = std::env::temp_dir().join(format!("prest-db-{}", Uuid::now_v7()));
+        let tree = sled::Config::default()
+            .path(path.clone())
+            .flush_every_ms(None)
+            .open::<1024>()
+            .unwrap();
+        let save = |account: &Account| Transaction::Save {
+            name: Account::STRUCT_NAME,
+            key: account.get_pkey().into_sql_key().unwrap(),
+            row: account.into_row().unwrap(),
+        };
+
+        let db = Db::start(tree.clone(), None, Default::default());
+        db._register_schema(Account::schema());
+        db.migrate().await.unwrap();
+        db.write(save(&Account {
+            id: 1,
+            balance: 100,
+        }))
+        .await
+        .unwrap();
+        assert!(db.recovery_report().is_none());
+        let schemas = db.schemas.clone();
+        db.shutdown().unwrap();
+
+        // applied but never marked as finished, like a write of a killed process
+        let state_bytes = tree.get(WRITE_STATE_KEY).unwrap().unwrap();
+        let committed: WriteState = bitcode::deserialize(&state_bytes).unwrap();
+        let tables = TablesCache::default();
+        let mut conn = DbConn {
+            state: WriteState {
+                tx_id: committed.tx_id + 1,
+                in_progress: true,
+            },
+            committed: committed.tx_id,
+            stale: Default::default(),
+            readonly: false,
+            user: None,
+            touched: Some(Default::default()),
+            tree: &tree,
+            schemas: &schemas,
+            tables: &tables,
+        };
+        conn.record_state().unwrap();
+        let torn = Account {
+            id: 1,
+            balance: -100,
+        };
+        apply(&mut conn, save(&torn), &mut vec![]).await.unwrap();
+        tables.release();
+
+        let db = Db::start(tree, Some(path), Default::default());
+        db._register_schema(Account::schema());
+        let report = db.recovery_report().unwrap();
+        assert_eq!(report.tx_id, committed.tx_id + 1);
+        assert_eq!(report.rows, 1);
+
+        let Payload::Rows(rows) = db
+            .read(Query::GetByPKey {
+                name: Account::STRUCT_NAME,
+                pkey: torn.get_pkey().into_sql_key().unwrap(),
+            })
+            .await
+            .unwrap()
+        else {
+            panic!("rows expected");
+        };
+        let balances: Vec<i64> = Account::from_rows(rows)
+            .unwrap()
+            .into_iter()
+            .map(|a| a.balance)
+            .collect();
+        assert_eq!(balances, [100]);
+        db.shutdown().unwrap();
+    }
+
+    #[tokio::test]
+    async fn saves_return_stored_timestamps() {
+        let _db = TestDb::new([Stamped::schema()]).await;
+        let unset = Stamped {
+            id: 1,
+            created: NaiveDateTime::default(),
+            updated: NaiveDateTime::default(),
+        };
+        let inserted = unset.insert_self().await.unwrap();
+        assert_ne!(inserted.created, NaiveDateTime::default());
+
+        let saved = unset.save().await.unwrap();
+        assert_eq!(saved.created, inserted.created);
+        assert!(saved.updated >= inserted.updated);
+
+        let stored = Stamped::get_by_pkey(1).await.unwrap().unwrap();
+        assert_eq!(
+            (stored.created, stored.updated),
+            (saved.created, saved.updated)
+        );
+    }
+}
@@ -0,0 +1,545 @@
+use {
+    crate::*,
+    gluesql_core::ast::{SetExpr, Statement, TableFactor, ToSql},
+    std::{
+        cell::Cell,
+        collections::HashMap,
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+/// Default of the `DB_SLOW_QUERY_MS` threshold
+const DEFAULT_SLOW_QUERY_MS: u64 = 100;
+/// Slow queries kept in memory until the next flush, the rest are dropped
+const MAX_PENDING_SLOW_QUERIES: usize = 1000;
+/// Longer statements are cut in the slow query log
+const MAX_STATEMENT_LEN: usize = 500;
+
+/// How the query accessed the rows, ordered from the cheapest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum QueryPlan {
+    PkLookup,
+    PkRange,
+    Index,
+    FullScan,
+}
+
+impl std::fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            QueryPlan::PkLookup => "pk lookup",
+            QueryPlan::PkRange => "pk range",
+            QueryPlan::Index => "index",
+            QueryPlan::FullScan => "full scan",
+        })
+    }
+}
+
+thread_local! {
+    /// Widest access and number of rows read by the query running on this DB thread
+    static ACCESS: Cell<(Option<QueryPlan>, u64)> = const { Cell::new((None, 0)) };
+}
+
+/// Records that the query running on this thread read rows this way, called by the storages
+pub(crate) fn accessed(plan: QueryPlan, rows: u64) {
+    ACCESS.with(|access| {
+        let (widest, scanned) = access.get();
+        access.set((widest.max(Some(plan)), scanned + rows));
+    });
+}
+
+fn take_access() -> (Option<QueryPlan>, u64) {
+    ACCESS.with(|access| access.replace((None, 0)))
+}
+
+/// Query which took longer than `DB_SLOW_QUERY_MS` including the time in the queue, kept for a week
+#[derive(Debug, Storage, Clone, Serialize, Deserialize)]
+#[storage(ttl = "7d")]
+pub struct SlowQuery {
+    pub id: Uuid,
+    /// Table the query was issued for, `-` if it isn't known
+    pub table_name: String,
+    /// Variant of the query or the write like `GetByPKey` or `Save`
+    pub kind: String,
+    /// Widest access to the rows made by the query, `-` if it didn't read any tables
+    pub plan: String,
+    /// SQL with literals replaced by `?` placeholders or the description of the query without its values
+    pub statement: String,
+    pub is_write: bool,
+    pub queued_ms: f64,
+    pub exec_ms: f64,
+    pub scanned: u64,
+    pub returned: u64,
+    pub failed: bool,
+    #[created_at]
+    pub recorded_at: NaiveDateTime,
+}
+
+/// Aggregated profile of the queries issued for the table since the start
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TableQueryStats {
+    pub table: String,
+    pub reads: u64,
+    pub writes: u64,
+    /// Queries over the `DB_SLOW_QUERY_MS` threshold
+    pub slow: u64,
+    pub full_scans: u64,
+    pub scanned: u64,
+    pub returned: u64,
+    /// Time in the queue and in execution of all the queries
+    pub total_time: Duration,
+    pub max_time: Duration,
+}
+
+impl TableQueryStats {
+    pub fn avg_time(&self) -> Duration {
+        self.total_time / (self.reads + self.writes).max(1) as u32
+    }
+}
+
+/// Per-table stats and pending slow queries, shared by the [`Db`] and its threads
+#[derive(Debug)]
+pub(crate) struct QueryProfiler {
+    threshold: Duration,
+    tables: Mutex<HashMap<String, TableQueryStats>>,
+    slow: Mutex<Vec<SlowQuery>>,
+}
+
+impl Default for QueryProfiler {
+    fn default() -> Self {
+        let threshold = env_var("DB_SLOW_QUERY_MS")
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .unwrap_or(DEFAULT_SLOW_QUERY_MS);
+        Self {
+            threshold: Duration::from_millis(threshold),
+            tables: Default::default(),
+            slow: Default::default(),
+        }
+    }
+}
+
+/// Description of the query which is rendered only for the slow query log
+enum QueryStatement {
+    Blank,
+    /// Description without the values of the query like `limit 10`
+    Described(String),
+    /// SQL redacted only once the query turns out to be slow
+    Sql(String),
+    Parsed(Box<Statement>),
+}
+
+impl QueryStatement {
+    /// Description with literals replaced by placeholders and cut to the max length
+    fn render(self) -> String {
+        let mut statement = match self {
+            QueryStatement::Blank => String::new(),
+            QueryStatement::Described(statement) => statement,
+            QueryStatement::Sql(sql) => redact_literals(&sql),
+            QueryStatement::Parsed(stmt) => redact_literals(&stmt.to_sql()),
+        };
+        if statement.len() > MAX_STATEMENT_LEN {
+            let mut end = MAX_STATEMENT_LEN;
+            while !statement.is_char_boundary(end) {
+                end -= 1;
+            }
+            statement.truncate(end);
+            statement.push_str("...");
+        }
+        statement
+    }
+}
+
+/// Table, kind and description of the query taken before it runs
+pub(crate) struct QueryInfo {
+    table: String,
+    kind: &'static str,
+    statement: QueryStatement,
+    plan: Option<QueryPlan>,
+    is_write: bool,
+}
+
+impl QueryInfo {
+    pub fn of_read(query: &Query) -> Self {
+        let (table, kind, plan, statement) = match query {
+            Query::SqlString(sql) => (None, "Sql", None, QueryStatement::Sql(sql.clone())),
+            Query::SqlStatement(stmt) => (
+                sql_table(stmt),
+                "Sql",
+                None,
+                QueryStatement::Parsed(Box::new(stmt.clone())),
+            ),
+            Query::GetByPKey { name, .. } => (
+                Some(*name),
+                "GetByPKey",
+                Some(QueryPlan::PkLookup),
+                QueryStatement::Blank,
+            ),
+            Query::PKRange { name, .. } => (
+                Some(*name),
+                "PKRange",
+                Some(QueryPlan::PkRange),
+                QueryStatement::Blank,
+            ),
+            Query::IndexRange { name, index, .. } => (
+                Some(*name),
+                "IndexRange",
+                Some(QueryPlan::Index),
+                QueryStatement::Described(format!("{index} in ?..=?")),
+            ),
+            Query::Count { name } => (Some(*name), "Count", None, QueryStatement::Blank),
+            Query::History { name, pkey, limit } => {
+                let rows = if pkey.is_some() { "row" } else { "all rows" };
+                (
+                    Some(*name),
+                    "History",
+                    None,
+                    QueryStatement::Described(format!("{rows} limit {limit:?}")),
+                )
+            }
+            Query::Deleted { name } => (Some(*name), "Deleted", None, QueryStatement::Blank),
+            Query::Search { name, limit, .. } => (
+                Some(*name),
+                "Search",
+                None,
+                QueryStatement::Described(format!("limit {limit}")),
+            ),
+            Query::MigrationReports => (None, "MigrationReports", None, QueryStatement::Blank),
+            Query::Export { .. } => (None, "Export", None, QueryStatement::Blank),
+            Query::SyncChanges { since } => (
+                None,
+                "SyncChanges",
+                None,
+                QueryStatement::Described(format!("since {since}")),
+            ),
+            Query::ReplicationLog { from } => (
+                None,
+                "ReplicationLog",
+                None,
+                QueryStatement::Described(format!("from {from}")),
+            ),
+            Query::ReplicaPosition => (None, "ReplicaPosition", None, QueryStatement::Blank),
+        };
+        Self::new(table, kind, statement, plan, false)
+    }
+
+    pub fn of_write(tx: &Transaction) -> Self {
+        let (table, kind, statement) = match tx {
+            Transaction::SqlString(sql) => (None, "Sql", QueryStatement::Sql(sql.clone())),
+            Transaction::SqlStatement(stmt) => (
+                sql_table(stmt),
+                "Sql",
+                QueryStatement::Parsed(Box::new(stmt.clone())),
+            ),
+            Transaction::Insert { name, .. } => (Some(*name), "Insert", QueryStatement::Blank),
+            Transaction::Save { name, .. } => (Some(*name), "Save", QueryStatement::Blank),
+            Transaction::UpdateField { name, column, .. } => (
+                Some(*name),
+                "UpdateField",
+                QueryStatement::Described(format!("column {column}")),
+            ),
+            Transaction::Delete { name, .. } => (Some(*name), "Delete", QueryStatement::Blank),
+            Transaction::Restore { name, .. } => (Some(*name), "Restore", QueryStatement::Blank),
+            Transaction::Expire { rows } => (
+                None,
+                "Expire",
+                QueryStatement::Described(format!("{} rows", rows.len())),
+            ),
+            Transaction::Replicate { rows } => (
+                None,
+                "Replicate",
+                QueryStatement::Described(format!("{} rows", rows.len())),
+            ),
+            Transaction::SyncIndexes { name } => {
+                (Some(*name), "SyncIndexes", QueryStatement::Blank)
+            }
+            Transaction::Migrate { name } => (Some(*name), "Migrate", QueryStatement::Blank),
+            Transaction::Batch(ops) => {
+                let infos = ops.iter().map(Self::of_write).collect::<Vec<_>>();
+                // attributed to the table only if all the writes are issued for it
+                let table = match infos.first() {
+                    Some(first) if infos.iter().all(|info| info.table == first.table) => {
+                        Some(first.table.clone())
+                    }
+                    _ => None,
+                };
+                let statement = infos
+                    .iter()
+                    .map(|info| QueryStatement::Described(format!("{} {}", info.kind, info.table)))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let mut info = Self::new(
+                    None,
+                    "Batch",
+                    QueryStatement::Described(statement),
+                    None,
+                    true,
+                );
+                if let Some(table) = table {
+                    info.table = table;
+                }
+                return info;
+            }
+            Transaction::ImportRows { name, rows, .. } => (
+                Some(*name),
+                "ImportRows",
+                QueryStatement::Described(format!("{} rows", rows.len())),
+            ),
+            Transaction::PruneImported { name, limit, .. } => (
+                Some(*name),
+                "PruneImported",
+                QueryStatement::Described(format!("limit {limit}")),
+            ),
+            #[cfg(feature = "experimental")]
+            Transaction::Nuke => (None, "Nuke", QueryStatement::Blank),
+        };
+        Self::new(table, kind, statement, None, true)
+    }
+
+    fn new(
+        table: Option<&str>,
+        kind: &'static str,
+        statement: QueryStatement,
+        plan: Option<QueryPlan>,
+        is_write: bool,
+    ) -> Self {
+        Self {
+            table: table.unwrap_or("-").to_owned(),
+            kind,
+            statement,
+            plan,
+            is_write,
+        }
+    }
+}
+
+/// SQL with string and number literals replaced by `?` placeholders so that slow query logs
+/// don't keep values of the rows like emails or tokens
+fn redact_literals(sql: &str) -> String {
+    let mut redacted = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    // whether the previous char continues an identifier like `table2`
+    let mut in_word = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                // quotes inside of strings are escaped by doubling them
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.next_if_eq(&'\'').is_none() {
+                        break;
+                    }
+                }
+                redacted.push('?');
+                in_word = false;
+            }
+            '"' => {
+                redacted.push(c);
+                for c in chars.by_ref() {
+                    redacted.push(c);
+                    if c == '"' {
+                        break;
+                    }
+                }
+                in_word = false;
+            }
+            c if c.is_ascii_digit() && !in_word => {
+                while chars
+                    .next_if(|c| c.is_ascii_alphanumeric() || *c == '.')
+                    .is_some()
+                {}
+                redacted.push('?');
+            }
+            c => {
+                in_word = c.is_alphanumeric() || c == '_' || c == '$';
+                redacted.push(c);
+            }
+        }
+    }
+    redacted
+}
+
+/// Table targeted by the SQL statement if it's a plain query or a write of a single table
+fn sql_table(stmt: &Statement) -> Option<&str> {
+    match stmt {
+        Statement::Query(query) => match &query.body {
+            SetExpr::Select(select) => match &select.from.relation {
+                TableFactor::Table { name, .. } => Some(name.as_str()),
+                _ => None,
+            },
+            _ => None,
+        },
+        Statement::Insert { table_name, .. }
+        | Statement::Update { table_name, .. }
+        | Statement::Delete { table_name, .. } => Some(table_name.as_str()),
+        _ => None,
+    }
+}
+
+/// Measures the query run by the current DB thread
+pub(crate) struct QueryTimer {
+    info: QueryInfo,
+    queued_at: Instant,
+    started: Instant,
+}
+
+impl QueryTimer {
+    pub fn start(info: QueryInfo, queued_at: Instant) -> Self {
+        // drops accesses made outside of queries like the writer's expiration sweeps
+        take_access();
+        Self {
+            info,
+            queued_at,
+            started: Instant::now(),
+        }
+    }
+
+    /// Adds the query to the stats of its table and queues it for the slow query log if needed
+    pub fn finish(self, profiler: &QueryProfiler, result: &Result<Payload>) {
+        let exec = self.started.elapsed();
+        let queued = self.started.saturating_duration_since(self.queued_at);
+        let total = queued + exec;
+        let (accessed, scanned) = take_access();
+        let plan = self.info.plan.max(accessed);
+        let returned = match result {
+            Ok(payload) => returned_rows(payload),
+            Err(_) => 0,
+        };
+        let QueryInfo {
+            table,
+            kind,
+            statement,
+            is_write,
+            ..
+        } = self.info;
+        let plan_name = plan.map_or("-".to_owned(), |plan| plan.to_string());
+        trace!(
+            target: "db",
+            table = %table,
+            kind,
+            plan = %plan_name,
+            queued_us = queued.as_micros() as u64,
+            exec_us = exec.as_micros() as u64,
+            scanned,
+            returned,
+        );
+
+        let slow = total >= profiler.threshold;
+        {
+            let mut tables = profiler.tables.lock().unwrap();
+            let stats = tables
+                .entry(table.clone())
+                .or_insert_with(|| TableQueryStats {
+                    table: table.clone(),
+                    ..Default::default()
+                });
+            match is_write {
+                true => stats.writes += 1,
+                false => stats.reads += 1,
+            }
+            stats.slow += slow as u64;
+            stats.full_scans += (plan == Some(QueryPlan::FullScan)) as u64;
+            stats.scanned += scanned;
+            stats.returned += returned;
+            stats.total_time += total;
+            stats.max_time = stats.max_time.max(total);
+        }
+
+        // writes of the log itself aren't logged so that they don't feed themselves
+        if !slow || table == SlowQuery::STRUCT_NAME {
+            return;
+        }
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        warn!(target: "db", "slow {kind} of {table} took {:.1}ms ({plan_name}, {scanned} rows scanned)", ms(total));
+        let mut pending = profiler.slow.lock().unwrap();
+        if pending.len() < MAX_PENDING_SLOW_QUERIES {
+            pending.push(SlowQuery {
+                id: Uuid::now_v7(),
+                table_name: table,
+                kind: kind.to_owned(),
+                plan: plan_name,
+                statement: statement.render(),
+                is_write,
+                queued_ms: ms(queued),
+                exec_ms: ms(exec),
+                scanned,
+                returned,
+                failed: result.is_err(),
+                recorded_at: Utc::now().naive_utc(),
+            });
+        }
+    }
+}
+
+fn returned_rows(payload: &Payload) -> u64 {
+    match payload {
+        Payload::Rows(rows) => rows.len() as u64,
+        Payload::Affected(count) => *count as u64,
+        Payload::History(entries) => entries.len() as u64,
+        Payload::Sync(response) => response.changes.len() as u64,
+        Payload::Replication(entries) => entries.len() as u64,
+        Payload::Migrations(reports) => reports.len() as u64,
+        Payload::Count(_) | Payload::Cursor(_) => 1,
+        Payload::Sql(_) | Payload::Success => 0,
+    }
+}
+
+/// Persists slow queries collected by the global DB every few seconds
+pub(crate) fn schedule_slow_query_flush() {
+    RT.every(5)
+        .seconds()
+        .spawn(|| async { DB.flush_slow_queries().await });
+}
+
+impl Db {
+    /// Aggregated profiles of the queries per table since the start, slowest on average first
+    pub fn query_stats(&self) -> Vec<TableQueryStats> {
+        let mut stats = self
+            .profiler
+            .tables
+            .lock()
+            .unwrap()
+            .values()
+            .cloned()
+            .collect::<Vec<_>>();
+        stats.sort_by(|a, b| b.avg_time().cmp(&a.avg_time()));
+        stats
+    }
+
+    /// Saves queries over the threshold into the [`SlowQuery`] table
+    pub(crate) async fn flush_slow_queries(&self) -> Result {
+        let pending = std::mem::take(&mut *self.profiler.slow.lock().unwrap());
+        // followers don't write their own records
+        if pending.is_empty() || self.is_replica() {
+            return OK;
+        }
+        self.transaction(move |tx| async move {
+            for query in pending {
+                query.save_in(&tx)?;
+            }
+            OK
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::redact_literals;
+
+    #[test]
+    fn redacts_sql_literals() {
+        assert_eq!(
+            redact_literals("SELECT * FROM User WHERE email = 'a@b.c' AND age > 30 LIMIT 10"),
+            "SELECT * FROM User WHERE email = ? AND age > ? LIMIT ?"
+        );
+        assert_eq!(
+            redact_literals(r#"UPDATE "Todo2" SET task = 'it''s' WHERE id = -1.5"#),
+            r#"UPDATE "Todo2" SET task = ? WHERE id = -?"#
+        );
+        assert_eq!(
+            redact_literals("SELECT col1 FROM t WHERE id = $1"),
+            "SELECT col1 FROM t WHERE id = $1"
+        );
+    }
+}
@@ -0,0 +1,206 @@
+use {
+    super::{row_values, DbConn},
+    crate::*,
+    gluesql_core::store::{Store, StoreMut},
+};
+
+impl<'a> DbConn<'a> {
+    /// Checks that rows referenced by the enforced relations of the row exist
+    pub async fn check_references(&self, name: &str, row: &[sql::Value]) -> Result {
+        let Some(schema) = self.schemas.fetch_struct_schema(name) else {
+            return OK;
+        };
+        for (field, value) in schema.fields().iter().zip(row) {
+            let Some(referenced) = field.references else {
+                continue;
+            };
+            if field.on_delete == OnDelete::Keep || matches!(value, sql::Value::Null) {
+                continue;
+            }
+            let key = sql::Key::try_from(value.clone())?;
+            if self.fetch_data(referenced, &key).await?.is_none() {
+                return Err(e!(
+                    "{name}.{} references missing {referenced} row {key:?}",
+                    field.name
+                ));
+            }
+        }
+        OK
+    }
+
+    /// Deletes the row along with the rows referencing it with `OnDelete::Cascade`,
+    /// fails if it's referenced with `OnDelete::Restrict`
+    pub async fn delete_row(
+        &mut self,
+        name: &'static str,
+        key: sql::Key,
+        changes: &mut Vec<RowChange>,
+    ) -> Result {
+        if self.fetch_data(name, &key).await?.is_none() {
+            return Err(e!("deleting non-existent value {key:?}"));
+        }
+
+        let mut pending = vec![(name, key)];
+        while let Some((name, key)) = pending.pop() {
+            // might be already deleted through another relation
+            let Some(old) = row_values(self.fetch_data(name, &key).await?) else {
+                continue;
+            };
+            self.delete_data(name, vec![key]).await?;
+            if self.fetch_struct_schema(name)?.soft_delete() {
+                self.bury(name, &old)?;
+            }
+            pending.extend(self.referencing_rows(name, &old).await?);
+            changes.push(RowChange {
+                table: name,
+                old: Some(old),
+                new: None,
+            });
+        }
+        OK
+    }
+
+    /// Keys of the rows which should be deleted along with the referenced one
+    async fn referencing_rows(
+        &self,
+        name: &str,
+        row: &[sql::Value],
+    ) -> Result<Vec<(&'static str, sql::Key)>> {
+        let Some(schema) = self.schemas.fetch_struct_schema(name) else {
+            return Ok(vec![]);
+        };
+        let Some(pkey) = schema
+            .fields()
+            .iter()
+            .position(|f| f.pkey)
+            .and_then(|i| row.get(i))
+        else {
+            return Ok(vec![]);
+        };
+
+        let mut referencing = vec![];
+        for table in self.schemas.all() {
+            for (index, field) in table.fields().iter().enumerate() {
+                if field.references != Some(name) || field.on_delete == OnDelete::Keep {
+                    continue;
+                }
+
+                let keys = match field.index {
+                    true => self
+                        .index_entries(
+                            table.name(),
+                            field.name,
+                            Some(pkey.clone()),
+                            Some(pkey.clone()),
+                        )?
+                        .into_iter()
+                        .map(|(key, _)| sql::Key::Bytea(key))
+                        .collect(),
+                    // referencing primary keys aren't indexed separately
+                    false => self.scan_referencing(table.name(), index, pkey).await?,
+                };
+                for key in keys {
+                    if field.on_delete == OnDelete::Restrict {
+                        return Err(e!(
+                            "{name} row is referenced by {}.{}",
+                            table.name(),
+                            field.name
+                        ));
+                    }
+                    referencing.push((table.name(), key));
+                }
+            }
+        }
+        Ok(referencing)
+    }
+
+    async fn scan_referencing(
+        &self,
+        table_name: &str,
+        index: usize,
+        pkey: &sql::Value,
+    ) -> Result<Vec<sql::Key>> {
+        let mut keys = vec![];
+        let mut rows = self.scan_data(table_name).await?;
+        while let Some(item) = rows.next().await {
+            let (key, row) = item?;
+            if row_values(Some(row))
+                .and_then(|r| r.get(index).cloned())
+                .as_ref()
+                == Some(pkey)
+            {
+                keys.push(key);
+            }
+        }
+        Ok(keys)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::*;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Owner {
+        id: u8,
+    }
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Task {
+        id: u8,
+        #[references(Owner)]
+        owner: u8,
+        #[references(Owner, on_delete = cascade)]
+        reviewer: Option<u8>,
+    }
+
+    fn ids(tasks: Vec<Task>) -> Vec<u8> {
+        let mut ids = tasks.into_iter().map(|t| t.id).collect::<Vec<_>>();
+        ids.sort();
+        ids
+    }
+
+    #[tokio::test]
+    async fn gets_related_rows_and_cascades_deletes() {
+        let _db = TestDb::new([Owner::schema(), Task::schema()]).await;
+        for id in [1, 2] {
+            Owner { id }.save().await.unwrap();
+        }
+        let tasks = [(1, 1, Some(2)), (2, 1, None), (3, 2, None)];
+        for (id, owner, reviewer) in tasks {
+            Task {
+                id,
+                owner,
+                reviewer,
+            }
+            .save()
+            .await
+            .unwrap();
+        }
+
+        let task = Task::get_by_pkey(1).await.unwrap().unwrap();
+        assert_eq!(task.owner().await.unwrap(), Some(Owner { id: 1 }));
+        assert_eq!(task.reviewer().await.unwrap(), Some(Owner { id: 2 }));
+        let unreviewed = Task::get_by_pkey(2).await.unwrap().unwrap();
+        assert_eq!(unreviewed.reviewer().await.unwrap(), None);
+        assert_eq!(ids(Owner { id: 1 }.tasks_by_owner().await.unwrap()), [1, 2]);
+        assert_eq!(ids(Owner { id: 2 }.tasks_by_reviewer().await.unwrap()), [1]);
+
+        // only the relations with `on_delete` are enforced
+        let unknown_reviewer = Task {
+            id: 4,
+            owner: 1,
+            reviewer: Some(9),
+        };
+        assert!(unknown_reviewer.save().await.is_err());
+        let unknown_owner = Task {
+            id: 4,
+            owner: 9,
+            reviewer: None,
+        };
+        unknown_owner.save().await.unwrap();
+
+        Owner { id: 2 }.remove().await.unwrap();
+        assert_eq!(ids(Task::get_all().await.unwrap()), [2, 3, 4]);
+    }
+}
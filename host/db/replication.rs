@@ -0,0 +1,432 @@
+use {
+    super::{row_values, AsStorageError, DbConn, DbCore, Snapshot},
+    crate::*,
+    gluesql_core::{
+        error::Result as StorageResult,
+        store::{DataRow, Store, StoreMut},
+    },
+    std::sync::atomic::Ordering,
+};
+
+/// Position of the follower in the primary's log, see [`ReplicaPosition`]
+const REPLICA_POSITION_KEY: &[u8] = b"state/replica";
+/// Id of the latest write removed from the log or written before it was enabled
+const LOG_TRIMMED_KEY: &[u8] = b"state/replication_trimmed";
+/// Default number of the latest write ids kept in the log, configurable with `DB_REPLICATION_LOG_LIMIT`
+const DEFAULT_LOG_LIMIT: usize = 100_000;
+/// Max number of writes served to a follower per request
+const REPLICATION_BATCH: usize = 1000;
+
+/// Replication role of the instance, enabled by the `replication` feature
+///
+/// `DB_REPLICATION_TOKEN` makes it log committed writes and serve them to followers which present the token,
+/// and `DB_REPLICATE_FROM` makes it a read-only follower of the primary at the provided url
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ReplicationConfig {
+    pub token: Option<String>,
+    pub primary: Option<String>,
+}
+
+impl ReplicationConfig {
+    pub fn from_env() -> Self {
+        if !cfg!(feature = "replication") {
+            return Self::default();
+        }
+        let non_empty = |name| env_var(name).ok().filter(|v| !v.is_empty());
+        Self {
+            token: non_empty("DB_REPLICATION_TOKEN"),
+            primary: non_empty("DB_REPLICATE_FROM").map(|url| url.trim_end_matches('/').to_owned()),
+        }
+    }
+}
+
+/// Committed writes kept in their own tree as `{tx_id}` => [`LoggedWrite`]
+pub(crate) struct ReplicationLog {
+    tree: sled::Tree,
+    limit: usize,
+}
+
+/// Write as it's kept in the log: final values of the rows it modified
+///
+/// Followers store these values instead of re-running the write so that SQL statements,
+/// timestamps and cascades are evaluated only once by the primary
+#[derive(Serialize, Deserialize)]
+struct LoggedWrite {
+    rows: Vec<LoggedRow>,
+    user: Option<Uuid>,
+    committed_at: NaiveDateTime,
+}
+
+#[derive(Serialize, Deserialize)]
+struct LoggedRow {
+    table: String,
+    /// Key of the row in the table's data tree
+    key: Vec<u8>,
+    /// `None` if the row was removed
+    values: Option<Vec<sql::Value>>,
+}
+
+/// Position of the follower which is rolled back along with the write that moved it
+#[derive(Serialize, Deserialize)]
+struct ReplicaPosition {
+    /// Id of the primary's write that will be applied next
+    next: u64,
+    previous: u64,
+    /// Id of the follower's own write that moved the position
+    tx_id: u64,
+}
+
+impl ReplicationLog {
+    /// Opens the log, writes made before it was enabled are marked as unavailable for followers
+    pub fn open(core: &DbCore) -> StorageResult<Self> {
+        let tree = core.tree.open_tree("#replication").as_storage_err()?;
+        let next_id = core.tracker.next_id.load(Ordering::SeqCst);
+        if core.tree.get(LOG_TRIMMED_KEY).as_storage_err()?.is_none() && next_id > 0 {
+            core.tree
+                .insert(LOG_TRIMMED_KEY, (next_id - 1).to_be_bytes().to_vec())
+                .as_storage_err()?;
+        }
+        let limit = env_var("DB_REPLICATION_LOG_LIMIT")
+            .ok()
+            .and_then(|v| v.parse::<usize>().ok())
+            .unwrap_or(DEFAULT_LOG_LIMIT)
+            .max(1);
+        Ok(Self { tree, limit })
+    }
+
+    /// Serializes values of the rows touched by the applied write, `None` if it didn't modify any
+    fn entry(conn: &DbConn) -> Result<Option<Vec<u8>>> {
+        let Some(touched) = &conn.touched else {
+            return Ok(None);
+        };
+        let touched = touched.lock().expect("touched keys lock isn't poisoned");
+
+        let mut rows = vec![];
+        for (table, keys) in touched.iter() {
+            let trees = conn.table(table)?;
+            for key in keys.rows.iter() {
+                let values = match trees.data.get(key)? {
+                    Some(value) => {
+                        let snapshot: Snapshot<DataRow> = bitcode::deserialize(&value).somehow()?;
+                        row_values(conn.visible(snapshot))
+                    }
+                    None => None,
+                };
+                rows.push(LoggedRow {
+                    table: table.clone(),
+                    key: key.clone(),
+                    values,
+                });
+            }
+        }
+        if rows.is_empty() {
+            return Ok(None);
+        }
+
+        let entry = LoggedWrite {
+            rows,
+            user: conn.user,
+            committed_at: Utc::now().naive_utc(),
+        };
+        Ok(Some(bitcode::serialize(&entry).somehow()?))
+    }
+
+    /// Committed writes starting from the id, up to the latest committed one
+    pub fn read(&self, core: &DbCore, from: u64, committed: u64) -> Result<Vec<(u64, Vec<u8>)>> {
+        if let Some(trimmed) = core.tree.get(LOG_TRIMMED_KEY)? {
+            let trimmed = u64::from_be_bytes(trimmed.as_ref().try_into().somehow()?);
+            if from <= trimmed {
+                return Err(e!(
+                    "write {from} isn't in the replication log anymore, restart the follower from a copy of the primary's data"
+                ));
+            }
+        }
+        let mut entries = vec![];
+        for item in self.tree.range(from.to_be_bytes()..) {
+            let (key, value) = item?;
+            let tx_id = u64::from_be_bytes(key.as_ref().try_into().somehow()?);
+            if tx_id > committed || entries.len() == REPLICATION_BATCH {
+                break;
+            }
+            entries.push((tx_id, value.to_vec()));
+        }
+        Ok(entries)
+    }
+
+    /// Removes writes older than the limit, runs on the writer thread between writes
+    pub fn trim(&self, core: &DbCore) -> Result {
+        let next_id = core.tracker.next_id.load(Ordering::SeqCst);
+        let Some(oldest_kept) = next_id.checked_sub(self.limit as u64) else {
+            return OK;
+        };
+        let mut trimmed = None;
+        for item in self.tree.range(..oldest_kept.to_be_bytes()) {
+            let (key, _) = item?;
+            self.tree.remove(&key)?;
+            trimmed = Some(key);
+        }
+        if let Some(key) = trimmed {
+            core.tree.insert(LOG_TRIMMED_KEY, key)?;
+        }
+        OK
+    }
+}
+
+/// Prepares the follower's position before any of its own writes move the ids
+pub(super) fn init_replica(core: &DbCore) -> StorageResult<()> {
+    if core
+        .tree
+        .get(REPLICA_POSITION_KEY)
+        .as_storage_err()?
+        .is_some()
+    {
+        return Ok(());
+    }
+    // data copied from the primary continues right after the primary's last write
+    let next = core.tracker.next_id.load(Ordering::SeqCst);
+    let position = ReplicaPosition {
+        next,
+        previous: next,
+        tx_id: u64::MAX,
+    };
+    let position = bitcode::serialize(&position).as_storage_err()?;
+    core.tree
+        .insert(REPLICA_POSITION_KEY, position)
+        .as_storage_err()?;
+    Ok(())
+}
+
+/// Id of the primary's write that the follower will apply next
+pub(super) fn replica_position(core: &DbCore) -> Result<u64> {
+    let Some(position) = core.tree.get(REPLICA_POSITION_KEY)? else {
+        return Err(e!("DB isn't a replica"));
+    };
+    let position: ReplicaPosition = bitcode::deserialize(&position).somehow()?;
+    Ok(position.next)
+}
+
+/// Appends the write into the log if it's `logged` and moves the follower's position if it was replicated
+pub(super) fn record(
+    core: &DbCore,
+    conn: &DbConn,
+    tx_id: u64,
+    logged: bool,
+    replicated: Option<u64>,
+) -> Result {
+    if let (Some(log), true) = (&core.log, logged) {
+        if let Some(entry) = ReplicationLog::entry(conn)? {
+            log.tree.insert(tx_id.to_be_bytes(), entry)?;
+        }
+    }
+    if let Some(primary_tx_id) = replicated {
+        let previous = replica_position(core)?;
+        let position = ReplicaPosition {
+            next: primary_tx_id + 1,
+            previous,
+            tx_id,
+        };
+        core.tree.insert(
+            REPLICA_POSITION_KEY,
+            bitcode::serialize(&position).somehow()?,
+        )?;
+    }
+    OK
+}
+
+/// Removes traces of the write which failed or was interrupted by a crash
+pub(super) fn rollback(core: &DbCore, tx_id: u64) -> Result {
+    if let Some(log) = &core.log {
+        log.tree.remove(tx_id.to_be_bytes())?;
+    }
+    if let Some(position) = core.tree.get(REPLICA_POSITION_KEY)? {
+        let position: ReplicaPosition = bitcode::deserialize(&position).somehow()?;
+        if position.tx_id == tx_id {
+            let restored = ReplicaPosition {
+                next: position.previous,
+                ..position
+            };
+            core.tree.insert(
+                REPLICA_POSITION_KEY,
+                bitcode::serialize(&restored).somehow()?,
+            )?;
+        }
+    }
+    OK
+}
+
+/// Followers apply only the primary's writes and keep their schemas up to date themselves
+pub(super) fn allowed_on_replica(tx: &Transaction) -> bool {
+    match tx {
+        Transaction::Migrate { .. } | Transaction::SyncIndexes { .. } => true,
+        Transaction::Batch(ops) => ops.iter().all(allowed_on_replica),
+        _ => false,
+    }
+}
+
+impl<'a> DbConn<'a> {
+    /// Stores the value of the row replicated from the primary, burying and unburying it like the primary did
+    pub async fn replicate_row(
+        &mut self,
+        name: &'static str,
+        key: sql::Key,
+        values: Option<Vec<sql::Value>>,
+        changes: &mut Vec<RowChange>,
+    ) -> Result {
+        let old = row_values(self.fetch_data(name, &key).await?);
+        match (&old, &values) {
+            (old, Some(new)) => {
+                if old.is_none() {
+                    self.unbury(name, &key)?;
+                }
+                self.insert_data(name, vec![(key, DataRow::Vec(new.clone()))])
+                    .await?;
+            }
+            (Some(old), None) => {
+                self.delete_data(name, vec![key]).await?;
+                if self.fetch_struct_schema(name)?.soft_delete() {
+                    self.bury(name, old)?;
+                }
+            }
+            (None, None) => return OK,
+        }
+        changes.push(RowChange {
+            table: name,
+            old,
+            new: values,
+        });
+        OK
+    }
+}
+
+impl Db {
+    /// Whether this instance follows the primary set with `DB_REPLICATE_FROM` and rejects other writes
+    pub fn is_replica(&self) -> bool {
+        self.replication.primary.is_some()
+    }
+
+    /// Serialized committed writes starting from the id, at most [`REPLICATION_BATCH`] of them
+    pub(crate) async fn replication_log(&self, from: u64) -> Result<Vec<(u64, Vec<u8>)>> {
+        match self.read(Query::ReplicationLog { from }).await? {
+            Payload::Replication(entries) => Ok(entries),
+            p => Err(e!("Got {p:?} instead of replication log")),
+        }
+    }
+
+    /// Id of the primary's write that this follower will apply next
+    pub(crate) async fn replica_position(&self) -> Result<u64> {
+        match self.read(Query::ReplicaPosition).await? {
+            Payload::Cursor(position) => Ok(position),
+            p => Err(e!("Got {p:?} instead of replica position")),
+        }
+    }
+
+    /// Applies the write from the primary's log and moves the position past it
+    pub(crate) async fn replicate(&self, tx_id: u64, entry: &[u8]) -> Result<NaiveDateTime> {
+        let write: LoggedWrite = bitcode::deserialize(entry).somehow()?;
+        let mut rows = vec![];
+        for row in write.rows {
+            let Some(schema) = self.schemas.fetch_struct_schema(&row.table) else {
+                return Err(e!("replicated table {} isn't registered", row.table));
+            };
+            rows.push((schema.name(), sql::Key::Bytea(row.key), row.values));
+        }
+        let tx = Transaction::Replicate { rows };
+        let (user, committed_at) = (write.user, write.committed_at);
+
+        let (returner, result) = async_oneshot_channel::oneshot::<Result<Payload>>();
+        self.write
+            .send((tx, returner, user, Some(tx_id), std::time::Instant::now()))
+            .await?;
+        result.recv().await.ok_or(e!("missing db return"))??;
+        Ok(committed_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    struct Account {
+        id: u8,
+        balance: i64,
+    }
+
+    async fn open(replication: ReplicationConfig) -> Db {
+        let path = std::env::temp_dir().join(format!("prest-db-{}", Uuid::now_v7()));
+        let tree = sled::Config::default()
+            .path(path.clone())
+            .flush_every_ms(None)
+            .open::<1024>()
+            .unwrap();
+        let db = Db::start(tree, Some(path), replication);
+        db._register_schema(Account::schema());
+        db.migrate().await.unwrap();
+        db
+    }
+
+    fn save(account: &Account) -> Transaction {
+        Transaction::Save {
+            name: Account::STRUCT_NAME,
+            key: account.get_pkey().into_sql_key().unwrap(),
+            row: account.into_row().unwrap(),
+        }
+    }
+
+    async fn accounts(db: &Db) -> Vec<Account> {
+        let sql = format!("SELECT * FROM {} ORDER BY id", Account::STRUCT_NAME);
+        match db.read_sql(&sql).await.unwrap() {
+            Payload::Rows(rows) => Account::from_rows(rows).unwrap(),
+            p => panic!("rows expected, got {p:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn followers_apply_logged_writes_of_the_primary() {
+        let primary = open(ReplicationConfig {
+            token: Some("secret".to_owned()),
+            primary: None,
+        })
+        .await;
+        let follower = open(ReplicationConfig {
+            token: None,
+            primary: Some("http://primary.test".to_owned()),
+        })
+        .await;
+
+        for (id, balance) in [(1, 100), (2, 50)] {
+            primary.write(save(&Account { id, balance })).await.unwrap();
+        }
+        let removed = Account { id: 2, balance: 50 };
+        let delete = Transaction::Delete {
+            name: Account::STRUCT_NAME,
+            key: removed.get_pkey().into_sql_key().unwrap(),
+        };
+        primary.write(delete).await.unwrap();
+
+        // migrations aren't logged since followers run them themselves
+        let from = follower.replica_position().await.unwrap();
+        let entries = primary.replication_log(from).await.unwrap();
+        assert_eq!(entries.len(), 3);
+        for (tx_id, entry) in &entries {
+            follower.replicate(*tx_id, entry).await.unwrap();
+        }
+        let (last, _) = entries.last().unwrap();
+        assert_eq!(follower.replica_position().await.unwrap(), last + 1);
+        assert_eq!(accounts(&follower).await, accounts(&primary).await);
+        assert_eq!(
+            accounts(&follower).await,
+            [Account {
+                id: 1,
+                balance: 100
+            }]
+        );
+
+        assert!(follower.is_replica());
+        let rejected = follower.write(save(&Account { id: 3, balance: 0 })).await;
+        assert!(rejected.is_err());
+
+        primary.shutdown().unwrap();
+        follower.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,215 @@
+use {
+    super::{
+        accessed, index_sync::build_index_key_prefix, AsStorageError, DbConn, QueryPlan, Snapshot,
+    },
+    crate::*,
+    gluesql_core::store::DataRow,
+    std::collections::{BTreeSet, HashMap},
+};
+
+/// BM25 term frequency saturation
+const K1: f64 = 1.2;
+
+/// Column with the full-text index, see `#[searchable]`
+///
+/// Its tokens are stored in the table's index tree as `{index}/{token}` => `Vec<Snapshot<data key>>`
+/// just like values of the regular indexes so that they are rolled back and rebuilt the same way
+#[derive(Debug, Clone)]
+pub(crate) struct SearchColumn {
+    pub index_name: String,
+    pub position: usize,
+    pub stemmed: bool,
+}
+
+impl SearchColumn {
+    pub fn of(schema: StructSchema) -> Vec<Self> {
+        schema
+            .fields()
+            .iter()
+            .enumerate()
+            .filter(|(_, field)| field.searchable)
+            .map(|(position, field)| Self {
+                // stemming changes tokens so the index is rebuilt when it's toggled
+                index_name: match field.stemmed {
+                    true => format!("#search-stemmed:{}", field.name),
+                    false => format!("#search:{}", field.name),
+                },
+                position,
+                stemmed: field.stemmed,
+            })
+            .collect()
+    }
+
+    /// All tokens of the column's text in the row
+    pub fn tokens(&self, row: &DataRow) -> Vec<String> {
+        match row {
+            DataRow::Vec(values) => match values.get(self.position) {
+                Some(sql::Value::Str(text)) => tokenize(text, self.stemmed),
+                _ => vec![],
+            },
+            DataRow::Map(_) => vec![],
+        }
+    }
+
+    /// Distinct tokens of the column's text in the row
+    pub fn token_set(&self, row: &DataRow) -> BTreeSet<String> {
+        self.tokens(row).into_iter().collect()
+    }
+
+    pub fn key(&self, token: &str) -> Vec<u8> {
+        let mut key = build_index_key_prefix(&self.index_name);
+        key.extend_from_slice(token.as_bytes());
+        key
+    }
+}
+
+/// Lowercased alphanumeric words of the text, optionally reduced to their stems
+pub(crate) fn tokenize(text: &str, stemmed: bool) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(|word| {
+            let word = word.to_lowercase();
+            match stemmed {
+                true => stem(&word),
+                false => word,
+            }
+        })
+        .collect()
+}
+
+/// Strips common english suffixes so that forms like `tasks`, `tasked` and `tasking` match
+fn stem(word: &str) -> String {
+    let long_enough = |stem: &str| stem.chars().count() >= 3;
+    if let Some(stem) = word.strip_suffix("ies").filter(|s| long_enough(s)) {
+        return format!("{stem}y");
+    }
+    if let Some(stem) = word.strip_suffix("es") {
+        if ["x", "z", "ch", "sh", "ss"]
+            .iter()
+            .any(|end| stem.ends_with(end))
+        {
+            return stem.to_owned();
+        }
+    }
+    for suffix in ["ingly", "edly", "ing", "ed", "ly", "s"] {
+        if let Some(stem) = word.strip_suffix(suffix).filter(|s| long_enough(s)) {
+            if !(suffix == "s" && stem.ends_with('s')) {
+                return stem.to_owned();
+            }
+        }
+    }
+    word.to_owned()
+}
+
+impl<'a> DbConn<'a> {
+    /// Rows with the most relevant matches of the query in the searchable columns, ranked by BM25
+    pub async fn search(
+        &self,
+        table_name: &str,
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<Vec<sql::Value>>> {
+        let schema = self.fetch_struct_schema(table_name)?;
+        let columns = SearchColumn::of(schema);
+        if columns.is_empty() {
+            return Err(e!("{table_name} has no #[searchable] columns"));
+        }
+
+        let trees = self.table(table_name)?;
+        let total = self.count_rows(table_name)? as f64;
+
+        // weights of the query tokens per column and the rows containing them
+        let mut terms = vec![];
+        let mut candidates = BTreeSet::new();
+        for column in columns.iter() {
+            let tokens = tokenize(query, column.stemmed)
+                .into_iter()
+                .collect::<BTreeSet<_>>();
+            for token in tokens {
+                let Some(postings) = trees.index.get(column.key(&token)).as_storage_err()? else {
+                    continue;
+                };
+                let postings: Vec<Snapshot<Vec<u8>>> =
+                    bitcode::deserialize(&postings).as_storage_err()?;
+                let keys = postings
+                    .into_iter()
+                    .filter_map(|s| self.visible(s))
+                    .collect::<Vec<_>>();
+                if keys.is_empty() {
+                    continue;
+                }
+                let found = keys.len() as f64;
+                let idf = (1.0 + (total - found + 0.5) / (found + 0.5)).ln();
+                candidates.extend(keys);
+                terms.push((column, token, idf));
+            }
+        }
+
+        let mut ranked = vec![];
+        for key in candidates {
+            let Some(value) = trees.data.get(&key).as_storage_err()? else {
+                continue;
+            };
+            let snapshot: Snapshot<DataRow> = bitcode::deserialize(&value).as_storage_err()?;
+            let Some(row) = self.visible(snapshot) else {
+                continue;
+            };
+
+            let mut frequencies: HashMap<usize, HashMap<String, usize>> = HashMap::new();
+            let mut score = 0.0;
+            for (column, token, idf) in terms.iter() {
+                let counts = frequencies.entry(column.position).or_insert_with(|| {
+                    let mut counts = HashMap::new();
+                    for token in column.tokens(&row) {
+                        *counts.entry(token).or_default() += 1;
+                    }
+                    counts
+                });
+                let frequency = counts.get(token).copied().unwrap_or(0) as f64;
+                score += idf * frequency * (K1 + 1.0) / (frequency + K1);
+            }
+
+            if let DataRow::Vec(values) = row {
+                ranked.push((score, values));
+            }
+        }
+
+        accessed(QueryPlan::Index, ranked.len() as u64);
+        ranked.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        Ok(ranked
+            .into_iter()
+            .take(limit)
+            .map(|(_, values)| values)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{stem, tokenize};
+
+    #[test]
+    fn tokenizes_lowercased_words() {
+        assert_eq!(
+            tokenize("Buy 2 Apples, then call Bob's mom!", false),
+            ["buy", "2", "apples", "then", "call", "bob", "s", "mom"]
+        );
+        assert_eq!(tokenize("Tasks: tasked, tasking", true), ["task"; 3]);
+    }
+
+    #[test]
+    fn stems_common_suffixes() {
+        let stems = [
+            ("stories", "story"),
+            ("boxes", "box"),
+            ("wishes", "wish"),
+            ("quickly", "quick"),
+            ("class", "class"),
+            ("bus", "bus"),
+            ("red", "red"),
+        ];
+        for (word, expected) in stems {
+            assert_eq!(stem(word), expected, "{word}");
+        }
+    }
+}
@@ -49,6 +49,7 @@ impl<T: Clone + Debug> Snapshot<T> {
             if let Some(data) = self.data.take() {
                 self.backup = Some(data);
                 self.backup_txid = Some(self.data_txid);
+                self.data_txid = state.tx_id;
                 return Some(self);
             }
             // and there is no data - remove
@@ -93,27 +94,66 @@ impl<T: Clone + Debug> Snapshot<T> {
         }
     }
 
-    pub fn take(mut self, state: WriteState) -> Option<T> {
-        if state.in_progress && state.tx_id == self.data_txid {
+    /// Version visible to connections which see writes up to the `visible` id:
+    /// the current data or the backup if it was modified by a later write
+    pub fn take(mut self, visible: u64) -> Option<T> {
+        if self.data_txid > visible {
             self.backup.take()
         } else {
             self.data.take()
         }
     }
 
-    pub fn get(&self, state: WriteState) -> Option<T> {
-        if state.in_progress && state.tx_id == self.data_txid {
-            self.backup.clone().take()
+    pub fn get(&self, visible: u64) -> Option<T> {
+        if self.data_txid > visible {
+            self.backup.clone()
         } else {
-            self.data.clone().take()
+            self.data.clone()
         }
     }
 
-    pub fn get_mut(&mut self, state: WriteState) -> Option<&mut T> {
-        if let Some(data) = &mut self.data {
-            Some(data)
-        } else {
-            None
+    /// Whether the version visible up to the id was already overwritten by later writes
+    pub fn outdated(&self, visible: u64) -> bool {
+        self.data_txid > visible && self.backup_txid.is_some_and(|txid| txid > visible)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Snapshot, WriteState};
+
+    fn write(tx_id: u64) -> WriteState {
+        WriteState {
+            tx_id,
+            in_progress: true,
         }
     }
+
+    #[test]
+    fn readers_see_versions_committed_before_their_snapshot() {
+        let mut snapshot = Snapshot::new(1, "a");
+        snapshot.update(write(2), "b");
+        assert_eq!(snapshot.get(1), Some("a"));
+        assert_eq!(snapshot.get(2), Some("b"));
+        assert!(!snapshot.outdated(1));
+
+        snapshot.update(write(3), "c");
+        assert_eq!(snapshot.get(2), Some("b"));
+        assert!(!snapshot.outdated(2));
+        assert!(
+            snapshot.outdated(1),
+            "version of write 1 is overwritten by write 3"
+        );
+    }
+
+    #[test]
+    fn readers_see_rows_deleted_after_their_snapshot() {
+        let snapshot = Snapshot::new(5, "a");
+        assert_eq!(snapshot.get(4), None);
+        assert!(!snapshot.outdated(4));
+
+        let deleted = snapshot.delete(write(6)).unwrap();
+        assert_eq!(deleted.get(5), Some("a"));
+        assert_eq!(deleted.get(6), None);
+    }
 }
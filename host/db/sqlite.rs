@@ -0,0 +1,282 @@
+use {
+    super::AsStorageError,
+    crate::_Somehow,
+    async_trait::async_trait,
+    futures::stream::iter,
+    gluesql_core::{
+        data::{Key, Schema},
+        error::{Error, Result},
+        store::{
+            AlterTable, CustomFunction, CustomFunctionMut, DataRow, Index, IndexMut, Metadata,
+            RowIter, Store, StoreMut, Transaction,
+        },
+    },
+    rusqlite::{params, Connection, OptionalExtension},
+};
+
+/// Name of the file in the app's data directory used by the SQLite backend
+pub(crate) const SQLITE_FILE_NAME: &str = "db.sqlite";
+/// Table with the serialized GlueSQL schemas
+const SCHEMAS_TABLE: &str = "\"prest/schemas\"";
+
+/// GlueSQL storage which keeps rows of every table serialized in a SQLite table ordered by their keys
+pub(crate) struct SqliteStorage {
+    conn: Connection,
+}
+
+impl SqliteStorage {
+    /// Opens the file or a throwaway in-memory DB without the path
+    pub fn open(path: Option<&std::path::Path>) -> crate::Result<Self> {
+        let conn = match path {
+            Some(path) => Connection::open(path),
+            None => Connection::open_in_memory(),
+        }
+        .somehow()?;
+        conn.execute(
+            &format!("CREATE TABLE IF NOT EXISTS {SCHEMAS_TABLE} (name TEXT PRIMARY KEY, schema BLOB NOT NULL)"),
+            [],
+        )
+        .somehow()?;
+        Ok(Self { conn })
+    }
+}
+
+/// Quoted name of the SQLite table which keeps rows of the GlueSQL table
+fn data_table(name: &str) -> String {
+    format!("\"prest/data/{}\"", name.replace('"', "\"\""))
+}
+
+#[async_trait(?Send)]
+impl Store for SqliteStorage {
+    async fn fetch_all_schemas(&self) -> Result<Vec<Schema>> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!("SELECT schema FROM {SCHEMAS_TABLE} ORDER BY name"))
+            .as_storage_err()?;
+        let schemas = stmt
+            .query_map([], |row| row.get::<_, Vec<u8>>(0))
+            .as_storage_err()?
+            .map(|bytes| bitcode::deserialize(&bytes.as_storage_err()?).as_storage_err())
+            .collect();
+        schemas
+    }
+
+    async fn fetch_schema(&self, table_name: &str) -> Result<Option<Schema>> {
+        self.conn
+            .query_row(
+                &format!("SELECT schema FROM {SCHEMAS_TABLE} WHERE name = ?1"),
+                [table_name],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .as_storage_err()?
+            .map(|bytes| bitcode::deserialize(&bytes))
+            .transpose()
+            .as_storage_err()
+    }
+
+    async fn fetch_data(&self, table_name: &str, key: &Key) -> Result<Option<DataRow>> {
+        self.conn
+            .query_row(
+                &format!("SELECT row FROM {} WHERE key = ?1", data_table(table_name)),
+                [key.to_cmp_be_bytes()?],
+                |row| row.get::<_, Vec<u8>>(0),
+            )
+            .optional()
+            .as_storage_err()?
+            .map(|bytes| bitcode::deserialize(&bytes))
+            .transpose()
+            .as_storage_err()
+    }
+
+    async fn scan_data(&self, table_name: &str) -> Result<RowIter> {
+        let mut stmt = self
+            .conn
+            .prepare(&format!(
+                "SELECT pkey, row FROM {} ORDER BY key",
+                data_table(table_name)
+            ))
+            .as_storage_err()?;
+        let rows = stmt
+            .query_map([], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, Vec<u8>>(1)?))
+            })
+            .as_storage_err()?
+            .map(|item| {
+                let (key, row) = item.as_storage_err()?;
+                let key: Key = bitcode::deserialize(&key).as_storage_err()?;
+                let row: DataRow = bitcode::deserialize(&row).as_storage_err()?;
+                Ok((key, row))
+            })
+            .collect::<Vec<_>>();
+        Ok(Box::pin(iter(rows)))
+    }
+}
+
+#[async_trait(?Send)]
+impl StoreMut for SqliteStorage {
+    async fn insert_schema(&mut self, schema: &Schema) -> Result<()> {
+        let bytes = bitcode::serialize(schema).as_storage_err()?;
+        self.conn
+            .execute(
+                &format!("INSERT OR REPLACE INTO {SCHEMAS_TABLE} (name, schema) VALUES (?1, ?2)"),
+                params![schema.table_name, bytes],
+            )
+            .as_storage_err()?;
+        self.conn
+            .execute(
+                &format!(
+                    "CREATE TABLE IF NOT EXISTS {} (key BLOB PRIMARY KEY, pkey BLOB NOT NULL, row BLOB NOT NULL)",
+                    data_table(&schema.table_name)
+                ),
+                [],
+            )
+            .as_storage_err()?;
+        Ok(())
+    }
+
+    async fn delete_schema(&mut self, table_name: &str) -> Result<()> {
+        self.conn
+            .execute(
+                &format!("DELETE FROM {SCHEMAS_TABLE} WHERE name = ?1"),
+                [table_name],
+            )
+            .as_storage_err()?;
+        self.conn
+            .execute(
+                &format!("DROP TABLE IF EXISTS {}", data_table(table_name)),
+                [],
+            )
+            .as_storage_err()?;
+        Ok(())
+    }
+
+    async fn append_data(&mut self, table_name: &str, rows: Vec<DataRow>) -> Result<()> {
+        let rows = rows
+            .into_iter()
+            .map(|row| (Key::Uuid(uuid::Uuid::now_v7().as_u128()), row))
+            .collect();
+        self.insert_data(table_name, rows).await
+    }
+
+    async fn insert_data(&mut self, table_name: &str, rows: Vec<(Key, DataRow)>) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(&format!(
+                "INSERT OR REPLACE INTO {} (key, pkey, row) VALUES (?1, ?2, ?3)",
+                data_table(table_name)
+            ))
+            .as_storage_err()?;
+        for (key, row) in rows {
+            let cmp_key = key.to_cmp_be_bytes()?;
+            let key = bitcode::serialize(&key).as_storage_err()?;
+            let row = bitcode::serialize(&row).as_storage_err()?;
+            stmt.execute(params![cmp_key, key, row]).as_storage_err()?;
+        }
+        Ok(())
+    }
+
+    async fn delete_data(&mut self, table_name: &str, keys: Vec<Key>) -> Result<()> {
+        let mut stmt = self
+            .conn
+            .prepare_cached(&format!(
+                "DELETE FROM {} WHERE key = ?1",
+                data_table(table_name)
+            ))
+            .as_storage_err()?;
+        for key in keys {
+            stmt.execute([key.to_cmp_be_bytes()?]).as_storage_err()?;
+        }
+        Ok(())
+    }
+}
+
+impl Index for SqliteStorage {}
+impl IndexMut for SqliteStorage {}
+impl AlterTable for SqliteStorage {}
+/// Writes of the `GlueDb` run inside of `BEGIN`/`COMMIT` so that they are applied as a whole,
+/// GlueSQL's statements inside of them don't start their own transactions
+#[async_trait(?Send)]
+impl Transaction for SqliteStorage {
+    async fn begin(&mut self, autocommit: bool) -> Result<bool> {
+        if !self.conn.is_autocommit() {
+            return match autocommit {
+                true => Ok(false),
+                false => Err(Error::StorageMsg(
+                    "nested transactions are not supported".to_owned(),
+                )),
+            };
+        }
+        self.conn.execute_batch("BEGIN").as_storage_err()?;
+        Ok(autocommit)
+    }
+
+    async fn rollback(&mut self) -> Result<()> {
+        if !self.conn.is_autocommit() {
+            self.conn.execute_batch("ROLLBACK").as_storage_err()?;
+        }
+        Ok(())
+    }
+
+    async fn commit(&mut self) -> Result<()> {
+        if !self.conn.is_autocommit() {
+            self.conn.execute_batch("COMMIT").as_storage_err()?;
+        }
+        Ok(())
+    }
+}
+impl Metadata for SqliteStorage {}
+impl CustomFunction for SqliteStorage {}
+impl CustomFunctionMut for SqliteStorage {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gluesql_core::{ast::ColumnDef, prelude::DataType};
+
+    fn schema() -> Schema {
+        Schema {
+            table_name: "notes".to_owned(),
+            column_defs: Some(vec![ColumnDef {
+                name: "id".to_owned(),
+                data_type: DataType::Int,
+                nullable: false,
+                default: None,
+                unique: None,
+                comment: None,
+            }]),
+            indexes: vec![],
+            engine: None,
+            foreign_keys: vec![],
+            comment: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn rolls_back_uncommitted_writes() {
+        let mut storage = SqliteStorage::open(None).unwrap();
+        storage.insert_schema(&schema()).await.unwrap();
+        let key = Key::I64(1);
+        let row = DataRow::Vec(vec![gluesql_core::prelude::Value::I64(1)]);
+
+        assert!(!storage.begin(false).await.unwrap());
+        storage
+            .insert_data("notes", vec![(key.clone(), row.clone())])
+            .await
+            .unwrap();
+        assert!(
+            !storage.begin(true).await.unwrap(),
+            "statements join the open transaction"
+        );
+        storage.rollback().await.unwrap();
+        assert!(storage.fetch_data("notes", &key).await.unwrap().is_none());
+
+        storage.begin(false).await.unwrap();
+        storage
+            .insert_data("notes", vec![(key.clone(), row.clone())])
+            .await
+            .unwrap();
+        storage.commit().await.unwrap();
+        assert_eq!(storage.fetch_data("notes", &key).await.unwrap(), Some(row));
+    }
+}
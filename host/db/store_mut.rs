@@ -18,8 +18,33 @@ impl<'a> StoreMut for DbConn<'a> {
         unimplemented!("Schemas are only defined by code")
     }
 
-    async fn append_data(&mut self, table_name: &str, _rows: Vec<DataRow>) -> Result<()> {
-        panic!("append data (called for {table_name}) is only for unstructured data? shouldn't be used... or... for what?");
+    /// GlueSQL appends rows into tables without a single primary key column, which happens
+    /// for composite keys so they are assembled from the pkey columns here
+    async fn append_data(&mut self, table_name: &str, rows: Vec<DataRow>) -> Result<()> {
+        let schema = self.fetch_struct_schema(table_name)?;
+
+        let rows = rows
+            .into_iter()
+            .map(|row| {
+                let DataRow::Vec(values) = &row else {
+                    return Err(Error::StorageMsg(
+                        "schemaless rows are not supported".into(),
+                    ));
+                };
+                let parts = schema
+                    .fields()
+                    .iter()
+                    .zip(values.iter())
+                    .filter(|(field, _)| field.pkey)
+                    .map(|(_, value)| Ok(Key::try_from(value.clone())?))
+                    .collect::<Result<Vec<_>>>()?;
+                let key =
+                    crate::composite_key(parts).map_err(|e| Error::StorageMsg(e.to_string()))?;
+                Ok((key, row))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        self.insert_data(table_name, rows).await
     }
 
     async fn insert_data(&mut self, table_name: &str, rows: Vec<(Key, DataRow)>) -> Result<()> {
@@ -31,24 +56,33 @@ impl<'a> StoreMut for DbConn<'a> {
 
         let tx_rows = &rows;
 
-        // let index_sync = IndexSync::new(&self.tree, self.state, table_name)?;
+        let index_sync = IndexSync::new(self, table_name)?;
+        let trees = self.table(table_name)?;
+        let mut inserted = 0;
 
         for (key, new_row) in tx_rows.iter() {
-            let key = super::sled_key(table_name, key.clone())?;
+            let key = super::sled_key(key.clone())?;
 
-            let snapshot = match self.tree.get(&key).as_storage_err()? {
+            let snapshot = match trees.data.get(&key).as_storage_err()? {
                 Some(snapshot) => {
                     let mut snapshot: Snapshot<DataRow> =
                         bitcode::deserialize(&snapshot).as_storage_err()?;
 
-                    snapshot.update(self.state, new_row.clone());
+                    match &snapshot.data {
+                        Some(old_row) => index_sync.update(&key, old_row, new_row).await?,
+                        None => {
+                            index_sync.insert(&key, new_row).await?;
+                            inserted += 1;
+                        }
+                    }
 
-                    // index_sync.update(&key, &old_row, new_row).await?;
+                    snapshot.update(self.state, new_row.clone());
 
                     snapshot
                 }
                 None => {
-                    // index_sync.insert(&key, new_row).await?;
+                    index_sync.insert(&key, new_row).await?;
+                    inserted += 1;
 
                     Snapshot::new(self.state.tx_id, new_row.clone())
                 }
@@ -56,10 +90,11 @@ impl<'a> StoreMut for DbConn<'a> {
 
             let snapshot = bitcode::serialize(&snapshot).as_storage_err()?;
 
-            self.tree.insert(&key, snapshot).as_storage_err()?;
+            trees.data.insert(&key, snapshot).as_storage_err()?;
+            self.touch_row(table_name, &key);
         }
 
-        Ok(())
+        self.adjust_count(trees, inserted)
     }
 
     async fn delete_data(&mut self, table_name: &str, keys: Vec<Key>) -> Result<()> {
@@ -71,30 +106,36 @@ impl<'a> StoreMut for DbConn<'a> {
 
         let tx_keys = &keys;
 
-        // let index_sync = IndexSync::new(&self.tree, self.state, table_name)?;
+        let index_sync = IndexSync::new(self, table_name)?;
+        let trees = self.table(table_name)?;
+        let mut deleted = 0;
 
         for key in tx_keys.iter() {
-            let key = super::sled_key(table_name, key.clone())?;
+            let key = super::sled_key(key.clone())?;
 
-            let snapshot = self
-                .tree
+            let snapshot = trees
+                .data
                 .get(&key)
                 .as_storage_err()?
                 .ok_or(Error::StorageMsg("not found item to delete".into()))?;
             let snapshot: Snapshot<DataRow> = bitcode::deserialize(&snapshot).as_storage_err()?;
 
+            if let Some(old_row) = &snapshot.data {
+                index_sync.delete(&key, old_row).await?;
+                deleted += 1;
+            }
+
+            self.touch_row(table_name, &key);
             let Some(updated) = snapshot.delete(self.state) else {
-                self.tree.remove(&key).as_storage_err()?;
+                trees.data.remove(&key).as_storage_err()?;
                 continue;
             };
 
             bitcode::serialize(&updated)
                 .as_storage_err()
-                .map(|snapshot| self.tree.insert(&key, snapshot).as_storage_err())??;
-
-            // index_sync.delete(&key, &updated).await?;
+                .map(|snapshot| trees.data.insert(&key, snapshot).as_storage_err())??;
         }
-        Ok(())
+        self.adjust_count(trees, -deleted)
     }
 }
 
@@ -106,34 +147,47 @@ impl DbConn<'_> {
         field: usize,
         value: sql::Value,
     ) -> Result<()> {
-        // let index_sync = IndexSync::new(&self.tree, self.state, table_name)?;
+        let index_sync = IndexSync::new(self, table_name)?;
+        let trees = self.table(table_name)?;
 
-        let key = super::sled_key(table_name, key.clone())?;
+        let key = super::sled_key(key.clone())?;
 
-        match self
-            .tree
+        match trees
+            .data
             .get(&key)
             .as_storage_err()?
             .map(|s| bitcode::deserialize::<Snapshot<DataRow>>(&s).as_storage_err())
             .transpose()?
         {
             Some(mut snapshot) => {
-                if let Some(row) = snapshot.get_mut(self.state) {
-                    if let DataRow::Vec(row) = row {
-                        if let Some(cell) = row.get_mut(field) {
-                            *cell = value;
-                            let snapshot = bitcode::serialize(&snapshot).as_storage_err()?;
-                            self.tree.insert(&key, snapshot).as_storage_err()?;
-                            // index_sync.update(&key, &old_row, new_row).await?;
-                        } else {
-                            panic!("update_cell with non-existent value");
-                        }
-                    } else {
-                        crate::error!("update_cell used with DataRow::Map");
-                    }
-                }
+                let Some(old_row) = snapshot.data.clone() else {
+                    return Err(Error::StorageMsg("update_cell with deleted row".into()));
+                };
+                let DataRow::Vec(mut row) = old_row.clone() else {
+                    return Err(Error::StorageMsg(
+                        "update_cell used with DataRow::Map".into(),
+                    ));
+                };
+                let Some(cell) = row.get_mut(field) else {
+                    return Err(Error::StorageMsg(
+                        "update_cell with non-existent value".into(),
+                    ));
+                };
+                *cell = value;
+                let new_row = DataRow::Vec(row);
+
+                index_sync.update(&key, &old_row, &new_row).await?;
+
+                snapshot.update(self.state, new_row);
+                let snapshot = bitcode::serialize(&snapshot).as_storage_err()?;
+                trees.data.insert(&key, snapshot).as_storage_err()?;
+                self.touch_row(table_name, &key);
+            }
+            None => {
+                return Err(Error::StorageMsg(
+                    "update_cell with non-existent row".into(),
+                ))
             }
-            None => panic!("update_cell with non-existent row"),
         };
 
         Ok(())
@@ -0,0 +1,103 @@
+use {
+    super::{history::row_pkey, trees::TableTrees, AsStorageError, DbConn, Snapshot},
+    crate::*,
+    gluesql_core::{error::Result, store::DataRow},
+};
+
+/// Size of the `{tx_id}{seq}` keys of the sync log
+const SYNC_KEY_LEN: usize = 12;
+
+impl<'a> DbConn<'a> {
+    /// Appends changes of `#[storage(sync)]` rows into the logs pulled by service workers
+    pub fn record_sync(&self, changes: &[RowChange]) -> Result<()> {
+        let timestamp = Utc::now().naive_utc();
+        for (seq, change) in changes.iter().enumerate() {
+            let schema = self.fetch_struct_schema(change.table)?;
+            if !schema.sync() {
+                continue;
+            }
+            let Some(values) = change.new.as_ref().or(change.old.as_ref()) else {
+                continue;
+            };
+            let entry = SyncChange {
+                table: change.table.to_owned(),
+                key: row_pkey(schema, values)?,
+                row: change.new.clone(),
+                removed: change.new.is_none().then(|| values.clone()),
+                timestamp,
+            };
+
+            let mut key = self.state.tx_id.to_be_bytes().to_vec();
+            key.extend((seq as u32).to_be_bytes());
+            // removed values are skipped when changes are sent, so they are logged next to them
+            let entry = bitcode::serialize(&(&entry, &entry.removed)).as_storage_err()?;
+            self.table(change.table)?
+                .sync
+                .insert(key, entry)
+                .as_storage_err()?;
+        }
+        Ok(())
+    }
+
+    /// Committed changes of the synchronized tables after the cursor in the order of writes
+    ///
+    /// Cursor `0` means that the client has nothing yet, so it gets all the current rows instead
+    pub fn sync_changes(&self, since: u64) -> Result<SyncResponse> {
+        let cursor = self.view();
+
+        let mut changes = vec![];
+        for schema in self.schemas.all().into_iter().filter(|s| s.sync()) {
+            let trees = self.table(schema.name())?;
+            if since == 0 {
+                let timestamp = Utc::now().naive_utc();
+                for item in trees.data.iter() {
+                    let (_, value) = item.as_storage_err()?;
+                    let snapshot: Snapshot<DataRow> =
+                        bitcode::deserialize(&value).as_storage_err()?;
+                    if let Some(DataRow::Vec(row)) = self.visible(snapshot) {
+                        let change = SyncChange {
+                            table: schema.name().to_owned(),
+                            key: row_pkey(schema, &row)?,
+                            row: Some(row),
+                            removed: None,
+                            timestamp,
+                        };
+                        changes.push((0, change));
+                    }
+                }
+                continue;
+            }
+
+            let start = (since + 1).to_be_bytes();
+            for item in trees.sync.range(start..) {
+                let (key, value) = item.as_storage_err()?;
+                let tx_id = u64::from_be_bytes(key[..8].try_into().as_storage_err()?);
+                if tx_id > cursor {
+                    break;
+                }
+                let (mut change, removed): (SyncChange, _) =
+                    bitcode::deserialize(&value).as_storage_err()?;
+                change.removed = removed;
+                changes.push((tx_id, change));
+            }
+        }
+
+        // logs are kept per table so they are merged in the order of writes
+        changes.sort_by_key(|(tx_id, _)| *tx_id);
+        Ok(SyncResponse {
+            cursor,
+            changes: changes.into_iter().map(|(_, change)| change).collect(),
+        })
+    }
+
+    /// Removes changes logged by the current write
+    pub(super) fn rollback_sync(&self, trees: &TableTrees) -> Result<()> {
+        for item in trees.sync.scan_prefix(self.state.tx_id.to_be_bytes()) {
+            let (key, _) = item.as_storage_err()?;
+            if key.len() == SYNC_KEY_LEN {
+                trees.sync.remove(&key).as_storage_err()?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+use {super::DbConn, crate::*, gluesql_core::error::Result};
+
+impl<'a> DbConn<'a> {
+    /// Keeps `#[created_at]` values of the row which is being overwritten
+    pub fn keep_created_at(&self, name: &str, old: &[sql::Value], row: &mut [sql::Value]) {
+        let Some(schema) = self.schemas.fetch_struct_schema(name) else {
+            return;
+        };
+        for (index, field) in schema.fields().iter().enumerate() {
+            if !field.created_at {
+                continue;
+            }
+            if let (Some(old), Some(value)) = (old.get(index), row.get_mut(index)) {
+                if !matches!(old, sql::Value::Null) {
+                    *value = old.clone();
+                }
+            }
+        }
+    }
+
+    /// Sets `#[updated_at]` values of the row to the current time unless the column itself was updated
+    pub async fn touch_updated_at(
+        &mut self,
+        name: &str,
+        key: &sql::Key,
+        updated: usize,
+    ) -> Result<()> {
+        let Some(schema) = self.schemas.fetch_struct_schema(name) else {
+            return Ok(());
+        };
+        let now = sql::Value::Timestamp(Utc::now().naive_utc());
+        for (index, field) in schema.fields().iter().enumerate() {
+            if field.updated_at && index != updated {
+                self.update_cell(name, key.clone(), index, now.clone())
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+}
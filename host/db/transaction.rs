@@ -1,76 +1,163 @@
 use {
-    super::{AsStorageError, DbConn, Snapshot, WRITE_STATE_KEY},
+    super::{trees::TableTrees, AsStorageError, DbConn, Snapshot, WRITE_STATE_KEY},
     async_trait::async_trait,
     gluesql_core::{
         error::{Error, Result},
         store::{DataRow, Transaction},
     },
+    std::collections::{BTreeMap, BTreeSet},
 };
 
+/// Writes are committed or rolled back by the writer thread as a whole (see `DB.transaction`),
+/// so GlueSQL's autocommit transactions don't need to do anything here
 #[async_trait(?Send)]
 impl<'a> Transaction for DbConn<'a> {
     async fn begin(&mut self, autocommit: bool) -> Result<bool> {
         if !autocommit {
             return Err(Error::StorageMsg(
-                "nested and non-autocommitted transactions are not supported".to_owned(),
+                "BEGIN is not supported, use DB.transaction to group multiple writes".to_owned(),
             ));
         }
-
-        if !self.readonly {
-            self.state.in_progress = true;
-            if let Err(e) = bitcode::serialize(&self.state)
-                .as_storage_err()
-                .map(|state| self.tree.insert(WRITE_STATE_KEY, state))
-            {
-                crate::error!("failed to update latest transaction id: {e}");
-                return Err(Error::StorageMsg(
-                    "failed to record tx beginning".to_owned(),
-                ));
-            }
-        }
-
-        Ok(true)
+        Ok(false)
     }
 
     async fn rollback(&mut self) -> Result<()> {
-        if !self.readonly {
-            self.rollback_self()?;
-            // CURRENT_TXID.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
-        }
         Ok(())
     }
 
     async fn commit(&mut self) -> Result<()> {
-        if !self.readonly {
-            self.state.in_progress = false;
-            if let Err(e) = bitcode::serialize(&self.state)
-                .as_storage_err()
-                .map(|state| self.tree.insert(WRITE_STATE_KEY, state))
-            {
-                crate::error!("failed to update latest transaction id: {e}")
-            }
-        }
         Ok(())
     }
 }
 
 impl<'a> DbConn<'a> {
-    pub fn rollback_self(&self) -> Result<()> {
+    /// Persists the write state so that unfinished writes can be detected after restarts
+    pub fn record_state(&self) -> Result<()> {
+        let state = bitcode::serialize(&self.state).as_storage_err()?;
+        self.tree.insert(WRITE_STATE_KEY, state).as_storage_err()?;
+        Ok(())
+    }
+
+    /// Remembers the row modified by the current write so that its failure rolls back only it
+    pub fn touch_row(&self, table_name: &str, key: &[u8]) {
+        if let Some(touched) = &self.touched {
+            let mut touched = touched.lock().expect("touched keys lock isn't poisoned");
+            let table = touched.entry(table_name.to_owned()).or_default();
+            table.rows.insert(key.to_vec());
+        }
+    }
+
+    /// Remembers the index entry modified by the current write, see [`DbConn::touch_row`]
+    pub fn touch_index(&self, table_name: &str, key: &[u8]) {
+        if let Some(touched) = &self.touched {
+            let mut touched = touched.lock().expect("touched keys lock isn't poisoned");
+            let table = touched.entry(table_name.to_owned()).or_default();
+            table.index.insert(key.to_vec());
+        }
+    }
+
+    /// Restores rows and index entries modified by the current write to their backups,
+    /// returns numbers of affected rows and index entries
+    ///
+    /// Only the keys recorded by the write are visited, all tables are scanned
+    /// when they are unknown like after crashes
+    pub fn rollback_self(&self) -> Result<(usize, usize)> {
+        let touched = match &self.touched {
+            Some(touched) => {
+                let touched = touched.lock().expect("touched keys lock isn't poisoned");
+                Some(std::mem::take(&mut *touched))
+            }
+            None => None,
+        };
+        let tables = match &touched {
+            Some(touched) => touched.keys().cloned().collect(),
+            None => self.stored_tables()?,
+        };
+
         let mut affected = 0;
-        for item in self.tree.scan_prefix(b"data/") {
-            let (key, value) = item.as_storage_err()?;
-            let snapshot = bitcode::deserialize::<Snapshot<DataRow>>(&value).as_storage_err()?;
-            if let Some(v) = snapshot.rollback(self.state) {
-                affected += 1;
-                if let Some(restored) = v {
-                    let restored = bitcode::serialize(&restored).as_storage_err()?;
-                    self.tree.insert(key, restored).as_storage_err()?;
-                } else {
-                    self.tree.remove(key).as_storage_err()?;
+        let mut affected_index_entries = 0;
+        for table_name in tables {
+            let trees = self.table(&table_name)?;
+            let keys = touched.as_ref().and_then(|t| t.get(&table_name));
+
+            match keys {
+                Some(keys) => {
+                    for key in keys.rows.iter() {
+                        affected += self.rollback_row(trees, key)? as usize;
+                    }
+                    for key in keys.index.iter() {
+                        affected_index_entries += self.rollback_index_entry(trees, key)? as usize;
+                    }
+                }
+                None => {
+                    for item in trees.data.iter() {
+                        let (key, _) = item.as_storage_err()?;
+                        affected += self.rollback_row(trees, &key)? as usize;
+                    }
+                    for item in trees.index.iter() {
+                        let (key, _) = item.as_storage_err()?;
+                        affected_index_entries += self.rollback_index_entry(trees, &key)? as usize;
+                    }
                 }
             }
+
+            self.rollback_count(trees)?;
+            self.rollback_history(trees, keys.map(|k| &k.rows))?;
+            self.rollback_sync(trees)?;
         }
-        crate::warn!("rolled back {affected} affected rows");
-        Ok(())
+
+        crate::warn!(
+            target: "db",
+            "rolled back {affected} rows and {affected_index_entries} index entries"
+        );
+        Ok((affected, affected_index_entries))
+    }
+
+    fn rollback_row(&self, trees: &TableTrees, key: &[u8]) -> Result<bool> {
+        let Some(value) = trees.data.get(key).as_storage_err()? else {
+            return Ok(false);
+        };
+        let snapshot = bitcode::deserialize::<Snapshot<DataRow>>(&value).as_storage_err()?;
+        let Some(restored) = snapshot.rollback(self.state) else {
+            return Ok(false);
+        };
+        if let Some(restored) = restored {
+            let restored = bitcode::serialize(&restored).as_storage_err()?;
+            trees.data.insert(key, restored).as_storage_err()?;
+        } else {
+            trees.data.remove(key).as_storage_err()?;
+        }
+        Ok(true)
     }
+
+    fn rollback_index_entry(&self, trees: &TableTrees, key: &[u8]) -> Result<bool> {
+        let Some(value) = trees.index.get(key).as_storage_err()? else {
+            return Ok(false);
+        };
+        let data_keys = bitcode::deserialize::<Vec<Snapshot<Vec<u8>>>>(&value).as_storage_err()?;
+        if !data_keys.iter().any(|s| s.data_txid == self.state.tx_id) {
+            return Ok(false);
+        }
+        let restored = data_keys
+            .into_iter()
+            .filter_map(|s| s.clone().rollback(self.state).unwrap_or(Some(s)))
+            .collect::<Vec<_>>();
+        if restored.is_empty() {
+            trees.index.remove(key).as_storage_err()?;
+        } else {
+            let restored = bitcode::serialize(&restored).as_storage_err()?;
+            trees.index.insert(key, restored).as_storage_err()?;
+        }
+        Ok(true)
+    }
+}
+
+/// Rows and index entries modified by the write
+#[derive(Debug, Default)]
+pub(crate) struct TableKeys {
+    pub rows: BTreeSet<Vec<u8>>,
+    pub index: BTreeSet<Vec<u8>>,
 }
+
+/// Keys modified by the write per table, see [`DbConn::rollback_self`]
+pub(crate) type TouchedKeys = BTreeMap<String, TableKeys>;
@@ -0,0 +1,240 @@
+use {
+    super::{AsStorageError, DbConn},
+    crate::*,
+    gluesql_core::error::Result,
+    std::{collections::HashMap, sync::RwLock},
+};
+
+pub(crate) const FORMAT_VERSION_KEY: &[u8] = b"state/format";
+/// 1 - all tables in the default tree under `/{table}/` prefixes, 2 - trees per table,
+/// 3 - row counters in the meta trees, 4 - history logs ordered by writes,
+/// 5 - index entries of `u128` values past `i128::MAX` ordered after the other integers
+pub(crate) const FORMAT_VERSION: u32 = 5;
+const TABLES_PREFIX: &str = "state/tables/";
+
+/// Trees holding rows and metadata of a single table
+pub(crate) struct TableTrees {
+    /// Snapshots of rows by their primary keys
+    pub data: sled::Tree,
+    /// `{index}/{value}` => snapshots of primary keys
+    pub index: sled::Tree,
+    /// Persisted schema and states of the indexes
+    pub meta: sled::Tree,
+    /// `{pkey length}{pkey}{tx_id}{seq}` => versions of rows, see `#[storage(history)]`
+    pub history: sled::Tree,
+    /// `{tx_id}{seq}` => keys of the versions in the history tree in the order of writes
+    pub history_log: sled::Tree,
+    /// Snapshots of the rows removed from `#[storage(soft_delete)]` tables by their primary keys
+    pub tombstones: sled::Tree,
+    /// `{tx_id}{seq}` => changes of `#[storage(sync)]` rows pulled by service workers
+    pub sync: sled::Tree,
+}
+
+/// Opened table trees, shared by the DB threads
+///
+/// Trees are leaked so that iterators over them can outlive connections, there are only a few of them per table
+#[derive(Clone, Default)]
+pub(crate) struct TablesCache(Arc<RwLock<HashMap<String, &'static TableTrees>>>);
+
+impl TablesCache {
+    /// Forgets opened trees so that they are registered again once reopened
+    pub fn clear(&self) {
+        self.0.write().unwrap().clear();
+    }
+
+    /// Drops the leaked trees so that sled can close its files, called by [`Db::shutdown`]
+    /// once all the threads which could hold references to them are joined
+    pub fn release(&self) {
+        for (_, trees) in self.0.write().unwrap().drain() {
+            // SAFETY: trees were leaked from boxes in `DbConn::table` and there are no connections left
+            drop(unsafe { Box::from_raw(trees as *const TableTrees as *mut TableTrees) });
+        }
+    }
+}
+
+impl<'a> DbConn<'a> {
+    /// Opens trees of the table and records it so that rollbacks can find all of them
+    pub fn table(&self, table_name: &str) -> Result<&'static TableTrees> {
+        if let Some(trees) = self.tables.0.read().unwrap().get(table_name).copied() {
+            return Ok(trees);
+        }
+
+        let open = |kind: &str| self.tree.open_tree(format!("{table_name}/{kind}"));
+        let trees: &'static TableTrees = Box::leak(Box::new(TableTrees {
+            data: open("data").as_storage_err()?,
+            index: open("index").as_storage_err()?,
+            meta: open("meta").as_storage_err()?,
+            history: open("history").as_storage_err()?,
+            history_log: open("history_log").as_storage_err()?,
+            tombstones: open("tombstones").as_storage_err()?,
+            sync: open("sync").as_storage_err()?,
+        }));
+        self.tree
+            .insert(
+                format!("{TABLES_PREFIX}{table_name}").as_bytes(),
+                Vec::<u8>::new(),
+            )
+            .as_storage_err()?;
+
+        self.tables
+            .0
+            .write()
+            .unwrap()
+            .insert(table_name.to_owned(), trees);
+        Ok(trees)
+    }
+
+    /// Names of all the tables that ever had trees, including ones removed from the code
+    pub fn stored_tables(&self) -> Result<Vec<String>> {
+        self.tree
+            .scan_prefix(TABLES_PREFIX.as_bytes())
+            .map(|item| {
+                let (key, _) = item.as_storage_err()?;
+                Ok(String::from_utf8_lossy(&key[TABLES_PREFIX.len()..]).to_string())
+            })
+            .collect()
+    }
+
+    /// Checks the on-disk format and moves tables from the prefixed layout into their own trees
+    pub fn upgrade_format(&self) -> Result<()> {
+        let version = match self.tree.get(FORMAT_VERSION_KEY).as_storage_err()? {
+            Some(v) => bitcode::deserialize::<u32>(&v).as_storage_err()?,
+            None => 1,
+        };
+
+        if version > FORMAT_VERSION {
+            return Err(gluesql_core::error::Error::StorageMsg(format!(
+                "DB format v{version} was written by a newer version of prest"
+            )));
+        }
+
+        if version == 1 {
+            self.upgrade_from_prefixed_layout()?;
+        }
+        if version < 3 {
+            self.init_row_counters()?;
+        }
+        if version < 4 {
+            self.init_history_logs()?;
+        }
+        if version < 5 {
+            self.reset_index_states()?;
+        }
+
+        let version = bitcode::serialize(&FORMAT_VERSION).as_storage_err()?;
+        self.tree
+            .insert(FORMAT_VERSION_KEY, version)
+            .as_storage_err()?;
+        self.tree.flush().as_storage_err()?;
+        Ok(())
+    }
+
+    fn upgrade_from_prefixed_layout(&self) -> Result<()> {
+        let mut moved_rows = 0;
+        for item in self.tree.scan_prefix(b"/") {
+            let (key, value) = item.as_storage_err()?;
+            let Some(separator) = key[1..].iter().position(|b| *b == b'/') else {
+                continue;
+            };
+            let table_name = String::from_utf8_lossy(&key[1..separator + 1]).to_string();
+            self.table(&table_name)?
+                .data
+                .insert(&key[separator + 2..], value)
+                .as_storage_err()?;
+            self.tree.remove(&key).as_storage_err()?;
+            moved_rows += 1;
+        }
+
+        for item in self
+            .tree
+            .scan_prefix(super::migrate::LEGACY_SCHEMA_PREFIX.as_bytes())
+        {
+            let (key, value) = item.as_storage_err()?;
+            let table_name =
+                String::from_utf8_lossy(&key[super::migrate::LEGACY_SCHEMA_PREFIX.len()..])
+                    .to_string();
+            self.table(&table_name)?
+                .meta
+                .insert(super::migrate::SCHEMA_KEY, value)
+                .as_storage_err()?;
+            self.tree.remove(&key).as_storage_err()?;
+        }
+
+        // indexes point to prefixed keys so they are rebuilt by sync_indexes instead of moving
+        for prefix in [&b"index/"[..], &b"state/index/"[..]] {
+            for item in self.tree.scan_prefix(prefix) {
+                let (key, _) = item.as_storage_err()?;
+                self.tree.remove(&key).as_storage_err()?;
+            }
+        }
+
+        if moved_rows > 0 {
+            info!(target: "db", "moved {moved_rows} rows into per-table trees");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::host::db::{migrate::SCHEMA_KEY, Snapshot, WriteState},
+        gluesql_core::store::DataRow,
+    };
+
+    #[test]
+    fn moves_prefixed_tables_into_their_own_trees() {
+        let path = std::env::temp_dir().join(format!("prest-db-{}", Uuid::now_v7()));
+        let tree = sled::Config::default()
+            .path(path.clone())
+            .flush_every_ms(None)
+            .open::<1024>()
+            .unwrap();
+        let row = DataRow::Vec(vec![sql::Value::U8(1)]);
+        let snapshot = bitcode::serialize(&Snapshot::new(1, row)).unwrap();
+        tree.insert(b"/accounts/1", snapshot.clone()).unwrap();
+        tree.insert(b"schema/accounts", b"schema".to_vec()).unwrap();
+        tree.insert(b"index/accounts/balance", Vec::<u8>::new())
+            .unwrap();
+
+        let (schemas, tables) = (Schemas::default(), TablesCache::default());
+        let conn = DbConn {
+            state: WriteState {
+                tx_id: 1,
+                in_progress: false,
+            },
+            committed: 1,
+            stale: Default::default(),
+            readonly: false,
+            user: None,
+            touched: None,
+            tree: &tree,
+            schemas: &schemas,
+            tables: &tables,
+        };
+        conn.upgrade_format().unwrap();
+
+        let trees = conn.table("accounts").unwrap();
+        assert_eq!(&*trees.data.get(b"1").unwrap().unwrap(), &snapshot[..]);
+        assert_eq!(&*trees.meta.get(SCHEMA_KEY).unwrap().unwrap(), b"schema");
+        assert_eq!(conn.stored_tables().unwrap(), ["accounts"]);
+        assert_eq!(conn.count_rows("accounts").unwrap(), 1);
+        for legacy in [
+            &b"/accounts/1"[..],
+            b"schema/accounts",
+            b"index/accounts/balance",
+        ] {
+            assert!(tree.get(legacy).unwrap().is_none());
+        }
+        let version = tree.get(FORMAT_VERSION_KEY).unwrap().unwrap();
+        assert_eq!(
+            bitcode::deserialize::<u32>(&version).unwrap(),
+            FORMAT_VERSION
+        );
+
+        tables.release();
+        drop(tree);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -20,6 +20,16 @@ pub use monitoring::SYSTEM_INFO;
 mod sse;
 pub use sse::*;
 
+#[cfg(feature = "db")]
+mod sync;
+#[cfg(feature = "db")]
+pub use sync::{Resolution, SyncConflict, SyncPolicy};
+
+#[cfg(feature = "replication")]
+mod replication;
+#[cfg(feature = "replication")]
+pub use replication::REPLICATION_ROUTE;
+
 #[cfg(feature = "auth")]
 pub(crate) mod auth;
 #[cfg(feature = "auth")]
@@ -35,6 +45,8 @@ use logs::*;
 
 #[cfg(feature = "db")]
 pub(crate) mod db;
+#[cfg(feature = "db")]
+pub use db::{DbBackend, ExportSink, QueryPlan, SlowQuery, TableQueryStats};
 // #[cfg(feature = "db")]
 // pub use db;
 
@@ -74,6 +86,10 @@ pub trait HostUtils: Sized {
     async fn add_default_assets(self) -> Self;
     fn add_analytics(self) -> Self;
     fn add_auth(self) -> Result<Self>;
+    fn add_replication(self) -> Self;
+    /// Exchanges changes of `#[storage(sync)]` tables with service workers, see [`SyncPolicy`]
+    #[cfg(feature = "db")]
+    fn sync_db(self, policy: impl SyncPolicy) -> Self;
 }
 
 #[async_trait]
@@ -88,6 +104,7 @@ impl HostUtils for Router {
         let admin = admin::routes().await;
         self.route("/health", get(StatusCode::OK))
             .add_auth()?
+            .add_replication()
             .add_default_assets()
             .await
             .add_analytics()
@@ -120,11 +137,31 @@ impl HostUtils for Router {
         #[cfg(feature = "auth")]
         {
             let (auth_layer, auth_routes) = auth::init_auth_module()?;
-            Ok(self.merge(auth_routes).layer(auth_layer))
+            Ok(self
+                .merge(auth_routes)
+                .layer(axum::middleware::from_fn(attribute_writes))
+                .layer(auth_layer))
         }
         #[cfg(not(feature = "auth"))]
         Ok(self)
     }
+    fn add_replication(self) -> Self {
+        #[cfg(feature = "replication")]
+        {
+            replication::follow();
+            self.merge(replication::routes())
+        }
+        #[cfg(not(feature = "replication"))]
+        self
+    }
+    #[cfg(feature = "db")]
+    fn sync_db(self, policy: impl SyncPolicy) -> Self {
+        let policy: Arc<dyn SyncPolicy> = Arc::new(policy);
+        self.route(
+            SYNC_ROUTE,
+            post(move |body: axum::body::Bytes| sync::sync_handler(policy.clone(), body)),
+        )
+    }
     fn add_analytics(self) -> Self {
         #[cfg(feature = "traces")]
         return self.layer(analytics::AnalyticsLayer::init());
@@ -178,12 +215,21 @@ impl HostUtils for Router {
 #[cfg(feature = "auth")]
 async fn check_admin(user: User, request: Request, next: Next) -> Result<Response> {
     if user.is_admin() {
-        Ok(next.run(request).await)
+        Ok(as_user(user.id, next.run(request)).await)
     } else {
         Err(Error::Unauthorized)
     }
 }
 
+/// Attributes writes made while handling the request to the authenticated user
+#[cfg(feature = "auth")]
+async fn attribute_writes(auth: Auth, request: Request, next: Next) -> Response {
+    match auth.user {
+        Some(user) => as_user(user.id, next.run(request)).await,
+        None => next.run(request).await,
+    }
+}
+
 fn handle_panic(err: Box<dyn std::any::Any + Send + 'static>) -> Response {
     let details = get_panic_message(err);
 
@@ -5,7 +5,9 @@ use sysinfo::{
 
 /// Describes collected stats for system resources
 #[derive(Debug, Storage, Serialize, Deserialize)]
+#[storage(ttl = "1d")]
 pub(crate) struct SystemStat {
+    #[created_at]
     pub timestamp: NaiveDateTime,
     pub app_cpu: f32,
     pub other_cpu: f32,
@@ -53,20 +55,6 @@ impl SystemInfo {
             .second()
             .spawn(|| async { SYSTEM_INFO.record().await });
 
-        // Cleanup job: Delete SystemStat entries older than 1 day every 10 minutes
-        RT.every(30)
-            .minutes()
-            .schedule("SystemStat Cleanup", || async {
-                match SystemInfo::cleanup_old_stats().await {
-                    Ok(deleted_count) => {
-                        debug!(target: "gc", "Deleted {} old SystemStat entries", deleted_count);
-                    }
-                    Err(e) => {
-                        warn!(target: "gc", "Failed to cleanup old SystemStat entries: {}", e);
-                    }
-                }
-            });
-
         // Cleanup job: Delete trace log files older than 1 month every 24 hours
         RT.every(24).hours().schedule("Log Cleanup", || async {
             match LOGS.cleanup_old_traces() {
@@ -112,6 +100,10 @@ impl SystemInfo {
             other_ram,
         };
 
+        // followers don't write their own stats
+        if DB.is_replica() {
+            return Ok(());
+        }
         if let Err(e) = stats.save().await {
             warn!(target: "system info", "Failed to save system stats: {e}");
         }
@@ -128,26 +120,4 @@ impl SystemInfo {
             ProcessRefreshKind::everything(),
         );
     }
-
-    /// Cleanup SystemStat entries older than 1 day
-    pub async fn cleanup_old_stats() -> Result<usize> {
-        let cutoff_time = Utc::now().naive_utc() - chrono::Duration::days(1);
-
-        // Use SQL DELETE to efficiently remove old entries
-        let delete_sql = format!(
-            "DELETE FROM SystemStat WHERE timestamp < '{}'",
-            cutoff_time.format("%Y-%m-%d %H:%M:%S")
-        );
-
-        match DB.write_sql(&delete_sql).await? {
-            Payload::Affected(count) => {
-                debug!(target: "gc", "Successfully deleted {} SystemStat entries older than {}", count, cutoff_time);
-                Ok(count)
-            }
-            other => {
-                warn!(target: "gc", "Unexpected response from cleanup: {:?}", other);
-                Ok(0)
-            }
-        }
-    }
 }
@@ -0,0 +1,125 @@
+use {crate::*, std::time::Duration};
+
+/// Route of the primary which serves committed writes to the followers
+pub const REPLICATION_ROUTE: &str = "/db/replication";
+/// How long the primary holds requests of the followers which are up to date
+const LONG_POLL: Duration = Duration::from_secs(25);
+/// Delay before the follower reconnects after a failure
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+#[derive(Deserialize)]
+struct LogParams {
+    from: u64,
+}
+
+/// Serves the replication log if `DB_REPLICATION_TOKEN` is set
+pub(crate) fn routes() -> Router {
+    match DB.replication.token {
+        Some(_) => route(REPLICATION_ROUTE, get(serve_log)),
+        None => Router::new(),
+    }
+}
+
+/// Responds with the writes committed starting from the requested id as soon as there are any
+async fn serve_log(
+    headers: HeaderMap,
+    extract::Query(params): extract::Query<LogParams>,
+) -> Result<Vec<u8>> {
+    let Some(token) = &DB.replication.token else {
+        return Err(Error::NotFound);
+    };
+    let authorized = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| tokens_match(value.as_bytes(), token.as_bytes()));
+    if !authorized {
+        return Err(Error::Unauthorized);
+    }
+
+    let mut committed = DB.committed.clone();
+    let entries = loop {
+        // marked as seen before reading so that commits made during the read wake it up
+        committed.borrow_and_update();
+        let entries = DB.replication_log(params.from).await?;
+        if !entries.is_empty() {
+            break entries;
+        }
+        match timeout(LONG_POLL, committed.changed()).await {
+            Ok(Ok(())) => continue,
+            // the follower reconnects after an empty response
+            _ => break entries,
+        }
+    };
+    bitcode::serialize(&entries).somehow()
+}
+
+/// Compares the tokens in time that depends only on their lengths so that responses don't leak
+/// how many leading bytes of a guess are right
+fn tokens_match(presented: &[u8], expected: &[u8]) -> bool {
+    if presented.len() != expected.len() {
+        return false;
+    }
+    let diff = presented
+        .iter()
+        .zip(expected)
+        .fold(0u8, |diff, (a, b)| diff | (a ^ b));
+    std::hint::black_box(diff) == 0
+}
+
+/// Tails the log of the primary set with `DB_REPLICATE_FROM` and applies its writes
+pub(crate) fn follow() {
+    let Some(primary) = DB.replication.primary.clone() else {
+        return;
+    };
+    let token = DB.replication.token.clone().unwrap_or_default();
+    RT.spawn(async move {
+        let client = reqwest::Client::new();
+        let url = format!("{primary}{REPLICATION_ROUTE}");
+        loop {
+            if let Err(e) = tail(&client, &url, &token).await {
+                warn!(target: "replication", "failed to replicate from {primary}: {e}");
+                sleep(RETRY_DELAY).await;
+            }
+        }
+    });
+}
+
+async fn tail(client: &reqwest::Client, url: &str, token: &str) -> Result {
+    let mut from = DB.replica_position().await?;
+    info!(target: "replication", "following {url} from write {from}");
+    loop {
+        let response = client
+            .get(format!("{url}?from={from}"))
+            .bearer_auth(token)
+            .timeout(LONG_POLL * 2)
+            .send()
+            .await
+            .somehow()?;
+        if !response.status().is_success() {
+            return Err(e!("primary responded with {}", response.status()));
+        }
+        let body = response.bytes().await.somehow()?;
+        let entries: Vec<(u64, Vec<u8>)> = bitcode::deserialize(&body).somehow()?;
+        for (tx_id, entry) in entries {
+            let committed_at = DB.replicate(tx_id, &entry).await?;
+            from = tx_id + 1;
+            let lag = Utc::now().naive_utc() - committed_at;
+            trace!(target: "replication", tx_id, lag_ms = lag.num_milliseconds());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::tokens_match;
+
+    #[test]
+    fn matches_only_identical_tokens() {
+        assert!(tokens_match(b"secret", b"secret"));
+        assert!(!tokens_match(b"secreT", b"secret"));
+        assert!(!tokens_match(b"Secret", b"secret"));
+        assert!(!tokens_match(b"secret1", b"secret"));
+        assert!(!tokens_match(b"", b"secret"));
+    }
+}
@@ -146,9 +146,11 @@ impl std::ops::Deref for PrestRuntime {
 
 /// Describes collected stats for scheduled jobs
 #[derive(Debug, Storage, Clone, Serialize, Deserialize)]
+#[storage(ttl = "30d")]
 pub struct ScheduledJobRecord {
     pub id: Uuid,
     pub name: String,
+    #[created_at]
     pub start: NaiveDateTime,
     pub end: Option<NaiveDateTime>,
     pub error: Option<String>,
@@ -164,6 +166,10 @@ impl ScheduledJobRecord {
             error: None,
         };
         trace!(target:"runtime", job = %name, start = %stat.start);
+        // followers don't write their own records
+        if DB.is_replica() {
+            return stat;
+        }
         let stat_clone = stat.clone();
         RT.spawn(async move {
             if let Err(e) = stat_clone.save().await {
@@ -178,6 +184,13 @@ impl ScheduledJobRecord {
 
         trace!(target:"runtime", job = %self.name, end = %end);
 
+        if DB.is_replica() {
+            if let Some(e) = error {
+                error!(target:"runtime", "Scheduled job {} error: {e}", self.name);
+            }
+            return;
+        }
+
         if let Err(e) = self.update_end(Some(end)).await {
             error!(target:"runtime", "Failed to record end of the scheduled job stat {self:?} : {e}");
         }
@@ -0,0 +1,326 @@
+use {crate::*, axum::body::Bytes, std::collections::HashMap};
+
+/// Decides which writes of service workers are applied to the `#[storage(sync)]` tables of the host
+/// and which changes of the host are sent to them
+///
+/// Nothing is shared unless the policy allows it, while conflicts are resolved by the last writer wins by default:
+/// ```rust,ignore
+/// struct OwnTodosOnly;
+///
+/// #[async_trait]
+/// impl SyncPolicy for OwnTodosOnly {
+///     async fn accept(&self, user: Option<Uuid>, change: &mut SyncChange) -> Result<bool> {
+///         let (Some(user), sql::Key::Uuid(id)) = (user, &change.key) else {
+///             return Ok(false);
+///         };
+///         let stored = Todo::get_by_pkey(Uuid::from_u128(*id)).await?;
+///         let pushed = change.value::<Todo>()?;
+///         Ok([stored, pushed].iter().flatten().all(|todo| todo.owner == user))
+///     }
+///
+///     async fn visible(&self, user: Option<Uuid>, change: &SyncChange) -> Result<bool> {
+///         let todo = change.value::<Todo>()?.or(change.removed::<Todo>()?);
+///         Ok(todo.is_some_and(|todo| Some(todo.owner) == user))
+///     }
+/// }
+///
+/// router.sync_db(OwnTodosOnly)
+/// ```
+#[async_trait]
+pub trait SyncPolicy: Send + Sync + 'static {
+    /// Checks and possibly adjusts the change pushed by the user, rejected ones are reverted on the client
+    ///
+    /// Key of the change always matches the primary key of its row, if it has one
+    async fn accept(&self, user: Option<Uuid>, change: &mut SyncChange) -> Result<bool>;
+
+    /// Checks whether the change of the host is sent to the user, hidden rows are removed on the client
+    ///
+    /// Removals provide values of the removed rows with [`SyncChange::removed`]
+    async fn visible(&self, user: Option<Uuid>, change: &SyncChange) -> Result<bool>;
+
+    /// Picks the version of the row changed both by the client and on the host since the client's last pull
+    fn resolve(&self, conflict: SyncConflict<'_>) -> Resolution {
+        match conflict.client.timestamp >= conflict.host.timestamp {
+            true => Resolution::Client,
+            false => Resolution::Host,
+        }
+    }
+}
+
+/// Row changed both by the client and on the host since the client's last pull
+pub struct SyncConflict<'a> {
+    pub client: &'a SyncChange,
+    pub host: &'a SyncChange,
+}
+
+/// Outcome of a [`SyncConflict`]
+pub enum Resolution {
+    /// Client's change is applied on the host
+    Client,
+    /// Client's change is dropped and the host's version is sent back
+    Host,
+    /// Provided values are applied instead of both, `None` removes the row
+    Merged(Option<Vec<sql::Value>>),
+}
+
+/// Applies changes pushed by the service worker and responds with the host's ones after its cursor
+pub(crate) async fn sync_handler(policy: Arc<dyn SyncPolicy>, body: Bytes) -> Result<Vec<u8>> {
+    let request: SyncRequest = bitcode::deserialize(&body).somehow()?;
+    let user = acting_user();
+    // clocks of the clients can't be trusted so their changes are ordered by the moment of receiving
+    let received = Utc::now().naive_utc();
+
+    // changes of the host which the client hasn't seen yet, the latest ones per row
+    let unseen = DB.sync_changes(request.cursor).await?;
+    let mut host_changes = HashMap::new();
+    for change in unseen.changes.iter() {
+        host_changes.insert(row_id(change)?, change);
+    }
+
+    // only the final state of every row matters
+    let mut pushed: Vec<SyncChange> = vec![];
+    for change in request.changes {
+        let id = row_id(&change)?;
+        pushed.retain(|c| row_id(c).map_or(true, |c| c != id));
+        pushed.push(change);
+    }
+
+    let mut writes = vec![];
+    let mut reverted = vec![];
+    for mut change in pushed {
+        let Some(schema) = DB
+            .schemas
+            .fetch_struct_schema(&change.table)
+            .filter(|s| s.sync())
+        else {
+            return Err(e!("{} table isn't synchronized", change.table));
+        };
+        let name = schema.name();
+        let id = row_id(&change)?;
+        change.timestamp = received;
+        change.removed = None;
+        // rows are stored by their pkeys, so changes under other keys are rejected before the policy sees them
+        if !matches_key(schema, &change.key, change.row.as_deref())?
+            || !policy.accept(user, &mut change).await?
+        {
+            reverted.push((name, change.key));
+            continue;
+        }
+        let row = match host_changes.get(&id) {
+            Some(host) => match policy.resolve(SyncConflict {
+                client: &change,
+                host,
+            }) {
+                Resolution::Client => change.row,
+                // the host's version is already among the unseen changes
+                Resolution::Host => continue,
+                Resolution::Merged(row) => row,
+            },
+            None => change.row,
+        };
+        let key = change.key;
+        match row {
+            Some(row)
+                if !matches_key(schema, &key, Some(&row))?
+                    || schema.validate_row(row.clone()).is_err() =>
+            {
+                reverted.push((name, key))
+            }
+            Some(row) => writes.push(Transaction::Save { name, key, row }),
+            None if current_row(name, &key).await?.is_some() => {
+                writes.push(Transaction::Delete { name, key })
+            }
+            None => {}
+        }
+    }
+    if !writes.is_empty() {
+        DB.write(Transaction::Batch(writes)).await?;
+    }
+
+    // current versions of the rejected rows go first so that later changes of them override these
+    let mut changes = vec![];
+    for (name, key) in reverted {
+        let mut change = SyncChange {
+            table: name.to_owned(),
+            row: current_row(name, &key).await?,
+            removed: None,
+            key,
+            timestamp: received,
+        };
+        // keys of the reverted rows came from the client, so only their values are hidden
+        if change.row.is_some() && !policy.visible(user, &change).await? {
+            change.row = None;
+        }
+        changes.push(change);
+    }
+
+    let mut response = DB.sync_changes(request.cursor).await?;
+    for mut change in response.changes {
+        match (change.row.is_some(), policy.visible(user, &change).await?) {
+            (_, true) => changes.push(change),
+            // rows hidden from the user are removed on the client in case it got them before
+            (true, false) => {
+                change.row = None;
+                changes.push(change);
+            }
+            // removals of hidden rows aren't sent so that their keys don't leak
+            (false, false) => {}
+        }
+    }
+    response.changes = changes;
+
+    bitcode::serialize(&response).somehow()
+}
+
+async fn current_row(name: &'static str, key: &sql::Key) -> Result<Option<Vec<sql::Value>>> {
+    let pkey = key.clone();
+    match DB.read(Query::GetByPKey { name, pkey }).await? {
+        Payload::Rows(mut rows) => Ok(rows.pop()),
+        p => Err(e!("Got {p:?} instead of rows")),
+    }
+}
+
+/// Whether the row is missing or its primary key matches the key of the change
+fn matches_key(schema: StructSchema, key: &sql::Key, row: Option<&[sql::Value]>) -> Result<bool> {
+    match row {
+        Some(row) => Ok(crate::host::db::row_pkey(schema, row)? == *key),
+        None => Ok(true),
+    }
+}
+
+fn row_id(change: &SyncChange) -> Result<(String, Vec<u8>)> {
+    Ok((change.table.clone(), change.key.to_cmp_be_bytes()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    #[derive(Storage, Debug, Clone, PartialEq, Serialize, Deserialize)]
+    #[storage(sync)]
+    struct Todo {
+        id: u8,
+        owner: Uuid,
+        task: String,
+    }
+
+    struct OwnersOnly;
+
+    #[async_trait]
+    impl SyncPolicy for OwnersOnly {
+        async fn accept(&self, user: Option<Uuid>, change: &mut SyncChange) -> Result<bool> {
+            let Some(user) = user else {
+                return Ok(false);
+            };
+            let stored = match change.value::<Todo>()? {
+                Some(todo) => Todo::get_by_pkey(todo.id).await?,
+                None => None,
+            };
+            let pushed = change.value::<Todo>()?;
+            Ok([stored, pushed].iter().flatten().all(|t| t.owner == user))
+        }
+
+        async fn visible(&self, user: Option<Uuid>, change: &SyncChange) -> Result<bool> {
+            let todo = change.value::<Todo>()?.or(change.removed::<Todo>()?);
+            Ok(todo.is_some_and(|t| Some(t.owner) == user))
+        }
+    }
+
+    fn todo(id: u8, owner: Uuid, task: &str) -> Todo {
+        Todo {
+            id,
+            owner,
+            task: task.to_owned(),
+        }
+    }
+
+    fn pushed(key: u8, todo: &Todo) -> SyncChange {
+        SyncChange {
+            table: Todo::STRUCT_NAME.to_owned(),
+            key: key.into_sql_key().unwrap(),
+            row: Some(todo.into_row().unwrap()),
+            removed: None,
+            timestamp: Utc::now().naive_utc(),
+        }
+    }
+
+    async fn sync(user: Uuid, cursor: u64, changes: Vec<SyncChange>) -> SyncResponse {
+        let request = bitcode::serialize(&SyncRequest { cursor, changes }).unwrap();
+        let handled = sync_handler(Arc::new(OwnersOnly), request.into());
+        let response = as_user(user, handled).await.unwrap();
+        bitcode::deserialize(&response).unwrap()
+    }
+
+    /// Rows of the client after it applies the response in order
+    fn applied(response: &SyncResponse) -> BTreeMap<sql::Key, Option<Todo>> {
+        response
+            .changes
+            .iter()
+            .map(|c| (c.key.clone(), c.value::<Todo>().unwrap()))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn applies_accepted_changes_and_reverts_rejected_ones() {
+        let _db = TestDb::new([Todo::schema()]).await;
+        let (user, other) = (Uuid::now_v7(), Uuid::now_v7());
+        todo(2, other, "theirs").save().await.unwrap();
+
+        let response = sync(
+            user,
+            0,
+            vec![
+                pushed(1, &todo(1, user, "mine")),
+                pushed(2, &todo(2, user, "stolen")),
+                // under the key of another row
+                pushed(3, &todo(4, user, "misplaced")),
+            ],
+        )
+        .await;
+
+        let stored = Todo::get_all().await.unwrap();
+        assert_eq!(stored.len(), 2);
+        assert_eq!(
+            Todo::get_by_pkey(1).await.unwrap(),
+            Some(todo(1, user, "mine"))
+        );
+        assert_eq!(
+            Todo::get_by_pkey(2).await.unwrap(),
+            Some(todo(2, other, "theirs"))
+        );
+
+        let client = applied(&response);
+        let key = |id: u8| id.into_sql_key().unwrap();
+        assert_eq!(client[&key(1)], Some(todo(1, user, "mine")));
+        // rows of other users are removed on the client without leaking their values
+        assert_eq!(client[&key(2)], None);
+        assert_eq!(client[&key(3)], None);
+        for change in response.changes {
+            let value = change.value::<Todo>().unwrap();
+            assert!(value.map_or(true, |t| t.owner == user));
+        }
+    }
+
+    #[tokio::test]
+    async fn resolves_conflicts_by_the_last_writer() {
+        let _db = TestDb::new([Todo::schema()]).await;
+        let user = Uuid::now_v7();
+        let pulled = sync(user, 0, vec![pushed(1, &todo(1, user, "first"))]).await;
+
+        // changed on the host after the client's pull and then by the client
+        todo(1, user, "host").save().await.unwrap();
+        let response = sync(
+            user,
+            pulled.cursor,
+            vec![pushed(1, &todo(1, user, "client"))],
+        )
+        .await;
+
+        let stored = Todo::get_by_pkey(1).await.unwrap().unwrap();
+        assert_eq!(stored.task, "client");
+        let key = 1u8.into_sql_key().unwrap();
+        assert_eq!(applied(&response)[&key], Some(stored));
+        assert!(response.cursor > pulled.cursor);
+    }
+}
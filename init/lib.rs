@@ -12,6 +12,7 @@ type AttributeArgs = syn::punctuated::Punctuated<syn::Meta, syn::Token![,]>;
 #[derive(Debug, Default)]
 struct Config {
     log_filters: Vec<(String, String)>,
+    db: Option<Ident>,
     manifest: Manifest,
     tables: Vec<Ident>,
 }
@@ -58,6 +59,7 @@ fn build_config(input: &ItemFn, args: AttributeArgs) -> Result<Config, syn::Erro
     // parse all source files in search for Storage derivations
 
     let mut log_filters = vec![];
+    let mut db = None;
 
     for arg in args {
         match arg {
@@ -127,9 +129,27 @@ fn build_config(input: &ItemFn, args: AttributeArgs) -> Result<Config, syn::Erro
                             log_filters.push((filter, level));
                         }
                     }
+                    "db" => {
+                        let backend = match &namevalue.value {
+                            syn::Expr::Lit(syn::ExprLit { lit, .. }) => lit,
+                            expr => return Err(syn::Error::new_spanned(expr, "Must be a literal")),
+                        };
+                        let span = syn::spanned::Spanned::span(backend);
+                        let backend = parse_string(backend.clone(), span, "db")?;
+                        let variant = match backend.to_lowercase().as_str() {
+                            "sled" => "Sled",
+                            "memory" => "Memory",
+                            "sqlite" => "Sqlite",
+                            _ => {
+                                let msg = "Must be one of `sled`, `memory` or `sqlite`";
+                                return Err(syn::Error::new(span, msg));
+                            }
+                        };
+                        db = Some(ident(variant));
+                    }
                     name => {
                         let msg = format!(
-                            "Unknown attribute {name} is specified; expected `log_filters`",
+                            "Unknown attribute {name} is specified; expected `log_filters` or `db`",
                         );
                         return Err(syn::Error::new_spanned(namevalue, msg));
                     }
@@ -185,6 +205,7 @@ fn build_config(input: &ItemFn, args: AttributeArgs) -> Result<Config, syn::Erro
 
     Ok(Config {
         log_filters,
+        db,
         manifest,
         tables,
     })
@@ -240,6 +261,11 @@ fn expand(mut input: ItemFn, config: Config) -> TokenStream {
         let __________ = std::thread::spawn(|| prest::logs::init_tracing_subscriber(&[ #(#filters ,)* ]))
     );
 
+    let init_db = match config.db {
+        Some(backend) => quote!( prest::DB._init_with(prest::DbBackend::#backend) ),
+        None => quote!(prest::DB._init()),
+    };
+
     let register_tables = config
         .tables
         .into_iter()
@@ -255,10 +281,16 @@ fn expand(mut input: ItemFn, config: Config) -> TokenStream {
         std::thread::spawn(|| {
             prest::Lazy::force(&prest::SYSTEM_INFO);
         });
-        std::thread::spawn(|| {
-            prest::Lazy::force(&prest::DB);
+        let __db_init = std::thread::spawn(|| {
+            #init_db?;
             #(#register_tables)*
+            prest::OK
         });
+        // migrations must see all the registered schemas
+        if let Err(e) = __db_init.join().expect("DB initialization should finish successfully") {
+            eprintln!("Failed to start the DB: {e}");
+            std::process::exit(1);
+        }
         prest::RT.block_on(async {
             prest::DB.migrate().await.expect("DB migration should be successful");
         });
@@ -48,6 +48,9 @@ pub enum Error {
     #[cfg(feature = "db")]
     #[error(transparent)]
     SQL(#[from] gluesql_core::error::Error),
+    #[cfg(feature = "db")]
+    #[error("{0}")]
+    Validation(crate::ValidationErrors),
     #[cfg(all(host, feature = "db"))]
     #[error(transparent)]
     IO(#[from] std::io::Error),
@@ -86,6 +89,10 @@ impl IntoResponse for Error {
             | Error::Session(_)
             | Error::OpenIDClaimVerification(_) => StatusCode::UNAUTHORIZED.into_response(),
             Error::NotFound => StatusCode::NOT_FOUND.into_response(),
+            #[cfg(feature = "db")]
+            Error::Validation(errors) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, Json(errors)).into_response()
+            }
             _ => {
                 error!("{self}");
                 StatusCode::INTERNAL_SERVER_ERROR.into_response()
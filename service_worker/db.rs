@@ -0,0 +1,95 @@
+use {crate::*, futures::lock::Mutex, gluesql_idb_storage::IdbStorage, std::rc::Rc};
+
+/// Name of the IndexedDB database which keeps the tables of the service worker
+const IDB_NAMESPACE: &str = "prest";
+
+thread_local! {
+    /// Opened lazily by the first query, locked by every query so that they don't interleave
+    pub(super) static SW_DB: Rc<Mutex<Option<SwDb>>> = Default::default();
+}
+
+/// Runs the query on the service worker's event loop and sends back its result
+pub(crate) fn read(query: Query, returner: Returner) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let db = SW_DB.with(Rc::clone);
+        let mut db = db.lock().await;
+        let result = match connect(&mut db).await {
+            Ok(db) => db.read(query).await,
+            Err(e) => Err(e),
+        };
+        if returner.send(result).is_err() {
+            warn!(target: "db", "failed to return read result");
+        }
+    });
+}
+
+/// Applies the write on the service worker's event loop and sends back its result
+pub(crate) fn write(tx: Transaction, returner: Returner) {
+    wasm_bindgen_futures::spawn_local(async move {
+        let db = SW_DB.with(Rc::clone);
+        let mut db = db.lock().await;
+        let result = match connect(&mut db).await {
+            Ok(db) => write_and_enqueue(db, tx).await,
+            Err(e) => Err(e),
+        };
+        if returner.send(result).is_err() {
+            warn!(target: "db", "failed to return write result");
+        }
+    });
+}
+
+/// Tables of the registered [`Storage`] structs kept in the browser's IndexedDB
+///
+/// Works without the network so that the same `Storage` calls render pages offline.
+/// Writes of `#[storage(sync)]` rows made through the `Storage` methods are queued for the host, see [`sync_db`]
+pub(super) type SwDb = GlueDb<IdbStorage>;
+
+pub(super) async fn connect(db: &mut Option<SwDb>) -> Result<&mut SwDb> {
+    if db.is_none() {
+        let storage = IdbStorage::new(Some(IDB_NAMESPACE.to_owned())).await?;
+        // local rows are a cache of the host's ones so they aren't migrated
+        *db = Some(GlueDb::new(
+            storage,
+            DB.schemas.clone(),
+            "service worker",
+            true,
+        ));
+    }
+    let db = db.as_mut().expect("DB is opened above");
+    db.sync_tables().await?;
+    Ok(db)
+}
+
+/// Applies the write and queues its changes of the `#[storage(sync)]` rows for the host
+async fn write_and_enqueue(db: &mut SwDb, tx: Transaction) -> Result<Payload> {
+    let (payload, written) = db.write(tx).await?;
+    let synced = written
+        .iter()
+        .filter(|row| {
+            DB.schemas
+                .fetch_struct_schema(row.table)
+                .is_some_and(|s| s.sync())
+        })
+        .map(|row| SyncChange {
+            table: row.table.to_owned(),
+            key: row.key.clone(),
+            row: row.new.clone(),
+            removed: None,
+            timestamp: Utc::now().naive_utc(),
+        })
+        .collect::<Vec<_>>();
+    if synced.is_empty() {
+        return Ok(payload);
+    }
+    // queued along with the write so that it either reaches the host or is rolled back
+    match db.enqueue(synced).await {
+        Ok(()) => {
+            super::sync::schedule();
+            Ok(payload)
+        }
+        Err(e) => {
+            db.undo(written).await;
+            Err(e)
+        }
+    }
+}
@@ -1,6 +1,9 @@
 use crate::*;
 
+pub(crate) mod db;
 mod state;
+mod sync;
+pub use sync::sync_db;
 
 pub use console_error_panic_hook::set_once as set_panic_hook;
 use js_sys::{Array, Reflect, Set, Uint8Array};
@@ -10,15 +13,11 @@ pub use web_sys::{console, FetchEvent, ServiceWorkerGlobalScope};
 
 impl Db {
     pub(crate) fn init() -> Self {
-        // TODO: figure out how to use gluesql::idb_storage::IdbStorage or another browser-based for SW
-        let (write_sender, writes) = std::sync::mpsc::sync_channel::<DbWriteMessage>(1);
-        let (read_sender, reads) = std::sync::mpsc::sync_channel::<DbReadMessage>(1);
+        // there are no DB threads in the service worker, queries run on its event loop in `db`
         Db {
-            read: read_sender,
-            write: write_sender,
-            internal_schemas: Arc::new(vec![]),
-            custom_schemas: Default::default(),
-            handles: Arc::new(vec![]),
+            schemas: Schemas::new(vec![sync::SyncOutbox::schema(), sync::SyncCursor::schema()]),
+            read_metrics: Default::default(),
+            temp_path: None,
         }
     }
 }
@@ -106,6 +105,8 @@ pub async unsafe fn handle_fetch(sw: ServiceWorkerGlobalScope, event: FetchEvent
         return;
     }
 
+    sync::schedule_periodic();
+
     let response = axum_response_to_websys(response);
     let promise = wasm_bindgen_futures::future_to_promise(response);
     event.respond_with(&promise).unwrap();
@@ -0,0 +1,209 @@
+use {
+    super::db::{connect, SwDb, SW_DB},
+    crate::*,
+    gluesql_core::store::StoreMut,
+    js_sys::{Date, Uint8Array},
+    std::{cell::Cell, collections::HashSet, rc::Rc},
+    wasm_bindgen::JsCast,
+    wasm_bindgen_futures::JsFuture,
+};
+
+/// Minimal interval between syncs triggered by the fetch events
+const SYNC_INTERVAL_MS: f64 = 30_000.0;
+
+/// Local write of a `#[storage(sync)]` row which wasn't accepted by the host yet
+#[derive(Storage, Debug, Serialize, Deserialize)]
+pub(crate) struct SyncOutbox {
+    /// Time-ordered so that writes are replayed in the order they were made
+    pub id: Uuid,
+    /// Serialized [`SyncChange`]
+    pub change: Vec<u8>,
+}
+
+/// Id of the last host's write that was pulled into the service worker
+#[derive(Storage, Debug, Serialize, Deserialize)]
+pub(crate) struct SyncCursor {
+    pub id: u8,
+    pub cursor: u64,
+}
+
+thread_local! {
+    static SYNCING: Cell<bool> = const { Cell::new(false) };
+    static SYNC_REQUESTED: Cell<bool> = const { Cell::new(false) };
+    static LAST_SYNC: Cell<f64> = const { Cell::new(0.0) };
+}
+
+/// Pushes local writes of the `#[storage(sync)]` tables to the host and pulls the host's ones
+///
+/// Runs automatically after such writes and periodically while the service worker handles requests,
+/// but can be awaited directly to sync on demand. Requires [`HostUtils::sync_db`] on the host
+pub async fn sync_db() -> Result {
+    if SYNCING.with(|s| s.replace(true)) {
+        // the running sync will go again once it's done
+        SYNC_REQUESTED.with(|r| r.set(true));
+        return OK;
+    }
+    let mut result = OK;
+    loop {
+        SYNC_REQUESTED.with(|r| r.set(false));
+        LAST_SYNC.with(|l| l.set(Date::now()));
+        result = sync_once().await;
+        if result.is_err() || !SYNC_REQUESTED.with(|r| r.get()) {
+            break;
+        }
+    }
+    SYNCING.with(|s| s.set(false));
+    result
+}
+
+async fn sync_once() -> Result {
+    let db = SW_DB.with(Rc::clone);
+
+    let (cursor, outbox) = {
+        let mut db = db.lock().await;
+        connect(&mut db).await?.outbox().await?
+    };
+    let (sent, changes): (Vec<_>, Vec<_>) = outbox.into_iter().unzip();
+    let request = bitcode::serialize(&SyncRequest { cursor, changes }).somehow()?;
+
+    // the DB isn't locked while the request is in flight so local writes go on
+    let response = push_and_pull(request).await?;
+    let response: SyncResponse = bitcode::deserialize(&response).somehow()?;
+
+    let mut db = db.lock().await;
+    connect(&mut db).await?.pulled(cursor, sent, response).await
+}
+
+async fn push_and_pull(body: Vec<u8>) -> Result<Vec<u8>> {
+    let sw = js_sys::global().unchecked_into::<ServiceWorkerGlobalScope>();
+    let init = web_sys::RequestInit::new();
+    init.set_method("POST");
+    init.set_body(&Uint8Array::from(body.as_slice()).into());
+
+    let response = JsFuture::from(sw.fetch_with_str_and_init(SYNC_ROUTE, &init))
+        .await
+        .map_err(|e| e!("sync request failed: {e:?}"))?
+        .unchecked_into::<web_sys::Response>();
+    if !response.ok() {
+        return Err(e!("sync request failed with status {}", response.status()));
+    }
+    let buf = response
+        .array_buffer()
+        .map_err(|e| e!("failed to read sync response: {e:?}"))?;
+    let buf = JsFuture::from(buf)
+        .await
+        .map_err(|e| e!("failed to read sync response: {e:?}"))?;
+    Ok(Uint8Array::new(&buf).to_vec())
+}
+
+/// Starts the sync in the background
+pub(crate) fn schedule() {
+    wasm_bindgen_futures::spawn_local(async {
+        if let Err(e) = sync_db().await {
+            warn!(target: "db", "failed to sync with the host: {e}");
+        }
+    });
+}
+
+/// Starts the sync if there are synchronized tables and it didn't run recently
+pub(crate) fn schedule_periodic() {
+    if Date::now() - LAST_SYNC.with(|l| l.get()) < SYNC_INTERVAL_MS {
+        return;
+    }
+    if DB.schemas.custom().iter().any(|s| s.sync()) {
+        schedule();
+    }
+}
+
+impl SwDb {
+    /// Queues local changes for the host
+    pub async fn enqueue(&mut self, changes: Vec<SyncChange>) -> Result {
+        for change in changes {
+            let entry = SyncOutbox {
+                id: Uuid::now_v7(),
+                change: bitcode::serialize(&change).somehow()?,
+            };
+            self.write_row(
+                "SyncOutbox",
+                entry.id.into_sql_key()?,
+                Some(entry.into_row()?),
+            )
+            .await?;
+        }
+        OK
+    }
+
+    /// Current cursor and queued changes in the order they were made
+    async fn outbox(&self) -> Result<(u64, Vec<(sql::Key, SyncChange)>)> {
+        let cursor = match self.fetch("SyncCursor", &0u8.into_sql_key()?).await? {
+            Some(row) => SyncCursor::from_row(row)?.cursor,
+            None => 0,
+        };
+        let mut entries = self
+            .scan("SyncOutbox")
+            .await?
+            .into_iter()
+            .map(|(_, row)| SyncOutbox::from_row(row))
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.id);
+        let mut changes = vec![];
+        for entry in entries {
+            let change = bitcode::deserialize(&entry.change).somehow()?;
+            changes.push((entry.id.into_sql_key()?, change));
+        }
+        Ok((cursor, changes))
+    }
+
+    /// Drops pushed changes and applies the host's ones except for rows changed locally in the meantime
+    async fn pulled(&mut self, cursor: u64, sent: Vec<sql::Key>, response: SyncResponse) -> Result {
+        self.glue.storage.delete_data("SyncOutbox", sent).await?;
+        let (_, pending) = self.outbox().await?;
+        let pending = pending
+            .into_iter()
+            .map(|(_, change)| Ok((change.table, change.key.to_cmp_be_bytes()?)))
+            .collect::<Result<HashSet<_>>>()?;
+
+        let synced = DB
+            .schemas
+            .custom()
+            .into_iter()
+            .filter(|schema| schema.sync())
+            .collect::<Vec<_>>();
+
+        // the first pull brings all the rows so the ones that host doesn't have are stale
+        if cursor == 0 {
+            let snapshot = response
+                .changes
+                .iter()
+                .map(|change| Ok((change.table.clone(), change.key.to_cmp_be_bytes()?)))
+                .collect::<Result<HashSet<_>>>()?;
+            for schema in synced.iter() {
+                let name = schema.name();
+                for (key, _) in self.scan(name).await? {
+                    let id = (name.to_owned(), key.to_cmp_be_bytes()?);
+                    if !snapshot.contains(&id) && !pending.contains(&id) {
+                        self.write_row(name, key, None).await?;
+                    }
+                }
+            }
+        }
+
+        for change in response.changes {
+            if !synced.iter().any(|schema| schema.name() == change.table) {
+                continue;
+            }
+            let id = (change.table, change.key.to_cmp_be_bytes()?);
+            if pending.contains(&id) {
+                continue;
+            }
+            self.write_row(&id.0, change.key, change.row).await?;
+        }
+
+        let cursor = SyncCursor {
+            id: 0,
+            cursor: response.cursor,
+        };
+        self.write_row("SyncCursor", 0u8.into_sql_key()?, Some(cursor.into_row()?))
+            .await
+    }
+}
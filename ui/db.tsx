@@ -10,11 +10,23 @@ type FieldSchema = {
     list: boolean;
     optional: boolean;
     serialized: boolean;
+    structured: boolean;
+    searchable: boolean;
+    references: string | null;
 };
 
 type TableDescription = {
     name: string;
     fields: FieldSchema[];
+    history: boolean;
+};
+
+type HistoryRow = {
+    tx_id: number;
+    timestamp: string;
+    user: string | null;
+    action: 'Insert' | 'Update' | 'Delete';
+    row: string[];
 };
 
 type TableData = {
@@ -22,7 +34,8 @@ type TableData = {
     fields: FieldSchema[];
     rows: string[][];
     has_more: boolean;
-    total_pages?: number;
+    total_rows: number;
+    total_pages: number;
 };
 
 type EditingRow = {
@@ -39,7 +52,12 @@ let editingRow: EditingRow = null;
 let isCreating: boolean = false;
 let newRowValues: { [key: string]: string } = {};
 let currentPage: number = 0;
+let showHistory: boolean = false;
+let historyRows: HistoryRow[] | null = null;
+let searchQuery: string = '';
+let searchedQuery: string | null = null;
 const PAGE_SIZE = 20;
+const HISTORY_LIMIT = 100;
 
 // Ensure schema is always an array
 function getSchema(): TableDescription[] {
@@ -67,15 +85,68 @@ async function loadTableData(tableName: string, page: number = 0) {
         const data = await resp.json();
         tableData[tableName] = data;
         currentPage = page;
+        searchedQuery = null;
         renderDatabase();
     } catch (error) {
         console.error('Failed to load table data:', error);
     }
 }
 
+async function search() {
+    if (!selectedTable) return;
+    const query = searchQuery.trim();
+    if (!query) {
+        clearSearch();
+        return;
+    }
+
+    try {
+        const resp = await fetch(`/admin/db/table/${selectedTable}/search?q=${encodeURIComponent(query)}&limit=${PAGE_SIZE}`);
+        const rows: string[][] = await resp.json();
+        const table = tableData[selectedTable];
+        if (table) {
+            tableData[selectedTable] = { ...table, rows, has_more: false };
+        }
+        searchedQuery = query;
+        renderDatabase();
+    } catch (error) {
+        console.error('Failed to search:', error);
+    }
+}
+
+function clearSearch() {
+    searchQuery = '';
+    if (selectedTable) {
+        loadTableData(selectedTable, 0);
+    }
+}
+
+async function loadHistory(tableName: string) {
+    try {
+        const resp = await fetch(`/admin/db/table/${tableName}/history?limit=${HISTORY_LIMIT}`);
+        historyRows = await resp.json();
+        renderDatabase();
+    } catch (error) {
+        console.error('Failed to load history:', error);
+    }
+}
+
+function toggleHistory(history: boolean) {
+    showHistory = history;
+    historyRows = null;
+    renderDatabase();
+    if (history && selectedTable) {
+        loadHistory(selectedTable);
+    }
+}
+
 function selectTable(tableName: string) {
     selectedTable = tableName;
     currentPage = 0;
+    showHistory = false;
+    historyRows = null;
+    searchQuery = '';
+    searchedQuery = null;
     // Clear cached data to force reload with pagination
     delete tableData[tableName];
     loadTableData(tableName, 0);
@@ -94,6 +165,19 @@ function prevDbPage() {
     loadTableData(selectedTable, currentPage - 1);
 }
 
+function firstDbPage() {
+    if (!selectedTable || currentPage === 0) return;
+    loadTableData(selectedTable, 0);
+}
+
+function lastDbPage() {
+    if (!selectedTable) return;
+    const table = tableData[selectedTable];
+    if (table && currentPage < table.total_pages - 1) {
+        loadTableData(selectedTable, table.total_pages - 1);
+    }
+}
+
 function startEditing(tableIndex: number, rowIndex: number) {
     const table = tableData[selectedTable!];
     const row = table.rows[rowIndex];
@@ -106,7 +190,7 @@ function startEditing(tableIndex: number, rowIndex: number) {
             value = value === 'true' ? 'true' : 'false';
         }
         // For JSON fields, format for display in textarea
-        else if (field.serialized && value) {
+        else if (isJson(field) && value) {
             try {
                 const parsed = JSON.parse(value);
                 value = JSON.stringify(parsed, null, 2);
@@ -145,7 +229,7 @@ async function saveEdit() {
     // Process special fields - convert strings to proper types
     const processedValues: { [key: string]: any } = { ...editingRow.values };
     table.fields.forEach(field => {
-        if (field.serialized && processedValues[field.name]) {
+        if (isJson(field) && processedValues[field.name]) {
             try {
                 processedValues[field.name] = JSON.parse(processedValues[field.name]);
             } catch (e) {
@@ -189,7 +273,7 @@ async function createRow() {
     // Process special fields - convert strings to proper types  
     const processedValues: { [key: string]: any } = { ...newRowValues };
     table.fields.forEach(field => {
-        if (field.serialized && processedValues[field.name]) {
+        if (isJson(field) && processedValues[field.name]) {
             try {
                 processedValues[field.name] = JSON.parse(processedValues[field.name]);
             } catch (e) {
@@ -240,7 +324,7 @@ async function deleteRow(rowIndex: number) {
             value = value === 'true';
         }
         // Parse JSON fields
-        else if (field.serialized && value) {
+        else if (isJson(field) && value) {
             try {
                 value = JSON.parse(value);
             } catch (e) {
@@ -284,7 +368,7 @@ function startCreating() {
             newRowValues[field.name] = 'false';
         }
         // For JSON fields, default to empty object
-        else if (field.serialized) {
+        else if (isJson(field)) {
             newRowValues[field.name] = '{}';
         } else {
             newRowValues[field.name] = '';
@@ -294,13 +378,20 @@ function startCreating() {
     renderDatabase();
 }
 
+// encoded values and structured ones except for TEXT are edited as JSON
+function isJson(field: FieldSchema): boolean {
+    return field.serialized || (field.structured && field.sql_type !== 'Text');
+}
+
 function getInputType(field: FieldSchema): string {
-    if (field.serialized) {
+    if (isJson(field)) {
         return 'json';
     } else if (field.sql_type === 'Boolean' && !field.list && !field.optional) {
         return 'checkbox';
     } else if (field.numeric && !field.list && !field.optional) {
         return 'number';
+    } else if (field.sql_type === 'Date' && !field.list && !field.optional) {
+        return 'date';
     }
     return 'text';
 }
@@ -337,6 +428,65 @@ const DatabaseAdmin = () => {
         </Fragment>
     );
 
+    const renderTabs = () => {
+        const description = currentSchema.find(t => t.name === selectedTable);
+        if (!description?.history) {
+            return null;
+        }
+        return (
+            <div className="table-tabs">
+                <button className={showHistory ? '' : 'selected'} onClick={() => toggleHistory(false)}>
+                    Rows
+                </button>
+                <button className={showHistory ? 'selected' : ''} onClick={() => toggleHistory(true)}>
+                    History
+                </button>
+            </div>
+        );
+    };
+
+    const renderHistory = () => {
+        const table = tableData[selectedTable!];
+        if (!historyRows || !table) {
+            return <div className="table-loading">Loading history...</div>;
+        }
+
+        return (
+            <div className="table-view">
+                <div className="table-header">
+                    <h2>{table.name} history</h2>
+                    {renderTabs()}
+                </div>
+                <table className="data-table">
+                    <thead>
+                        <tr>
+                            <th>Tx</th>
+                            <th>Time</th>
+                            <th>User</th>
+                            <th>Action</th>
+                            {table.fields.map(field => (
+                                <th key={field.name}>{formatFieldType(field)}</th>
+                            ))}
+                        </tr>
+                    </thead>
+                    <tbody>
+                        {historyRows.map(version => (
+                            <tr key={`${version.tx_id}-${version.row.join()}`} className="view-row">
+                                <td>{version.tx_id}</td>
+                                <td>{version.timestamp}</td>
+                                <td>{version.user || '-'}</td>
+                                <td>{version.action}</td>
+                                {version.row.map((value, index) => (
+                                    <td key={index}><span>{value}</span></td>
+                                ))}
+                            </tr>
+                        ))}
+                    </tbody>
+                </table>
+            </div>
+        );
+    };
+
     const renderTable = () => {
         if (!selectedTable) {
             return <div className="table-placeholder">Please select a table from above to view its data</div>;
@@ -346,24 +496,57 @@ const DatabaseAdmin = () => {
             return <div className="table-loading">Loading table data...</div>;
         }
 
+        if (showHistory) {
+            return renderHistory();
+        }
+
         const table = tableData[selectedTable];
+        const searchable = table.fields.some(field => field.searchable);
         
         return (
             <div className="table-view">
                 <div className="table-header">
                     <h2>{table.name}</h2>
+                    {renderTabs()}
                     <div className="table-controls">
-                        <div className="pagination-info">
-                            Page {currentPage + 1} ({table.rows.length} rows)
-                        </div>
-                        <div className="pagination-controls">
-                            <button onClick={prevDbPage} disabled={currentPage === 0}>
-                                ← Previous
-                            </button>
-                            <button onClick={nextDbPage} disabled={!table.has_more}>
-                                Next →
-                            </button>
-                        </div>
+                        {searchable && (
+                            <form className="table-search" onSubmit={(e) => { e.preventDefault(); search(); }}>
+                                <input
+                                    type="search"
+                                    value={searchQuery}
+                                    onInput={(e) => { searchQuery = e.currentTarget.value; }}
+                                    placeholder="Search..."
+                                />
+                                {searchedQuery !== null && (
+                                    <button type="button" onClick={clearSearch}>✗</button>
+                                )}
+                            </form>
+                        )}
+                        {searchedQuery !== null ? (
+                            <div className="pagination-info">
+                                {table.rows.length} most relevant rows for "{searchedQuery}"
+                            </div>
+                        ) : (
+                            <Fragment>
+                                <div className="pagination-info">
+                                    Page {currentPage + 1} of {Math.max(table.total_pages, 1)} ({table.total_rows} rows)
+                                </div>
+                                <div className="pagination-controls">
+                                    <button onClick={firstDbPage} disabled={currentPage === 0}>
+                                        « First
+                                    </button>
+                                    <button onClick={prevDbPage} disabled={currentPage === 0}>
+                                        ← Previous
+                                    </button>
+                                    <button onClick={nextDbPage} disabled={!table.has_more}>
+                                        Next →
+                                    </button>
+                                    <button onClick={lastDbPage} disabled={currentPage >= table.total_pages - 1}>
+                                        Last »
+                                    </button>
+                                </div>
+                            </Fragment>
+                        )}
                         <button onClick={startCreating} disabled={isCreating}>
                             Add New Row
                         </button>
@@ -377,6 +560,7 @@ const DatabaseAdmin = () => {
                                 <th key={field.name}>
                                     {formatFieldType(field)}
                                     {field.pkey && <span className="primary-key">🔑</span>}
+                                    {field.references && <span className="reference">→ {field.references}</span>}
                                 </th>
                             ))}
                             <th>Actions</th>
@@ -387,7 +571,7 @@ const DatabaseAdmin = () => {
                             <tr className="creating-row">
                                 {table.fields.map(field => (
                                     <td key={field.name}>
-                                        {field.serialized ? (
+                                        {isJson(field) ? (
                                             <textarea
                                                 value={newRowValues[field.name] || '{}'}
                                                 onInput={(e) => updateEditValue(field.name, e.currentTarget.value)}
@@ -428,7 +612,7 @@ const DatabaseAdmin = () => {
                                     {table.fields.map((field, fieldIndex) => (
                                         <td key={field.name}>
                                             {isEditing ? (
-                                                field.serialized ? (
+                                                isJson(field) ? (
                                                     <textarea
                                                         value={editingRow?.values[field.name] || '{}'}
                                                         onInput={(e) => updateEditValue(field.name, e.currentTarget.value)}
@@ -453,7 +637,13 @@ const DatabaseAdmin = () => {
                                                     />
                                                 )
                                             ) : (
-                                                <span>{row[fieldIndex]}</span>
+                                                field.references ? (
+                                                    <a className="reference" href="#" onClick={(e) => { e.preventDefault(); selectTable(field.references!); }}>
+                                                        {row[fieldIndex]}
+                                                    </a>
+                                                ) : (
+                                                    <span>{row[fieldIndex]}</span>
+                                                )
                                             )}
                                         </td>
                                     ))}